@@ -1,15 +1,15 @@
-use std::{any::Any, sync::{Arc, Mutex}, ops::Deref};
+use std::{any::{Any, TypeId, type_name}, sync::{Arc, Mutex}};
 
 use serde::{Deserialize, Serialize, de::DeserializeOwned};
 
 use crate::{value::Value, prelude::{Type}};
 
-pub struct Adapter {
-    adapter_impl: Box<dyn AdapterImpl>
+pub struct Adapter<'a> {
+    adapter_impl: Box<dyn AdapterImpl + 'a>
 }
 
-impl Adapter {
-    pub fn new<A: AdapterImpl + 'static>(adapter: A) -> Self {
+impl<'a> Adapter<'a> {
+    pub fn new<A: AdapterImpl + 'a>(adapter: A) -> Self {
         Self { adapter_impl: Box::new(adapter) }
     }
 
@@ -18,25 +18,41 @@ impl Adapter {
         bincode::deserialize(&arg_bytes).unwrap()
     }
 
-    pub fn get_foreign_arg<T: 'static>(&self, arg_index: usize) -> Arc<Mutex<T>> {
+    /// Returns the foreign object a handle argument refers to, see
+    /// `Adapter::get_foreign_ptr`
+    pub fn get_foreign_arg<T: Send + 'static>(&self, arg_index: usize) -> Result<Arc<Mutex<T>>, ForeignError> {
         let arg_bytes = self.adapter_impl.get_arg_bytes(arg_index);
         let ptr: u64 = bincode::deserialize(&arg_bytes).unwrap();
         self.get_foreign_ptr(ptr)
     }
 
-    pub fn get_foreign_ptr<T: 'static>(&self, ptr: u64) -> Arc<Mutex<T>> {
-        let raw_box = self.adapter_impl.get_foreign_ptr(ptr);
-        let arc_box: Box<Arc<Mutex<T>>> = raw_box.downcast().expect("ERROR WHEN DOWNCASTING");
-        *arc_box
+    /// Returns the string argument at the index, if the backend can read it
+    pub fn get_str_arg(&self, arg_index: usize) -> Option<String> {
+        let arg_bytes = self.adapter_impl.get_arg_bytes(arg_index);
+        let ptr: u64 = bincode::deserialize(&arg_bytes).ok()?;
+        self.adapter_impl.get_str(ptr)
+    }
+
+    /// Returns the foreign object behind the handle. Fails if there is none
+    /// or it has another type, which also fails the running call with the
+    /// matching error of the backend.
+    pub fn get_foreign_ptr<T: Send + 'static>(&self, ptr: u64) -> Result<Arc<Mutex<T>>, ForeignError> {
+        let item = self
+            .adapter_impl
+            .get_foreign_object(ptr)
+            .and_then(|object| object.get::<T>(ptr));
+        if let Err(err) = &item {
+            self.adapter_impl.set_foreign_error(err.clone());
+        }
+        item
     }
 
-    pub fn insert_foreign_object<T: 'static>(&mut self, val: T) -> u64 {
+    pub fn insert_foreign_object<T: Send + 'static>(&mut self, val: T) -> u64 {
         let val_arc = Arc::new(Mutex::new(val));
-        let any_box: Box<dyn Any> = Box::new(val_arc);
-        self.adapter_impl.insert_foreign_ptr(any_box)
+        self.adapter_impl.insert_foreign_ptr(ForeignObject::new(val_arc))
     }
 
-    pub fn ret_foreign_object<T: 'static>(&mut self, val: T) {
+    pub fn ret_foreign_object<T: Send + 'static>(&mut self, val: T) {
         let ptr = self.insert_foreign_object(val);
         self.ret(ptr);
     }
@@ -50,6 +66,105 @@ impl Adapter {
 pub trait AdapterImpl {
     fn ret(&mut self, bytes: &[u8]);
     fn get_arg_bytes(&self, arg_index: usize) -> Vec<u8>;
-    fn get_foreign_ptr(&self, ptr: u64) -> Box<dyn Any>;
-    fn insert_foreign_ptr(&mut self, object: Box<dyn Any>) -> u64;
-}
\ No newline at end of file
+    /// Returns the foreign object behind the pointer, sharing it with the
+    /// backend
+    fn get_foreign_object(&self, ptr: u64) -> Result<ForeignObject, ForeignError>;
+    /// Records that a host function failed to get a foreign object, which
+    /// fails the running call
+    fn set_foreign_error(&self, err: ForeignError);
+    /// Reads the string the pointer of a `&str` argument points to
+    fn get_str(&self, ptr: u64) -> Option<String>;
+    /// Inserts a foreign object, returning the pointer scripts refer to it by
+    fn insert_foreign_ptr(&mut self, object: ForeignObject) -> u64;
+}
+
+/// A foreign object shared with a core, with what the core needs to
+/// check its type and count the references the host holds on it
+pub struct ForeignObject {
+    /// The `Arc<Mutex<T>>` holding the object
+    pub object: Box<dyn Any + Send>,
+    /// The type id of `Arc<Mutex<T>>`
+    pub type_id: TypeId,
+    /// The name of `T`, for error messages
+    pub type_name: &'static str,
+    host_refs: fn(&(dyn Any + Send)) -> usize,
+    share: fn(&(dyn Any + Send)) -> Box<dyn Any + Send>,
+}
+
+impl Clone for ForeignObject {
+    /// Shares the object, cloning the `Arc` holding it
+    fn clone(&self) -> Self {
+        Self {
+            object: (self.share)(self.object.as_ref()),
+            type_id: self.type_id,
+            type_name: self.type_name,
+            host_refs: self.host_refs,
+            share: self.share,
+        }
+    }
+}
+
+/// Why a host function can't get the foreign object behind a handle
+#[derive(Clone, Debug, PartialEq)]
+pub enum ForeignError {
+    /// No foreign object exists for the handle, or it was removed
+    InvalidHandle(u64),
+    /// The foreign object behind the handle has another type than requested
+    TypeMismatch {
+        /// The handle
+        ptr: u64,
+        /// The requested type
+        expected: &'static str,
+        /// The type of the object
+        found: &'static str,
+    },
+}
+
+impl ForeignObject {
+    /// Wraps an object shared between the host and a core
+    pub fn new<T: Send + 'static>(item: Arc<Mutex<T>>) -> Self {
+        Self {
+            object: Box::new(item),
+            type_id: TypeId::of::<Arc<Mutex<T>>>(),
+            type_name: type_name::<T>(),
+            host_refs: host_refs::<T>,
+            share: share::<T>,
+        }
+    }
+
+    /// Returns the object if it has the type `T`, the object behind the
+    /// given handle
+    pub fn get<T: Send + 'static>(&self, ptr: u64) -> Result<Arc<Mutex<T>>, ForeignError> {
+        self.object
+            .downcast_ref::<Arc<Mutex<T>>>()
+            .cloned()
+            .ok_or(ForeignError::TypeMismatch {
+                ptr,
+                expected: type_name::<T>(),
+                found: self.type_name,
+            })
+    }
+
+    /// Returns the number of references the host holds on the object
+    pub fn get_host_refs(&self) -> usize {
+        (self.host_refs)(self.object.as_ref())
+    }
+}
+
+fn host_refs<T: Send + 'static>(object: &(dyn Any + Send)) -> usize {
+    object
+        .downcast_ref::<Arc<Mutex<T>>>()
+        .map(|item| Arc::strong_count(item) - 1)
+        .unwrap_or(0)
+}
+
+/// Stands in for an object replaced by one of another type, which can't be
+/// shared and which no host function asks for
+struct Unshareable;
+
+fn share<T: Send + 'static>(object: &(dyn Any + Send)) -> Box<dyn Any + Send> {
+    match object.downcast_ref::<Arc<Mutex<T>>>() {
+        Some(item) => Box::new(item.clone()),
+        None => Box::new(Unshareable),
+    }
+}
@@ -1 +1,2 @@
+#[derive(Clone)]
 pub struct Container;
\ No newline at end of file
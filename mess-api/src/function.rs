@@ -1,8 +1,26 @@
-use std::{sync::{Arc, Mutex}, ops::DerefMut};
+use std::{sync::{Arc, Mutex}, ops::DerefMut, future::Future, pin::Pin};
 
 use crate::{var_type::Type, adapter::Adapter};
 use derivative::Derivative;
 
+/// Places the result of an async host function once its future completes,
+/// e.g. with `Adapter::ret`
+pub type Completion = Box<dyn FnOnce(&mut Adapter) + Send>;
+
+/// The future an async host function returns. The script is suspended
+/// until it completes.
+pub type HostFuture = Pin<Box<dyn Future<Output = Completion> + Send>>;
+
+type SyncClosure = Arc<Mutex<dyn FnMut(&mut Adapter) + Send>>;
+
+type AsyncClosure = Arc<Mutex<dyn FnMut(&mut Adapter) -> HostFuture + Send>>;
+
+#[derive(Clone)]
+enum Closure {
+    Sync(SyncClosure),
+    Async(AsyncClosure),
+}
+
 #[derive(Derivative)]
 #[derivative(Clone, Debug, PartialEq)]
 pub struct Function {
@@ -11,7 +29,7 @@ pub struct Function {
     pub returns: Type,
     arg_sizes: Vec<usize>,
     #[derivative(Debug="ignore", PartialEq="ignore")]
-    closure: Arc<Mutex<dyn FnMut(&mut Adapter)>>
+    closure: Closure
 }
 
 impl Function {
@@ -21,14 +39,42 @@ impl Function {
             args,
             arg_sizes: vec![],
             returns,
-            closure: Arc::new(Mutex::new(closure))
+            closure: Closure::Sync(Arc::new(Mutex::new(closure)))
+        }
+    }
+
+    /// Creates a host function that reads its arguments and returns a future.
+    /// The calling script is suspended until the future completes.
+    pub fn new_async<S: Into<String>>(name: S, args: Vec<Type>, returns: Type, closure: fn(&mut Adapter) -> HostFuture) -> Self {
+        Self {
+            name: name.into(),
+            args,
+            arg_sizes: vec![],
+            returns,
+            closure: Closure::Async(Arc::new(Mutex::new(closure)))
         }
     }
 
-    pub fn run(&self, adapter: &mut Adapter) {
-        let mut closure_lock = self.closure.lock().unwrap();
-        let closure = closure_lock.deref_mut();
-        closure(adapter);
+    /// Whether the function completes through a future
+    pub fn is_async(&self) -> bool {
+        matches!(self.closure, Closure::Async(_))
+    }
+
+    /// Runs the function, returning its future if it is async
+    pub fn run(&self, adapter: &mut Adapter) -> Option<HostFuture> {
+        match &self.closure {
+            Closure::Sync(closure) => {
+                let mut closure_lock = closure.lock().unwrap();
+                let closure = closure_lock.deref_mut();
+                closure(adapter);
+                None
+            }
+            Closure::Async(closure) => {
+                let mut closure_lock = closure.lock().unwrap();
+                let closure = closure_lock.deref_mut();
+                Some(closure(adapter))
+            }
+        }
     }
 
     pub fn set_arg_sizes(&mut self, arg_sizes: Vec<usize>) {
@@ -48,7 +94,12 @@ impl Function {
         offset
     }
 
+    /// Whether the size of every argument was set with `set_arg_sizes`
+    pub fn has_arg_sizes(&self) -> bool {
+        self.arg_sizes.len() == self.args.len()
+    }
+
     pub fn get_arg_size(&self, arg_index: usize) -> usize {
         self.arg_sizes[arg_index]
     }
-}
\ No newline at end of file
+}
@@ -1 +1,2 @@
+#[derive(Clone)]
 pub struct Interface;
\ No newline at end of file
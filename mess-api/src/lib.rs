@@ -12,7 +12,7 @@ pub mod value;
 
 pub mod var_type;
 
-#[cfg(feature = "exec-vm")]
+#[cfg(any(feature = "exec-vm", feature = "exec-jit"))]
 pub mod adapter;
 
 pub mod prelude {
@@ -22,6 +22,6 @@ pub mod prelude {
     pub use super::module::Module;
     pub use super::value::{ Value };
     pub use super::var_type::Type;
-    #[cfg(feature = "exec-vm")]
-    pub use super::adapter::{ Adapter, AdapterImpl };
+    #[cfg(any(feature = "exec-vm", feature = "exec-jit"))]
+    pub use super::adapter::{ Adapter, AdapterImpl, ForeignError, ForeignObject };
 }
\ No newline at end of file
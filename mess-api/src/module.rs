@@ -2,6 +2,7 @@ use std::collections::BTreeMap;
 
 use crate::{function::Function, container::Container, interface::Interface};
 
+#[derive(Clone)]
 pub struct Module {
     pub name: String,
     pub functions: BTreeMap<String, Function>,
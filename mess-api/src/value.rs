@@ -2,25 +2,41 @@ use std::any::Any;
 
 use serde::Deserialize;
 
+use crate::var_type::Type;
+
 pub trait Value {
     fn is_foreign() -> bool { false }
     fn size() -> usize;
+    /// The script type this value is passed as
+    fn var_type() -> Type;
 }
 
-impl Value for i32 {
-    fn size() -> usize {
-        4
-    }
-}
+macro_rules! impl_value {
+    ($($rust_type:ty => $var_type:ident),* $(,)?) => {
+        $(
+            impl Value for $rust_type {
+                fn size() -> usize {
+                    std::mem::size_of::<$rust_type>()
+                }
 
-impl Value for bool {
-    fn size() -> usize {
-        1
-    }
+                fn var_type() -> Type {
+                    Type::$var_type
+                }
+            }
+        )*
+    };
 }
 
-impl Value for f32 {
-    fn size() -> usize {
-        4
-    }
-}
\ No newline at end of file
+impl_value! {
+    i8 => I8,
+    i16 => I16,
+    i32 => I32,
+    i64 => Int,
+    u8 => U8,
+    u16 => U16,
+    u32 => U32,
+    u64 => U64,
+    f32 => Float,
+    f64 => F64,
+    bool => Bool,
+}
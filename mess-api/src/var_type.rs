@@ -1,8 +1,26 @@
 #[derive(Debug, Clone, PartialEq)]
 pub enum Type {
     Void,
+    /// `i64`, also written as `int`
     Int,
+    /// `f32`, also written as `float`
     Float,
+    /// A signed 8 bit integer
+    I8,
+    /// A signed 16 bit integer
+    I16,
+    /// A signed 32 bit integer
+    I32,
+    /// An unsigned 8 bit integer
+    U8,
+    /// An unsigned 16 bit integer
+    U16,
+    /// An unsigned 32 bit integer
+    U32,
+    /// An unsigned 64 bit integer
+    U64,
+    /// A 64 bit float
+    F64,
     Bool,
     Str,
     Named(String),
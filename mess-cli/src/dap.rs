@@ -0,0 +1,354 @@
+//! A Debug Adapter Protocol server driving a `Core`, for debugging scripts from editors
+
+use std::{
+    collections::HashMap,
+    io::{
+        BufRead,
+        Error as IoError,
+        ErrorKind,
+        Result as IoResult,
+        Write,
+    },
+};
+
+use mess::{
+    engine::Engine,
+    vm::{
+        exec::{
+            core::CoreResult,
+            debug::{
+                DebugValue,
+                StopReason,
+            },
+        },
+        Core,
+    },
+};
+use serde_json::{
+    json,
+    Value,
+};
+
+/// The id of the only thread a core runs
+const THREAD_ID: u64 = 1;
+/// The variables reference of the register view
+const REGISTERS_REF: usize = 1;
+/// The variables reference of the locals of frame 0, following frames count up
+const LOCALS_REF: usize = 2;
+/// The variables reference of the fields of the first container shown since
+/// the last stop, following containers count up
+const FIELDS_REF: usize = 1 << 16;
+
+/// A DAP server, reading requests from the input and writing responses
+/// and events to the output
+pub struct DapServer<R: BufRead, W: Write> {
+    input: R,
+    output: W,
+    seq: u64,
+    core: Option<Core>,
+    stop_on_entry: bool,
+    source_breakpoints: HashMap<String, Vec<usize>>,
+    /// The fields of the containers shown since the last stop
+    expanded_fields: Vec<Vec<(String, DebugValue)>>,
+}
+
+impl<R: BufRead, W: Write> DapServer<R, W> {
+    /// Creates a new server on the given transport
+    pub fn new(input: R, output: W) -> Self {
+        Self {
+            input,
+            output,
+            seq: 1,
+            core: None,
+            stop_on_entry: false,
+            source_breakpoints: HashMap::new(),
+            expanded_fields: Vec::new(),
+        }
+    }
+
+    /// Handles requests until the client disconnects or closes the input
+    pub fn run(&mut self) -> IoResult<()> {
+        while let Some(request) = self.read_message()? {
+            let command = request["command"].as_str().unwrap_or_default().to_string();
+            let arguments = request["arguments"].clone();
+            let request_seq = request["seq"].as_u64().unwrap_or_default();
+            match self.handle(&command, &arguments) {
+                Ok(body) => self.respond(request_seq, &command, true, None, body)?,
+                Err(message) => self.respond(request_seq, &command, false, Some(message), Value::Null)?,
+            };
+            match command.as_str() {
+                // Breakpoints can only be resolved once the program is loaded
+                "launch" if self.core.is_some() => self.event("initialized", Value::Null)?,
+                "configurationDone" => {
+                    if self.stop_on_entry {
+                        self.event("stopped", json!({ "reason": "entry", "threadId": THREAD_ID }))?;
+                    } else {
+                        self.resume(Core::continue_execution)?;
+                    }
+                }
+                "continue" => self.resume(Core::continue_execution)?,
+                "next" => self.resume(Core::step_over)?,
+                "stepIn" => self.resume(Core::step)?,
+                "stepOut" => self.resume(Core::step_out)?,
+                "disconnect" => break,
+                _ => {}
+            };
+        }
+        Ok(())
+    }
+
+    /// Handles a request, returning the response body or an error message
+    fn handle(&mut self, command: &str, arguments: &Value) -> Result<Value, String> {
+        match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+            })),
+            "launch" => {
+                let program = arguments["program"]
+                    .as_str()
+                    .ok_or("Missing program to launch")?;
+                let entry = arguments["entry"].as_str().unwrap_or("main");
+                self.stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+                let output = Engine::compile_vm_file(program).map_err(|err| format!("{:?}", err))?;
+                let uid = output
+                    .get_function_uid(entry)
+                    .ok_or_else(|| format!("Unknown entry function {}", entry))?;
+                let offset = *output
+                    .functions
+                    .get(&uid)
+                    .ok_or("Entry function has no code")?;
+                let mut core = Core::new(1024);
+                core.load_program(output);
+                core.enter_at(offset);
+                self.core = Some(core);
+                Ok(Value::Null)
+            }
+            "setBreakpoints" => {
+                let path = arguments["source"]["path"]
+                    .as_str()
+                    .ok_or("Missing source path")?
+                    .to_string();
+                let core = self.core.as_mut().ok_or("No program launched")?;
+                for offset in self.source_breakpoints.remove(&path).unwrap_or_default() {
+                    core.remove_breakpoint(offset);
+                }
+                let mut offsets = Vec::new();
+                let mut breakpoints = Vec::new();
+                let lines = arguments["breakpoints"].as_array().cloned().unwrap_or_default();
+                for breakpoint in lines.iter() {
+                    let line = breakpoint["line"].as_u64().unwrap_or_default() as usize;
+                    let verified = match core.add_line_breakpoint(&path, line) {
+                        Ok(mut line_offsets) => {
+                            offsets.append(&mut line_offsets);
+                            true
+                        }
+                        Err(_) => false,
+                    };
+                    breakpoints.push(json!({ "verified": verified, "line": line }));
+                }
+                self.source_breakpoints.insert(path, offsets);
+                Ok(json!({ "breakpoints": breakpoints }))
+            }
+            "configurationDone" | "next" | "stepIn" | "stepOut" | "disconnect" => Ok(Value::Null),
+            "continue" => Ok(json!({ "allThreadsContinued": true })),
+            "threads" => Ok(json!({
+                "threads": [{ "id": THREAD_ID, "name": "main" }],
+            })),
+            "stackTrace" => {
+                let core = self.core.as_ref().ok_or("No program launched")?;
+                let frames: Vec<Value> = core
+                    .backtrace()
+                    .frames
+                    .iter()
+                    .enumerate()
+                    .map(|(index, frame)| {
+                        let mut frame_json = json!({
+                            "id": index,
+                            "name": frame.fn_name.clone().unwrap_or_else(|| String::from("<unknown>")),
+                            "line": 0,
+                            "column": 0,
+                        });
+                        if let Some(location) = frame.location.as_ref() {
+                            frame_json["line"] = json!(location.line);
+                            frame_json["column"] = json!(1);
+                            frame_json["source"] = json!({ "path": location.file });
+                        }
+                        frame_json
+                    })
+                    .collect();
+                Ok(json!({ "stackFrames": frames, "totalFrames": frames.len() }))
+            }
+            "scopes" => {
+                let frame_id = arguments["frameId"].as_u64().unwrap_or_default() as usize;
+                let mut scopes = vec![json!({
+                    "name": "Locals",
+                    "variablesReference": LOCALS_REF + frame_id,
+                    "expensive": false,
+                })];
+                if frame_id == 0 {
+                    scopes.push(json!({
+                        "name": "Registers",
+                        "variablesReference": REGISTERS_REF,
+                        "expensive": false,
+                    }));
+                }
+                Ok(json!({ "scopes": scopes }))
+            }
+            "variables" => {
+                let core = self.core.as_ref().ok_or("No program launched")?;
+                let reference =
+                    arguments["variablesReference"].as_u64().unwrap_or_default() as usize;
+                let variables: Vec<Value> = if reference >= FIELDS_REF {
+                    let fields = self
+                        .expanded_fields
+                        .get(reference - FIELDS_REF)
+                        .cloned()
+                        .ok_or("Unknown variables reference")?;
+                    fields
+                        .into_iter()
+                        .map(|(name, value)| self.variable(name, None, value))
+                        .collect()
+                } else if reference == REGISTERS_REF {
+                    core.get_registers()
+                        .iter()
+                        .enumerate()
+                        .map(|(index, reg)| {
+                            json!({
+                                "name": format!("R{}", index),
+                                "value": format!("{}", reg.get::<i64>()),
+                                "variablesReference": 0,
+                            })
+                        })
+                        .collect()
+                } else {
+                    core.get_locals(reference.saturating_sub(LOCALS_REF))
+                        .map_err(|err| err.to_string())?
+                        .into_iter()
+                        .map(|(local, value)| {
+                            let type_name = format!("{:?}", local.var_type);
+                            self.variable(local.name, Some(type_name), value)
+                        })
+                        .collect()
+                };
+                Ok(json!({ "variables": variables }))
+            }
+            _ => Err(format!("Unsupported request {}", command)),
+        }
+    }
+
+    /// Describes a local or field, giving containers a reference to expand
+    /// their fields with
+    fn variable(&mut self, name: String, type_name: Option<String>, value: DebugValue) -> Value {
+        let mut variable = json!({
+            "name": name,
+            "value": value.to_string(),
+            "variablesReference": 0,
+        });
+        if let Some(type_name) = type_name {
+            variable["type"] = json!(type_name);
+        }
+        if let DebugValue::Fields(fields) = value {
+            variable["variablesReference"] = json!(FIELDS_REF + self.expanded_fields.len());
+            self.expanded_fields.push(fields);
+        }
+        variable
+    }
+
+    /// Runs the core with the given stepping function and reports where it stopped
+    fn resume(&mut self, step_fn: fn(&mut Core) -> CoreResult<StopReason>) -> IoResult<()> {
+        let core = match self.core.as_mut() {
+            Some(core) => core,
+            None => return Ok(()),
+        };
+        // Values may change once the core runs again
+        self.expanded_fields.clear();
+        match step_fn(core) {
+            Ok(StopReason::Breakpoint(_)) => {
+                self.event("stopped", json!({ "reason": "breakpoint", "threadId": THREAD_ID }))
+            }
+            Ok(StopReason::Step) => {
+                self.event("stopped", json!({ "reason": "step", "threadId": THREAD_ID }))
+            }
+            Ok(StopReason::Finished) => {
+                self.event("exited", json!({ "exitCode": 0 }))?;
+                self.event("terminated", Value::Null)
+            }
+            Err(err) => {
+                let message = format!("{}\n", err);
+                self.event("output", json!({ "category": "stderr", "output": message }))?;
+                self.event("exited", json!({ "exitCode": 1 }))?;
+                self.event("terminated", Value::Null)
+            }
+        }
+    }
+
+    fn respond(
+        &mut self,
+        request_seq: u64,
+        command: &str,
+        success: bool,
+        message: Option<String>,
+        body: Value,
+    ) -> IoResult<()> {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request_seq,
+            "command": command,
+            "success": success,
+        });
+        if let Some(message) = message {
+            response["message"] = json!(message);
+        }
+        if !body.is_null() {
+            response["body"] = body;
+        }
+        self.write_message(response)
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> IoResult<()> {
+        let mut message = json!({ "type": "event", "event": event });
+        if !body.is_null() {
+            message["body"] = body;
+        }
+        self.write_message(message)
+    }
+
+    fn write_message(&mut self, mut message: Value) -> IoResult<()> {
+        message["seq"] = json!(self.seq);
+        self.seq += 1;
+        let content = message.to_string();
+        write!(self.output, "Content-Length: {}\r\n\r\n{}", content.len(), content)?;
+        self.output.flush()
+    }
+
+    /// Reads the next message, or `None` at the end of the input
+    fn read_message(&mut self) -> IoResult<Option<Value>> {
+        read_message(&mut self.input)
+    }
+}
+
+/// Reads a `Content-Length` framed JSON message, or `None` at the end of the input
+pub fn read_message<R: BufRead>(input: &mut R) -> IoResult<Option<Value>> {
+    let mut content_length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            if content_length.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some(length) = header.strip_prefix("Content-Length:") {
+            content_length = length.trim().parse::<usize>().ok();
+        }
+    }
+    let mut content = vec![0; content_length.unwrap_or_default()];
+    input.read_exact(&mut content)?;
+    serde_json::from_slice(&content)
+        .map(Some)
+        .map_err(|err| IoError::new(ErrorKind::InvalidData, err))
+}
@@ -0,0 +1,191 @@
+use std::{
+    error::Error as StdError,
+    io::{stdin, stdout, BufRead, Write},
+};
+
+use mess::{
+    engine::Engine,
+    vm::{
+        exec::{core::CoreResult, debug::StopReason},
+        Core,
+    },
+};
+
+use crate::{dap::DapServer, DebugArgs};
+
+const HELP: &str = "Commands:
+  break [file:]<line> | *<offset> Set a breakpoint
+  delete <offset>                 Remove a breakpoint
+  continue                        Run until the next breakpoint
+  step                            Execute one instruction
+  next                            Execute one instruction, stepping over calls
+  out                             Run until the current function returns
+  regs                            Show the registers
+  locals [frame]                  Show the named locals of a frame
+  backtrace                       Show the call stack
+  quit                            Stop debugging";
+
+/// Loads the file into a core and runs the interactive debugger on stdin
+pub fn debug(debug_args: DebugArgs) -> Result<(), Box<dyn StdError>> {
+    let file_name = debug_args.script_file.display().to_string();
+    let output = Engine::compile_vm_file(&debug_args.script_file)?;
+    let uid = output
+        .get_function_uid(&debug_args.entry)
+        .ok_or_else(|| format!("Unknown entry function {}", debug_args.entry))?;
+    let offset = *output.functions.get(&uid).ok_or("Entry function has no code")?;
+
+    let mut core = Core::new(1024);
+    core.load_program(output);
+    core.enter_at(offset);
+
+    let stdin = stdin();
+    let mut stdout = stdout();
+    run_repl(&mut core, &file_name, stdin.lock(), &mut stdout)
+}
+
+/// Runs a DAP server on stdio until the client disconnects
+pub fn dap() -> Result<(), Box<dyn StdError>> {
+    let stdin = stdin();
+    let stdout = stdout();
+    DapServer::new(stdin.lock(), stdout.lock()).run()?;
+    Ok(())
+}
+
+/// Reads debugger commands from the input until the program finishes or fails
+pub fn run_repl<R: BufRead, W: Write>(
+    core: &mut Core,
+    file_name: &str,
+    input: R,
+    out: &mut W,
+) -> Result<(), Box<dyn StdError>> {
+    writeln!(out, "Stopped at {}", core.backtrace().frames[0])?;
+    write!(out, "(mess) ")?;
+    out.flush()?;
+    for line in input.lines() {
+        let line = line?;
+        let mut words = line.split_whitespace();
+        let command = match words.next() {
+            Some(command) => command,
+            None => {
+                write!(out, "(mess) ")?;
+                out.flush()?;
+                continue;
+            }
+        };
+        let arg = words.next();
+        let result = match command {
+            "c" | "continue" => Some(core.continue_execution()),
+            "s" | "step" => Some(core.step()),
+            "n" | "next" => Some(core.step_over()),
+            "o" | "out" | "finish" => Some(core.step_out()),
+            "b" | "break" => {
+                set_breakpoint(core, file_name, arg, out)?;
+                None
+            }
+            "d" | "delete" => {
+                match arg.and_then(|arg| arg.parse().ok()) {
+                    Some(offset) if core.remove_breakpoint(offset) => {
+                        writeln!(out, "Removed breakpoint at offset {}", offset)?
+                    }
+                    _ => writeln!(out, "No such breakpoint")?,
+                };
+                None
+            }
+            "r" | "regs" => {
+                for (index, reg) in core.get_registers().iter().enumerate() {
+                    writeln!(out, "R{:<3} {:#018x} ({})", index, reg.get::<u64>(), reg.get::<i64>())?;
+                }
+                writeln!(out, "SP   {:#018x}", core.get_sp())?;
+                writeln!(out, "IP   {}", core.get_ip())?;
+                None
+            }
+            "l" | "locals" => {
+                let frame_index = arg.and_then(|arg| arg.parse().ok()).unwrap_or(0);
+                match core.get_locals(frame_index) {
+                    Ok(locals) => {
+                        for (local, value) in locals {
+                            writeln!(out, "{}: {:?} = {}", local.name, local.var_type, value)?;
+                        }
+                    }
+                    Err(err) => writeln!(out, "error: {}", err)?,
+                };
+                None
+            }
+            "bt" | "backtrace" => {
+                write!(out, "{}", core.backtrace())?;
+                None
+            }
+            "q" | "quit" => return Ok(()),
+            _ => {
+                writeln!(out, "{}", HELP)?;
+                None
+            }
+        };
+        if let Some(result) = result {
+            if report_stop(core, result, out)? {
+                return Ok(());
+            }
+        }
+        write!(out, "(mess) ")?;
+        out.flush()?;
+    }
+    Ok(())
+}
+
+fn set_breakpoint<W: Write>(
+    core: &mut Core,
+    file_name: &str,
+    arg: Option<&str>,
+    out: &mut W,
+) -> Result<(), Box<dyn StdError>> {
+    let arg = match arg {
+        Some(arg) => arg,
+        None => {
+            writeln!(out, "Expected an offset or a line")?;
+            return Ok(());
+        }
+    };
+    if let Some(offset) = arg.strip_prefix('*') {
+        let offset = offset.parse()?;
+        core.add_breakpoint(offset);
+        writeln!(out, "Breakpoint at offset {}", offset)?;
+        return Ok(());
+    }
+    let (file, line) = match arg.rsplit_once(':') {
+        Some((file, line)) => (file, line),
+        None => (file_name, arg),
+    };
+    match core.add_line_breakpoint(file, line.parse()?) {
+        Ok(offsets) => writeln!(out, "Breakpoint at {}:{}, offsets {:?}", file, line, offsets)?,
+        Err(err) => writeln!(out, "error: {}", err)?,
+    };
+    Ok(())
+}
+
+/// Prints why the core stopped, returning whether debugging is over
+fn report_stop<W: Write>(
+    core: &Core,
+    result: CoreResult<StopReason>,
+    out: &mut W,
+) -> Result<bool, Box<dyn StdError>> {
+    match result {
+        Ok(StopReason::Finished) => {
+            writeln!(out, "Program finished")?;
+            Ok(true)
+        }
+        Ok(StopReason::Breakpoint(offset)) => {
+            writeln!(out, "Breakpoint at offset {}", offset)?;
+            writeln!(out, "Stopped at {}", core.backtrace().frames[0])?;
+            Ok(false)
+        }
+        Ok(StopReason::Step) => {
+            writeln!(out, "Stopped at {}", core.backtrace().frames[0])?;
+            Ok(false)
+        }
+        Err(err) => {
+            // The error carries the backtrace of where the script failed
+            writeln!(out, "error: {}", err)?;
+            Ok(true)
+        }
+    }
+}
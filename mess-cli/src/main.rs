@@ -1,11 +1,37 @@
-use std::{path::PathBuf, process::exit, collections::HashMap, error::Error as StdError, hash::Hash};
+use std::{path::{Path, PathBuf}, process::exit, collections::HashMap, error::Error as StdError, hash::Hash, fs, thread, time::Duration};
 
-use clap::{Parser, ArgEnum};
-use mess::{engine::Engine, error::Error};
+use clap::{Parser, Subcommand, Args, ArgEnum, CommandFactory, ErrorKind};
+use mess::{api::prelude::{Adapter, Function, Module, Type}, engine::Engine, error::Error, vm::exec::tier::TierPolicy};
+
+mod debug;
+
+mod dap;
+
+#[cfg(test)]
+mod tests;
+
+/// How often `run --watch` checks the script file for changes
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
 
 #[derive(Parser)]
 #[clap(name = "mess")]
 #[clap(author, version, about)]
+struct Cli {
+    #[clap(subcommand)]
+    command: Command
+}
+
+#[derive(Subcommand)]
+enum Command {
+    #[clap(about = "Run a script file")]
+    Run(RunArgs),
+    #[clap(about = "Debug a script or VM assembly file interactively")]
+    Debug(DebugArgs),
+    #[clap(about = "Serve the Debug Adapter Protocol over stdio, for editors")]
+    Dap
+}
+
+#[derive(Args)]
 struct RunArgs {
     #[clap(help = "Execution/compilation target", short, long, arg_enum, default_value_t=Target::Vm)]
     target: Target,
@@ -13,10 +39,24 @@ struct RunArgs {
     options: Option<HashMap<String, String>>,
     #[clap(help = "Path to the output file, triggers AOT-only if supplied", short = 'O', long)]
     output: Option<PathBuf>,
+    #[clap(help = "Print a profile of the script execution", long)]
+    profile: bool,
+    #[clap(help = "Write the profile as folded stacks for flamegraph tools to this file", long)]
+    profile_folded: Option<PathBuf>,
+    #[clap(help = "Keep running, reloading the script and running it again whenever it changes", short, long)]
+    watch: bool,
     #[clap(help = "Path to the script file to execute", index = 1)]
     script_file: PathBuf
 }
 
+#[derive(Args)]
+struct DebugArgs {
+    #[clap(help = "Name of the function to start debugging at", short, long, default_value = "main")]
+    entry: String,
+    #[clap(help = "Path to the script or VM assembly (.asm) file to debug", index = 1)]
+    script_file: PathBuf
+}
+
 #[derive(Clone, ArgEnum)]
 enum Target {
     #[clap(help = "Run with the bytecode interpeter")]
@@ -24,7 +64,9 @@ enum Target {
     #[clap(help = "Run with the CHIP-8 backend")]
     Chip8,
     #[clap(help = "Run with the AMD64 JIT compiler")]
-    Jit
+    Jit,
+    #[clap(help = "Run with the bytecode interpreter, JIT compiling hot functions")]
+    Tiered
 }
 
 fn parse_options(s: &str) -> HashMap<String, String> {
@@ -57,21 +99,103 @@ fn parse_options(s: &str) -> HashMap<String, String> {
     ret
 }
 
-fn main() -> Result<(), Error> {
-    let run_args = RunArgs::parse();
+fn main() -> Result<(), Box<dyn StdError>> {
+    match Cli::parse().command {
+        Command::Run(run_args) => {
+            if let Err(err) = check_run_args(&run_args) {
+                err.exit();
+            }
+            run(run_args)?
+        },
+        Command::Debug(debug_args) => debug::debug(debug_args)?,
+        Command::Dap => debug::dap()?
+    };
+    Ok(())
+}
+
+/// Rejects combinations of options the chosen target doesn't support
+fn check_run_args(run_args: &RunArgs) -> Result<(), clap::Error> {
+    let profiling = run_args.profile || run_args.profile_folded.is_some();
+    if matches!(run_args.target, Target::Jit) && profiling {
+        return Err(Cli::command().error(
+            ErrorKind::ArgumentConflict,
+            "--profile and --profile-folded can't be used with --target jit, only the vm and tiered targets are profiled",
+        ));
+    }
+    Ok(())
+}
+
+fn run(run_args: RunArgs) -> Result<(), Error> {
     println!("Options: {:#?}", run_args.options);
     let mut engine = match run_args.target {
-        Target::Jit => {
-            println!("JIT not implemented yet!");
-            return Ok(());
+        Target::Jit => Engine::new_jit()?,
+        Target::Tiered => {
+            Engine::new_tiered(1024, TierPolicy::default())
+                .with_profiling(run_args.profile || run_args.profile_folded.is_some())
         },
         Target::Vm => {
-            Engine::new_vm(1024)
+            Engine::new_vm(1024).with_profiling(run_args.profile || run_args.profile_folded.is_some())
         },
         Target::Chip8 => {
             println!("CHIP-8 not implemented yet!");
             return Ok(())
         }
     };
-    engine.run_file(&run_args.script_file)
+    engine.register_module(io_module())?;
+    let result = engine.run_file(&run_args.script_file);
+    if let Some(profiler) = engine.get_vm_profiler() {
+        if run_args.profile {
+            eprint!("{}", profiler.report());
+        }
+        if let Some(folded_path) = run_args.profile_folded.as_ref() {
+            fs::write(folded_path, profiler.folded())?;
+        }
+    }
+    if run_args.watch {
+        if let Err(err) = result {
+            eprintln!("{}", err);
+        }
+        return watch(&mut engine, &run_args.script_file);
+    }
+    result
+}
+
+/// The host functions scripts run by the CLI can call as `io::function`
+fn io_module() -> Module {
+    let mut module = Module::new(String::from("io"));
+    module.add_function(Function::new(
+        "print",
+        vec![Type::Ref(Box::new(Type::Str))],
+        Type::Void,
+        print,
+    ));
+    module
+}
+
+fn print(adapter: &mut Adapter) {
+    println!("{}", adapter.get_str_arg(0).unwrap_or_default());
+}
+
+/// Polls the modification time of the script file, hot reloading the script
+/// and running its `main` function again after every change. Errors are
+/// printed and the previous version keeps running until the next change.
+fn watch(engine: &mut Engine, script_file: &Path) -> Result<(), Error> {
+    let mut modified = fs::metadata(script_file)?.modified()?;
+    loop {
+        thread::sleep(WATCH_INTERVAL);
+        // The file may be missing for a moment while an editor saves it
+        let current = match fs::metadata(script_file).and_then(|metadata| metadata.modified()) {
+            Ok(current) => current,
+            Err(_) => continue,
+        };
+        if current == modified {
+            continue;
+        }
+        modified = current;
+        println!("Reloading {}", script_file.display());
+        let result = engine.reload_file(script_file).and_then(|_| engine.run_fn("main"));
+        if let Err(err) = result {
+            eprintln!("{}", err);
+        }
+    }
 }
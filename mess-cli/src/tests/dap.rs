@@ -0,0 +1,143 @@
+use std::{
+    env,
+    fs,
+    io::Cursor,
+    process,
+};
+
+use serde_json::{json, Value};
+
+use super::Result;
+use crate::dap::{read_message, DapServer};
+
+const PROGRAM: &str = ".container Point, x: int, y: bool
+main:
+    .local x, int, 0
+    .local p, Point, 8
+    ADDU_I SP, 17, SP
+    LDI 5, R1
+    MOVI_RA R1, [SP - 17]
+    MOVI_RA R1, [SP - 9]
+    LDB true, R2
+    MOVB_RA R2, [SP - 1]
+    CALL double
+    RET
+double:
+    ADDI R1, R1, R1
+    RET
+";
+
+/// Frames the requests, runs a server over them and returns everything it sent
+fn run_session(requests: Vec<Value>) -> std::result::Result<Vec<Value>, Box<dyn std::error::Error>> {
+    let mut input = Vec::new();
+    for (seq, mut request) in requests.into_iter().enumerate() {
+        request["seq"] = json!(seq + 1);
+        request["type"] = json!("request");
+        let content = request.to_string();
+        input.extend(format!("Content-Length: {}\r\n\r\n{}", content.len(), content).bytes());
+    }
+    let mut output = Vec::new();
+    DapServer::new(Cursor::new(input), &mut output).run()?;
+
+    let mut messages = Vec::new();
+    let mut reader = Cursor::new(output);
+    while let Some(message) = read_message(&mut reader)? {
+        messages.push(message);
+    }
+    Ok(messages)
+}
+
+fn find<'a>(messages: &'a [Value], kind: &str, name: &str) -> Vec<&'a Value> {
+    let key = if kind == "event" { "event" } else { "command" };
+    messages
+        .iter()
+        .filter(|message| message["type"] == kind && message[key] == name)
+        .collect()
+}
+
+#[test]
+fn test_dap_session() -> Result {
+    let path = env::temp_dir().join(format!("mess-dap-{}.asm", process::id()));
+    fs::write(&path, PROGRAM)?;
+    let path_str = path.to_string_lossy().to_string();
+
+    let messages = run_session(vec![
+        json!({ "command": "initialize", "arguments": { "adapterID": "mess" } }),
+        json!({ "command": "launch", "arguments": { "program": path_str } }),
+        json!({ "command": "setBreakpoints", "arguments": {
+            "source": { "path": path_str },
+            "breakpoints": [{ "line": 11 }, { "line": 3 }],
+        } }),
+        json!({ "command": "configurationDone" }),
+        json!({ "command": "threads" }),
+        json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+        json!({ "command": "scopes", "arguments": { "frameId": 0 } }),
+        json!({ "command": "variables", "arguments": { "variablesReference": 2 } }),
+        json!({ "command": "variables", "arguments": { "variablesReference": 1 << 16 } }),
+        json!({ "command": "stepIn", "arguments": { "threadId": 1 } }),
+        json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+        json!({ "command": "variables", "arguments": { "variablesReference": 1 } }),
+        json!({ "command": "stepOut", "arguments": { "threadId": 1 } }),
+        json!({ "command": "continue", "arguments": { "threadId": 1 } }),
+        json!({ "command": "evaluate", "arguments": { "expression": "x" } }),
+        json!({ "command": "disconnect" }),
+    ])?;
+    fs::remove_file(&path)?;
+
+    assert_eq!(find(&messages, "event", "initialized").len(), 1);
+    let breakpoints = &find(&messages, "response", "setBreakpoints")[0]["body"]["breakpoints"];
+    assert_eq!(breakpoints[0]["verified"], true);
+    assert_eq!(breakpoints[1]["verified"], false);
+
+    let stopped: Vec<_> = find(&messages, "event", "stopped")
+        .iter()
+        .map(|event| event["body"]["reason"].as_str().unwrap_or_default())
+        .collect();
+    assert_eq!(stopped, vec!["breakpoint", "step", "step"]);
+
+    let traces = find(&messages, "response", "stackTrace");
+    let frames = &traces[0]["body"]["stackFrames"];
+    assert_eq!(frames[0]["name"], "main");
+    assert_eq!(frames[0]["line"], 11);
+    assert_eq!(frames[0]["source"]["path"], path_str.as_str());
+    let frames = &traces[1]["body"]["stackFrames"];
+    assert_eq!(frames.as_array().map(Vec::len), Some(2));
+    assert_eq!(frames[0]["name"], "double");
+    assert_eq!(frames[0]["line"], 14);
+
+    let scopes = &find(&messages, "response", "scopes")[0]["body"]["scopes"];
+    assert_eq!(scopes[0]["name"], "Locals");
+    let variables = find(&messages, "response", "variables");
+    assert_eq!(variables[0]["body"]["variables"][0]["name"], "x");
+    assert_eq!(variables[0]["body"]["variables"][0]["value"], "5");
+    // Containers expand into their fields
+    let point = &variables[0]["body"]["variables"][1];
+    assert_eq!(point["value"], "{ x: 5, y: true }");
+    assert_eq!(point["variablesReference"], 1 << 16);
+    let fields = &variables[1]["body"]["variables"];
+    assert_eq!(fields[0]["name"], "x");
+    assert_eq!(fields[0]["value"], "5");
+    assert_eq!(fields[1]["name"], "y");
+    assert_eq!(fields[1]["value"], "true");
+    assert_eq!(variables[2]["body"]["variables"][1]["value"], "5");
+
+    assert_eq!(find(&messages, "event", "terminated").len(), 1);
+    assert_eq!(find(&messages, "event", "exited")[0]["body"]["exitCode"], 0);
+    assert_eq!(find(&messages, "response", "evaluate")[0]["success"], false);
+    assert_eq!(find(&messages, "response", "disconnect")[0]["success"], true);
+    Ok(())
+}
+
+#[test]
+fn test_dap_launch_error() -> Result {
+    let messages = run_session(vec![
+        json!({ "command": "initialize" }),
+        json!({ "command": "launch", "arguments": { "program": "/nonexistent/missing.asm" } }),
+        json!({ "command": "disconnect" }),
+    ])?;
+    let launch = find(&messages, "response", "launch")[0];
+    assert_eq!(launch["success"], false);
+    assert!(launch["message"].is_string());
+    assert!(find(&messages, "event", "initialized").is_empty());
+    Ok(())
+}
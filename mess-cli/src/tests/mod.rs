@@ -0,0 +1,7 @@
+use std::{error::Error, result::Result as StdResult};
+
+mod dap;
+
+mod run;
+
+type Result = StdResult<(), Box<dyn Error>>;
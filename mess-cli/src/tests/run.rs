@@ -0,0 +1,32 @@
+use std::result::Result as StdResult;
+
+use clap::{ErrorKind, Parser};
+
+use super::Result;
+use crate::{check_run_args, Cli, Command};
+
+/// Parses the arguments of `mess run` and checks them like the CLI does
+fn check(args: &[&str]) -> StdResult<(), clap::Error> {
+    let cli = Cli::try_parse_from(["mess", "run"].iter().chain(args))?;
+    match cli.command {
+        Command::Run(run_args) => check_run_args(&run_args),
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn test_run_profile_targets() -> Result {
+    check(&["--profile", "script.mess"])?;
+    check(&["-t", "tiered", "--profile-folded", "out.folded", "script.mess"])?;
+    check(&["-t", "jit", "script.mess"])?;
+    // The JIT isn't profiled, so asking for a profile is an error
+    let rejected: [&[&str]; 2] = [
+        &["-t", "jit", "--profile", "script.mess"],
+        &["-t", "jit", "--profile-folded", "out.folded", "script.mess"],
+    ];
+    for args in rejected {
+        let err = check(args).err().ok_or("Expected the profile options to be rejected")?;
+        assert_eq!(err.kind(), ErrorKind::ArgumentConflict);
+    }
+    Ok(())
+}
@@ -0,0 +1,130 @@
+//! Checks whether a changed script can replace a loaded one at runtime
+
+use std::{
+    collections::BTreeMap,
+    fmt::{
+        Display,
+        Formatter,
+        Result as FmtResult,
+    },
+};
+
+use crate::parser::ast::{
+    Declaration,
+    Type,
+};
+
+/// A change between two versions of a script that code or data of the
+/// loaded version depends on, so it can't be reloaded in place
+#[derive(Clone, PartialEq, Debug)]
+pub enum Incompatibility {
+    /// The arguments or return type of the function at the path changed
+    FunctionSignature(String),
+    /// The member variables of the container at the path changed
+    ContainerLayout(String),
+    /// The type of the static variable at the path changed
+    StaticType(String),
+}
+
+impl Display for Incompatibility {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Incompatibility::FunctionSignature(path) => {
+                write!(f, "the signature of function {} changed", path)
+            }
+            Incompatibility::ContainerLayout(path) => {
+                write!(f, "the layout of container {} changed", path)
+            }
+            Incompatibility::StaticType(path) => {
+                write!(f, "the type of static variable {} changed", path)
+            }
+        }
+    }
+}
+
+type Signature<'d> = (&'d [(String, Type)], &'d Type);
+
+/// The items of a declaration list that must stay the same, by their path
+#[derive(Default)]
+struct Items<'d> {
+    functions: BTreeMap<String, Signature<'d>>,
+    containers: BTreeMap<String, &'d BTreeMap<String, Type>>,
+    statics: BTreeMap<String, &'d Type>,
+}
+
+impl<'d> Items<'d> {
+    fn collect(&mut self, path: &str, decl_list: &'d [Declaration]) {
+        for decl in decl_list {
+            match decl {
+                Declaration::Function {
+                    name,
+                    returns,
+                    arguments,
+                    ..
+                } => {
+                    self.functions
+                        .insert(format!("{}{}", path, name), (arguments, returns));
+                }
+                Declaration::StaticVariable { name, r#type, .. } => {
+                    self.statics.insert(format!("{}{}", path, name), r#type);
+                }
+                Declaration::Module { name, decl_list } => {
+                    self.collect(&format!("{}{}::", path, name), decl_list);
+                }
+                Declaration::Container {
+                    name,
+                    member_variables,
+                    member_functions,
+                    ..
+                } => {
+                    let cont_path = format!("{}{}", path, name);
+                    for function in member_functions {
+                        self.functions.insert(
+                            format!("{}::{}", cont_path, function.name),
+                            (&function.arguments, &function.returns),
+                        );
+                    }
+                    self.containers.insert(cont_path, member_variables);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Checks that the new declarations can replace the loaded ones without
+/// invalidating callers, live container instances or static data. Items may
+/// be added and removed, only items present in both versions are compared.
+pub fn check_compatible(
+    loaded: &[Declaration],
+    new: &[Declaration],
+) -> Result<(), Incompatibility> {
+    let mut old_items = Items::default();
+    old_items.collect("", loaded);
+    let mut new_items = Items::default();
+    new_items.collect("", new);
+
+    for (path, signature) in new_items.functions.iter() {
+        match old_items.functions.get(path) {
+            Some(old) if old != signature => {
+                return Err(Incompatibility::FunctionSignature(path.clone()))
+            }
+            _ => {}
+        }
+    }
+    for (path, members) in new_items.containers.iter() {
+        match old_items.containers.get(path) {
+            Some(old) if old != members => {
+                return Err(Incompatibility::ContainerLayout(path.clone()))
+            }
+            _ => {}
+        }
+    }
+    for (path, var_type) in new_items.statics.iter() {
+        match old_items.statics.get(path) {
+            Some(old) if old != var_type => return Err(Incompatibility::StaticType(path.clone())),
+            _ => {}
+        }
+    }
+    Ok(())
+}
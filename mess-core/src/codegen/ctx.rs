@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 use crate::parser::ast::Type;
 
@@ -6,7 +6,7 @@ pub struct StackContext {
     pub stack_extent: i32,
     pub stack_pos: i32,
     pub uid: u64,
-    variable_positions: HashMap<String, (i32, Type)>,
+    variable_positions: BTreeMap<String, (i32, Type)>,
 }
 
 impl StackContext {
@@ -15,12 +15,12 @@ impl StackContext {
             uid,
             stack_extent: 0,
             stack_pos: 0,
-            variable_positions: HashMap::new(),
+            variable_positions: BTreeMap::new(),
         }
     }
 
     pub fn extend_from(uid: u64, context: &StackContext) -> Self {
-        let mut variables = HashMap::new();
+        let mut variables = BTreeMap::new();
 
         let context_size = context.stack_extent;
         for (var_name, (var_offset, var_type)) in context.variable_positions.iter() {
@@ -52,9 +52,19 @@ impl StackContext {
             .insert(String::from(name), (stack_pos, var_type.clone()));
     }
 
+    /// Whether a variable with the name is in this context
+    pub fn has_var(&self, name: &str) -> bool {
+        self.variable_positions.contains_key(name)
+    }
+
     pub fn get_var(&self, name: &str) -> &(i32, Type) {
         self.variable_positions.get(name).unwrap()
     }
+
+    /// Returns all variables of this context with their stack positions
+    pub fn get_vars(&self) -> impl Iterator<Item = (&String, &(i32, Type))> {
+        self.variable_positions.iter()
+    }
 }
 
 pub struct FnContext {
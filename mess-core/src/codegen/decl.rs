@@ -1,4 +1,7 @@
-use std::collections::VecDeque;
+use std::collections::{
+    BTreeMap,
+    VecDeque,
+};
 
 use crate::{
     codegen::def::{
@@ -11,6 +14,8 @@ use crate::{
 pub struct Declarator {
     mod_def_stack: VecDeque<ModuleDef>,
     label_uid_ctr: u64,
+    /// Uids to give functions by canonical name, instead of the next one
+    label_uids: BTreeMap<String, u64>,
 }
 
 impl Default for Declarator {
@@ -20,6 +25,7 @@ impl Default for Declarator {
         Self {
             mod_def_stack,
             label_uid_ctr: 0,
+            label_uids: BTreeMap::new(),
         }
     }
 }
@@ -31,6 +37,20 @@ impl Declarator {
         Self {
             mod_def_stack,
             label_uid_ctr: 0,
+            label_uids: BTreeMap::new(),
+        }
+    }
+
+    /// Creates a declarator giving functions the uids they were declared
+    /// with before, by canonical name, e.g. to compile a changed script again
+    /// without invalidating the uids of its functions. Other functions get
+    /// uids above all of the given ones.
+    pub fn with_label_uids(label_uids: BTreeMap<String, u64>) -> Self {
+        let label_uid_ctr = label_uids.values().max().map_or(0, |uid| uid + 1);
+        Self {
+            label_uid_ctr,
+            label_uids,
+            ..Self::default()
         }
     }
 
@@ -54,7 +74,7 @@ impl Declarator {
 
     pub fn get_result(&mut self) -> Result<(ModuleDef, u64), ()> {
         let mod_def = self.mod_def_stack.get(0).cloned().ok_or(())?;
-        if self.mod_def_stack.len() > 0 {
+        if self.mod_def_stack.len() > 1 {
             return Err(());
         }
         Ok((mod_def, self.label_uid_ctr))
@@ -66,6 +86,8 @@ impl Declarator {
                 Declaration::Module { .. } => self.declare_mod(decl)?,
                 Declaration::Function { .. } => self.declare_fn(decl)?,
                 Declaration::Container { .. } => self.declare_cont(decl)?,
+                // Statics are laid out by the compiler
+                Declaration::StaticVariable { .. } => {}
                 _ => return Err(()),
             };
         }
@@ -93,8 +115,11 @@ impl Declarator {
 
     fn declare_fn(&mut self, fn_decl: &Declaration) -> Result<(), ()> {
         let module_path = self.build_mod_path();
-        let label_uid = self.get_next_label_uid();
-        let fn_def = FunctionDef::from_decl(label_uid, &module_path, fn_decl)?;
+        let mut fn_def = FunctionDef::from_decl(0, &module_path, fn_decl)?;
+        fn_def.label_uid = match self.label_uids.get(&fn_def.canon_name) {
+            Some(label_uid) => *label_uid,
+            None => self.get_next_label_uid(),
+        };
         let front_mod = &mut self.mod_def_stack[0];
         front_mod.add_function(fn_def);
         Ok(())
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 use crate::parser::ast::Type;
 
@@ -6,5 +6,5 @@ use crate::parser::ast::Type;
 pub struct ContDef {
     pub name: String,
     pub canon_name: String,
-    pub members: HashMap<String, (u64, Type)>,
+    pub members: BTreeMap<String, (u64, Type)>,
 }
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 use mess_api::prelude::Module;
 
@@ -10,8 +10,8 @@ use super::FunctionDef;
 pub struct ModuleDef {
     pub name: String,
     pub canon_name: String,
-    pub functions: HashMap<String, FunctionDef>,
-    pub modules: HashMap<String, ModuleDef>,
+    pub functions: BTreeMap<String, FunctionDef>,
+    pub modules: BTreeMap<String, ModuleDef>,
 }
 
 impl ModuleDef {
@@ -20,8 +20,8 @@ impl ModuleDef {
         Self {
             canon_name: format!("{}{}", module_path, name),
             name,
-            functions: HashMap::new(),
-            modules: HashMap::new(),
+            functions: BTreeMap::new(),
+            modules: BTreeMap::new(),
         }
     }
 
@@ -61,7 +61,7 @@ impl ModuleDef {
     }
 
     pub fn from_api(uid_gen: &mut UIDGenerator, mod_path: &str, api_mod: Module) -> Self {
-        let fn_defs: HashMap<String, FunctionDef> = api_mod.functions.into_iter()
+        let fn_defs: BTreeMap<String, FunctionDef> = api_mod.functions.into_iter()
             .map(|(fn_name, api_fun)| {
                 let fn_uid = uid_gen.generate();
                 let fn_def = FunctionDef::from_api(fn_uid, mod_path, api_fun);
@@ -72,7 +72,7 @@ impl ModuleDef {
             name: api_mod.name.clone(),
             canon_name: format!("{}{}", mod_path, api_mod.name),
             functions: fn_defs,
-            modules: HashMap::new()
+            modules: BTreeMap::new()
         }
     }
 }
\ No newline at end of file
@@ -1,3 +1,5 @@
+pub mod compat;
+
 pub mod ctx;
 
 pub mod data;
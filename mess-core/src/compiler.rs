@@ -12,7 +12,7 @@ pub trait Compiler {
 
     fn compile(&mut self, decl_list: &[Declaration]) -> Result<(), Self::Error>;
 
-    fn get_output(&mut self) -> Self::Output;
+    fn get_output(&mut self) -> Result<Self::Output, Self::Error>;
 
     fn register_module(&mut self, module: Module) -> Result<(), Self::Error>;
 }
\ No newline at end of file
@@ -1,12 +1,18 @@
 use std::{collections::{BTreeMap}, ops::{Range, Deref}};
 
 use mess_api::prelude::Type as ApiType;
+use serde::{Deserialize, Serialize};
 
 use super::Token;
 
+/// An AST node with the position it was parsed from
+#[derive(Debug, Clone)]
 pub struct AstItem<T> {
     pub item: T,
-    pub range: Range<usize>
+    /// The byte range of the node in the source
+    pub range: Range<usize>,
+    /// The line the node starts on, counting from 1
+    pub line: usize,
 }
 
 impl<T> Deref for AstItem<T> {
@@ -25,7 +31,7 @@ pub enum Declaration {
         name: String,
         returns: Type,
         arguments: Vec<(String, Type)>,
-        body: Option<Vec<Statement>>,
+        body: Option<Vec<AstItem<Statement>>>,
     },
     StaticVariable {
         public: bool,
@@ -60,7 +66,7 @@ pub struct ContainerFunction {
     pub name: String,
     pub returns: Type,
     pub arguments: Vec<(String, Type)>,
-    pub body: Vec<Statement>
+    pub body: Vec<AstItem<Statement>>
 }
 
 #[derive(Debug, Clone)]
@@ -68,7 +74,7 @@ pub struct InterfaceFunction {
     pub name: String,
     pub returns: Type,
     pub arguments: Vec<(String, Type)>,
-    pub body: Option<Vec<Statement>>,
+    pub body: Option<Vec<AstItem<Statement>>>,
 }
 
 #[derive(Debug, Clone)]
@@ -97,12 +103,12 @@ pub enum Statement {
     Yield(Option<Expression>),
     Break,
     Continue,
-    While(Expression, Vec<Statement>),
+    While(Expression, Vec<AstItem<Statement>>),
     Condition {
         expr: Expression,
-        cond_body: Vec<Statement>,
-        cond_chain: Vec<(Expression, Vec<Statement>)>,
-        else_body: Vec<Statement>,
+        cond_body: Vec<AstItem<Statement>>,
+        cond_chain: Vec<(Expression, Vec<AstItem<Statement>>)>,
+        else_body: Vec<AstItem<Statement>>,
     },
     ExpressionStmt(Expression),
 }
@@ -162,7 +168,7 @@ impl Operator {
     pub fn prec(&self) -> i8 {
         match self {
             Operator::Plus => 2,
-            Operator::Minus => 1,
+            Operator::Minus => 2,
             Operator::LessThan => 0,
             Operator::LessThanEquals => 0,
             Operator::GreaterThan => 0,
@@ -200,21 +206,40 @@ pub enum Expression {
     Variable(String),
     Unary(Operator, Box<Expression>),
     Binary(Box<Expression>, Operator, Box<Expression>),
+    Cast(Box<Expression>, Type),
     Condition {
         expr: Box<Expression>,
-        cond_body: Vec<Statement>,
-        cond_chain: Vec<(Expression, Vec<Statement>)>,
-        else_body: Vec<Statement>,
+        cond_body: Vec<AstItem<Statement>>,
+        cond_chain: Vec<(Expression, Vec<AstItem<Statement>>)>,
+        else_body: Vec<AstItem<Statement>>,
         yield_expr: Option<Box<Expression>>,
     },
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Type {
     Auto,
     Void,
+    /// `i64`, also written as `int`
     Int,
+    /// `f32`, also written as `float`
     Float,
+    /// A signed 8 bit integer
+    I8,
+    /// A signed 16 bit integer
+    I16,
+    /// A signed 32 bit integer
+    I32,
+    /// An unsigned 8 bit integer
+    U8,
+    /// An unsigned 16 bit integer
+    U16,
+    /// An unsigned 32 bit integer
+    U32,
+    /// An unsigned 64 bit integer
+    U64,
+    /// A 64 bit float
+    F64,
     Bool,
     Str,
     This,
@@ -224,6 +249,25 @@ pub enum Type {
     Deref(Box<Type>),
     UnsizedArray(Box<Type>),
     SizedArray(Box<Type>),
+    /// `coroutine<T>`, a handle of a spawned coroutine producing values of `T`
+    Coroutine(Box<Type>),
+}
+
+impl Type {
+    /// Whether this is a signed or unsigned integer type
+    pub fn is_integer(&self) -> bool {
+        self.is_signed() || matches!(self, Type::U8 | Type::U16 | Type::U32 | Type::U64)
+    }
+
+    /// Whether this is a signed integer type
+    pub fn is_signed(&self) -> bool {
+        matches!(self, Type::Int | Type::I8 | Type::I16 | Type::I32)
+    }
+
+    /// Whether this is a floating point type
+    pub fn is_float(&self) -> bool {
+        matches!(self, Type::Float | Type::F64)
+    }
 }
 
 impl From<ApiType> for Type {
@@ -233,9 +277,17 @@ impl From<ApiType> for Type {
             ApiType::Void => Type::Void,
             ApiType::Float => Type::Float,
             ApiType::Int => Type::Int,
+            ApiType::I8 => Type::I8,
+            ApiType::I16 => Type::I16,
+            ApiType::I32 => Type::I32,
+            ApiType::U8 => Type::U8,
+            ApiType::U16 => Type::U16,
+            ApiType::U32 => Type::U32,
+            ApiType::U64 => Type::U64,
+            ApiType::F64 => Type::F64,
             ApiType::Str => Type::Str,
             ApiType::Named(name) => Type::Named(name),
-            _ => unimplemented!("Not implemented yet")
+            ApiType::Ref(inner) => Type::Ref(Box::new((*inner).into())),
         }
     }
 }
\ No newline at end of file
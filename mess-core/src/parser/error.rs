@@ -30,6 +30,8 @@ pub enum Error {
     ExpectedType,
     ExpectedWhile,
     ExpectedVar,
+    /// A static variable declaration lacks its `static` keyword
+    ExpectedStatic,
     ExpectedSemicolon,
     MalformedExpression,
     MalformedImport,
@@ -16,6 +16,7 @@ use std::{
 };
 
 use ast::{
+    AstItem,
     Declaration,
     EnumVariant,
     Expression,
@@ -116,6 +117,11 @@ impl Parser {
             .ok_or(Error::Unknown)
     }
 
+    /// Returns the line of a byte offset into the source, counting from 1
+    fn get_line(&self, offset: usize) -> usize {
+        self.source[..offset.min(self.source.len())].matches('\n').count() + 1
+    }
+
     /// Parses the source into a root decl list
     pub fn parse(&mut self) -> Result<Vec<Declaration>> {
         self.parse_decl_list(&[])
@@ -141,6 +147,7 @@ impl Parser {
                 Token::Import => self.parse_decl_import()?,
                 Token::Intf => self.parse_decl_intf()?,
                 Token::Enum => self.parse_decl_enum()?,
+                Token::Static => self.parse_decl_static()?,
                 _ => return Err(Error::Unknown),
             };
             ret.push(decl);
@@ -398,7 +405,7 @@ impl Parser {
         }
 
         token = self.get_token()?;
-        let stmt_list: Option<Vec<Statement>> = match token {
+        let stmt_list: Option<Vec<AstItem<Statement>>> = match token {
             Token::Semicolon => {
                 self.advance();
                 None
@@ -610,14 +617,32 @@ impl Parser {
             Token::Identifier => {
                 let ident_value = self.get_value()?;
                 self.advance();
-                Type::Named(ident_value)
+                if ident_value == "coroutine" && self.get_token().ok() == Some(Token::LessThan) {
+                    self.advance();
+                    let inner_type = self.parse_type()?;
+                    if self.get_token()? != Token::GreaterThan {
+                        return Err(Error::ExpectedType);
+                    }
+                    self.advance();
+                    Type::Coroutine(Box::new(inner_type))
+                } else {
+                    Type::Named(ident_value)
+                }
             }
             Token::PrimitiveType => {
                 let token_val = self.get_value()?;
                 self.advance();
                 match token_val.as_str() {
-                    "int" => Type::Int,
-                    "float" => Type::Float,
+                    "int" | "i64" => Type::Int,
+                    "float" | "f32" => Type::Float,
+                    "i8" => Type::I8,
+                    "i16" => Type::I16,
+                    "i32" => Type::I32,
+                    "u8" => Type::U8,
+                    "u16" => Type::U16,
+                    "u32" => Type::U32,
+                    "u64" => Type::U64,
+                    "f64" => Type::F64,
                     "bool" => Type::Bool,
                     "str" => Type::Str,
                     _ => return Err(Error::ExpectedType),
@@ -633,6 +658,52 @@ impl Parser {
         Err(Error::Unimplemented("mod decl"))
     }
 
+    /// Parses a static variable declaration
+    pub fn parse_decl_static(&mut self) -> Result<Declaration> {
+        let mut token = self.get_token()?;
+
+        let mut public = false;
+        if token == Token::Pub {
+            public = true;
+            self.advance();
+            token = self.get_token()?;
+        }
+
+        if token != Token::Static {
+            return Err(Error::ExpectedStatic);
+        }
+        self.advance();
+
+        token = self.get_token()?;
+        if token != Token::Identifier {
+            return Err(Error::ExpectedIdentifier);
+        }
+        let var_name = self.get_value()?;
+        self.advance();
+
+        token = self.get_token()?;
+        if token != Token::Colon {
+            return Err(Error::ExpectedColon);
+        }
+        self.advance();
+        let var_type = self.parse_type()?;
+
+        token = self.get_token()?;
+        if token != Token::Assign {
+            return Err(Error::ExpectedAssign);
+        }
+        self.advance();
+
+        let var_expr = self.parse_expr(&[Token::Semicolon])?;
+
+        Ok(Declaration::StaticVariable {
+            public,
+            name: var_name,
+            r#type: var_type,
+            expr: var_expr,
+        })
+    }
+
     /// Parses a container declaration
     pub fn parse_decl_cont(&mut self) -> Result<Declaration> {
         let mut token = self.get_token()?;
@@ -775,7 +846,7 @@ impl Parser {
     }
 
     /// Parses a statement list, breaking on a set of given delimiters
-    pub fn parse_stmt_list(&mut self, delims: &[Token]) -> Result<Vec<Statement>> {
+    pub fn parse_stmt_list(&mut self, delims: &[Token]) -> Result<Vec<AstItem<Statement>>> {
         let mut statements = vec![];
         while self.token_pos < self.tokens.len() {
             let token = self.get_token()?;
@@ -783,8 +854,17 @@ impl Parser {
                 self.advance();
                 break;
             }
-            let stmt = self.parse_stmt()?;
-            statements.push(stmt);
+            let start = self.get_range()?.start;
+            let item = self.parse_stmt()?;
+            let end = self
+                .tokens
+                .get(self.token_pos.saturating_sub(1))
+                .map_or(start, |(_token, range)| range.end);
+            statements.push(AstItem {
+                item,
+                range: start..end,
+                line: self.get_line(start),
+            });
         }
         Ok(statements)
     }
@@ -798,11 +878,12 @@ impl Parser {
             Token::While => self.parse_stmt_while(),
             Token::Yield => {
                 self.advance();
-                let _next_token = self.get_token()?;
-                let expr_opt = if token != Token::Semicolon {
+                let next_token = self.get_token()?;
+                let expr_opt = if next_token != Token::Semicolon {
                     let expr = self.parse_expr(&[Token::Semicolon])?;
                     Some(expr)
                 } else {
+                    self.advance();
                     None
                 };
                 if !self.yield_stack.is_empty() {
@@ -815,11 +896,12 @@ impl Parser {
             }
             Token::Return => {
                 self.advance();
-                let _next_token = self.get_token()?;
-                let expr_opt = if token != Token::Semicolon {
+                let next_token = self.get_token()?;
+                let expr_opt = if next_token != Token::Semicolon {
                     let expr = self.parse_expr(&[Token::Semicolon])?;
                     Some(expr)
                 } else {
+                    self.advance();
                     None
                 };
                 Ok(Statement::Return(expr_opt))
@@ -830,6 +912,7 @@ impl Parser {
                 if next_token != Token::Semicolon {
                     Err(Error::ExpectedSemicolon)
                 } else {
+                    self.advance();
                     Ok(Statement::Continue)
                 }
             }
@@ -839,6 +922,7 @@ impl Parser {
                 if next_token != Token::Semicolon {
                     Err(Error::ExpectedSemicolon)
                 } else {
+                    self.advance();
                     Ok(Statement::Break)
                 }
             }
@@ -900,8 +984,8 @@ impl Parser {
 
         let cond_expr = self.parse_expr(&[Token::OpenBlock])?;
         let cond_body = self.parse_stmt_list(&[Token::CloseBlock])?;
-        let mut else_body: Vec<Statement> = vec![];
-        let mut cond_chain: Vec<(Expression, Vec<Statement>)> = vec![];
+        let mut else_body: Vec<AstItem<Statement>> = vec![];
+        let mut cond_chain: Vec<(Expression, Vec<AstItem<Statement>>)> = vec![];
         if self.token_pos < self.tokens.len() {
             token = self.get_token()?;
             while token == Token::Else {
@@ -938,8 +1022,8 @@ impl Parser {
         self.yield_stack.push_front(None);
         let cond_expr = self.parse_expr(&[Token::OpenBlock])?;
         let cond_body = self.parse_stmt_list(&[Token::CloseBlock])?;
-        let mut else_body: Vec<Statement> = vec![];
-        let mut cond_chain: Vec<(Expression, Vec<Statement>)> = vec![];
+        let mut else_body: Vec<AstItem<Statement>> = vec![];
+        let mut cond_chain: Vec<(Expression, Vec<AstItem<Statement>>)> = vec![];
         if self.token_pos < self.tokens.len() {
             token = self.get_token()?;
             while token == Token::Else {
@@ -986,7 +1070,13 @@ impl Parser {
     /// Parses an expression
     pub fn parse_expr(&mut self, delims: &[Token]) -> Result<Expression> {
         if let Token::On = self.get_token()? {
-            return self.parse_expr_on();
+            let expr = self.parse_expr_on()?;
+            // Like any other expression, a condition ends at a delimiter
+            if !delims.contains(&self.get_token()?) {
+                return Err(Error::MalformedExpression);
+            }
+            self.advance();
+            return Ok(expr);
         }
 
         let mut op_stack: VecDeque<Operator> = VecDeque::new();
@@ -1001,12 +1091,32 @@ impl Parser {
         while self.token_pos < self.tokens.len() {
             // Read a token
             let token = self.get_token()?;
-            if delims.contains(&token) || (token == Token::CloseParan && paran_count == 0) {
+            // Parentheses opened in the expression close in it, even if they
+            // delimit it, e.g. in call arguments
+            let closes_paran = token == Token::CloseParan && paran_count > 0;
+            if (delims.contains(&token) && !closes_paran)
+                || (token == Token::CloseParan && paran_count == 0)
+            {
                 self.advance();
                 break;
             }
+            // A cast applies to the operand before it, binding tighter than
+            // binary operators but looser than unary ones
+            if token == Token::As {
+                while let Some(op) = op_stack.front() {
+                    if !op.unary() {
+                        break;
+                    }
+                    out_queue.push_back(ExprOutput::Operator(op_stack.pop_front().unwrap()));
+                }
+                self.advance();
+                let cast_type = self.parse_type()?;
+                out_queue.push_back(ExprOutput::Cast(cast_type));
+                // The loop advances past the last token of the type
+                self.token_pos -= 1;
+            }
             // If it is an operator
-            if let Some(mut op) = Operator::from(token.clone()) {
+            else if let Some(mut op) = Operator::from(token.clone()) {
                 match op {
                     // If its an "(", push it onto the operator stack
                     Operator::OpenParan => {
@@ -1029,45 +1139,34 @@ impl Parser {
                     }
                     // Any other operator
                     _ => {
-                        let op = &mut op;
-                        while !op_stack.is_empty() {
-                            let last_op_opt = Operator::from(last_token.clone());
-                            if let Some(last_op) = last_op_opt {
-                                if last_op != Operator::Assign {
-                                    *op = match op {
-                                        Operator::Minus => Operator::Neg,
-                                        Operator::Plus => Operator::Pos,
-                                        _ => return Err(Error::Unknown),
-                                    };
-                                }
-                            }
-                            let op_front = op_stack.pop_front().unwrap();
-                            if op_front.prec() > op.prec() {
-                                out_queue.push_back(ExprOutput::Operator(op_front));
-                            } else {
-                                op_stack.push_front(op_front);
+                        // An operator starting the expression or following
+                        // another operator is a prefix operator
+                        let prefix = match Operator::from(last_token.clone()) {
+                            Some(Operator::CloseParan) => false,
+                            Some(_) => true,
+                            None => last_token == Token::Error,
+                        };
+                        if prefix {
+                            op = match op {
+                                Operator::Minus => Operator::Neg,
+                                Operator::Plus => Operator::Pos,
+                                op if op.unary() => op,
+                                _ => return Err(Error::MalformedExpression),
+                            };
+                        }
+                        // Prefix operators apply to what follows them, binary
+                        // ones are left associative except for assignments
+                        while let Some(op_front) = op_stack.front() {
+                            let pops = *op_front != Operator::OpenParan
+                                && !op.unary()
+                                && (op_front.prec() > op.prec()
+                                    || (op_front.prec() == op.prec() && op.prec() >= 0));
+                            if !pops {
                                 break;
                             }
+                            out_queue.push_back(ExprOutput::Operator(op_stack.pop_front().unwrap()));
                         }
-                        match op {
-                            Operator::Plus => {
-                                if Operator::from(last_token.clone()).is_none() {
-                                    op_stack.push_front(op.clone());
-                                } else {
-                                    let op = Operator::Pos;
-                                    op_stack.push_front(op);
-                                }
-                            }
-                            Operator::Minus => {
-                                if Operator::from(last_token.clone()).is_none() {
-                                    op_stack.push_front(op.clone());
-                                } else {
-                                    let op = Operator::Neg;
-                                    op_stack.push_front(op);
-                                }
-                            }
-                            _ => op_stack.push_front(op.clone()),
-                        };
+                        op_stack.push_front(op);
                     }
                 };
             }
@@ -1090,6 +1189,10 @@ impl Parser {
             let expr_output = out_queue.pop_front().unwrap();
             match expr_output {
                 ExprOutput::Expression(expr) => out_stack.push_front(expr),
+                ExprOutput::Cast(cast_type) => {
+                    let expr = out_stack.pop_front().ok_or(Error::MalformedExpression)?;
+                    out_stack.push_front(Expression::Cast(Box::new(expr), cast_type));
+                }
                 ExprOutput::Operator(op) => {
                     if !op.unary() {
                         let rhs_expr = out_stack.pop_front().unwrap();
@@ -1131,6 +1234,8 @@ impl Parser {
             }
             Token::StringLiteral => {
                 let str_val = self.get_value()?;
+                // The token includes the quotes around the string
+                let str_val = str_val[1..str_val.len() - 1].to_string();
                 Expression::StringLiteral(str_val)
             },
             Token::Identifier => {
@@ -1159,9 +1264,19 @@ impl Parser {
         if token != Token::Identifier {
             return Err(Error::ExpectedIdentifier);
         }
-        let ident_string = self.get_value()?;
+        let mut ident_string = self.get_value()?;
         self.advance();
 
+        // Functions of other modules are called by their path, e.g. `math::abs`
+        while self.get_token()? == Token::DoubleColon {
+            self.advance();
+            if self.get_token()? != Token::Identifier {
+                return Err(Error::ExpectedIdentifier);
+            }
+            ident_string = format!("{}::{}", ident_string, self.get_value()?);
+            self.advance();
+        }
+
         token = self.get_token()?;
         if token != Token::OpenParan {
             return Err(Error::ExpectedOpenParan);
@@ -1174,6 +1289,9 @@ impl Parser {
     fn parse_expr_call_args(&mut self) -> Result<Vec<Expression>> {
         let mut last_token;
         let mut args = Vec::new();
+        if self.get_token()? == Token::CloseParan {
+            return Ok(args);
+        }
         while self.token_pos < self.tokens.len() {
             let arg_expr = self.parse_expr(&[Token::Comma, Token::CloseParan])?;
             args.push(arg_expr);
@@ -1195,4 +1313,5 @@ impl Parser {
 enum ExprOutput {
     Expression(Expression),
     Operator(Operator),
+    Cast(Type),
 }
@@ -86,7 +86,7 @@ pub enum Token {
     #[token("=")]
     Assign,
 
-    #[regex("int|bool|float|str")]
+    #[regex("int|bool|float|str|i8|i16|i32|i64|u8|u16|u32|u64|f32|f64")]
     PrimitiveType,
 
     #[token("<")]
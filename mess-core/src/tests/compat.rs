@@ -0,0 +1,66 @@
+use crate::{
+    codegen::compat::{check_compatible, Incompatibility},
+    parser::Parser,
+};
+
+use std::{result::Result as StdResult, error::Error};
+
+type Result = StdResult<(), Box<dyn Error>>;
+
+const LOADED: &str = "
+fun add(a: int, b: int) ~ int {
+    return a;
+}
+
+cont Vector {
+    pub x: float;
+    pub y: float;
+}
+";
+
+#[test]
+fn test_compatible_reload() -> Result {
+    let loaded = Parser::new(LOADED).parse()?;
+    let changed = Parser::new("
+    fun add(a: int, b: int) ~ int {
+        return b;
+    }
+
+    fun sub(a: int, b: int) ~ int {
+        return a;
+    }
+
+    cont Vector {
+        pub x: float;
+        pub y: float;
+    }
+    ").parse()?;
+    assert_eq!(check_compatible(&loaded, &changed), Ok(()));
+    Ok(())
+}
+
+#[test]
+fn test_incompatible_reload() -> Result {
+    let loaded = Parser::new(LOADED).parse()?;
+    let signature = Parser::new("
+    fun add(a: int, b: float) ~ int {
+        return a;
+    }
+    ").parse()?;
+    assert_eq!(
+        check_compatible(&loaded, &signature),
+        Err(Incompatibility::FunctionSignature(String::from("add")))
+    );
+    let layout = Parser::new("
+    cont Vector {
+        pub x: float;
+        pub y: float;
+        pub z: float;
+    }
+    ").parse()?;
+    assert_eq!(
+        check_compatible(&loaded, &layout),
+        Err(Incompatibility::ContainerLayout(String::from("Vector")))
+    );
+    Ok(())
+}
@@ -1,9 +1,36 @@
-use crate::parser::Parser;
+use crate::parser::{
+    Parser,
+    ast::{Declaration, Expression, Operator, Statement, Type},
+};
 
 use std::{result::Result as StdResult, error::Error};
 
 type Result = StdResult<(), Box<dyn Error>>;
 
+/// Parses the statements of a function body
+fn parse_body(body: &str) -> StdResult<Vec<Statement>, Box<dyn Error>> {
+    let code = format!("fun main() {{ {} }}", body);
+    let decl_list = Parser::new(code).parse()?;
+    match decl_list.into_iter().next() {
+        Some(Declaration::Function { body: Some(body), .. }) => {
+            Ok(body.into_iter().map(|stmt| stmt.item).collect())
+        }
+        _ => Err("Expected a function".into()),
+    }
+}
+
+/// Parses the expression of a single expression statement
+fn parse_expr(expr: &str) -> StdResult<Expression, Box<dyn Error>> {
+    match parse_body(&format!("{};", expr))?.into_iter().next() {
+        Some(Statement::ExpressionStmt(expr)) => Ok(expr),
+        stmt => Err(format!("Expected an expression, got {:?}", stmt).into()),
+    }
+}
+
+fn is_var(expr: &Expression, name: &str) -> bool {
+    matches!(expr, Expression::Variable(var_name) if var_name == name)
+}
+
 #[test]
 fn test_parse_fn_call() -> Result {
     let code = r#"
@@ -16,4 +43,248 @@ fn test_parse_fn_call() -> Result {
     let decl_list_res = parser.parse();
     assert!(decl_list_res.is_ok());
     Ok(())
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_parse_cast() -> Result {
+    let code = r#"
+    fun main() {
+        var x = b + -a as float;
+    }
+    "#;
+
+    let mut parser = Parser::new(code);
+    let decl_list = parser.parse()?;
+    let expr = match decl_list.first() {
+        Some(Declaration::Function { body: Some(body), .. }) => match body.first().map(|stmt| &stmt.item) {
+            Some(Statement::VarDeclaration { expr, .. }) => expr,
+            _ => return Err("Expected a variable declaration".into()),
+        },
+        _ => return Err("Expected a function".into()),
+    };
+    // The cast binds tighter than binary operators, but looser than unary ones
+    let Expression::Binary(lhs, Operator::Plus, rhs) = expr else {
+        return Err(format!("Expected an addition, got {:?}", expr).into());
+    };
+    assert!(matches!(lhs.as_ref(), Expression::Variable(name) if name == "b"));
+    let Expression::Cast(cast_expr, Type::Float) = rhs.as_ref() else {
+        return Err(format!("Expected a cast, got {:?}", rhs).into());
+    };
+    assert!(matches!(cast_expr.as_ref(), Expression::Unary(Operator::Neg, _)));
+    Ok(())
+}
+
+#[test]
+fn test_parse_cast_named() -> Result {
+    let code = r#"
+    fun main() {
+        var x = (a + b) as int as Counter;
+    }
+    "#;
+
+    let mut parser = Parser::new(code);
+    parser.parse()?;
+    Ok(())
+}
+
+#[test]
+fn test_parse_sized_types() -> Result {
+    let code = r#"
+    fun main(a: u8, b: i16) ~ f64 {
+        var x: i64 = a as int + b as i64;
+        var y: float = x as f32;
+        return y as f64;
+    }
+    "#;
+
+    let mut parser = Parser::new(code);
+    let decl_list = parser.parse()?;
+    let Some(Declaration::Function { arguments, returns, .. }) = decl_list.first() else {
+        return Err("Expected a function".into());
+    };
+    // `int` and `float` are aliases of `i64` and `f32`
+    assert_eq!(arguments[0].1, Type::U8);
+    assert_eq!(arguments[1].1, Type::I16);
+    assert_eq!(*returns, Type::F64);
+    Ok(())
+}
+
+#[test]
+fn test_parse_prefix_and_associativity() -> Result {
+    let code = r#"
+    fun main() {
+        var x = -a - b + (c) * d;
+        host::log();
+        return;
+    }
+    "#;
+
+    let mut parser = Parser::new(code);
+    let decl_list = parser.parse()?;
+    let Some(Declaration::Function { body: Some(body), .. }) = decl_list.first() else {
+        return Err("Expected a function".into());
+    };
+    let Some(Statement::VarDeclaration { expr, .. }) = body.first().map(|stmt| &stmt.item) else {
+        return Err("Expected a variable declaration".into());
+    };
+    // Binary operators of the same precedence are left associative
+    let Expression::Binary(lhs, Operator::Plus, rhs) = expr else {
+        return Err(format!("Expected an addition, got {:?}", expr).into());
+    };
+    let Expression::Binary(neg, Operator::Minus, _) = lhs.as_ref() else {
+        return Err(format!("Expected a subtraction, got {:?}", lhs).into());
+    };
+    assert!(matches!(neg.as_ref(), Expression::Unary(Operator::Neg, _)));
+    assert!(matches!(rhs.as_ref(), Expression::Binary(_, Operator::Times, _)));
+    let Some(Statement::ExpressionStmt(Expression::Call(name, args))) = body.get(1).map(|stmt| &stmt.item) else {
+        return Err("Expected a call".into());
+    };
+    assert_eq!(name, "host::log");
+    assert!(args.is_empty());
+    assert!(matches!(body.get(2).map(|stmt| &stmt.item), Some(Statement::Return(None))));
+    Ok(())
+}
+
+#[test]
+fn test_parse_minus_precedence() -> Result {
+    // Subtraction binds like addition, both left to right
+    let Expression::Binary(lhs, Operator::Plus, c) = parse_expr("a - b + c")? else {
+        return Err("Expected an addition".into());
+    };
+    assert!(matches!(lhs.as_ref(), Expression::Binary(a, Operator::Minus, b) if is_var(a, "a") && is_var(b, "b")));
+    assert!(is_var(&c, "c"));
+
+    let Expression::Binary(lhs, Operator::Minus, c) = parse_expr("a + b - c")? else {
+        return Err("Expected a subtraction".into());
+    };
+    assert!(matches!(lhs.as_ref(), Expression::Binary(_, Operator::Plus, _)));
+    assert!(is_var(&c, "c"));
+
+    let Expression::Binary(a, Operator::Minus, rhs) = parse_expr("a - b * c")? else {
+        return Err("Expected a subtraction".into());
+    };
+    assert!(is_var(&a, "a"));
+    assert!(matches!(rhs.as_ref(), Expression::Binary(_, Operator::Times, _)));
+    Ok(())
+}
+
+#[test]
+fn test_parse_prefix_operators() -> Result {
+    // Prefix operators start an expression or follow another operator
+    let Expression::Binary(lhs, Operator::Minus, rhs) = parse_expr("-a - -b")? else {
+        return Err("Expected a subtraction".into());
+    };
+    assert!(matches!(lhs.as_ref(), Expression::Unary(Operator::Neg, a) if is_var(a, "a")));
+    assert!(matches!(rhs.as_ref(), Expression::Unary(Operator::Neg, b) if is_var(b, "b")));
+
+    let Expression::Binary(a, Operator::Times, rhs) = parse_expr("a * +b")? else {
+        return Err("Expected a multiplication".into());
+    };
+    assert!(is_var(&a, "a"));
+    assert!(matches!(rhs.as_ref(), Expression::Unary(Operator::Pos, _)));
+
+    // A minus after a closing parenthesis is binary
+    let Expression::Binary(lhs, Operator::Minus, _) = parse_expr("(a) - b")? else {
+        return Err("Expected a subtraction".into());
+    };
+    assert!(is_var(&lhs, "a"));
+
+    assert!(parse_expr("a * / b").is_err());
+    Ok(())
+}
+
+#[test]
+fn test_parse_assign_associativity() -> Result {
+    // Assignments are right associative
+    let Expression::Binary(a, Operator::Assign, rhs) = parse_expr("a = b = c")? else {
+        return Err("Expected an assignment".into());
+    };
+    assert!(is_var(&a, "a"));
+    assert!(matches!(rhs.as_ref(), Expression::Binary(b, Operator::Assign, c) if is_var(b, "b") && is_var(c, "c")));
+
+    let Expression::Binary(a, Operator::AddAssign, rhs) = parse_expr("a += b - c")? else {
+        return Err("Expected an assignment".into());
+    };
+    assert!(is_var(&a, "a"));
+    assert!(matches!(rhs.as_ref(), Expression::Binary(_, Operator::Minus, _)));
+    Ok(())
+}
+
+#[test]
+fn test_parse_jump_semicolons() -> Result {
+    // `return`, `break` and `continue` consume their semicolon
+    let body = parse_body("return; var x = 1; return x;")?;
+    assert!(matches!(body.as_slice(), [
+        Statement::Return(None),
+        Statement::VarDeclaration { .. },
+        Statement::Return(Some(_)),
+    ]));
+
+    let body = parse_body("while x { break; continue; } return;")?;
+    let [Statement::While(_, while_body), Statement::Return(None)] = body.as_slice() else {
+        return Err(format!("Expected a loop and a return, got {:?}", body).into());
+    };
+    let while_body: Vec<&Statement> = while_body.iter().map(|stmt| &stmt.item).collect();
+    assert!(matches!(while_body.as_slice(), [Statement::Break, Statement::Continue]));
+
+    assert!(parse_body("break x;").is_err());
+    Ok(())
+}
+
+#[test]
+fn test_parse_string_literal() -> Result {
+    // The quotes are not part of the string
+    let expr = parse_expr("\"four\"")?;
+    assert!(matches!(expr, Expression::StringLiteral(string) if string == "four"));
+    let expr = parse_expr("\"\"")?;
+    assert!(matches!(expr, Expression::StringLiteral(string) if string.is_empty()));
+    Ok(())
+}
+
+#[test]
+fn test_parse_path_calls() -> Result {
+    let Expression::Call(name, args) = parse_expr("math::abs(x)")? else {
+        return Err("Expected a call".into());
+    };
+    assert_eq!(name, "math::abs");
+    assert_eq!(args.len(), 1);
+
+    let Expression::Call(name, args) = parse_expr("a::b::c()")? else {
+        return Err("Expected a call".into());
+    };
+    assert_eq!(name, "a::b::c");
+    assert!(args.is_empty());
+
+    let Expression::Call(name, args) = parse_expr("f(g(), 1)")? else {
+        return Err("Expected a call".into());
+    };
+    assert_eq!(name, "f");
+    assert!(matches!(args.as_slice(), [Expression::Call(g, g_args), Expression::IntLiteral(1)] if g == "g" && g_args.is_empty()));
+
+    let Expression::Call(_, args) = parse_expr("f((0 - 3) as i8, (x))")? else {
+        return Err("Expected a call".into());
+    };
+    assert!(matches!(args.as_slice(), [Expression::Cast(_, Type::I8), Expression::Variable(x)] if x == "x"));
+
+    assert!(parse_expr("math::(x)").is_err());
+    Ok(())
+}
+
+#[test]
+fn test_parse_condition_expression() -> Result {
+    let stmts = parse_body("var x = on a { yield 1; } else on b { yield 2; } else { yield 3; }; x += 1;")?;
+    let [Statement::VarDeclaration { expr, .. }, Statement::ExpressionStmt(_)] = stmts.as_slice() else {
+        return Err(format!("Expected a declaration and an expression, got {:?}", stmts).into());
+    };
+    let Expression::Condition { expr: cond, cond_chain, else_body, yield_expr, .. } = expr else {
+        return Err("Expected a condition".into());
+    };
+    assert!(is_var(cond, "a"));
+    assert_eq!(cond_chain.len(), 1);
+    assert_eq!(else_body.len(), 1);
+    assert!(matches!(yield_expr.as_deref(), Some(Expression::IntLiteral(3))));
+
+    // The condition has to end like the expression it stands for
+    assert!(parse_body("var x = on a { yield 1; } x += 1;").is_err());
+    Ok(())
+}
@@ -6,7 +6,9 @@ mod r#enum;
 
 mod expr;
 
-use crate::{parser::Parser, codegen::decl};
+mod compat;
+
+use crate::parser::{Parser, ast::{Declaration, Expression, Statement, Type}};
 use std::{result::Result as StdResult, error::Error};
 
 type Result = StdResult<(), Box<dyn Error>>;
@@ -87,4 +89,98 @@ fn test_parse_complex_on() -> Result {
     let decl_list = parser.parse()?;
     println!("{:#?}", decl_list);
     Ok(())
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_parse_yield() -> Result {
+    let code = "
+    fun counter() {
+        yield 1;
+        yield;
+    }
+    ";
+
+    let mut parser = Parser::new(code);
+    let decl_list = parser.parse()?;
+    let body = match &decl_list[0] {
+        Declaration::Function { body: Some(body), .. } => body,
+        _ => return Err("Expected a function".into()),
+    };
+    assert!(matches!(body[0].item, Statement::Yield(Some(_))));
+    assert!(matches!(body[1].item, Statement::Yield(None)));
+    Ok(())
+}
+
+#[test]
+fn test_parse_coroutine_type() -> Result {
+    let code = "
+    fun take(gen: coroutine<int>) ~ coroutine<(int, bool)> {
+        return gen;
+    }
+    ";
+
+    let mut parser = Parser::new(code);
+    let decl_list = parser.parse()?;
+    let (arguments, returns) = match &decl_list[0] {
+        Declaration::Function { arguments, returns, .. } => (arguments, returns),
+        _ => return Err("Expected a function".into()),
+    };
+    assert_eq!(arguments[0].1, Type::Coroutine(Box::new(Type::Int)));
+    assert_eq!(
+        *returns,
+        Type::Coroutine(Box::new(Type::Tuple(vec![Type::Int, Type::Bool])))
+    );
+    Ok(())
+}
+
+#[test]
+fn test_parse_static() -> Result {
+    let code = "
+    pub static count: int = 1;
+    static ratio: float = 0.5;
+    ";
+
+    let mut parser = Parser::new(code);
+    let decl_list = parser.parse()?;
+    assert_eq!(decl_list.len(), 2);
+    match &decl_list[0] {
+        Declaration::StaticVariable { public, name, r#type, expr } => {
+            assert!(*public);
+            assert_eq!(name, "count");
+            assert_eq!(*r#type, Type::Int);
+            assert!(matches!(expr, Expression::IntLiteral(1)));
+        }
+        _ => return Err("Expected a static variable".into()),
+    }
+    assert!(matches!(
+        &decl_list[1],
+        Declaration::StaticVariable { public: false, r#type: Type::Float, .. }
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_parse_statement_lines() -> Result {
+    let code = "fun main() {
+    var x = 1;
+
+    while x < 3 {
+        x += 1;
+    }
+}";
+
+    let mut parser = Parser::new(code);
+    let decl_list = parser.parse()?;
+    let body = match &decl_list[0] {
+        Declaration::Function { body: Some(body), .. } => body,
+        _ => return Err("Expected a function".into()),
+    };
+    let lines: Vec<usize> = body.iter().map(|stmt| stmt.line).collect();
+    assert_eq!(lines, vec![2, 4]);
+    assert_eq!(&code[body[0].range.clone()], "var x = 1;");
+    let Statement::While(_, while_body) = &body[1].item else {
+        return Err("Expected a while loop".into());
+    };
+    assert_eq!(while_body[0].line, 5);
+    Ok(())
+}
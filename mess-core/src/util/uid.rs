@@ -1,33 +1,23 @@
-use std::collections::HashSet;
-
-use rand::{
-    prelude::ThreadRng,
-    thread_rng,
-    RngCore,
-};
-
-/// Convenience struct for generating unique u64s
+/// Convenience struct for generating unique u64s. Uids are handed out in
+/// sequence, so the same sequence of calls always yields the same uids and
+/// compiling the same source twice gives the same output.
+#[derive(Default)]
 pub struct UIDGenerator {
-    uid_set: HashSet<u64>,
-    rng: ThreadRng,
+    next_uid: u64,
 }
 
-impl Default for UIDGenerator {
-    fn default() -> UIDGenerator {
+impl UIDGenerator {
+    /// Creates a generator handing out uids from the given one upwards, e.g.
+    /// to keep them apart from uids assigned elsewhere
+    pub fn starting_at(first_uid: u64) -> UIDGenerator {
         UIDGenerator {
-            uid_set: HashSet::new(),
-            rng: thread_rng(),
+            next_uid: first_uid,
         }
     }
-}
 
-impl UIDGenerator {
     pub fn generate(&mut self) -> u64 {
-        let mut uid = self.rng.next_u64();
-        while self.uid_set.contains(&uid) {
-            uid = self.rng.next_u64();
-        }
-        self.uid_set.insert(uid);
+        let uid = self.next_uid;
+        self.next_uid += 1;
         uid
     }
 }
@@ -1,8 +1,14 @@
 #![allow(unused_must_use)]
 
-use std::collections::{
-    HashMap,
-    VecDeque,
+use std::{
+    collections::{
+        BTreeMap,
+        HashMap,
+        VecDeque,
+    },
+    iter,
+    mem,
+    result::Result as StdResult,
 };
 
 use dynasmrt::{
@@ -13,25 +19,33 @@ use dynasmrt::{
     DynasmApi,
     DynasmLabelApi,
 };
+use mess_api::prelude::{
+    Function,
+    Module,
+};
 use mess_core::{
     codegen::{
         ctx::{
             FnContext,
             StackContext,
         },
+        decl::Declarator,
         def::{
             ContDef,
             FunctionDef,
             ModuleDef,
         },
     },
+    compiler::Compiler as CompilerTrait,
     parser::ast::{
+        AstItem,
         Declaration,
         Expression,
         Operator,
         Statement,
         Type,
     },
+    util::uid::UIDGenerator,
 };
 
 use super::{
@@ -39,9 +53,116 @@ use super::{
         Error,
         Result,
     },
-    output::Output,
+    output::{
+        Output,
+        FLOAT_ARG_COUNT,
+        INT_ARG_COUNT,
+    },
 };
+use crate::exec::{
+    convert,
+    host::{
+        self,
+        HostContext,
+    },
+    runtime::RuntimeError,
+};
+
+/// The first uid handed out to host functions. The declarator numbers script
+/// functions from 0, so their uids stay below it.
+const FIRST_GENERATED_UID: u64 = 1 << 32;
+
+/// The integer argument registers in the order of the System V calling
+/// convention, `rdi`, `rsi`, `rdx`, `rcx`, `r8` and `r9`
+const INT_ARG_REGS: [u8; INT_ARG_COUNT] = [7, 6, 2, 1, 8, 9];
+
+/// Returns the offset from `rbp` of the value at a stack position. The stack
+/// grows down, so a value at position `pos` ends `pos` bytes below `rbp`.
+fn slot(pos: i32, size: usize) -> i32 {
+    -(pos + size as i32)
+}
+
+/// Which registers a value is passed and returned in
+#[derive(Clone, Copy, PartialEq)]
+enum ArgClass {
+    Int,
+    Float,
+}
+
+fn get_arg_class(var_type: &Type) -> Result<ArgClass> {
+    match var_type {
+        Type::Float | Type::F64 => Ok(ArgClass::Float),
+        Type::Bool | Type::Ref(_) | Type::Named(_) => Ok(ArgClass::Int),
+        _ if var_type.is_integer() => Ok(ArgClass::Int),
+        _ => Err(Error::UnknownType(var_type.clone())),
+    }
+}
+
+/// Where a function takes one of its arguments
+#[derive(Clone, Copy)]
+enum ArgLocation {
+    /// The integer register with the number
+    Int(u8),
+    /// The SSE register with the number
+    Float(u8),
+    /// The 8 byte stack slot with the index, counted up from the return
+    /// address
+    Stack(i32),
+}
+
+/// Returns where a function takes its arguments. Like in the System V
+/// calling convention, arguments go in the next free register of their
+/// class, and on the stack in order once those run out.
+fn get_arg_locations<'t>(arg_types: impl Iterator<Item = &'t Type>) -> Result<Vec<ArgLocation>> {
+    let mut int_reg_ctr = 0;
+    let mut float_reg_ctr = 0;
+    let mut stack_ctr = 0;
+    let mut locations = Vec::new();
+    for arg_type in arg_types {
+        let location = match get_arg_class(arg_type)? {
+            ArgClass::Int if int_reg_ctr < INT_ARG_COUNT => {
+                int_reg_ctr += 1;
+                ArgLocation::Int(INT_ARG_REGS[int_reg_ctr - 1])
+            }
+            ArgClass::Float if float_reg_ctr < FLOAT_ARG_COUNT => {
+                float_reg_ctr += 1;
+                ArgLocation::Float(float_reg_ctr as u8 - 1)
+            }
+            _ => {
+                stack_ctr += 1;
+                ArgLocation::Stack(stack_ctr - 1)
+            }
+        };
+        locations.push(location);
+    }
+    Ok(locations)
+}
+
+/// What integer arithmetic of compiled code does when its result is out of
+/// range, like `OverflowMode` of the VM
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum OverflowMode {
+    /// Keep the low bits of the result, like two's complement hardware
+    #[default]
+    Wrap,
+    /// Stop with `RuntimeError::IntegerOverflow`
+    Trap,
+}
+
+/// Creates an assembler for code in executable memory
+fn new_assembler() -> Result<Assembler> {
+    Assembler::new().map_err(|_| Error::ExecutableMemory)
+}
 
+/// The stub compiled code is entered through, and the label unwinding to it
+#[derive(Clone, Copy)]
+struct EntryStub {
+    entry: AssemblyOffset,
+    unwind: DynamicLabel,
+}
+
+/// Compiles scripts to AMD64 machine code. Functions follow the System V
+/// calling convention and keep their locals in stack slots below `rbp`.
 pub struct Compiler {
     mod_def_stack: VecDeque<ModuleDef>,
     stack_ctx_stack: VecDeque<StackContext>,
@@ -49,19 +170,108 @@ pub struct Compiler {
     label_map: HashMap<u64, DynamicLabel>,
     uid_counter: u64,
     assembler: Assembler,
+    declarator: Declarator,
+    uid_gen: UIDGenerator,
+    foreign_functions: BTreeMap<u64, Function>,
+    foreign_modules: Vec<ModuleDef>,
+    /// The `continue` and `break` targets of the enclosing loops
+    loop_labels: Vec<(DynamicLabel, DynamicLabel)>,
+    /// The stack position and type of the values of the enclosing condition
+    /// expressions, and their ends, which `yield` jumps to
+    cond_exprs: Vec<(i32, Type, DynamicLabel)>,
+    entry_stub: Option<EntryStub>,
+    context: Box<HostContext>,
+    function_map: BTreeMap<String, AssemblyOffset>,
+    function_defs: BTreeMap<String, FunctionDef>,
+    overflow_mode: OverflowMode,
+}
+
+impl CompilerTrait for Compiler {
+    type Output = Output;
+    type Error = Error;
+
+    fn get_output(&mut self) -> StdResult<Self::Output, Self::Error> {
+        let entry = self.get_entry_stub().entry;
+        let mut assembler = new_assembler()?;
+        mem::swap(&mut self.assembler, &mut assembler);
+        let buffer = assembler.finalize().map_err(|_| Error::ExecutableMemory)?;
+        self.label_map.clear();
+        self.entry_stub = None;
+        let mut context = mem::take(&mut self.context);
+        context.set_functions(self.foreign_functions.clone());
+        Ok(Output::new(
+            buffer,
+            mem::take(&mut self.function_map),
+            mem::take(&mut self.function_defs),
+            entry,
+            context,
+        ))
+    }
+
+    /// Compiles the declarations, adding their functions to the output. On
+    /// errors, the code compiled since the last output is discarded.
+    fn compile(&mut self, decl_list: &[Declaration]) -> StdResult<(), Self::Error> {
+        let result = self.compile_root(decl_list);
+        if result.is_err() {
+            self.reset()?;
+        }
+        result
+    }
+
+    /// Makes the functions of a host module callable as `module::function`.
+    /// Each function gets a uid, which calls to it pass to the host.
+    fn register_module(&mut self, module: Module) -> StdResult<(), Self::Error> {
+        let mut mod_def = ModuleDef::new("root::", module.name.as_str());
+        let fn_path = format!("root::{}::", module.name);
+        for (_, function) in module.functions {
+            let uid = self.uid_gen.generate();
+            mod_def.add_function(FunctionDef::from_api(uid, &fn_path, function.clone()));
+            self.foreign_functions.insert(uid, function);
+        }
+        self.foreign_modules.push(mod_def);
+        Ok(())
+    }
 }
 
 impl Compiler {
-    pub fn new() -> Self {
-        let mod_def_stack = VecDeque::new();
-        Self {
-            mod_def_stack,
+    /// Creates a compiler without host modules. Fails if no executable
+    /// memory can be allocated for the code.
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            mod_def_stack: VecDeque::new(),
             uid_counter: 0,
             stack_ctx_stack: VecDeque::new(),
             fn_ctx_stack: VecDeque::new(),
             label_map: HashMap::new(),
-            assembler: Assembler::new().expect("Couldnt create x64 JIT assembler!"),
-        }
+            assembler: new_assembler()?,
+            declarator: Declarator::default(),
+            uid_gen: UIDGenerator::starting_at(FIRST_GENERATED_UID),
+            foreign_functions: BTreeMap::new(),
+            foreign_modules: Vec::new(),
+            loop_labels: Vec::new(),
+            cond_exprs: Vec::new(),
+            entry_stub: None,
+            context: Box::default(),
+            function_map: BTreeMap::new(),
+            function_defs: BTreeMap::new(),
+            overflow_mode: OverflowMode::default(),
+        })
+    }
+
+    /// Sets what integer arithmetic compiled afterwards does on overflow
+    pub fn with_overflow_mode(mut self, overflow_mode: OverflowMode) -> Compiler {
+        self.overflow_mode = overflow_mode;
+        self
+    }
+
+    /// Sets what integer arithmetic compiled afterwards does on overflow
+    pub fn set_overflow_mode(&mut self, overflow_mode: OverflowMode) {
+        self.overflow_mode = overflow_mode;
+    }
+
+    /// Returns what integer arithmetic does on overflow
+    pub fn get_overflow_mode(&self) -> OverflowMode {
+        self.overflow_mode
     }
 
     pub fn set_root_module(&mut self, module_def: ModuleDef) {
@@ -73,6 +283,44 @@ impl Compiler {
         self.uid_counter = counter;
     }
 
+    /// Discards all compiled code and declared script functions, keeping
+    /// the registered host modules, e.g. to compile a changed script
+    pub fn reset(&mut self) -> Result<()> {
+        self.mod_def_stack.clear();
+        self.stack_ctx_stack.clear();
+        self.fn_ctx_stack.clear();
+        self.label_map.clear();
+        self.loop_labels.clear();
+        self.cond_exprs.clear();
+        self.assembler = new_assembler()?;
+        self.declarator = Declarator::default();
+        self.entry_stub = None;
+        self.context = Box::default();
+        self.function_map.clear();
+        self.function_defs.clear();
+        Ok(())
+    }
+
+    fn compile_root(&mut self, decl_list: &[Declaration]) -> Result<()> {
+        self.declarator.declare(decl_list).map_err(|_| Error::Unknown)?;
+        let (mut root_mod_def, _) = self.declarator.get_result().map_err(|_| Error::Unknown)?;
+        for mod_def in self.foreign_modules.iter() {
+            root_mod_def.add_module(mod_def.clone());
+        }
+        self.set_root_module(root_mod_def);
+        // Checked up front, as functions can use statics declared after them
+        if decl_list.iter().any(|decl| matches!(decl, Declaration::StaticVariable { .. })) {
+            return Err(Error::Unimplemented("Static variables"));
+        }
+        self.get_entry_stub();
+        self.compile_decl_list(decl_list)?;
+
+        self.function_map.clear();
+        self.function_defs.clear();
+        let mod_def = self.get_current_module()?.clone();
+        self.build_fn_map(&mod_def)
+    }
+
     fn get_module_path(&self) -> Result<String> {
         let mut path = String::new();
         for mod_def in self.mod_def_stack.iter() {
@@ -95,10 +343,6 @@ impl Compiler {
         Ok(stack_ctx.stack_extent)
     }
 
-    fn get_stack_offset(&self) -> Result<i32> {
-        Ok(-(self.get_stack_pos()? as i32))
-    }
-
     fn inc_stack(&mut self, inc: isize) -> Result<()> {
         let stack_ctx = self.get_stack_ctx()?;
         stack_ctx.inc_stack(inc);
@@ -111,20 +355,24 @@ impl Compiler {
         Ok(())
     }
 
-    fn get_stack_ctx(&mut self) -> Result<&mut StackContext> {
-        self.stack_ctx_stack.get_mut(0).ok_or(Error::Unknown)
+    /// Resets the stack position to where it was before values were pushed
+    fn reset_stack(&mut self, pos: i32) -> Result<()> {
+        let current_pos = self.get_stack_pos()?;
+        self.dec_stack((current_pos - pos) as isize)
     }
 
-    fn get_var_position(&self, name: &str) -> Result<i32> {
-        let stack_ctx = self.stack_ctx_stack.get(0).ok_or(Error::Unknown)?;
-        let raw = stack_ctx.get_var(name);
-        Ok(raw.0)
+    fn get_stack_ctx(&mut self) -> Result<&mut StackContext> {
+        self.stack_ctx_stack.get_mut(0).ok_or(Error::Unknown)
     }
 
-    fn get_var_type(&self, name: &str) -> Result<Type> {
+    /// Returns the stack position and type of a variable
+    fn get_var(&self, name: &str) -> Result<(i32, Type)> {
         let stack_ctx = self.stack_ctx_stack.get(0).ok_or(Error::Unknown)?;
-        let raw = stack_ctx.get_var(name);
-        Ok(raw.1.clone())
+        stack_ctx
+            .get_vars()
+            .find(|(var_name, _)| var_name.as_str() == name)
+            .map(|(_, (var_pos, var_type))| (*var_pos, var_type.clone()))
+            .ok_or_else(|| Error::UnknownVariable(String::from(name)))
     }
 
     fn get_current_module(&self) -> Result<&ModuleDef> {
@@ -148,7 +396,7 @@ impl Compiler {
                 }
             };
 
-            for i in 1..mod_path.len() - 1 {
+            for i in 0..mod_path.len() - 1 {
                 let mod_name = mod_path[i];
                 if mod_def.has_module(mod_name) {
                     mod_def = mod_def.get_module(mod_name)?;
@@ -190,18 +438,97 @@ impl Compiler {
         }
     }
 
-    fn get_dynamic_label_raw(&self, uid: &u64) -> Result<DynamicLabel> {
-        self.label_map.get(uid).cloned().ok_or(Error::Unknown)
-    }
-
     fn get_size_of_type(&self, var_type: &Type) -> Result<usize> {
         match var_type {
-            Type::Int => Ok(8),
-            Type::Float => Ok(4),
-            Type::Ref(_) => Ok(8),
+            Type::Void => Ok(0),
+            Type::Int | Type::U64 | Type::F64 => Ok(8),
+            Type::Float | Type::I32 | Type::U32 => Ok(4),
+            Type::I16 | Type::U16 => Ok(2),
+            Type::I8 | Type::U8 => Ok(1),
+            // Named types are handles of foreign objects
+            Type::Ref(_) | Type::Named(_) => Ok(8),
             Type::Bool => Ok(1),
-            _ => return Err(Error::UnknownType(var_type.clone())),
+            _ => Err(Error::UnknownType(var_type.clone())),
+        }
+    }
+
+    /// Returns the address of the host context, which compiled code passes
+    /// to the functions it calls in the host
+    fn get_context_ptr(&self) -> i64 {
+        &*self.context as *const HostContext as i64
+    }
+
+    /// Returns the entry stub of the code being assembled, emitting it first
+    /// if needed. The stub saves its stack pointer in the host context, loads
+    /// the argument registers from the arrays it is passed and calls the
+    /// target, returning `rax` and `xmm0`. Traps restore the stack pointer
+    /// and return through the stub.
+    ///
+    /// Host functions can call compiled code again, so the stub keeps the
+    /// stack pointer saved by the entry it is nested in and the call depth
+    /// on its own stack and restores them on the way out, which also resets
+    /// the depth of the functions a trap unwound. Arguments that don't fit into registers
+    /// are pushed from the array in `r8`, whose length is in `r9`.
+    fn get_entry_stub(&mut self) -> EntryStub {
+        if let Some(entry_stub) = self.entry_stub {
+            return entry_stub;
         }
+        let context = self.get_context_ptr();
+        let unwind = self.assembler.new_dynamic_label();
+        let entry = self.assembler.offset();
+        dynasm!(self.assembler
+            ; push rbp
+            ; mov rbp, rsp
+            // Pushing the saved stack pointer and call depth keeps `rsp` aligned
+            ; push QWORD [rdi]
+            ; push QWORD [rdi + 8]
+            ; mov QWORD [rdi], rsp
+            ; test r9b, 1
+            ; jz >push_args
+            ; sub rsp, 8
+            ; push_args:
+            ; test r9, r9
+            ; jz >call_target
+            ; dec r9
+            ; push QWORD [r8 + r9 * 8]
+            ; jmp <push_args
+            ; call_target:
+            ; mov r11, rsi
+            ; mov r10, rdx
+            ; movsd xmm0, QWORD [rcx]
+            ; movsd xmm1, QWORD [rcx + 8]
+            ; movsd xmm2, QWORD [rcx + 16]
+            ; movsd xmm3, QWORD [rcx + 24]
+            ; movsd xmm4, QWORD [rcx + 32]
+            ; movsd xmm5, QWORD [rcx + 40]
+            ; movsd xmm6, QWORD [rcx + 48]
+            ; movsd xmm7, QWORD [rcx + 56]
+            ; mov rdi, QWORD [r10]
+            ; mov rsi, QWORD [r10 + 8]
+            ; mov rdx, QWORD [r10 + 16]
+            ; mov rcx, QWORD [r10 + 24]
+            ; mov r8, QWORD [r10 + 32]
+            ; mov r9, QWORD [r10 + 40]
+            ; call r11
+            ; movq rdx, xmm0
+            ; jmp >restore
+            ; =>unwind
+            ; xor eax, eax
+            ; xor edx, edx
+            ; restore:
+            ; mov r11, QWORD context
+            ; mov rsp, QWORD [r11]
+            ; mov rcx, QWORD [rsp + 8]
+            ; mov QWORD [r11], rcx
+            ; mov rcx, QWORD [rsp]
+            ; mov QWORD [r11 + 8], rcx
+            ; add rsp, 16
+            ; pop rbp
+            ; ret
+        );
+        let entry_stub = EntryStub { entry, unwind };
+        self.entry_stub = Some(entry_stub);
+        entry_stub
     }
 
     pub fn compile_decl_list(&mut self, decl_list: &[Declaration]) -> Result<()> {
@@ -213,64 +540,84 @@ impl Compiler {
 
     pub fn compile_decl(&mut self, decl: &Declaration) -> Result<()> {
         match decl {
-            &Declaration::Function { .. } => self.compile_decl_fn(decl),
-            _ => Err(Error::Unknown),
+            Declaration::Function { .. } => self.compile_decl_fn(decl),
+            Declaration::StaticVariable { .. } => Err(Error::Unimplemented("Static variables")),
+            _ => Err(Error::UnsupportedDeclaration),
         }
     }
 
     pub fn compile_decl_fn(&mut self, decl: &Declaration) -> Result<()> {
         let (name, ret_type, args, stmt_list) = match decl {
             Declaration::Function {
-                public: false,
-                external: false,
                 name,
                 arguments,
                 returns,
                 body,
-            } => {
-                if body.is_none() {
-                    return Ok(());
-                }
-                (name, returns, arguments, body.as_ref().unwrap())
-            }
+                ..
+            } => match body {
+                Some(body) => (name, returns, arguments, body),
+                None => return Ok(()),
+            },
             _ => return Err(Error::Unknown),
         };
         let fn_uid = self.get_current_module()?.get_function(name)?.label_uid;
         let fn_label = self.get_dynamic_label(&fn_uid)?;
-        dynasm!(&mut self.assembler
+        dynasm!(self.assembler
             ; =>fn_label
             ; push rbp
             ; mov rbp, rsp
         );
+        self.asm_enter_call();
         let stack_init_offset = self.assembler.offset();
         dynasm!(self.assembler; sub rsp, DWORD 0);
-        let mut stack_ctx = StackContext::new(self.get_next_uid());
+        let stack_ctx = StackContext::new(self.get_next_uid());
         let fn_ctx = FnContext::new(ret_type.clone(), stack_ctx.uid);
         self.fn_ctx_stack.push_front(fn_ctx);
         self.stack_ctx_stack.push_front(stack_ctx);
         self.asm_args_to_stack(args)?;
         self.compile_stmt_list(stmt_list)?;
-        stack_ctx = self.stack_ctx_stack.pop_front().ok_or(Error::Unknown)?;
+        // Falling off the end returns like an empty return statement, which
+        // functions returning a value can't use
+        if *ret_type == Type::Void {
+            self.asm_epilogue();
+        } else {
+            self.asm_trap(RuntimeError::NoReturnValue);
+        }
+        // Calls need `rsp` aligned to 16 bytes, as it is after `push rbp`
+        let stack_extent = (self.get_stack_extent()? + 15) & !15;
+        self.stack_ctx_stack.pop_front().ok_or(Error::Unknown)?;
         let mut modifier = self.assembler.alter_uncommitted();
         modifier.goto(stack_init_offset);
-        dynasm!(modifier; sub rsp, DWORD stack_ctx.stack_extent);
+        dynasm!(modifier; sub rsp, DWORD stack_extent);
         self.fn_ctx_stack.pop_front();
         Ok(())
     }
 
-    pub fn compile_stmt_list(&mut self, stmt_list: &[Statement]) -> Result<()> {
+    pub fn compile_stmt_list(&mut self, stmt_list: &[AstItem<Statement>]) -> Result<()> {
         for stmt in stmt_list {
             self.compile_stmt(stmt)?;
         }
         Ok(())
     }
 
+    /// Compiles the statements of a loop or condition body, freeing the
+    /// stack space of the variables they declare afterwards
+    fn compile_block(&mut self, stmt_list: &[AstItem<Statement>]) -> Result<()> {
+        let pos = self.get_stack_pos()?;
+        self.compile_stmt_list(stmt_list)?;
+        self.reset_stack(pos)
+    }
+
     pub fn compile_stmt(&mut self, stmt: &Statement) -> Result<()> {
         match stmt {
             Statement::VarDeclaration { .. } => self.compile_stmt_var_decl(stmt)?,
             Statement::ExpressionStmt(_) => self.compile_stmt_expr(stmt)?,
             Statement::Return(_) => self.compile_stmt_return(stmt)?,
-            _ => return Err(Error::Unknown),
+            Statement::While(..) => self.compile_stmt_while(stmt)?,
+            Statement::Condition { .. } => self.compile_stmt_cond(stmt)?,
+            Statement::Break | Statement::Continue => self.compile_stmt_loop_jump(stmt)?,
+            Statement::Yield(_) => self.compile_stmt_yield(stmt)?,
+            Statement::Import(_) => return Err(Error::Unimplemented("Import statements")),
         };
         Ok(())
     }
@@ -280,45 +627,28 @@ impl Compiler {
             Statement::Return(expr_opt) => expr_opt,
             _ => return Err(Error::Unknown),
         };
-        let (ret_type, suid) = {
-            let fn_ctx = self.get_current_fn_ctx()?;
-            (fn_ctx.ret_type.clone(), fn_ctx.stack_ctx_uid)
-        };
+        let ret_type = self.get_current_fn_ctx()?.ret_type.clone();
 
-        if expr_opt.is_some() {
-            let expr = expr_opt.as_ref().unwrap();
-            //println!("Ret expr: {:#?}", expr);
-            let expr_type = self.get_expr_type(expr)?;
-            if ret_type != expr_type {
-                return Err(Error::TypeMismatch(ret_type, expr_type));
-            }
-            let pos = self.get_stack_pos()?;
-            self.compile_expr(expr)?;
-            match expr_type {
-                Type::Int => {
-                    dynasm!(&mut self.assembler
-                        ; mov rax, [rbp - pos]
-                    );
+        match expr_opt {
+            Some(expr) => {
+                let expr_type = self.get_expr_type(expr)?;
+                if ret_type != expr_type {
+                    return Err(Error::TypeMismatch(ret_type, expr_type));
                 }
-                Type::Float => {
-                    dynasm!(&mut self.assembler
-                        ; movss xmm0, DWORD [rbp - pos]
-                    );
-                }
-                _ => {
-                    return Err(Error::Unimplemented(
-                        "Other return types not implemented yet!",
-                    ))
-                }
-            };
-        }
-
-        self.asm_stack_ret(suid)?;
-        dynasm!(&mut self.assembler
-            ; pop rbp
-            ; ret
-        );
+                let pos = self.get_stack_pos()?;
+                self.compile_expr(expr)?;
+                let size = self.get_size_of_type(&expr_type)?;
+                match get_arg_class(&expr_type)? {
+                    ArgClass::Int => self.asm_load_int(0, slot(pos, size), size)?,
+                    ArgClass::Float => self.asm_load_float(0, slot(pos, size), size)?,
+                };
+                self.reset_stack(pos)?;
+            }
+            None if ret_type != Type::Void => return Err(Error::ExpectedReturnExpression),
+            None => {}
+        };
 
+        self.asm_epilogue();
         Ok(())
     }
 
@@ -333,11 +663,13 @@ impl Compiler {
         };
         let expr_type = self.get_expr_type(var_expr)?;
         if var_type == Type::Auto {
-            var_type = expr_type;
+            var_type = expr_type.clone();
         }
-        let var_size = self.get_size_of_type(&var_type)?;
+        if var_type != expr_type {
+            return Err(Error::TypeMismatch(var_type, expr_type));
+        }
+        let var_pos = self.get_stack_pos()?;
         self.compile_expr(var_expr)?;
-        let var_pos = self.get_stack_pos()? - var_size as i32;
         let stack_ctx = self.get_stack_ctx()?;
         stack_ctx.set_var(var_pos, var_name, &var_type);
         Ok(())
@@ -348,7 +680,112 @@ impl Compiler {
             Statement::ExpressionStmt(expr) => expr,
             _ => return Err(Error::Unknown),
         };
-        self.compile_expr(expr)
+        let pos = self.get_stack_pos()?;
+        self.compile_expr(expr)?;
+        self.reset_stack(pos)
+    }
+
+    fn compile_stmt_while(&mut self, stmt: &Statement) -> Result<()> {
+        let (cond_expr, body) = match stmt {
+            Statement::While(cond_expr, body) => (cond_expr, body),
+            _ => return Err(Error::Unknown),
+        };
+        let start_label = self.assembler.new_dynamic_label();
+        let end_label = self.assembler.new_dynamic_label();
+        dynasm!(self.assembler; =>start_label);
+        self.asm_jump_unless(cond_expr, end_label)?;
+        self.loop_labels.push((start_label, end_label));
+        let result = self.compile_block(body);
+        self.loop_labels.pop();
+        result?;
+        dynasm!(self.assembler
+            ; jmp =>start_label
+            ; =>end_label
+        );
+        Ok(())
+    }
+
+    fn compile_stmt_cond(&mut self, stmt: &Statement) -> Result<()> {
+        let (expr, cond_body, cond_chain, else_body) = match stmt {
+            Statement::Condition {
+                expr,
+                cond_body,
+                cond_chain,
+                else_body,
+            } => (expr, cond_body, cond_chain, else_body),
+            _ => return Err(Error::Unknown),
+        };
+        self.compile_branches(expr, cond_body, cond_chain, else_body)
+    }
+
+    /// Compiles the branches of a condition, of which the first one whose
+    /// condition is true runs, or else the else branch
+    fn compile_branches(
+        &mut self,
+        expr: &Expression,
+        cond_body: &[AstItem<Statement>],
+        cond_chain: &[(Expression, Vec<AstItem<Statement>>)],
+        else_body: &[AstItem<Statement>],
+    ) -> Result<()> {
+        let end_label = self.assembler.new_dynamic_label();
+        let branches = iter::once((expr, cond_body))
+            .chain(cond_chain.iter().map(|(cond_expr, body)| (cond_expr, body.as_slice())));
+        for (cond_expr, body) in branches {
+            let next_label = self.assembler.new_dynamic_label();
+            self.asm_jump_unless(cond_expr, next_label)?;
+            self.compile_block(body)?;
+            dynasm!(self.assembler
+                ; jmp =>end_label
+                ; =>next_label
+            );
+        }
+        self.compile_block(else_body)?;
+        dynasm!(self.assembler; =>end_label);
+        Ok(())
+    }
+
+    /// Compiles `yield`, which gives the enclosing condition expression its
+    /// value and leaves it
+    fn compile_stmt_yield(&mut self, stmt: &Statement) -> Result<()> {
+        let expr_opt = match stmt {
+            Statement::Yield(expr_opt) => expr_opt,
+            _ => return Err(Error::Unknown),
+        };
+        let (value_pos, value_type, end_label) = self
+            .cond_exprs
+            .last()
+            .cloned()
+            .ok_or(Error::Unimplemented("Coroutines"))?;
+        match expr_opt {
+            Some(expr) => {
+                let expr_type = self.get_expr_type(expr)?;
+                if expr_type != value_type {
+                    return Err(Error::TypeMismatch(value_type, expr_type));
+                }
+                let pos = self.get_stack_pos()?;
+                self.compile_expr(expr)?;
+                let size = self.get_size_of_type(&expr_type)?;
+                self.asm_stack_copy(pos, value_pos, size)?;
+                self.reset_stack(pos)?;
+            }
+            None if value_type != Type::Void => return Err(Error::ExpectedReturnExpression),
+            None => {}
+        };
+        dynasm!(self.assembler; jmp =>end_label);
+        Ok(())
+    }
+
+    fn compile_stmt_loop_jump(&mut self, stmt: &Statement) -> Result<()> {
+        let (continue_label, break_label) = *self
+            .loop_labels
+            .last()
+            .ok_or(Error::LoopJumpOutsideLoop)?;
+        match stmt {
+            Statement::Break => dynasm!(self.assembler; jmp =>break_label),
+            Statement::Continue => dynasm!(self.assembler; jmp =>continue_label),
+            _ => return Err(Error::Unknown),
+        };
+        Ok(())
     }
 
     pub fn compile_expr(&mut self, expr: &Expression) -> Result<()> {
@@ -357,8 +794,7 @@ impl Compiler {
         let expr_size = self.get_size_of_type(&expr_type)?;
         match expr {
             Expression::Variable(var_name) => {
-                let var_pos = self.get_var_position(var_name)?;
-                let var_type = self.get_var_type(var_name)?;
+                let (var_pos, var_type) = self.get_var(var_name)?;
                 let var_size = self.get_size_of_type(&var_type)?;
                 let target_pos = self.get_stack_pos()?;
                 self.inc_stack(var_size as isize)?;
@@ -367,24 +803,46 @@ impl Compiler {
             Expression::IntLiteral(int_val) => {
                 let pos = self.get_stack_pos()?;
                 self.inc_stack(8)?;
-                dynasm!(&mut self.assembler
+                dynasm!(self.assembler
                     ; mov r11, QWORD *int_val
-                    ; mov QWORD [rbp - pos], r11
+                    ; mov QWORD [rbp + slot(pos, 8)], r11
                 );
             }
             Expression::FloatLiteral(float_val) => {
-                let float_int: i32 = unsafe { std::mem::transmute(*float_val) };
+                let float_int = float_val.to_bits() as i32;
                 let pos = self.get_stack_pos()?;
                 self.inc_stack(4)?;
-                dynasm!(&mut self.assembler
-                    ; mov DWORD [rbp - pos], DWORD float_int
+                dynasm!(self.assembler
+                    ; mov DWORD [rbp + slot(pos, 4)], DWORD float_int
+                );
+            }
+            Expression::BoolLiteral(bool_val) => {
+                let pos = self.get_stack_pos()?;
+                self.inc_stack(1)?;
+                dynasm!(self.assembler
+                    ; mov BYTE [rbp + slot(pos, 1)], *bool_val as i8
                 );
             }
+            Expression::StringLiteral(string) => {
+                let index = self.context.add_string(string);
+                let pos = self.get_stack_pos()?;
+                self.inc_stack(8)?;
+                dynasm!(self.assembler
+                    ; mov r11, QWORD index as i64
+                    ; mov QWORD [rbp + slot(pos, 8)], r11
+                );
+            }
+            Expression::Condition { .. } => self.compile_expr_cond(expr)?,
             Expression::Call(fn_name, fn_args) => self.compile_expr_call(fn_name, fn_args)?,
+            Expression::Cast(cast_expr, cast_type) => self.compile_expr_cast(cast_expr, cast_type)?,
             Expression::Unary(op, op_expr) => {
                 match op {
                     Operator::Ref => self.compile_expr_ref(op_expr)?,
-                    _ => return Err(Error::Unknown),
+                    Operator::Deref => self.compile_expr_deref(op_expr)?,
+                    Operator::Neg | Operator::Pos | Operator::Not => {
+                        self.compile_expr_unary(op, op_expr)?
+                    }
+                    _ => return Err(Error::UnsupportedOperator(op.clone())),
                 };
             }
             Expression::Binary(lhs_expr, op, rhs_expr) => {
@@ -393,10 +851,28 @@ impl Compiler {
                     Operator::Minus => self.compile_expr_sub(lhs_expr, rhs_expr)?,
                     Operator::Times => self.compile_expr_mul(lhs_expr, rhs_expr)?,
                     Operator::Divide => self.compile_expr_div(lhs_expr, rhs_expr)?,
-                    _ => return Err(Error::Unknown),
+                    Operator::LessThan
+                    | Operator::GreaterThan
+                    | Operator::LessThanEquals
+                    | Operator::GreaterThanEquals
+                    | Operator::Equals
+                    | Operator::NotEquals => self.compile_expr_cmp(lhs_expr, op, rhs_expr)?,
+                    Operator::Assign => self.compile_expr_assign(lhs_expr, rhs_expr)?,
+                    Operator::AddAssign => {
+                        self.compile_expr_op_assign(lhs_expr, Operator::Plus, rhs_expr)?
+                    }
+                    Operator::SubAssign => {
+                        self.compile_expr_op_assign(lhs_expr, Operator::Minus, rhs_expr)?
+                    }
+                    Operator::MulAssign => {
+                        self.compile_expr_op_assign(lhs_expr, Operator::Times, rhs_expr)?
+                    }
+                    Operator::DivAssign => {
+                        self.compile_expr_op_assign(lhs_expr, Operator::Divide, rhs_expr)?
+                    }
+                    _ => return Err(Error::UnsupportedOperator(op.clone())),
                 };
             }
-            _ => return Err(Error::Unknown),
         };
         let end_pos = self.get_stack_pos()?;
         if end_pos - start_pos > (expr_size as i32) {
@@ -409,228 +885,843 @@ impl Compiler {
         Ok(())
     }
 
-    fn compile_expr_call(&mut self, _fn_name: &String, _fn_args: &[Expression]) -> Result<()> {
+    /// Compiles a condition expression, whose value is given by the `yield`
+    /// of the branch taken. The value is zero if that branch doesn't yield.
+    /// Its type is the one of the last `yield`, which can't use variables
+    /// declared in its branch.
+    fn compile_expr_cond(&mut self, expr: &Expression) -> Result<()> {
+        let (cond_expr, cond_body, cond_chain, else_body) = match expr {
+            Expression::Condition {
+                expr,
+                cond_body,
+                cond_chain,
+                else_body,
+                ..
+            } => (expr, cond_body, cond_chain, else_body),
+            _ => return Err(Error::Unknown),
+        };
+        let value_type = self.get_expr_type(expr)?;
+        let size = self.get_size_of_type(&value_type)?;
+        let value_pos = self.get_stack_pos()?;
+        self.inc_stack(size as isize)?;
+        if size > 0 {
+            dynasm!(self.assembler; xor eax, eax);
+            self.asm_store_int(0, slot(value_pos, size), size)?;
+        }
+        let end_label = self.assembler.new_dynamic_label();
+        self.cond_exprs.push((value_pos, value_type, end_label));
+        let result = self.compile_branches(cond_expr, cond_body, cond_chain, else_body);
+        self.cond_exprs.pop();
+        result?;
+        dynasm!(self.assembler; =>end_label);
         Ok(())
     }
 
-    fn compile_expr_ref(&mut self, _expr: &Expression) -> Result<()> {
+    fn compile_expr_call(&mut self, fn_name: &str, fn_args: &[Expression]) -> Result<()> {
+        match fn_name {
+            "panic" => return self.compile_expr_panic(fn_args),
+            "assert" => return self.compile_expr_assert(fn_args),
+            _ => {}
+        };
+        let fn_def = self
+            .resolve_fn(fn_name)
+            .map_err(|_| Error::UnknownFunction(String::from(fn_name)))?
+            .clone();
+        if fn_args.len() != fn_def.arguments.len() {
+            return Err(Error::ArgumentCount(String::from(fn_name), fn_args.len()));
+        }
+        let args_pos = self.get_stack_pos()?;
+        let mut arg_positions = Vec::with_capacity(fn_args.len());
+        for (arg_expr, (_, arg_type)) in fn_args.iter().zip(fn_def.arguments.iter()) {
+            let expr_type = self.get_expr_type(arg_expr)?;
+            if expr_type != *arg_type {
+                return Err(Error::TypeMismatch(arg_type.clone(), expr_type));
+            }
+            arg_positions.push(self.get_stack_pos()?);
+            self.compile_expr(arg_expr)?;
+        }
+
+        let uid = fn_def.label_uid;
+        if self.foreign_functions.contains_key(&uid) {
+            return self.asm_host_call(uid, &fn_def.returns, args_pos);
+        }
+
+        let arg_types = fn_def.arguments.iter().map(|(_, arg_type)| arg_type);
+        let locations = get_arg_locations(arg_types.clone())?;
+        let stack_arg_count = locations
+            .iter()
+            .filter(|location| matches!(location, ArgLocation::Stack(_)))
+            .count();
+        // Padded to keep `rsp` aligned to 16 bytes
+        let stack_args_size = ((stack_arg_count * 8 + 15) & !15) as i32;
+        if stack_args_size > 0 {
+            dynasm!(self.assembler; sub rsp, stack_args_size);
+        }
+        for ((arg_type, arg_pos), location) in arg_types.zip(arg_positions).zip(locations) {
+            let arg_size = self.get_size_of_type(arg_type)?;
+            let offset = slot(arg_pos, arg_size);
+            match location {
+                ArgLocation::Int(reg) => self.asm_load_int(reg, offset, arg_size)?,
+                ArgLocation::Float(reg) => self.asm_load_float(reg, offset, arg_size)?,
+                ArgLocation::Stack(index) => {
+                    // Neither `rax` nor `r11` pass arguments
+                    self.asm_load_int(0, offset, arg_size)?;
+                    dynasm!(self.assembler
+                        ; mov QWORD [rsp + index * 8], rax
+                    );
+                }
+            };
+        }
+        let fn_label = self.get_dynamic_label(&uid)?;
+        dynasm!(self.assembler; call =>fn_label);
+        if stack_args_size > 0 {
+            dynasm!(self.assembler; add rsp, stack_args_size);
+        }
+
+        // The return value replaces the arguments
+        self.reset_stack(args_pos)?;
+        match fn_def.returns {
+            Type::Void => Ok(()),
+            ref ret_type => match get_arg_class(ret_type)? {
+                ArgClass::Int => self.asm_push_int(0, self.get_size_of_type(ret_type)?),
+                ArgClass::Float => self.asm_push_xmm0(self.get_size_of_type(ret_type)?),
+            },
+        }
+    }
+
+    /// Calls a host function through `host::host_call`, passing it the end
+    /// of the arguments on the stack and a slot for the return value.
+    /// Unwinds if the call fails.
+    fn asm_host_call(&mut self, uid: u64, ret_type: &Type, args_pos: i32) -> Result<()> {
+        let ret_pos = self.get_stack_pos()?;
+        self.inc_stack(8)?;
+        let context = self.get_context_ptr();
+        let unwind = self.get_entry_stub().unwind;
+        dynasm!(self.assembler
+            ; mov rdi, QWORD context
+            ; mov rsi, QWORD uid as i64
+            ; lea rdx, [rbp - args_pos]
+            ; lea rcx, [rbp + slot(ret_pos, 8)]
+            ; mov rax, QWORD host::host_call as *const () as i64
+            ; call rax
+            ; test rax, rax
+            ; jnz =>unwind
+        );
+        // The return value is in the low bytes of its slot
+        let ret_size = self.get_size_of_type(ret_type)?;
+        self.asm_stack_copy(ret_pos + 8 - ret_size as i32, args_pos, ret_size)?;
+        self.reset_stack(args_pos)?;
+        self.inc_stack(ret_size as isize)
+    }
+
+    /// Compiles `panic(message)`, stopping the script with the message
+    fn compile_expr_panic(&mut self, fn_args: &[Expression]) -> Result<()> {
+        let message = match fn_args {
+            [message] => self.get_panic_message(message)?,
+            _ => return Err(Error::ArgumentCount(String::from("panic"), fn_args.len())),
+        };
+        self.asm_trap(RuntimeError::ScriptPanic(message));
         Ok(())
     }
 
-    fn compile_expr_add(&mut self, lhs_expr: &Expression, rhs_expr: &Expression) -> Result<()> {
-        let expr_type = self.get_expr_type(lhs_expr)?;
-        match expr_type {
-            Type::Int => {
-                let lhs_pos = self.get_stack_pos()?;
-                self.compile_expr(lhs_expr)?;
-                let rhs_pos = self.get_stack_pos()?;
-                self.compile_expr(rhs_expr)?;
-                let pos = self.get_stack_pos()?;
-                dynasm!(&mut self.assembler
-                    ; mov rax, QWORD [rbp - lhs_pos]
-                    ; mov rbx, QWORD [rbp - rhs_pos]
-                    ; add rax, rbx
-                );
-                self.inc_stack(8)?;
-                dynasm!(&mut self.assembler
-                    ; mov QWORD [rbp - pos], rax
-                );
+    /// Compiles `assert(condition, message)`, stopping the script with the
+    /// message if the condition is false
+    fn compile_expr_assert(&mut self, fn_args: &[Expression]) -> Result<()> {
+        let (condition, message) = match fn_args {
+            [condition, message] => (condition, self.get_panic_message(message)?),
+            _ => return Err(Error::ArgumentCount(String::from("assert"), fn_args.len())),
+        };
+        let passed_label = self.assembler.new_dynamic_label();
+        self.asm_jump_if(condition, passed_label)?;
+        self.asm_trap(RuntimeError::ScriptPanic(message));
+        dynasm!(self.assembler; =>passed_label);
+        Ok(())
+    }
+
+    /// Returns the message of a `panic` or `assert`, which has to be a
+    /// string literal
+    fn get_panic_message(&mut self, message: &Expression) -> Result<String> {
+        match message {
+            Expression::StringLiteral(message) => Ok(message.clone()),
+            _ => {
+                let str_type = Type::Ref(Box::new(Type::Str));
+                let message_type = self.get_expr_type(message)?;
+                if message_type != str_type {
+                    return Err(Error::TypeMismatch(str_type, message_type));
+                }
+                Err(Error::Unimplemented("Panic message from a non-literal string"))
             }
-            Type::Float => {
-                let lhs_pos = self.get_stack_pos()?;
-                self.compile_expr(lhs_expr)?;
-                let rhs_pos = self.get_stack_pos()?;
-                self.compile_expr(rhs_expr)?;
-                let pos = self.get_stack_pos()?;
-                dynasm!(&mut self.assembler
-                    ; movss xmm0, DWORD [rbp - lhs_pos]
-                    ; movss xmm1, DWORD [rbp - rhs_pos]
-                    ; addss xmm0, xmm1
+        }
+    }
+
+    /// Compiles a cast like the VM does. Integers are extended to 64 bits
+    /// before and truncated to the size of the target type after, so integer
+    /// casts wrap. Floats saturate at the 64 bit integer bounds.
+    fn compile_expr_cast(&mut self, expr: &Expression, cast_type: &Type) -> Result<()> {
+        let expr_type = self.get_expr_type(expr)?;
+        let is_numeric = |var_type: &Type| var_type.is_integer() || var_type.is_float();
+        if expr_type != *cast_type
+            && (!(is_numeric(&expr_type) || expr_type == Type::Bool) || !is_numeric(cast_type))
+        {
+            return Err(Error::InvalidCast(expr_type, cast_type.clone()));
+        }
+        let pos = self.get_stack_pos()?;
+        self.compile_expr(expr)?;
+        if expr_type == *cast_type {
+            return Ok(());
+        }
+        let from_size = self.get_size_of_type(&expr_type)?;
+        let to_size = self.get_size_of_type(cast_type)?;
+        let offset = slot(pos, from_size);
+        match (&expr_type, cast_type) {
+            (Type::Float, Type::F64) => dynasm!(self.assembler
+                ; cvtss2sd xmm0, DWORD [rbp + offset]
+            ),
+            (Type::F64, Type::Float) => dynasm!(self.assembler
+                ; cvtsd2ss xmm0, QWORD [rbp + offset]
+            ),
+            (from, to) if from.is_float() => {
+                self.asm_load_float(0, offset, from_size)?;
+                let convert = match (from, to.is_signed()) {
+                    (Type::Float, true) => convert::f32_to_i64 as *const (),
+                    (Type::Float, false) => convert::f32_to_u64 as *const (),
+                    (_, true) => convert::f64_to_i64 as *const (),
+                    (_, false) => convert::f64_to_u64 as *const (),
+                };
+                self.asm_call_ptr(convert);
+            }
+            (from, to) => {
+                self.asm_load_extended(0, offset, from)?;
+                match (to, from.is_signed()) {
+                    (Type::Float, true) => dynasm!(self.assembler; cvtsi2ss xmm0, rax),
+                    (Type::F64, true) => dynasm!(self.assembler; cvtsi2sd xmm0, rax),
+                    (Type::Float, false) => {
+                        dynasm!(self.assembler; mov rdi, rax);
+                        self.asm_call_ptr(convert::u64_to_f32 as *const ());
+                    }
+                    (Type::F64, false) => {
+                        dynasm!(self.assembler; mov rdi, rax);
+                        self.asm_call_ptr(convert::u64_to_f64 as *const ());
+                    }
+                    _ => {}
+                };
+            }
+        };
+        self.reset_stack(pos)?;
+        if cast_type.is_float() {
+            self.asm_push_xmm0(to_size)
+        } else {
+            self.asm_push_int(0, to_size)
+        }
+    }
+
+    fn compile_expr_ref(&mut self, expr: &Expression) -> Result<()> {
+        let var_name = match expr {
+            Expression::Variable(var_name) => var_name,
+            _ => return Err(Error::Unimplemented("References to values other than variables")),
+        };
+        let (var_pos, var_type) = self.get_var(var_name)?;
+        let var_size = self.get_size_of_type(&var_type)?;
+        dynasm!(self.assembler
+            ; lea rax, [rbp + slot(var_pos, var_size)]
+        );
+        self.asm_push_int(0, 8)
+    }
+
+    fn compile_expr_deref(&mut self, expr: &Expression) -> Result<()> {
+        let value_type = match self.get_expr_type(expr)? {
+            Type::Ref(value_type) => *value_type,
+            expr_type => return Err(Error::UnsupportedOperands(Operator::Deref, expr_type)),
+        };
+        let value_size = self.get_size_of_type(&value_type)?;
+        let ptr_pos = self.get_stack_pos()?;
+        self.compile_expr(expr)?;
+        let pos = self.get_stack_pos()?;
+        self.inc_stack(value_size as isize)?;
+        dynasm!(self.assembler
+            ; mov rax, QWORD [rbp + slot(ptr_pos, 8)]
+        );
+        let offset = slot(pos, value_size);
+        match value_size {
+            8 => dynasm!(self.assembler
+                ; mov r11, QWORD [rax]
+                ; mov QWORD [rbp + offset], r11
+            ),
+            4 => dynasm!(self.assembler
+                ; mov r11d, DWORD [rax]
+                ; mov DWORD [rbp + offset], r11d
+            ),
+            2 => dynasm!(self.assembler
+                ; mov r11w, WORD [rax]
+                ; mov WORD [rbp + offset], r11w
+            ),
+            1 => dynasm!(self.assembler
+                ; mov r11b, BYTE [rax]
+                ; mov BYTE [rbp + offset], r11b
+            ),
+            _ => return Err(Error::UnknownType(value_type)),
+        };
+        Ok(())
+    }
+
+    fn compile_expr_unary(&mut self, op: &Operator, expr: &Expression) -> Result<()> {
+        let expr_type = self.get_expr_type(expr)?;
+        let pos = self.get_stack_pos()?;
+        self.compile_expr(expr)?;
+        let size = self.get_size_of_type(&expr_type)?;
+        let offset = slot(pos, size);
+        match (op, &expr_type) {
+            (Operator::Pos, _) if expr_type.is_integer() || expr_type.is_float() => {}
+            (Operator::Neg, _) if expr_type.is_signed() => {
+                self.asm_load_extended(0, offset, &expr_type)?;
+                dynasm!(self.assembler; neg rax);
+                if expr_type == Type::Int {
+                    self.asm_trap_overflow()?;
+                }
+                self.asm_check_narrow(&expr_type)?;
+                self.reset_stack(pos)?;
+                self.asm_push_int(0, size)?;
+            }
+            // The sign is the highest bit of the highest byte
+            (Operator::Neg, _) if expr_type.is_float() => {
+                dynasm!(self.assembler
+                    ; xor BYTE [rbp + offset + size as i32 - 1], 0x80_u8 as i8
                 );
-                self.inc_stack(4)?;
-                dynasm!(&mut self.assembler
-                    ; movss DWORD [rbp - pos], xmm0
+            }
+            (Operator::Not, Type::Bool) => {
+                dynasm!(self.assembler
+                    ; xor BYTE [rbp + offset], 1
                 );
             }
-            _ => return Err(Error::Unknown),
+            _ => return Err(Error::UnsupportedOperands(op.clone(), expr_type)),
         };
         Ok(())
     }
 
-    fn compile_expr_sub(&mut self, _lhs_expr: &Expression, _rhs_expr: &Expression) -> Result<()> {
-        Ok(())
+    /// Compiles both operands of a binary operator, returning their type and
+    /// their offsets from `rbp`
+    fn compile_operands(
+        &mut self,
+        lhs_expr: &Expression,
+        rhs_expr: &Expression,
+    ) -> Result<(Type, i32, i32)> {
+        let operand_type = self.get_expr_type(lhs_expr)?;
+        let size = self.get_size_of_type(&operand_type)?;
+        let lhs_pos = self.get_stack_pos()?;
+        self.compile_expr(lhs_expr)?;
+        let rhs_pos = self.get_stack_pos()?;
+        self.compile_expr(rhs_expr)?;
+        Ok((operand_type, slot(lhs_pos, size), slot(rhs_pos, size)))
+    }
+
+    fn compile_expr_add(&mut self, lhs_expr: &Expression, rhs_expr: &Expression) -> Result<()> {
+        self.compile_expr_arith(Operator::Plus, lhs_expr, rhs_expr)
+    }
+
+    fn compile_expr_sub(&mut self, lhs_expr: &Expression, rhs_expr: &Expression) -> Result<()> {
+        self.compile_expr_arith(Operator::Minus, lhs_expr, rhs_expr)
     }
 
     fn compile_expr_mul(&mut self, lhs_expr: &Expression, rhs_expr: &Expression) -> Result<()> {
-        let expr_type = self.get_expr_type(lhs_expr)?;
-        match expr_type {
-            Type::Int => {
-                let lhs_pos = self.get_stack_pos()?;
-                self.compile_expr(lhs_expr)?;
-                let rhs_pos = self.get_stack_pos()?;
-                self.compile_expr(rhs_expr)?;
-                let pos = self.get_stack_pos()?;
-                dynasm!(&mut self.assembler
-                    ; mov rax, QWORD [rbp - lhs_pos]
-                    ; mov rbx, QWORD [rbp - rhs_pos]
-                    ; mul rbx
+        self.compile_expr_arith(Operator::Times, lhs_expr, rhs_expr)
+    }
+
+    fn compile_expr_div(&mut self, lhs_expr: &Expression, rhs_expr: &Expression) -> Result<()> {
+        self.compile_expr_arith(Operator::Divide, lhs_expr, rhs_expr)
+    }
+
+    /// Compiles a binary arithmetic expression. Sized integers are computed
+    /// on 64 bits and fit back into their size after, which wraps or traps
+    /// like 64 bit arithmetic does, as in the VM.
+    fn compile_expr_arith(
+        &mut self,
+        op: Operator,
+        lhs_expr: &Expression,
+        rhs_expr: &Expression,
+    ) -> Result<()> {
+        let (operand_type, lhs, rhs) = self.compile_operands(lhs_expr, rhs_expr)?;
+        let size = self.get_size_of_type(&operand_type)?;
+        if operand_type.is_float() {
+            self.asm_load_float(0, lhs, size)?;
+            match (&op, size) {
+                (Operator::Plus, 4) => dynasm!(self.assembler; addss xmm0, DWORD [rbp + rhs]),
+                (Operator::Minus, 4) => dynasm!(self.assembler; subss xmm0, DWORD [rbp + rhs]),
+                (Operator::Times, 4) => dynasm!(self.assembler; mulss xmm0, DWORD [rbp + rhs]),
+                (_, 4) => dynasm!(self.assembler; divss xmm0, DWORD [rbp + rhs]),
+                (Operator::Plus, _) => dynasm!(self.assembler; addsd xmm0, QWORD [rbp + rhs]),
+                (Operator::Minus, _) => dynasm!(self.assembler; subsd xmm0, QWORD [rbp + rhs]),
+                (Operator::Times, _) => dynasm!(self.assembler; mulsd xmm0, QWORD [rbp + rhs]),
+                (_, _) => dynasm!(self.assembler; divsd xmm0, QWORD [rbp + rhs]),
+            };
+            return self.asm_push_xmm0(size);
+        }
+        if !operand_type.is_integer() {
+            return Err(Error::UnsupportedOperands(op, operand_type));
+        }
+        self.asm_load_extended(0, lhs, &operand_type)?;
+        self.asm_load_extended(1, rhs, &operand_type)?;
+        let signed = operand_type.is_signed();
+        match op {
+            Operator::Plus => dynasm!(self.assembler; add rax, rcx),
+            Operator::Minus => dynasm!(self.assembler; sub rax, rcx),
+            Operator::Times if signed => dynasm!(self.assembler; imul rax, rcx),
+            // Sets the overflow flag if the high half in `rdx` isn't zero
+            Operator::Times => dynasm!(self.assembler; mul rcx),
+            _ => return self.asm_div(&operand_type),
+        };
+        // Only 64 bit operands can overflow before they are narrowed
+        match (&operand_type, &op) {
+            (Type::Int, _) | (Type::U64, Operator::Times) => self.asm_trap_overflow()?,
+            (Type::U64, _) => self.asm_trap_carry()?,
+            _ => self.asm_check_narrow(&operand_type)?,
+        };
+        self.asm_push_int(0, size)
+    }
+
+    /// Divides `rax` by `rcx` as integers of the type and pushes the result
+    fn asm_div(&mut self, var_type: &Type) -> Result<()> {
+        dynasm!(self.assembler
+            ; test rcx, rcx
+            ; jnz >nonzero
+        );
+        self.asm_trap(RuntimeError::DivisionByZero);
+        dynasm!(self.assembler; nonzero:);
+        if var_type.is_signed() {
+            // `idiv` faults on `i64::MIN / -1`, which negating wraps or traps
+            dynasm!(self.assembler
+                ; cmp rcx, -1
+                ; jne >divide
+                ; neg rax
+            );
+            if *var_type == Type::Int {
+                self.asm_trap_overflow()?;
+            }
+            dynasm!(self.assembler
+                ; jmp >done
+                ; divide:
+                ; cqo
+                ; idiv rcx
+                ; done:
+            );
+        } else {
+            dynasm!(self.assembler
+                ; xor edx, edx
+                ; div rcx
+            );
+        }
+        self.asm_check_narrow(var_type)?;
+        self.asm_push_int(0, self.get_size_of_type(var_type)?)
+    }
+
+    /// Traps with `RuntimeError::IntegerOverflow` if the last operation set
+    /// the overflow flag and overflows trap
+    fn asm_trap_overflow(&mut self) -> Result<()> {
+        if self.overflow_mode == OverflowMode::Trap {
+            dynasm!(self.assembler; jno >no_overflow);
+            self.asm_trap(RuntimeError::IntegerOverflow);
+            dynasm!(self.assembler; no_overflow:);
+        }
+        Ok(())
+    }
+
+    /// Traps with `RuntimeError::IntegerOverflow` if the last operation set
+    /// the carry flag, i.e. unsigned overflow, and overflows trap
+    fn asm_trap_carry(&mut self) -> Result<()> {
+        if self.overflow_mode == OverflowMode::Trap {
+            dynasm!(self.assembler; jnc >no_carry);
+            self.asm_trap(RuntimeError::IntegerOverflow);
+            dynasm!(self.assembler; no_carry:);
+        }
+        Ok(())
+    }
+
+    /// Traps with `RuntimeError::IntegerOverflow` if the value in `rax`
+    /// doesn't fit into the sized integer type and overflows trap. Storing
+    /// the low bytes wraps otherwise.
+    fn asm_check_narrow(&mut self, var_type: &Type) -> Result<()> {
+        if self.overflow_mode == OverflowMode::Wrap {
+            return Ok(());
+        }
+        match var_type {
+            Type::I8 => dynasm!(self.assembler; movsx r11, al),
+            Type::I16 => dynasm!(self.assembler; movsx r11, ax),
+            Type::I32 => dynasm!(self.assembler; movsxd r11, eax),
+            Type::U8 => dynasm!(self.assembler; movzx r11d, al),
+            Type::U16 => dynasm!(self.assembler; movzx r11d, ax),
+            Type::U32 => dynasm!(self.assembler; mov r11d, eax),
+            _ => return Ok(()),
+        };
+        dynasm!(self.assembler
+            ; cmp r11, rax
+            ; je >fits
+        );
+        self.asm_trap(RuntimeError::IntegerOverflow);
+        dynasm!(self.assembler; fits:);
+        Ok(())
+    }
+
+    /// Compiles a comparison to a bool. Comparisons of floats are false if
+    /// either is NaN, except for `!=`.
+    fn compile_expr_cmp(
+        &mut self,
+        lhs_expr: &Expression,
+        op: &Operator,
+        rhs_expr: &Expression,
+    ) -> Result<()> {
+        let (operand_type, lhs, rhs) = self.compile_operands(lhs_expr, rhs_expr)?;
+        let size = self.get_size_of_type(&operand_type)?;
+        match (&operand_type, op) {
+            (_, _) if operand_type.is_integer() => {
+                self.asm_load_extended(0, lhs, &operand_type)?;
+                self.asm_load_extended(1, rhs, &operand_type)?;
+                dynasm!(self.assembler; cmp rax, rcx);
+            }
+            (Type::Ref(_), Operator::Equals | Operator::NotEquals) => {
+                dynasm!(self.assembler
+                    ; mov rax, QWORD [rbp + lhs]
+                    ; cmp rax, QWORD [rbp + rhs]
                 );
-                self.inc_stack(8)?;
-                dynasm!(&mut self.assembler
-                    ; mov QWORD [rbp - pos], rdx
+            }
+            (Type::Bool, Operator::Equals | Operator::NotEquals) => {
+                dynasm!(self.assembler
+                    ; mov al, BYTE [rbp + lhs]
+                    ; cmp al, BYTE [rbp + rhs]
                 );
             }
-            Type::Float => {
-                let lhs_pos = self.get_stack_pos()?;
-                self.compile_expr(lhs_expr)?;
-                let rhs_pos = self.get_stack_pos()?;
+            // Swapped, so unordered operands compare as false
+            (_, Operator::LessThan | Operator::LessThanEquals) if operand_type.is_float() => {
+                self.asm_float_cmp(rhs, lhs, size)?;
+            }
+            (_, _) if operand_type.is_float() => self.asm_float_cmp(lhs, rhs, size)?,
+            _ => return Err(Error::UnsupportedOperands(op.clone(), operand_type)),
+        };
+        let unsigned = operand_type.is_integer() && !operand_type.is_signed();
+        match (&operand_type, op) {
+            (_, Operator::Equals) if operand_type.is_float() => dynasm!(self.assembler
+                ; sete al
+                ; setnp cl
+                ; and al, cl
+            ),
+            (_, Operator::NotEquals) if operand_type.is_float() => dynasm!(self.assembler
+                ; setne al
+                ; setp cl
+                ; or al, cl
+            ),
+            (_, Operator::LessThan | Operator::GreaterThan) if operand_type.is_float() => {
+                dynasm!(self.assembler; seta al)
+            }
+            (_, _) if operand_type.is_float() => dynasm!(self.assembler; setae al),
+            (_, Operator::Equals) => dynasm!(self.assembler; sete al),
+            (_, Operator::NotEquals) => dynasm!(self.assembler; setne al),
+            (_, Operator::LessThan) if unsigned => dynasm!(self.assembler; setb al),
+            (_, Operator::GreaterThan) if unsigned => dynasm!(self.assembler; seta al),
+            (_, Operator::LessThanEquals) if unsigned => dynasm!(self.assembler; setbe al),
+            (_, _) if unsigned => dynasm!(self.assembler; setae al),
+            (_, Operator::LessThan) => dynasm!(self.assembler; setl al),
+            (_, Operator::GreaterThan) => dynasm!(self.assembler; setg al),
+            (_, Operator::LessThanEquals) => dynasm!(self.assembler; setle al),
+            (_, _) => dynasm!(self.assembler; setge al),
+        };
+        self.asm_push_int(0, 1)
+    }
+
+    /// Compares the floats at the offsets from `rbp`, setting the flags like
+    /// an unsigned comparison
+    fn asm_float_cmp(&mut self, lhs: i32, rhs: i32, size: usize) -> Result<()> {
+        self.asm_load_float(0, lhs, size)?;
+        match size {
+            4 => dynasm!(self.assembler; ucomiss xmm0, DWORD [rbp + rhs]),
+            _ => dynasm!(self.assembler; ucomisd xmm0, QWORD [rbp + rhs]),
+        };
+        Ok(())
+    }
+
+    /// Compiles an assignment to a variable or through a reference
+    fn compile_expr_assign(&mut self, lhs_expr: &Expression, rhs_expr: &Expression) -> Result<()> {
+        let value_type = self.get_expr_type(rhs_expr)?;
+        let size = self.get_size_of_type(&value_type)?;
+        match lhs_expr {
+            Expression::Variable(var_name) => {
+                let (var_pos, _) = self.get_var(var_name)?;
+                let pos = self.get_stack_pos()?;
                 self.compile_expr(rhs_expr)?;
+                self.asm_stack_copy(pos, var_pos, size)
+            }
+            Expression::Unary(Operator::Deref, ptr_expr) => {
+                let ptr_pos = self.get_stack_pos()?;
+                self.compile_expr(ptr_expr)?;
                 let pos = self.get_stack_pos()?;
-                dynasm!(&mut self.assembler
-                    ; movss xmm0, DWORD [rbp - lhs_pos]
-                    ; movss xmm1, DWORD [rbp - rhs_pos]
-                    ; mulss xmm0, xmm1
-                );
-                self.inc_stack(4)?;
-                dynasm!(&mut self.assembler
-                    ; movss DWORD [rbp - pos], xmm0
+                self.compile_expr(rhs_expr)?;
+                let offset = slot(pos, size);
+                dynasm!(self.assembler
+                    ; mov rax, QWORD [rbp + slot(ptr_pos, 8)]
                 );
+                match size {
+                    8 => dynasm!(self.assembler
+                        ; mov r11, QWORD [rbp + offset]
+                        ; mov QWORD [rax], r11
+                    ),
+                    4 => dynasm!(self.assembler
+                        ; mov r11d, DWORD [rbp + offset]
+                        ; mov DWORD [rax], r11d
+                    ),
+                    2 => dynasm!(self.assembler
+                        ; mov r11w, WORD [rbp + offset]
+                        ; mov WORD [rax], r11w
+                    ),
+                    1 => dynasm!(self.assembler
+                        ; mov r11b, BYTE [rbp + offset]
+                        ; mov BYTE [rax], r11b
+                    ),
+                    _ => return Err(Error::UnknownType(value_type)),
+                };
+                Ok(())
             }
-            _ => return Err(Error::Unknown),
+            _ => Err(Error::Unimplemented("Assignments to values other than variables")),
+        }
+    }
+
+    /// Compiles an assignment like `+=` as the assignment of the operation
+    fn compile_expr_op_assign(
+        &mut self,
+        lhs_expr: &Expression,
+        op: Operator,
+        rhs_expr: &Expression,
+    ) -> Result<()> {
+        let value_expr = Expression::Binary(
+            Box::new(lhs_expr.clone()),
+            op,
+            Box::new(rhs_expr.clone()),
+        );
+        self.compile_expr_assign(lhs_expr, &value_expr)
+    }
+
+    /// Jumps to the label if the bool expression is true
+    fn asm_jump_if(&mut self, cond_expr: &Expression, label: DynamicLabel) -> Result<()> {
+        let pos = self.asm_condition(cond_expr)?;
+        dynasm!(self.assembler; jne =>label);
+        self.reset_stack(pos)
+    }
+
+    /// Jumps to the label if the bool expression is false
+    fn asm_jump_unless(&mut self, cond_expr: &Expression, label: DynamicLabel) -> Result<()> {
+        let pos = self.asm_condition(cond_expr)?;
+        dynasm!(self.assembler; je =>label);
+        self.reset_stack(pos)
+    }
+
+    /// Compiles a bool expression and compares it to false, returning its
+    /// stack position
+    fn asm_condition(&mut self, cond_expr: &Expression) -> Result<i32> {
+        let cond_type = self.get_expr_type(cond_expr)?;
+        if cond_type != Type::Bool {
+            return Err(Error::TypeMismatch(Type::Bool, cond_type));
+        }
+        let pos = self.get_stack_pos()?;
+        self.compile_expr(cond_expr)?;
+        dynasm!(self.assembler
+            ; cmp BYTE [rbp + slot(pos, 1)], 0
+        );
+        Ok(pos)
+    }
+
+    /// Records the error in the host context and unwinds to the entry stub
+    fn asm_trap(&mut self, error: RuntimeError) {
+        let code = self.context.add_trap(error);
+        let context = self.get_context_ptr();
+        let unwind = self.get_entry_stub().unwind;
+        dynasm!(self.assembler
+            ; mov rdi, QWORD context
+            ; mov rsi, QWORD code as i64
+            ; mov rax, QWORD host::trap as *const () as i64
+            ; call rax
+            ; jmp =>unwind
+        );
+    }
+
+    /// Counts the call in the host context, trapping with
+    /// `RuntimeError::CallStackOverflow` if it nests too deep. Keeps the
+    /// argument registers.
+    fn asm_enter_call(&mut self) {
+        let context = self.get_context_ptr();
+        dynasm!(self.assembler
+            ; mov r11, QWORD context
+            ; mov rax, QWORD [r11 + 8]
+            ; inc rax
+            ; cmp rax, QWORD [r11 + 16]
+            ; jbe >depth_ok
+        );
+        self.asm_trap(RuntimeError::CallStackOverflow);
+        dynasm!(self.assembler
+            ; depth_ok:
+            ; mov QWORD [r11 + 8], rax
+        );
+    }
+
+    /// Returns from the function, which is no longer counted as running
+    fn asm_epilogue(&mut self) {
+        let context = self.get_context_ptr();
+        dynasm!(self.assembler
+            ; mov r11, QWORD context
+            ; dec QWORD [r11 + 8]
+            ; mov rsp, rbp
+            ; pop rbp
+            ; ret
+        );
+    }
+
+    /// Pushes the low bytes of an integer register
+    fn asm_push_int(&mut self, reg: u8, size: usize) -> Result<()> {
+        let pos = self.get_stack_pos()?;
+        self.inc_stack(size as isize)?;
+        self.asm_store_int(reg, slot(pos, size), size)
+    }
+
+    /// Pushes the float in `xmm0`, of 4 or 8 bytes
+    fn asm_push_xmm0(&mut self, size: usize) -> Result<()> {
+        let pos = self.get_stack_pos()?;
+        self.inc_stack(size as isize)?;
+        self.asm_store_float(0, slot(pos, size), size)
+    }
+
+    /// Loads the value at the offset from `rbp` into an integer register,
+    /// zero extending smaller values
+    fn asm_load_int(&mut self, reg: u8, offset: i32, size: usize) -> Result<()> {
+        match size {
+            8 => dynasm!(self.assembler
+                ; mov Rq(reg), QWORD [rbp + offset]
+            ),
+            4 => dynasm!(self.assembler
+                ; mov Rd(reg), DWORD [rbp + offset]
+            ),
+            2 => dynasm!(self.assembler
+                ; movzx Rd(reg), WORD [rbp + offset]
+            ),
+            1 => dynasm!(self.assembler
+                ; movzx r11d, BYTE [rbp + offset]
+                ; mov Rq(reg), r11
+            ),
+            _ => return Err(Error::Unimplemented("Integer values of this size")),
         };
         Ok(())
     }
 
-    fn compile_expr_div(&mut self, _lhs_expr: &Expression, _rhs_expr: &Expression) -> Result<()> {
+    /// Loads an integer of the type at the offset from `rbp` into a register,
+    /// extending it to 64 bits by its sign
+    fn asm_load_extended(&mut self, reg: u8, offset: i32, var_type: &Type) -> Result<()> {
+        match var_type {
+            Type::I32 => dynasm!(self.assembler; movsxd Rq(reg), DWORD [rbp + offset]),
+            Type::I16 => dynasm!(self.assembler; movsx Rq(reg), WORD [rbp + offset]),
+            Type::I8 => dynasm!(self.assembler; movsx Rq(reg), BYTE [rbp + offset]),
+            _ => return self.asm_load_int(reg, offset, self.get_size_of_type(var_type)?),
+        };
         Ok(())
     }
 
-    fn asm_stack_ret(&mut self, stack_ctx_uid: u64) -> Result<()> {
-        let mut pop_size = 0;
-        for stack_ctx in self.stack_ctx_stack.iter() {
-            pop_size += stack_ctx.stack_extent;
-            if stack_ctx.uid == stack_ctx_uid {
-                break;
-            }
-        }
-        self.dec_stack(pop_size as isize)?;
+    fn asm_store_int(&mut self, reg: u8, offset: i32, size: usize) -> Result<()> {
+        match size {
+            8 => dynasm!(self.assembler
+                ; mov QWORD [rbp + offset], Rq(reg)
+            ),
+            4 => dynasm!(self.assembler
+                ; mov DWORD [rbp + offset], Rd(reg)
+            ),
+            2 => dynasm!(self.assembler
+                ; mov WORD [rbp + offset], Rw(reg)
+            ),
+            1 => dynasm!(self.assembler
+                ; mov r11, Rq(reg)
+                ; mov BYTE [rbp + offset], r11b
+            ),
+            _ => return Err(Error::Unimplemented("Integer values of this size")),
+        };
+        Ok(())
+    }
+
+    /// Loads the float of 4 or 8 bytes at the offset from `rbp` into an SSE
+    /// register
+    fn asm_load_float(&mut self, reg: u8, offset: i32, size: usize) -> Result<()> {
+        match size {
+            4 => dynasm!(self.assembler; movss Rx(reg), DWORD [rbp + offset]),
+            8 => dynasm!(self.assembler; movsd Rx(reg), QWORD [rbp + offset]),
+            _ => return Err(Error::Unimplemented("Float values of this size")),
+        };
+        Ok(())
+    }
+
+    fn asm_store_float(&mut self, reg: u8, offset: i32, size: usize) -> Result<()> {
+        match size {
+            4 => dynasm!(self.assembler; movss DWORD [rbp + offset], Rx(reg)),
+            8 => dynasm!(self.assembler; movsd QWORD [rbp + offset], Rx(reg)),
+            _ => return Err(Error::Unimplemented("Float values of this size")),
+        };
+        Ok(())
+    }
+
+    /// Calls a function of the host taking and returning values in registers
+    fn asm_call_ptr(&mut self, function: *const ()) {
         dynasm!(self.assembler
-            ; add rsp, DWORD pop_size
+            ; mov r11, QWORD function as i64
+            ; call r11
         );
-        Ok(())
     }
 
+    /// Stores the arguments of a function in stack slots of its frame and
+    /// declares them as variables
     fn asm_args_to_stack(&mut self, fn_args: &[(String, Type)]) -> Result<()> {
-        let arg_size: isize = fn_args
-            .iter()
-            .map(|(_, arg_type)| self.get_size_of_type(arg_type).unwrap() as isize)
-            .sum();
-        self.inc_stack(arg_size)?;
-        let mut offset = 0;
-        let mut int_reg_ctr = 0;
-        let mut float_reg_ctr = 0;
-        for (arg_name, arg_type) in fn_args.iter() {
-            let _arg_size = self.get_size_of_type(arg_type)?;
-            match arg_type {
-                Type::Int => {
-                    match int_reg_ctr {
-                        0 => dynasm!(&mut self.assembler
-                            ; mov QWORD [rbp - offset as _], rdi
-                        ),
-                        1 => dynasm!(&mut self.assembler
-                            ; mov QWORD [rbp - offset as _], rsi
-                        ),
-                        2 => dynasm!(&mut self.assembler
-                            ; mov QWORD [rbp - offset as _], rdx
-                        ),
-                        3 => dynasm!(&mut self.assembler
-                            ; mov QWORD [rbp - offset as _], rcx
-                        ),
-                        _ => return Err(Error::Unknown),
-                    };
-                    int_reg_ctr += 1;
-                    self.get_stack_ctx()?.set_var(offset, arg_name, arg_type);
-                    offset += 8;
-                }
-                Type::Float => {
-                    match float_reg_ctr {
-                        0 => dynasm!(&mut self.assembler
-                            ; movss DWORD [rbp - offset as _], xmm0
-                        ),
-                        1 => dynasm!(&mut self.assembler
-                            ; movss DWORD [rbp - offset as _], xmm1
-                        ),
-                        2 => dynasm!(&mut self.assembler
-                            ; movss DWORD [rbp - offset as _], xmm2
-                        ),
-                        3 => dynasm!(&mut self.assembler
-                            ; movss DWORD [rbp - offset as _], xmm3
-                        ),
-                        _ => return Err(Error::Unknown),
-                    };
-                    float_reg_ctr += 1;
-                    self.get_stack_ctx()?.set_var(offset, arg_name, arg_type);
-                    offset += 4;
+        let locations = get_arg_locations(fn_args.iter().map(|(_, arg_type)| arg_type))?;
+        for ((arg_name, arg_type), location) in fn_args.iter().zip(locations) {
+            let arg_size = self.get_size_of_type(arg_type)?;
+            let pos = self.get_stack_pos()?;
+            self.inc_stack(arg_size as isize)?;
+            let offset = slot(pos, arg_size);
+            match location {
+                ArgLocation::Int(reg) => self.asm_store_int(reg, offset, arg_size)?,
+                ArgLocation::Float(reg) => self.asm_store_float(reg, offset, arg_size)?,
+                // Above the saved `rbp` and the return address
+                ArgLocation::Stack(index) => {
+                    self.asm_load_int(0, 16 + index * 8, arg_size)?;
+                    self.asm_store_int(0, offset, arg_size)?;
                 }
-                _ => return Err(Error::Unimplemented("NOT IMPLEMENTED")),
             };
+            self.get_stack_ctx()?.set_var(pos, arg_name, arg_type);
         }
         Ok(())
     }
 
-    fn asm_stack_copy(&mut self, from_offset: i32, to_offset: i32, n: usize) -> Result<()> {
-        let qword_copies = n / 8;
-        let qword_rest = n % 8;
-        let dword_copies = qword_rest / 4;
-        let dword_rest = qword_rest % 4;
-        let word_copies = dword_rest / 2;
-        let word_rest = dword_rest % 2;
-        let byte_copies = word_rest;
+    /// Copies `n` bytes between stack positions. The copy starts at the
+    /// highest address, so a value can be moved up over its own bytes.
+    fn asm_stack_copy(&mut self, from_pos: i32, to_pos: i32, n: usize) -> Result<()> {
+        let mut chunks = Vec::new();
         let mut offset = 0;
-        for _ in 0..qword_copies {
-            let pos = from_offset + offset;
-            let target_pos = to_offset + offset;
-            dynasm!(&mut self.assembler
-                ; mov r11, QWORD [rbp - pos as _]
-                ; mov QWORD [rbp - target_pos as _], r11
-            );
-            offset += 8;
-        }
-
-        if offset > to_offset {
-            return Err(Error::Unknown);
-        }
-
-        for _ in 0..dword_copies {
-            let pos = from_offset + offset;
-            let target_pos = to_offset + offset;
-            dynasm!(&mut self.assembler
-                ; mov r11d, DWORD [rbp - pos as _]
-                ; mov DWORD [rbp - target_pos as _], r11d
-            );
-            offset += 4;
-        }
-
-        for _ in 0..word_copies {
-            let pos = from_offset + offset;
-            let target_pos = to_offset + offset;
-            dynasm!(&mut self.assembler
-                ; mov r11w, WORD [rbp - pos as _]
-                ; mov WORD [rbp - target_pos as _], r11w
-            );
-            offset += 2;
+        for chunk_size in [8, 4, 2, 1] {
+            while n - offset >= chunk_size {
+                chunks.push((offset as i32, chunk_size));
+                offset += chunk_size;
+            }
         }
-
-        for _ in 0..byte_copies {
-            let pos = from_offset + offset;
-            let target_pos = to_offset + offset;
-            dynasm!(&mut self.assembler
-                ; mov r11b, BYTE [rbp - pos as _]
-                ; mov BYTE [rbp - target_pos as _], r11b
-            );
-            offset += 1;
+        for (offset, chunk_size) in chunks.into_iter().rev() {
+            let from = slot(from_pos, n) + offset;
+            let to = slot(to_pos, n) + offset;
+            match chunk_size {
+                8 => dynasm!(self.assembler
+                    ; mov r11, QWORD [rbp + from]
+                    ; mov QWORD [rbp + to], r11
+                ),
+                4 => dynasm!(self.assembler
+                    ; mov r11d, DWORD [rbp + from]
+                    ; mov DWORD [rbp + to], r11d
+                ),
+                2 => dynasm!(self.assembler
+                    ; mov r11w, WORD [rbp + from]
+                    ; mov WORD [rbp + to], r11w
+                ),
+                _ => dynasm!(self.assembler
+                    ; mov r11b, BYTE [rbp + from]
+                    ; mov BYTE [rbp + to], r11b
+                ),
+            };
         }
-
         Ok(())
     }
 
@@ -639,8 +1730,18 @@ impl Compiler {
             Expression::IntLiteral(_) => Type::Int,
             Expression::BoolLiteral(_) => Type::Bool,
             Expression::FloatLiteral(_) => Type::Float,
-            Expression::Variable(var_name) => self.get_var_type(var_name)?,
+            Expression::Variable(var_name) => self.get_var(var_name)?.1,
             Expression::StringLiteral(_) => Type::Ref(Box::new(Type::Str)),
+            Expression::Call(fn_name, _) if fn_name == "panic" || fn_name == "assert" => Type::Void,
+            Expression::Call(fn_name, _) if matches!(fn_name.as_str(), "spawn" | "resume" | "done") => {
+                return Err(Error::Unimplemented("Coroutines"))
+            }
+            Expression::Call(fn_name, _) => self
+                .resolve_fn(fn_name)
+                .map_err(|_| Error::UnknownFunction(fn_name.clone()))?
+                .returns
+                .clone(),
+            Expression::Cast(_, cast_type) => cast_type.clone(),
             Expression::Unary(op, op_expr) => match op {
                 Operator::Not => Type::Bool,
                 Operator::Ref => {
@@ -651,55 +1752,63 @@ impl Compiler {
                     let expr_type = self.get_expr_type(op_expr)?;
                     match expr_type {
                         Type::Ref(ret) => *ret,
-                        _ => return Err(Error::Unknown),
+                        _ => return Err(Error::UnsupportedOperands(Operator::Deref, expr_type)),
                     }
                 }
-                _ => {
-                    let op_type = self.get_expr_type(op_expr)?;
-                    op_type
-                }
+                _ => self.get_expr_type(op_expr)?,
             },
-            Expression::Binary(lhs_expr, _op, rhs_expr) => {
+            Expression::Binary(lhs_expr, op, rhs_expr) => {
                 let lhs_type = self.get_expr_type(lhs_expr)?;
                 let rhs_type = self.get_expr_type(rhs_expr)?;
                 if rhs_type != lhs_type {
                     return Err(Error::TypeMismatch(lhs_type, rhs_type));
                 }
-                lhs_type
+                match op {
+                    Operator::LessThan
+                    | Operator::GreaterThan
+                    | Operator::LessThanEquals
+                    | Operator::GreaterThanEquals
+                    | Operator::Equals
+                    | Operator::NotEquals => Type::Bool,
+                    Operator::Assign
+                    | Operator::AddAssign
+                    | Operator::SubAssign
+                    | Operator::MulAssign
+                    | Operator::DivAssign => Type::Void,
+                    _ => lhs_type,
+                }
             }
-            _ => return Err(Error::Unknown),
+            Expression::Condition { yield_expr, .. } => match yield_expr {
+                Some(yield_expr) => self.get_expr_type(yield_expr)?,
+                None => Type::Void,
+            },
         };
         Ok(ret)
     }
 
-    fn build_fn_map(
-        &self,
-        mod_def: &ModuleDef,
-        fn_map: &mut HashMap<String, AssemblyOffset>,
-    ) -> Result<()> {
-        for (uid, name) in mod_def.get_function_list()? {
-            println!("Fn: {}", name);
-            let dyn_label = self.get_dynamic_label_raw(&uid)?;
+    /// Collects the offsets and signatures of the compiled script functions
+    /// of the module and its submodules
+    fn build_fn_map(&mut self, mod_def: &ModuleDef) -> Result<()> {
+        for fn_def in mod_def.functions.values() {
+            if self.foreign_functions.contains_key(&fn_def.label_uid) {
+                continue;
+            }
+            // Functions declared without a body have no label unless called
+            let dyn_label = match self.label_map.get(&fn_def.label_uid) {
+                Some(dyn_label) => *dyn_label,
+                None => continue,
+            };
             let asm_offset = self
                 .assembler
                 .labels()
                 .resolve_dynamic(dyn_label)
-                .map_err(|_| Error::Unknown)?;
-            fn_map.insert(name, asm_offset);
+                .map_err(|_| Error::UnknownFunction(fn_def.canon_name.clone()))?;
+            self.function_map.insert(fn_def.canon_name.clone(), asm_offset);
+            self.function_defs.insert(fn_def.canon_name.clone(), fn_def.clone());
         }
         for (_, mod_def) in mod_def.modules.iter() {
-            self.build_fn_map(mod_def, fn_map)?;
+            self.build_fn_map(mod_def)?;
         }
         Ok(())
     }
-
-    pub fn get_output(&mut self) -> Result<Output> {
-        let mut fn_map = HashMap::new();
-        let mod_def = self.get_current_module()?;
-        self.build_fn_map(mod_def, &mut fn_map)?;
-        let mut assembler = Assembler::new().map_err(|_| Error::Unknown)?;
-        std::mem::swap(&mut self.assembler, &mut assembler);
-        let buffer = assembler.finalize().unwrap();
-        Ok(Output::new(fn_map, buffer))
-    }
 }
@@ -8,7 +8,10 @@ use std::{
     result::Result as StdResult,
 };
 
-use mess_core::parser::ast::Type;
+use mess_core::parser::ast::{
+    Operator,
+    Type,
+};
 
 pub type Result<T> = StdResult<T, Error>;
 
@@ -21,6 +24,23 @@ pub enum Error {
     UnsupportedDeclaration,
     ExpectedReturnExpression,
     RegisterMapping,
+    /// No function with the given name is declared
+    UnknownFunction(String),
+    /// No variable with the given name is in scope
+    UnknownVariable(String),
+    /// The named function was called with the given, wrong number of arguments
+    ArgumentCount(String, usize),
+    /// `break` or `continue` was used outside of a loop
+    LoopJumpOutsideLoop,
+    /// The operator can't be applied to values of the type
+    UnsupportedOperands(Operator, Type),
+    /// The operator can't be used in the position it was found in, like
+    /// `!` between two operands
+    UnsupportedOperator(Operator),
+    /// Values of the first type can't be cast to the second
+    InvalidCast(Type, Type),
+    /// No executable memory could be allocated for the compiled code
+    ExecutableMemory,
 }
 
 impl Display for Error {
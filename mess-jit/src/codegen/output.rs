@@ -1,30 +1,237 @@
-use std::collections::HashMap;
+use std::{
+    collections::BTreeMap,
+    mem,
+    result::Result as StdResult,
+};
 
 use dynasmrt::{
     AssemblyOffset,
     ExecutableBuffer,
 };
+use mess_core::{
+    artifact::Artifact,
+    codegen::def::FunctionDef,
+    parser::ast::Type,
+};
 
-use crate::codegen::error::{
-    Error,
-    Result,
+use crate::{
+    codegen::error::{
+        Error,
+        Result,
+    },
+    exec::{
+        function::{
+            JitArgs,
+            JitFn,
+            JitReturn,
+        },
+        host::HostContext,
+        runtime::RuntimeError,
+    },
 };
 
+/// The registers a function returns its value in, `rax` and `xmm0`
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct RawReturn {
+    pub(crate) int: u64,
+    pub(crate) float: u64,
+}
+
+/// The stub compiled code is entered through. It loads the argument
+/// registers, pushes the arguments passed on the stack, calls the target and
+/// returns both return registers.
+type EntryFn = extern "sysv64" fn(
+    context: *const HostContext,
+    target: *const u8,
+    int_args: *const u64,
+    float_args: *const u64,
+    stack_args: *const u64,
+    stack_arg_count: u64,
+) -> RawReturn;
+
+/// The number of integer argument registers, `rdi`, `rsi`, `rdx`, `rcx`,
+/// `r8` and `r9`
+pub(crate) const INT_ARG_COUNT: usize = 6;
+
+/// The number of float argument registers, `xmm0` to `xmm7`
+pub(crate) const FLOAT_ARG_COUNT: usize = 8;
+
+/// Compiled machine code with the offsets of its functions, by their
+/// canonical names, e.g. `root::main`
 pub struct Output {
     buffer: ExecutableBuffer,
-    function_map: HashMap<String, AssemblyOffset>,
+    function_map: BTreeMap<String, AssemblyOffset>,
+    function_defs: BTreeMap<String, FunctionDef>,
+    entry: AssemblyOffset,
+    context: Box<HostContext>,
 }
 
+impl Artifact for Output {}
+
 impl Output {
-    pub fn new(function_map: HashMap<String, AssemblyOffset>, buffer: ExecutableBuffer) -> Self {
+    pub(crate) fn new(
+        buffer: ExecutableBuffer,
+        function_map: BTreeMap<String, AssemblyOffset>,
+        function_defs: BTreeMap<String, FunctionDef>,
+        entry: AssemblyOffset,
+        context: Box<HostContext>,
+    ) -> Self {
         Self {
             buffer,
             function_map,
+            function_defs,
+            entry,
+            context,
         }
     }
 
+    /// Returns the canonical name of a function, which functions of the root
+    /// module can also be looked up without
+    fn resolve_name<'o>(&'o self, fn_name: &'o str) -> Option<&'o str> {
+        if self.function_map.contains_key(fn_name) {
+            return Some(fn_name);
+        }
+        self.function_map
+            .get_key_value(&format!("root::{}", fn_name))
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Returns the address of the compiled function. Errors of the script
+    /// unwind to the entry stub, so calling the address directly is only
+    /// sound for code that can't fail, see [`Output::get_fn`] instead.
     pub fn get_ptr(&self, fn_name: &str) -> Result<*const u8> {
-        let asm_offset = self.function_map.get(fn_name).ok_or(Error::Unknown)?;
-        Ok((&self.buffer[asm_offset.0]) as _)
+        let name = self.resolve_name(fn_name).ok_or(Error::Unknown)?;
+        Ok(self.buffer.ptr(self.function_map[name]))
+    }
+
+    /// Limits how many compiled functions can run nested, calls beyond it
+    /// stop the script with `RuntimeError::CallStackOverflow`. Nesting is
+    /// unlimited by default.
+    pub fn set_max_call_depth(&self, max_call_depth: Option<usize>) {
+        self.context.set_max_call_depth(max_call_depth);
+    }
+
+    /// Returns how many compiled functions can run nested, if limited
+    pub fn get_max_call_depth(&self) -> Option<usize> {
+        self.context.get_max_call_depth()
+    }
+
+    /// Returns the signature of the compiled function
+    pub fn get_function_def(&self, fn_name: &str) -> Option<&FunctionDef> {
+        self.resolve_name(fn_name)
+            .and_then(|name| self.function_defs.get(name))
+    }
+
+    /// Returns the compiled function for calls from Rust, after checking
+    /// that it takes the arguments `A` and returns `R`, e.g.
+    /// `output.get_fn::<(i64, f32), f32>("scale")`
+    pub fn get_fn<A: JitArgs, R: JitReturn>(
+        &self,
+        fn_name: &str,
+    ) -> StdResult<JitFn<'_, A, R>, RuntimeError> {
+        let name = self
+            .resolve_name(fn_name)
+            .ok_or_else(|| RuntimeError::UnknownFunctionName(String::from(fn_name)))?;
+        let def = &self.function_defs[name];
+        let arg_types = A::get_types();
+        let ret_type = R::get_type();
+        let args_match = def.arguments.iter().map(|(_, arg_type)| arg_type).eq(arg_types.iter());
+        if !args_match || def.returns != ret_type {
+            return Err(RuntimeError::SignatureMismatch(String::from(fn_name), arg_types, ret_type));
+        }
+        Ok(JitFn::new(self, String::from(name)))
+    }
+
+    /// Calls a function with the bits of its arguments, in the order of the
+    /// signature, returning the bits of its return value. Meant for callers
+    /// that only know the signature at runtime, see [`Output::get_fn`]
+    /// otherwise. Functions taking references can't be called this way.
+    pub fn call_dynamic(&self, fn_name: &str, args: &[u64]) -> StdResult<u64, RuntimeError> {
+        let def = self
+            .get_function_def(fn_name)
+            .ok_or_else(|| RuntimeError::UnknownFunctionName(String::from(fn_name)))?;
+        if def.arguments.len() != args.len() {
+            return Err(RuntimeError::ArgumentCount(String::from(fn_name), args.len()));
+        }
+        let mut int_args = Vec::new();
+        let mut float_args = Vec::new();
+        for ((_, arg_type), bits) in def.arguments.iter().zip(args) {
+            match arg_type {
+                Type::Float | Type::F64 => float_args.push(*bits),
+                // The bits could point anywhere
+                Type::Ref(_) => return Err(RuntimeError::RefArgument(String::from(fn_name))),
+                _ => int_args.push(*bits),
+            }
+        }
+        let ret = self.call_raw(fn_name, &int_args, &float_args)?;
+        // Only the low bytes of the registers hold smaller values
+        Ok(match def.returns {
+            Type::Void => 0,
+            Type::Float => ret.float as u32 as u64,
+            Type::F64 => ret.float,
+            Type::Bool | Type::I8 | Type::U8 => ret.int as u8 as u64,
+            Type::I16 | Type::U16 => ret.int as u16 as u64,
+            Type::I32 | Type::U32 => ret.int as u32 as u64,
+            _ => ret.int,
+        })
+    }
+
+    /// Calls a function with the given values of its integer and float
+    /// arguments, each in the order of the signature. Errors of the script
+    /// unwind to here.
+    pub(crate) fn call_raw(
+        &self,
+        fn_name: &str,
+        int_args: &[u64],
+        float_args: &[u64],
+    ) -> StdResult<RawReturn, RuntimeError> {
+        let name = self
+            .resolve_name(fn_name)
+            .ok_or_else(|| RuntimeError::UnknownFunctionName(String::from(fn_name)))?;
+        let arg_count = int_args.len() + float_args.len();
+        let arg_types = &self.function_defs[name].arguments;
+        let float_arg_count = arg_types
+            .iter()
+            .filter(|(_, arg_type)| matches!(arg_type, Type::Float | Type::F64))
+            .count();
+        if arg_types.len() != arg_count || float_args.len() != float_arg_count {
+            return Err(RuntimeError::ArgumentCount(String::from(fn_name), arg_count));
+        }
+        // Arguments that don't fit into registers go on the stack, in the
+        // order of the signature
+        let mut int_regs = [0; INT_ARG_COUNT];
+        let mut float_regs = [0; FLOAT_ARG_COUNT];
+        let mut stack_args = Vec::new();
+        let mut int_args = int_args.iter().copied().enumerate();
+        let mut float_args = float_args.iter().copied().enumerate();
+        for (_, arg_type) in arg_types {
+            let (regs, arg): (&mut [u64], _) = match arg_type {
+                Type::Float | Type::F64 => (&mut float_regs, float_args.next()),
+                _ => (&mut int_regs, int_args.next()),
+            };
+            let (index, bits) =
+                arg.ok_or_else(|| RuntimeError::ArgumentCount(String::from(fn_name), arg_count))?;
+            match regs.get_mut(index) {
+                Some(reg) => *reg = bits,
+                None => stack_args.push(bits),
+            }
+        }
+
+        let target = self.buffer.ptr(self.function_map[name]);
+        // The entry stub was compiled with the functions, for this signature
+        let entry: EntryFn = unsafe { mem::transmute(self.buffer.ptr(self.entry)) };
+        let ret = entry(
+            &*self.context,
+            target,
+            int_regs.as_ptr(),
+            float_regs.as_ptr(),
+            stack_args.as_ptr(),
+            stack_args.len() as u64,
+        );
+        match self.context.take_error() {
+            Some(err) => Err(err),
+            None => Ok(ret),
+        }
     }
 }
@@ -0,0 +1,36 @@
+//! Conversions compiled casts call, where the machine instructions differ
+//! from Rust's `as`, which the VM uses
+
+/// Converts a float to a signed integer, saturating at its bounds and
+/// converting NaN to 0
+pub(crate) extern "sysv64" fn f32_to_i64(value: f32) -> i64 {
+    value as i64
+}
+
+/// Converts a float to an unsigned integer, saturating at its bounds and
+/// converting NaN to 0
+pub(crate) extern "sysv64" fn f32_to_u64(value: f32) -> u64 {
+    value as u64
+}
+
+/// Converts a double to a signed integer, saturating at its bounds and
+/// converting NaN to 0
+pub(crate) extern "sysv64" fn f64_to_i64(value: f64) -> i64 {
+    value as i64
+}
+
+/// Converts a double to an unsigned integer, saturating at its bounds and
+/// converting NaN to 0
+pub(crate) extern "sysv64" fn f64_to_u64(value: f64) -> u64 {
+    value as u64
+}
+
+/// Converts an unsigned integer to the nearest float
+pub(crate) extern "sysv64" fn u64_to_f32(value: u64) -> f32 {
+    value as f32
+}
+
+/// Converts an unsigned integer to the nearest double
+pub(crate) extern "sysv64" fn u64_to_f64(value: u64) -> f64 {
+    value as f64
+}
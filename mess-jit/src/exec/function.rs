@@ -0,0 +1,207 @@
+//! Typed calls of compiled functions from the host
+
+use std::{
+    marker::PhantomData,
+    result::Result as StdResult,
+};
+
+use mess_core::parser::ast::Type;
+
+use super::runtime::RuntimeError;
+use crate::codegen::output::Output;
+
+/// A Rust type compiled code takes and returns in a single register
+pub trait JitValue: Sized {
+    /// Returns the script type the Rust type stands for
+    fn get_type() -> Type;
+
+    /// Returns the bits of the value, as passed in a register
+    fn to_bits(self) -> u64;
+
+    /// Reads the value from the bits of a register
+    fn from_bits(bits: u64) -> Self;
+}
+
+impl JitValue for i64 {
+    fn get_type() -> Type {
+        Type::Int
+    }
+
+    fn to_bits(self) -> u64 {
+        self as u64
+    }
+
+    fn from_bits(bits: u64) -> Self {
+        bits as i64
+    }
+}
+
+/// Implements `JitValue` for integers passed in the low bytes of a register
+macro_rules! impl_jit_int {
+    ($($int:ty => $var_type:ident),*) => {
+        $(
+            impl JitValue for $int {
+                fn get_type() -> Type {
+                    Type::$var_type
+                }
+
+                fn to_bits(self) -> u64 {
+                    self as u64
+                }
+
+                fn from_bits(bits: u64) -> Self {
+                    bits as $int
+                }
+            }
+        )*
+    };
+}
+
+impl_jit_int!(
+    u64 => U64,
+    i32 => I32,
+    u32 => U32,
+    i16 => I16,
+    u16 => U16,
+    i8 => I8,
+    u8 => U8
+);
+
+impl JitValue for f32 {
+    fn get_type() -> Type {
+        Type::Float
+    }
+
+    fn to_bits(self) -> u64 {
+        f32::to_bits(self) as u64
+    }
+
+    fn from_bits(bits: u64) -> Self {
+        f32::from_bits(bits as u32)
+    }
+}
+
+impl JitValue for f64 {
+    fn get_type() -> Type {
+        Type::F64
+    }
+
+    fn to_bits(self) -> u64 {
+        f64::to_bits(self)
+    }
+
+    fn from_bits(bits: u64) -> Self {
+        f64::from_bits(bits)
+    }
+}
+
+impl JitValue for bool {
+    fn get_type() -> Type {
+        Type::Bool
+    }
+
+    fn to_bits(self) -> u64 {
+        self as u64
+    }
+
+    // Only the low byte of the register holds the value
+    fn from_bits(bits: u64) -> Self {
+        bits as u8 != 0
+    }
+}
+
+/// A tuple of the arguments of a compiled function
+pub trait JitArgs {
+    /// Returns the script types of the arguments
+    fn get_types() -> Vec<Type>;
+
+    /// Returns the bits of the arguments, in order
+    fn to_bits(self) -> Vec<u64>;
+}
+
+macro_rules! impl_jit_args {
+    ($($arg:ident),*) => {
+        impl<$($arg: JitValue),*> JitArgs for ($($arg,)*) {
+            fn get_types() -> Vec<Type> {
+                vec![$($arg::get_type()),*]
+            }
+
+            #[allow(non_snake_case)]
+            fn to_bits(self) -> Vec<u64> {
+                let ($($arg,)*) = self;
+                vec![$($arg.to_bits()),*]
+            }
+        }
+    };
+}
+
+impl_jit_args!();
+impl_jit_args!(A);
+impl_jit_args!(A, B);
+impl_jit_args!(A, B, C);
+impl_jit_args!(A, B, C, D);
+impl_jit_args!(A, B, C, D, E);
+impl_jit_args!(A, B, C, D, E, F);
+
+/// The return type of a compiled function, `()` for functions returning
+/// nothing
+pub trait JitReturn: Sized {
+    /// Returns the script type the Rust type stands for
+    fn get_type() -> Type;
+
+    /// Reads the value from the integer and float return registers
+    fn from_registers(int: u64, float: u64) -> Self;
+}
+
+impl JitReturn for () {
+    fn get_type() -> Type {
+        Type::Void
+    }
+
+    fn from_registers(_: u64, _: u64) -> Self {}
+}
+
+impl<T: JitValue> JitReturn for T {
+    fn get_type() -> Type {
+        T::get_type()
+    }
+
+    fn from_registers(int: u64, float: u64) -> Self {
+        match T::get_type() {
+            Type::Float | Type::F64 => T::from_bits(float),
+            _ => T::from_bits(int),
+        }
+    }
+}
+
+/// A compiled function whose signature was checked to match `fn(A) -> R`,
+/// see [`Output::get_fn`]
+pub struct JitFn<'o, A, R> {
+    output: &'o Output,
+    fn_name: String,
+    signature: PhantomData<fn(A) -> R>,
+}
+
+impl<'o, A: JitArgs, R: JitReturn> JitFn<'o, A, R> {
+    pub(crate) fn new(output: &'o Output, fn_name: String) -> Self {
+        Self {
+            output,
+            fn_name,
+            signature: PhantomData,
+        }
+    }
+
+    /// Calls the function with a tuple of its arguments
+    pub fn call(&self, args: A) -> StdResult<R, RuntimeError> {
+        let mut int_args = Vec::new();
+        let mut float_args = Vec::new();
+        for (bits, arg_type) in args.to_bits().into_iter().zip(A::get_types()) {
+            match arg_type {
+                Type::Float | Type::F64 => float_args.push(bits),
+                _ => int_args.push(bits),
+            }
+        }
+        let ret = self.output.call_raw(&self.fn_name, &int_args, &float_args)?;
+        Ok(R::from_registers(ret.int, ret.float))
+    }
+}
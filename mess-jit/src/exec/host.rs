@@ -0,0 +1,240 @@
+//! Calls from compiled code back into the host
+
+use std::{
+    cell::{
+        Cell,
+        RefCell,
+    },
+    collections::BTreeMap,
+    panic::{
+        catch_unwind,
+        AssertUnwindSafe,
+    },
+    slice,
+};
+
+use mess_api::prelude::{
+    Adapter as ApiAdapter,
+    AdapterImpl,
+    ForeignError,
+    ForeignObject,
+    Function,
+    Type,
+};
+
+use super::runtime::RuntimeError;
+
+/// State shared by compiled code and the host. Compiled code embeds its
+/// address, so it is boxed and stays in place while the code can run.
+#[repr(C)]
+pub(crate) struct HostContext {
+    /// The stack pointer of the innermost entry stub, which unwinding returns
+    /// to. Has to be the first field, compiled code reads it at offset 0.
+    saved_rsp: Cell<u64>,
+    /// The number of running compiled functions, at offset 8
+    call_depth: Cell<u64>,
+    /// The number of compiled functions that can run nested before calls
+    /// trap, at offset 16
+    max_call_depth: Cell<u64>,
+    functions: BTreeMap<u64, Function>,
+    traps: Vec<RuntimeError>,
+    /// The string literals of the compiled code, which passes their indices
+    strings: Vec<String>,
+    foreign_objects: RefCell<BTreeMap<u64, ForeignObject>>,
+    next_foreign_ptr: Cell<u64>,
+    error: RefCell<Option<RuntimeError>>,
+}
+
+impl Default for HostContext {
+    fn default() -> Self {
+        Self {
+            saved_rsp: Cell::default(),
+            call_depth: Cell::default(),
+            max_call_depth: Cell::new(u64::MAX),
+            functions: BTreeMap::new(),
+            traps: Vec::new(),
+            strings: Vec::new(),
+            foreign_objects: RefCell::default(),
+            next_foreign_ptr: Cell::default(),
+            error: RefCell::default(),
+        }
+    }
+}
+
+impl HostContext {
+    /// Registers the error a trap stops the script with, returning the code
+    /// compiled code passes to `trap`
+    pub(crate) fn add_trap(&mut self, error: RuntimeError) -> u64 {
+        self.traps.push(error);
+        (self.traps.len() - 1) as u64
+    }
+
+    /// Registers a string literal, returning the value compiled code passes
+    /// for it
+    pub(crate) fn add_string(&mut self, string: &str) -> u64 {
+        self.strings.push(String::from(string));
+        (self.strings.len() - 1) as u64
+    }
+
+    pub(crate) fn set_max_call_depth(&self, max_call_depth: Option<usize>) {
+        self.max_call_depth
+            .set(max_call_depth.map_or(u64::MAX, |depth| depth as u64));
+    }
+
+    pub(crate) fn get_max_call_depth(&self) -> Option<usize> {
+        match self.max_call_depth.get() {
+            u64::MAX => None,
+            depth => Some(depth as usize),
+        }
+    }
+
+    pub(crate) fn set_functions(&mut self, functions: BTreeMap<u64, Function>) {
+        self.functions = functions;
+    }
+
+    pub(crate) fn take_error(&self) -> Option<RuntimeError> {
+        self.error.borrow_mut().take()
+    }
+
+    fn set_error(&self, error: RuntimeError) {
+        self.error.borrow_mut().get_or_insert(error);
+    }
+
+    fn call(&self, uid: u64, args_top: *const u8) -> Result<u64, RuntimeError> {
+        let function = self
+            .functions
+            .get(&uid)
+            .ok_or(RuntimeError::UnknownHostFunction(uid))?;
+        if function.is_async() {
+            return Err(RuntimeError::AsyncHostFunction(function.name.clone()));
+        }
+        let mut ret = [0; 8];
+        let adapter = Adapter {
+            context: self,
+            function,
+            args_top,
+            ret: &mut ret,
+        };
+        // Unwinding into compiled code is undefined, so panics end the call.
+        // Host functions can call compiled code calling host functions again,
+        // so the adapter only borrows the foreign objects while it uses them.
+        catch_unwind(AssertUnwindSafe(|| {
+            function.run(&mut ApiAdapter::new(adapter));
+        }))
+        .map_err(|_| RuntimeError::HostPanic(function.name.clone()))?;
+        match self.take_error() {
+            Some(err) => Err(err),
+            None => Ok(u64::from_le_bytes(ret)),
+        }
+    }
+}
+
+/// Called by compiled code for calls to host functions. The arguments end
+/// at `args_top`, the first one highest. Returns 0 after writing the return
+/// value to `ret`, or 1 after recording an error, upon which compiled code
+/// unwinds.
+pub(crate) extern "sysv64" fn host_call(
+    context: *const HostContext,
+    uid: u64,
+    args_top: *const u8,
+    ret: *mut u64,
+) -> u64 {
+    let context = unsafe { &*context };
+    match context.call(uid, args_top) {
+        Ok(value) => {
+            unsafe { ret.write_unaligned(value) };
+            0
+        }
+        Err(err) => {
+            context.set_error(err);
+            1
+        }
+    }
+}
+
+/// Called by compiled code before it unwinds, to record the error of the
+/// trap with the given code
+pub(crate) extern "sysv64" fn trap(context: *const HostContext, code: u64) {
+    let context = unsafe { &*context };
+    let error = context
+        .traps
+        .get(code as usize)
+        .cloned()
+        .unwrap_or(RuntimeError::UnknownTrap(code));
+    context.set_error(error);
+}
+
+/// Returns the size of a host function argument in compiled code
+fn get_size_of_type(var_type: &Type) -> usize {
+    match var_type {
+        Type::Void => 0,
+        Type::Bool | Type::I8 | Type::U8 => 1,
+        Type::I16 | Type::U16 => 2,
+        Type::Float | Type::I32 | Type::U32 => 4,
+        Type::Int | Type::U64 | Type::F64 => 8,
+        Type::Str | Type::Named(_) | Type::Ref(_) => 8,
+    }
+}
+
+/// Gives a host function access to the arguments compiled code passed, its
+/// return value and the foreign objects of the context
+struct Adapter<'h> {
+    context: &'h HostContext,
+    function: &'h Function,
+    args_top: *const u8,
+    ret: &'h mut [u8; 8],
+}
+
+impl<'h> AdapterImpl for Adapter<'h> {
+    fn ret(&mut self, bytes: &[u8]) {
+        if bytes.len() > self.ret.len() {
+            let name = self.function.name.clone();
+            self.context.set_error(RuntimeError::ReturnTooLarge(name));
+            return;
+        }
+        *self.ret = [0; 8];
+        self.ret[..bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn get_arg_bytes(&self, arg_index: usize) -> Vec<u8> {
+        let end: usize = self
+            .function
+            .args
+            .iter()
+            .take(arg_index + 1)
+            .map(get_size_of_type)
+            .sum();
+        let size = self.function.args.get(arg_index).map(get_size_of_type).unwrap_or(0);
+        // The compiler passed every argument of the signature
+        unsafe { slice::from_raw_parts(self.args_top.sub(end), size).to_vec() }
+    }
+
+    fn get_foreign_object(&self, ptr: u64) -> Result<ForeignObject, ForeignError> {
+        self.context
+            .foreign_objects
+            .borrow()
+            .get(&ptr)
+            .cloned()
+            .ok_or(ForeignError::InvalidHandle(ptr))
+    }
+
+    fn set_foreign_error(&self, err: ForeignError) {
+        self.context.set_error(match err {
+            ForeignError::InvalidHandle(ptr) => RuntimeError::InvalidHandle(ptr),
+            ForeignError::TypeMismatch { ptr, expected, found } => {
+                RuntimeError::ForeignTypeMismatch { ptr, expected, found }
+            }
+        });
+    }
+
+    fn get_str(&self, ptr: u64) -> Option<String> {
+        self.context.strings.get(ptr as usize).cloned()
+    }
+
+    fn insert_foreign_ptr(&mut self, object: ForeignObject) -> u64 {
+        let ptr = self.context.next_foreign_ptr.get() + 1;
+        self.context.next_foreign_ptr.set(ptr);
+        self.context.foreign_objects.borrow_mut().insert(ptr, object);
+        ptr
+    }
+}
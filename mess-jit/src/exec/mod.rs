@@ -1 +1,7 @@
+pub mod runtime;
 
+pub mod function;
+
+pub(crate) mod host;
+
+pub(crate) mod convert;
@@ -0,0 +1,117 @@
+//! Running compiled scripts and the errors stopping them
+
+use std::{
+    error::Error,
+    fmt::{
+        Display,
+        Formatter,
+        Result as FmtResult,
+    },
+};
+
+use mess_core::{
+    exec::Executor,
+    parser::ast::Type,
+};
+
+use crate::codegen::output::Output;
+
+/// Errors stopping compiled code, or keeping it from being called
+#[derive(Clone, PartialEq, Debug)]
+pub enum RuntimeError {
+    /// No output was loaded into the runtime
+    NoProgram,
+    /// No compiled function with the given name exists
+    UnknownFunctionName(String),
+    /// The function can't be called with the given number of arguments
+    ArgumentCount(String, usize),
+    /// The function doesn't take the given argument types or doesn't return
+    /// the given type
+    SignatureMismatch(String, Vec<Type>, Type),
+    /// The named function takes references, which the host can't pass
+    RefArgument(String),
+    /// An integer was divided by zero
+    DivisionByZero,
+    /// Integer arithmetic overflowed, compiled with `OverflowMode::Trap`
+    IntegerOverflow,
+    /// Compiled functions nested deeper than the call depth limit of the
+    /// output allows
+    CallStackOverflow,
+    /// A function returning a value ended without a return statement
+    NoReturnValue,
+    /// The script called `panic` or a failing `assert` with the message
+    ScriptPanic(String),
+    /// Compiled code trapped with a code the compiler didn't register
+    UnknownTrap(u64),
+    /// No host function with the given uid was registered
+    UnknownHostFunction(u64),
+    /// The named host function is async, which compiled code can't wait for
+    AsyncHostFunction(String),
+    /// A host function got a handle no foreign object exists for
+    InvalidHandle(u64),
+    /// A host function got a handle of a foreign object of another type
+    /// than it asked for
+    ForeignTypeMismatch {
+        /// The handle
+        ptr: u64,
+        /// The type the host function asked for
+        expected: &'static str,
+        /// The type of the object
+        found: &'static str,
+    },
+    /// The named host function panicked
+    HostPanic(String),
+    /// The named host function returned a value larger than 8 bytes
+    ReturnTooLarge(String),
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for RuntimeError {}
+
+/// Runs the functions of JIT compiled scripts
+#[derive(Default)]
+pub struct Runtime {
+    program: Option<Output>,
+}
+
+impl Runtime {
+    /// Creates a runtime without a program
+    pub fn new() -> Runtime {
+        Runtime::default()
+    }
+
+    /// Replaces the loaded program
+    pub fn load_program(&mut self, program: Output) {
+        self.program = Some(program);
+    }
+
+    /// Returns the loaded program
+    pub fn get_program(&self) -> Option<&Output> {
+        self.program.as_ref()
+    }
+}
+
+impl Executor for Runtime {
+    type Input = Output;
+    type Error = RuntimeError;
+
+    fn set_input(&mut self, input: Self::Input) {
+        self.load_program(input);
+    }
+
+    fn run(&mut self) -> Result<(), Self::Error> {
+        Executor::run_fn(self, "main")
+    }
+
+    /// Runs a function without arguments, discarding its return value
+    fn run_fn(&mut self, fn_name: &str) -> Result<(), Self::Error> {
+        let program = self.program.as_ref().ok_or(RuntimeError::NoProgram)?;
+        program.call_raw(fn_name, &[], &[])?;
+        Ok(())
+    }
+}
@@ -4,5 +4,8 @@ pub mod codegen;
 
 pub mod exec;
 
+pub use codegen::compiler::Compiler;
+pub use exec::runtime::Runtime;
+
 #[cfg(test)]
 mod test;
@@ -0,0 +1,163 @@
+use super::{
+    call_int,
+    compile,
+    Result,
+};
+
+#[test]
+fn test_while() -> Result {
+    let output = compile(
+        "
+        fun factorial(n: int) ~ int {
+            var acc = 1;
+            while n > 1 {
+                acc *= n;
+                n -= 1;
+            }
+            return acc;
+        }
+        ",
+    )?;
+    assert_eq!(call_int(&output, "factorial", &[1])?, 1);
+    assert_eq!(call_int(&output, "factorial", &[10])?, 3628800);
+    Ok(())
+}
+
+#[test]
+fn test_condition_chain() -> Result {
+    let output = compile(
+        "
+        fun sign(n: int) ~ int {
+            on n < 0 {
+                return -1;
+            } else on n == 0 {
+                return 0;
+            } else {
+                return 1;
+            }
+            return 2;
+        }
+        ",
+    )?;
+    assert_eq!(call_int(&output, "sign", &[-5])?, -1);
+    assert_eq!(call_int(&output, "sign", &[0])?, 0);
+    assert_eq!(call_int(&output, "sign", &[5])?, 1);
+    Ok(())
+}
+
+#[test]
+fn test_condition_expression() -> Result {
+    let output = compile(
+        "
+        fun clamp(n: int, max: int) ~ int {
+            var clamped = on n > max {
+                yield max;
+            } else on n < 0 {
+                yield 0;
+            } else {
+                yield n;
+            };
+            return clamped + 1;
+        }
+        fun nested(a: bool, b: bool) ~ int {
+            var outer = on a {
+                var unused = 3;
+                yield on b {
+                    yield 10;
+                } else {
+                    yield 20;
+                };
+            } else {
+                yield 5;
+            };
+            return outer + 1;
+        }
+        fun unyielded(n: int) ~ int {
+            var x = on n > 0 {
+                yield n;
+            };
+            return x;
+        }
+        ",
+    )?;
+    assert_eq!(call_int(&output, "clamp", &[15, 10])?, 11);
+    assert_eq!(call_int(&output, "clamp", &[-3, 10])?, 1);
+    assert_eq!(call_int(&output, "clamp", &[7, 10])?, 8);
+    assert_eq!(call_int(&output, "nested", &[1, 1])?, 11);
+    assert_eq!(call_int(&output, "nested", &[1, 0])?, 21);
+    assert_eq!(call_int(&output, "nested", &[0, 1])?, 6);
+    assert_eq!(call_int(&output, "unyielded", &[4])?, 4);
+    assert_eq!(call_int(&output, "unyielded", &[-4])?, 0);
+    Ok(())
+}
+
+#[test]
+fn test_break_continue() -> Result {
+    let output = compile(
+        "
+        fun sum_odd(limit: int) ~ int {
+            var i = 0;
+            var sum = 0;
+            while true {
+                i += 1;
+                on i > limit {
+                    break;
+                }
+                on (i / 2) * 2 == i {
+                    continue;
+                }
+                var odd = i;
+                sum += odd;
+            }
+            return sum;
+        }
+        ",
+    )?;
+    assert_eq!(call_int(&output, "sum_odd", &[10])?, 25);
+    Ok(())
+}
+
+#[test]
+fn test_recursion() -> Result {
+    let output = compile(
+        "
+        fun fib(n: int) ~ int {
+            on n < 2 {
+                return n;
+            }
+            return fib(n - 1) + fib(n - 2);
+        }
+        fun mixed(a: int, x: float, b: bool) ~ float {
+            on b {
+                return x * (a as float);
+            }
+            return x;
+        }
+        fun call_mixed() ~ float {
+            return mixed(3, 1.5, true) + mixed(3, 1.5, false);
+        }
+        ",
+    )?;
+    assert_eq!(call_int(&output, "fib", &[20])?, 6765);
+    let ret = output.call_raw("call_mixed", &[], &[])?;
+    assert_eq!(f32::from_bits(ret.float as u32), 6.0);
+    Ok(())
+}
+
+#[test]
+fn test_refs() -> Result {
+    let output = compile(
+        "
+        fun inc(p: &int) {
+            ~p = ~p + 1;
+        }
+        fun main() ~ int {
+            var x = 41;
+            inc(&x);
+            return x;
+        }
+        ",
+    )?;
+    assert_eq!(call_int(&output, "main", &[])?, 42);
+    Ok(())
+}
@@ -0,0 +1,316 @@
+use std::{
+    error::Error,
+    result::Result as StdResult,
+};
+
+use mess_core::{
+    compiler::Compiler as CompilerTrait,
+    parser::{
+        ast::Type,
+        Parser,
+    },
+};
+
+use super::{
+    call_float,
+    call_int,
+    compile,
+    compile_with,
+    Result,
+};
+use crate::{
+    codegen::{
+        compiler::OverflowMode,
+        error::Error as CompileError,
+    },
+    exec::runtime::RuntimeError,
+    Compiler,
+};
+
+#[test]
+fn test_int_arithmetic() -> Result {
+    let output = compile(
+        "
+        fun calc(a: int, b: int) ~ int {
+            var product = a * b;
+            var quotient = a / b;
+            return product - quotient;
+        }
+        fun negate(a: int) ~ int {
+            return -a;
+        }
+        ",
+    )?;
+    assert_eq!(call_int(&output, "calc", &[7, 2])?, 11);
+    assert_eq!(call_int(&output, "calc", &[-7, 2])?, -11);
+    assert_eq!(call_int(&output, "negate", &[5])?, -5);
+    Ok(())
+}
+
+#[test]
+fn test_int_division_wraps() -> Result {
+    let output = compile(
+        "
+        fun div(a: int, b: int) ~ int {
+            return a / b;
+        }
+        ",
+    )?;
+    assert_eq!(call_int(&output, "div", &[i64::MIN, -1])?, i64::MIN);
+    assert_eq!(call_int(&output, "div", &[-9, 2])?, -4);
+    Ok(())
+}
+
+#[test]
+fn test_float_arithmetic() -> Result {
+    let output = compile(
+        "
+        fun lerp(a: float, b: float, t: float) ~ float {
+            return a + ((b - a) * t);
+        }
+        fun half(a: float) ~ float {
+            return -(a / 2.0);
+        }
+        ",
+    )?;
+    assert_eq!(call_float(&output, "lerp", &[1.0, 3.0, 0.25])?, 1.5);
+    assert_eq!(call_float(&output, "half", &[3.0])?, -1.5);
+    Ok(())
+}
+
+#[test]
+fn test_comparisons() -> Result {
+    let output = compile(
+        "
+        fun compare(a: int, b: int) ~ int {
+            var result = 0;
+            on a < b {
+                result += 1;
+            }
+            on a <= b {
+                result += 10;
+            }
+            on a == b {
+                result += 100;
+            }
+            on a != b {
+                result += 1000;
+            }
+            on a >= b {
+                result += 10000;
+            }
+            on a > b {
+                result += 100000;
+            }
+            return result;
+        }
+        fun float_less(a: float, b: float) ~ int {
+            return (a < b) as int;
+        }
+        ",
+    )?;
+    assert_eq!(call_int(&output, "compare", &[1, 2])?, 1011);
+    assert_eq!(call_int(&output, "compare", &[2, 2])?, 10110);
+    assert_eq!(call_int(&output, "compare", &[3, 2])?, 111000);
+
+    let float_less = |a: f32, b: f32| -> StdResult<i64, Box<dyn Error>> {
+        let args = [a.to_bits() as u64, b.to_bits() as u64];
+        Ok(output.call_raw("float_less", &[], &args)?.int as i64)
+    };
+    assert_eq!(float_less(1.0, 2.0)?, 1);
+    assert_eq!(float_less(2.0, 1.0)?, 0);
+    assert_eq!(float_less(f32::NAN, 1.0)?, 0);
+    Ok(())
+}
+
+#[test]
+fn test_casts() -> Result {
+    let output = compile(
+        "
+        fun truncate(a: float) ~ int {
+            return a as int;
+        }
+        fun average(a: int, b: int) ~ float {
+            return (a + b) as float / 2.0;
+        }
+        ",
+    )?;
+    let truncated = output.call_raw("truncate", &[], &[(-2.7f32).to_bits() as u64])?;
+    assert_eq!(truncated.int as i64, -2);
+    let average = output.call_raw("average", &[3, 4], &[])?;
+    assert_eq!(f32::from_bits(average.float as u32), 3.5);
+    Ok(())
+}
+
+#[test]
+fn test_sized_arithmetic() -> Result {
+    let output = compile(
+        "
+        fun add8(a: i8, b: i8) ~ i8 {
+            return a + b;
+        }
+        fun mul_u8(a: u8, b: u8) ~ u8 {
+            return a * b;
+        }
+        fun div16(a: i16, b: i16) ~ i16 {
+            return a / b;
+        }
+        fun less32(a: i32, b: i32) ~ bool {
+            return a < b;
+        }
+        fun less_u32(a: u32, b: u32) ~ bool {
+            return a < b;
+        }
+        fun sub_u64(a: u64, b: u64) ~ u64 {
+            return a - b;
+        }
+        fun neg16(a: i16) ~ i16 {
+            return -a;
+        }
+        fun hypot(a: f64, b: f64) ~ f64 {
+            return (a * a) + (b * b);
+        }
+        fun less64(a: f64, b: f64) ~ bool {
+            return a < b;
+        }
+        ",
+    )?;
+    assert_eq!(output.get_fn::<(i8, i8), i8>("add8")?.call((100, 100))?, -56);
+    assert_eq!(output.get_fn::<(i8, i8), i8>("add8")?.call((-3, 1))?, -2);
+    assert_eq!(output.get_fn::<(u8, u8), u8>("mul_u8")?.call((16, 17))?, 16);
+    assert_eq!(output.get_fn::<(i16, i16), i16>("div16")?.call((-9, 2))?, -4);
+    assert_eq!(output.get_fn::<(i16, i16), i16>("div16")?.call((i16::MIN, -1))?, i16::MIN);
+    assert!(output.get_fn::<(i32, i32), bool>("less32")?.call((-1, 1))?);
+    assert!(!output.get_fn::<(u32, u32), bool>("less_u32")?.call((u32::MAX, 1))?);
+    assert_eq!(output.get_fn::<(u64, u64), u64>("sub_u64")?.call((0, 1))?, u64::MAX);
+    assert_eq!(output.get_fn::<(i16,), i16>("neg16")?.call((i16::MIN,))?, i16::MIN);
+    assert_eq!(output.get_fn::<(f64, f64), f64>("hypot")?.call((3.0, 4.0))?, 25.0);
+    assert!(output.get_fn::<(f64, f64), bool>("less64")?.call((-0.5, 0.25))?);
+    assert!(!output.get_fn::<(f64, f64), bool>("less64")?.call((f64::NAN, 0.25))?);
+    // Values passed to the host keep only the bytes of their type
+    assert_eq!(output.call_dynamic("add8", &[100, 100])?, 200);
+    Ok(())
+}
+
+#[test]
+fn test_overflow_trap() -> Result {
+    let compiler = Compiler::new()?.with_overflow_mode(OverflowMode::Trap);
+    let output = compile_with(
+        compiler,
+        "
+        fun add(a: int, b: int) ~ int {
+            return a + b;
+        }
+        fun div(a: int, b: int) ~ int {
+            return a / b;
+        }
+        fun neg(a: int) ~ int {
+            return -a;
+        }
+        fun add8(a: i8, b: i8) ~ i8 {
+            return a + b;
+        }
+        fun div8(a: i8, b: i8) ~ i8 {
+            return a / b;
+        }
+        fun mul_u64(a: u64, b: u64) ~ u64 {
+            return a * b;
+        }
+        fun sub_u32(a: u32, b: u32) ~ u32 {
+            return a - b;
+        }
+        ",
+    )?;
+    let overflow = Some(RuntimeError::IntegerOverflow);
+    assert_eq!(call_int(&output, "add", &[1, 2])?, 3);
+    assert_eq!(output.call_raw("add", &[i64::MAX as u64, 1], &[]).err(), overflow);
+    assert_eq!(output.call_raw("div", &[i64::MIN as u64, -1i64 as u64], &[]).err(), overflow);
+    assert_eq!(output.call_raw("neg", &[i64::MIN as u64], &[]).err(), overflow);
+    let add8 = output.get_fn::<(i8, i8), i8>("add8")?;
+    assert_eq!(add8.call((100, 27))?, 127);
+    assert_eq!(add8.call((100, 28)).err(), overflow);
+    assert_eq!(add8.call((-100, -29)).err(), overflow);
+    let div8 = output.get_fn::<(i8, i8), i8>("div8")?;
+    assert_eq!(div8.call((i8::MIN, -1)).err(), overflow);
+    assert_eq!(div8.call((i8::MIN, 0)).err(), Some(RuntimeError::DivisionByZero));
+    let mul_u64 = output.get_fn::<(u64, u64), u64>("mul_u64")?;
+    assert_eq!(mul_u64.call((1 << 32, 1 << 31))?, 1 << 63);
+    assert_eq!(mul_u64.call((1 << 32, 1 << 32)).err(), overflow);
+    let sub_u32 = output.get_fn::<(u32, u32), u32>("sub_u32")?;
+    assert_eq!(sub_u32.call((5, 3))?, 2);
+    assert_eq!(sub_u32.call((3, 5)).err(), overflow);
+    Ok(())
+}
+
+#[test]
+fn test_sized_casts() -> Result {
+    let output = compile(
+        "
+        fun to_u8(a: int) ~ u8 {
+            return a as u8;
+        }
+        fun to_i8(a: int) ~ i8 {
+            return a as i8;
+        }
+        fun widen(a: i8) ~ int {
+            return a as int;
+        }
+        fun widen_u16(a: u16) ~ int {
+            return a as int;
+        }
+        fun saturate(a: float) ~ int {
+            return a as int;
+        }
+        fun to_u64(a: f64) ~ u64 {
+            return a as u64;
+        }
+        fun from_u64(a: u64) ~ f64 {
+            return a as f64;
+        }
+        fun to_float(a: i32) ~ float {
+            return a as float;
+        }
+        fun narrow(a: f64) ~ float {
+            return a as float;
+        }
+        fun from_bool(a: bool) ~ int {
+            return a as int;
+        }
+        ",
+    )?;
+    assert_eq!(output.get_fn::<(i64,), u8>("to_u8")?.call((-1,))?, 255);
+    assert_eq!(output.get_fn::<(i64,), i8>("to_i8")?.call((300,))?, 44);
+    assert_eq!(output.get_fn::<(i8,), i64>("widen")?.call((-1,))?, -1);
+    assert_eq!(output.get_fn::<(u16,), i64>("widen_u16")?.call((u16::MAX,))?, 65535);
+    let saturate = output.get_fn::<(f32,), i64>("saturate")?;
+    assert_eq!(saturate.call((1e30,))?, i64::MAX);
+    assert_eq!(saturate.call((-1e30,))?, i64::MIN);
+    assert_eq!(saturate.call((f32::NAN,))?, 0);
+    let to_u64 = output.get_fn::<(f64,), u64>("to_u64")?;
+    assert_eq!(to_u64.call((-1.0,))?, 0);
+    assert_eq!(to_u64.call((1e19,))?, 10_000_000_000_000_000_000);
+    assert_eq!(output.get_fn::<(u64,), f64>("from_u64")?.call((u64::MAX,))?, u64::MAX as f64);
+    assert_eq!(output.get_fn::<(i32,), f32>("to_float")?.call((-3,))?, -3.0);
+    assert_eq!(output.get_fn::<(f64,), f32>("narrow")?.call((0.5,))?, 0.5);
+    assert_eq!(output.get_fn::<(bool,), i64>("from_bool")?.call((true,))?, 1);
+    Ok(())
+}
+
+#[test]
+fn test_invalid_cast() -> Result {
+    // Like on the VM, only numbers and bools can be cast, and only to numbers
+    let decl_list = Parser::new(
+        "
+        fun truthy(a: int) ~ bool {
+            return a as bool;
+        }
+        ",
+    )
+    .parse()?;
+    let mut compiler = Compiler::new()?;
+    assert!(matches!(
+        compiler.compile(&decl_list),
+        Err(CompileError::InvalidCast(Type::Int, Type::Bool))
+    ));
+    Ok(())
+}
@@ -0,0 +1,155 @@
+use mess_core::parser::ast::Type;
+
+use super::{
+    compile,
+    Result,
+};
+use crate::exec::runtime::RuntimeError;
+
+#[test]
+fn test_typed_call() -> Result {
+    let output = compile(
+        "
+        fun scale(a: int, x: float, negate: bool) ~ float {
+            on negate {
+                return -x * (a as float);
+            }
+            return x * (a as float);
+        }
+        fun is_even(n: int) ~ bool {
+            return (n / 2) * 2 == n;
+        }
+        fun nothing() {
+            var x = 1;
+        }
+        ",
+    )?;
+    let scale = output.get_fn::<(i64, f32, bool), f32>("scale")?;
+    assert_eq!(scale.call((3, 1.5, false))?, 4.5);
+    assert_eq!(scale.call((2, 1.5, true))?, -3.0);
+    let is_even = output.get_fn::<(i64,), bool>("root::is_even")?;
+    assert!(is_even.call((4,))?);
+    assert!(!is_even.call((7,))?);
+    output.get_fn::<(), ()>("nothing")?.call(())?;
+    Ok(())
+}
+
+#[test]
+fn test_typed_call_mismatch() -> Result {
+    let output = compile(
+        "
+        fun add(a: int, b: int) ~ int {
+            return a + b;
+        }
+        ",
+    )?;
+    assert_eq!(
+        output.get_fn::<(i64, f32), i64>("add").err(),
+        Some(RuntimeError::SignatureMismatch(
+            String::from("add"),
+            vec![Type::Int, Type::Float],
+            Type::Int
+        ))
+    );
+    assert_eq!(
+        output.get_fn::<(i64, i64), ()>("add").err(),
+        Some(RuntimeError::SignatureMismatch(
+            String::from("add"),
+            vec![Type::Int, Type::Int],
+            Type::Void
+        ))
+    );
+    assert_eq!(
+        output.get_fn::<(), ()>("missing").err(),
+        Some(RuntimeError::UnknownFunctionName(String::from("missing")))
+    );
+    Ok(())
+}
+
+#[test]
+fn test_typed_call_trap() -> Result {
+    let output = compile(
+        "
+        fun div(a: int, b: int) ~ int {
+            return a / b;
+        }
+        ",
+    )?;
+    let div = output.get_fn::<(i64, i64), i64>("div")?;
+    assert_eq!(div.call((1, 0)), Err(RuntimeError::DivisionByZero));
+    assert_eq!(div.call((9, 3))?, 3);
+    Ok(())
+}
+
+#[test]
+fn test_dynamic_call() -> Result {
+    let output = compile(
+        "
+        fun scale(a: int, x: float, negate: bool) ~ float {
+            on negate {
+                return -x * (a as float);
+            }
+            return x * (a as float);
+        }
+        fun is_positive(n: int) ~ bool {
+            return n > 0;
+        }
+        fun inc(p: &int) {
+            ~p = ~p + 1;
+        }
+        ",
+    )?;
+    let scaled = output.call_dynamic("scale", &[3, 1.5f32.to_bits() as u64, 1])?;
+    assert_eq!(f32::from_bits(scaled as u32), -4.5);
+    assert_eq!(output.call_dynamic("is_positive", &[5])?, 1);
+    assert_eq!(output.call_dynamic("is_positive", &[-5i64 as u64])?, 0);
+    assert_eq!(
+        output.call_dynamic("inc", &[0]),
+        Err(RuntimeError::RefArgument(String::from("inc")))
+    );
+    Ok(())
+}
+
+#[test]
+fn test_stack_args() -> Result {
+    let output = compile(
+        "
+        fun mixed(a: int, b: int, c: int, d: int, e: int, f: int, g: int, x: float, h: i8) ~ int {
+            var regs = a + b + c + d + e + f;
+            return regs + (g * 100) + ((x as int) * 1000) + ((h as int) * 10000);
+        }
+        fun floats(
+            a: f64, b: float, c: float, d: float, e: float, f: float, g: float, h: float,
+            i: float, j: f64, n: int
+        ) ~ f64 {
+            var tail = ((i as f64) * (10.0 as f64)) + (j * (100.0 as f64));
+            return a + tail + ((n * 1000) as f64);
+        }
+        fun seven(a: int, b: int, c: int, d: int, e: int, f: int, g: int) ~ int {
+            return g - a;
+        }
+        fun call_mixed() ~ int {
+            return mixed(1, 2, 3, 4, 5, 6, 7, 8.5, (0 - 3) as i8);
+        }
+        fun call_floats() ~ f64 {
+            return floats(1.0 as f64, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 2.0, 3.0 as f64, 4);
+        }
+        fun call_seven() ~ int {
+            return seven(1, 2, 3, 4, 5, 6, 10);
+        }
+        ",
+    )?;
+    assert_eq!(output.get_fn::<(), i64>("call_mixed")?.call(())?, -21279);
+    assert_eq!(output.get_fn::<(), f64>("call_floats")?.call(())?, 4321.0);
+    assert_eq!(output.get_fn::<(), i64>("call_seven")?.call(())?, 9);
+    // The host passes them on the stack as well
+    let mut args: Vec<u64> = (1..=7).collect();
+    args.extend([8.5f32.to_bits() as u64, -3i8 as u8 as u64]);
+    assert_eq!(output.call_dynamic("mixed", &args)? as i64, -21279);
+    let mut args = vec![1.0f64.to_bits()];
+    args.extend([0.0f32; 7].map(|arg| arg.to_bits() as u64));
+    args.extend([2.0f32.to_bits() as u64, 3.0f64.to_bits(), 4]);
+    assert_eq!(f64::from_bits(output.call_dynamic("floats", &args)?), 4321.0);
+    assert_eq!(output.call_dynamic("seven", &[1, 2, 3, 4, 5, 6, 10])?, 9);
+    Ok(())
+}
@@ -0,0 +1,187 @@
+use std::{
+    cell::Cell,
+    error::Error,
+    ptr,
+    result::Result as StdResult,
+};
+
+use mess_api::prelude::{
+    Adapter,
+    Function,
+    Module,
+    Type,
+};
+use mess_core::compiler::Compiler as CompilerTrait;
+
+use super::{
+    call_int,
+    compile_with,
+    Result,
+};
+use crate::{
+    codegen::output::Output,
+    exec::runtime::RuntimeError,
+    Compiler,
+};
+
+thread_local! {
+    /// The output `reenter` calls back into
+    static REENTERED: Cell<*const Output> = const { Cell::new(ptr::null()) };
+}
+
+struct Counter(i64);
+
+fn sub(adapter: &mut Adapter) {
+    let lhs: i64 = adapter.get_arg(0);
+    let rhs: i64 = adapter.get_arg(1);
+    adapter.ret(lhs - rhs);
+}
+
+fn scale(adapter: &mut Adapter) {
+    let value: f32 = adapter.get_arg(0);
+    let double: bool = adapter.get_arg(1);
+    adapter.ret(if double { value * 2.0 } else { value });
+}
+
+fn counter_new(adapter: &mut Adapter) {
+    adapter.ret_foreign_object(Counter(0));
+}
+
+fn counter_inc(adapter: &mut Adapter) {
+    // Failing to get the counter fails the call
+    let Ok(counter) = adapter.get_foreign_arg::<Counter>(0) else {
+        return;
+    };
+    let mut counter = counter.lock().unwrap();
+    counter.0 += 1;
+    adapter.ret(counter.0);
+}
+
+fn fail(_: &mut Adapter) {
+    panic!("Host function failed");
+}
+
+/// Calls the trapping `inner` function of the compiled script, returning
+/// whether it failed
+fn reenter(adapter: &mut Adapter) {
+    let output = REENTERED.with(Cell::get);
+    let result = unsafe { &*output }.call_raw("inner", &[], &[]);
+    adapter.ret(result.is_err());
+}
+
+fn host_compiler() -> StdResult<Compiler, Box<dyn Error>> {
+    let mut module = Module::new(String::from("host"));
+    module.add_function(Function::new("sub", vec![Type::Int, Type::Int], Type::Int, sub));
+    module.add_function(Function::new(
+        "scale",
+        vec![Type::Float, Type::Bool],
+        Type::Float,
+        scale,
+    ));
+    let counter_type = Type::Named(String::from("Counter"));
+    module.add_function(Function::new("counter_new", vec![], counter_type.clone(), counter_new));
+    module.add_function(Function::new("counter_inc", vec![counter_type], Type::Int, counter_inc));
+    module.add_function(Function::new("fail", vec![], Type::Void, fail));
+    module.add_function(Function::new("reenter", vec![], Type::Bool, reenter));
+    let mut compiler = Compiler::new()?;
+    compiler.register_module(module)?;
+    Ok(compiler)
+}
+
+#[test]
+fn test_host_fn_args() -> Result {
+    let output = compile_with(
+        host_compiler()?,
+        "
+        fun main() ~ int {
+            var scaled = host::scale(1.5, true);
+            return host::sub(50, 8) + (scaled as int);
+        }
+        ",
+    )?;
+    assert_eq!(call_int(&output, "main", &[])?, 45);
+    Ok(())
+}
+
+#[test]
+fn test_host_foreign_object() -> Result {
+    let output = compile_with(
+        host_compiler()?,
+        "
+        fun main() ~ int {
+            var counter = host::counter_new();
+            host::counter_inc(counter);
+            return host::counter_inc(counter);
+        }
+        ",
+    )?;
+    assert_eq!(call_int(&output, "main", &[])?, 2);
+    Ok(())
+}
+
+#[test]
+fn test_host_fn_panic() -> Result {
+    let output = compile_with(
+        host_compiler()?,
+        "
+        fun main() ~ int {
+            host::fail();
+            return 1;
+        }
+        ",
+    )?;
+    assert_eq!(
+        output.call_raw("main", &[], &[]).err(),
+        Some(RuntimeError::HostPanic(String::from("fail")))
+    );
+    Ok(())
+}
+
+#[test]
+fn test_host_fn_reentry() -> Result {
+    let output = compile_with(
+        host_compiler()?,
+        "
+        fun inner() {
+            panic(\"inner\");
+        }
+        fun outer(n: int) ~ int {
+            assert(host::reenter(), \"inner returned\");
+            return 10 / n;
+        }
+        ",
+    )?;
+    REENTERED.with(|reentered| reentered.set(&output));
+    assert_eq!(call_int(&output, "outer", &[2])?, 5);
+    // The outer call unwinds to its own entry after the inner one returned
+    assert_eq!(
+        output.call_raw("outer", &[0], &[]).err(),
+        Some(RuntimeError::DivisionByZero)
+    );
+    assert_eq!(call_int(&output, "outer", &[5])?, 2);
+    Ok(())
+}
+
+#[test]
+fn test_host_fn_reentry_host_calls() -> Result {
+    // Host functions called by the inner call share the foreign objects
+    // with the host function running the outer one
+    let output = compile_with(
+        host_compiler()?,
+        "
+        fun inner() {
+            var counter = host::counter_new();
+            host::counter_inc(counter);
+        }
+        fun outer() ~ int {
+            var counter = host::counter_new();
+            assert(host::reenter() == false, \"inner failed\");
+            host::counter_inc(counter);
+            return host::counter_inc(counter);
+        }
+        ",
+    )?;
+    REENTERED.with(|reentered| reentered.set(&output));
+    assert_eq!(call_int(&output, "outer", &[])?, 2);
+    Ok(())
+}
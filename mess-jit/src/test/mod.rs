@@ -1 +1,50 @@
+mod control;
 
+mod expr;
+
+mod function;
+
+mod host;
+
+mod trap;
+
+use std::{
+    error::Error,
+    result::Result as StdResult,
+};
+
+use mess_core::{
+    compiler::Compiler as CompilerTrait,
+    parser::Parser,
+};
+
+use crate::{
+    codegen::output::Output,
+    Compiler,
+};
+
+type Result = StdResult<(), Box<dyn Error>>;
+
+/// Compiles a script with the JIT compiler
+fn compile(source: &str) -> StdResult<Output, Box<dyn Error>> {
+    compile_with(Compiler::new()?, source)
+}
+
+/// Compiles a script with a compiler that host modules were registered with
+fn compile_with(mut compiler: Compiler, source: &str) -> StdResult<Output, Box<dyn Error>> {
+    let decl_list = Parser::new(source).parse()?;
+    compiler.compile(&decl_list)?;
+    Ok(compiler.get_output()?)
+}
+
+/// Calls a compiled function taking and returning integers
+fn call_int(output: &Output, fn_name: &str, args: &[i64]) -> StdResult<i64, Box<dyn Error>> {
+    let args: Vec<u64> = args.iter().map(|arg| *arg as u64).collect();
+    Ok(output.call_raw(fn_name, &args, &[])?.int as i64)
+}
+
+/// Calls a compiled function taking and returning floats
+fn call_float(output: &Output, fn_name: &str, args: &[f32]) -> StdResult<f32, Box<dyn Error>> {
+    let args: Vec<u64> = args.iter().map(|arg| arg.to_bits() as u64).collect();
+    Ok(f32::from_bits(output.call_raw(fn_name, &[], &args)?.float as u32))
+}
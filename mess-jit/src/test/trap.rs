@@ -0,0 +1,126 @@
+use mess_core::exec::Executor;
+
+use super::{
+    call_int,
+    compile,
+    Result,
+};
+use crate::{
+    exec::runtime::RuntimeError,
+    Runtime,
+};
+
+#[test]
+fn test_division_by_zero() -> Result {
+    let output = compile(
+        "
+        fun div(a: int, b: int) ~ int {
+            return a / b;
+        }
+        fun nested(a: int, b: int) ~ int {
+            return div(a, b) + 1;
+        }
+        ",
+    )?;
+    assert_eq!(
+        output.call_raw("nested", &[1, 0], &[]).err(),
+        Some(RuntimeError::DivisionByZero)
+    );
+    // The stack was unwound, so the code can be called again
+    assert_eq!(call_int(&output, "nested", &[9, 3])?, 4);
+    Ok(())
+}
+
+#[test]
+fn test_panic_assert() -> Result {
+    let output = compile(
+        r#"
+        fun check(n: int) ~ int {
+            assert(n > 0, "n must be positive");
+            on n > 10 {
+                panic("n is too large");
+            }
+            return n;
+        }
+        "#,
+    )?;
+    assert_eq!(call_int(&output, "check", &[5])?, 5);
+    assert_eq!(
+        output.call_raw("check", &[0], &[]).err(),
+        Some(RuntimeError::ScriptPanic(String::from("n must be positive")))
+    );
+    assert_eq!(
+        output.call_raw("check", &[11], &[]).err(),
+        Some(RuntimeError::ScriptPanic(String::from("n is too large")))
+    );
+    Ok(())
+}
+
+#[test]
+fn test_missing_return() -> Result {
+    let output = compile(
+        "
+        fun check(n: int) ~ int {
+            on n > 0 {
+                return n;
+            }
+        }
+        ",
+    )?;
+    assert_eq!(call_int(&output, "check", &[3])?, 3);
+    assert_eq!(output.call_raw("check", &[0], &[]).err(), Some(RuntimeError::NoReturnValue));
+    Ok(())
+}
+
+#[test]
+fn test_runtime_run_fn() -> Result {
+    let output = compile(
+        "
+        fun main() {
+            var x = 4;
+        }
+        fun add(a: int, b: int) ~ int {
+            return a + b;
+        }
+        ",
+    )?;
+    let mut runtime = Runtime::new();
+    assert_eq!(runtime.run_fn("main"), Err(RuntimeError::NoProgram));
+    runtime.set_input(output);
+    runtime.run_fn("main")?;
+    assert_eq!(
+        runtime.run_fn("add"),
+        Err(RuntimeError::ArgumentCount(String::from("add"), 0))
+    );
+    assert_eq!(
+        runtime.run_fn("missing"),
+        Err(RuntimeError::UnknownFunctionName(String::from("missing")))
+    );
+    Ok(())
+}
+
+#[test]
+fn test_call_depth_limit() -> Result {
+    let output = compile(
+        "
+        fun depth(n: int) ~ int {
+            on n == 0 {
+                return 0;
+            }
+            return depth(n - 1) + 1;
+        }
+        ",
+    )?;
+    assert_eq!(output.get_max_call_depth(), None);
+    assert_eq!(call_int(&output, "depth", &[100])?, 100);
+    output.set_max_call_depth(Some(10));
+    // The outermost call counts as well
+    assert_eq!(call_int(&output, "depth", &[9])?, 9);
+    assert_eq!(
+        output.call_raw("depth", &[10], &[]).err(),
+        Some(RuntimeError::CallStackOverflow)
+    );
+    // Unwinding reset the depth of the calls that trapped
+    assert_eq!(call_int(&output, "depth", &[9])?, 9);
+    Ok(())
+}
@@ -0,0 +1,100 @@
+use criterion::{
+    criterion_group,
+    criterion_main,
+    Criterion,
+};
+use mess_vm::{
+    codegen::asm::assemble,
+    Core,
+};
+
+/// Counts down from 100000 in a tight loop
+const LOOP: &str = "
+main:
+    LDI 100000, R1
+.loop:
+    SUBI_I R1, 1, R1
+    GTI R1, R0, R2
+    JMPT R2, .loop
+    RET
+";
+
+/// Computes fib(20) recursively, spilling to the stack around calls
+const FIB: &str = "
+main:
+    LDI 20, R1
+    CALL fib
+    RET
+fib:
+    LDI 2, R3
+    LTI R1, R3, R4
+    JMPF R4, .rec
+    MOVI R1, R2
+    RET
+.rec:
+    ADDU_I SP, 16, SP
+    MOVI_RA R1, [SP - 16]
+    SUBI_I R1, 1, R1
+    CALL fib
+    MOVI_RA R2, [SP - 8]
+    MOVI_AR [SP - 16], R1
+    SUBI_I R1, 2, R1
+    CALL fib
+    MOVI_AR [SP - 8], R5
+    ADDI R2, R5, R2
+    SUBU_I SP, 16, SP
+    RET
+";
+
+/// Moves values between stack slots and registers
+const MEMORY: &str = "
+main:
+    ADDU_I SP, 24, SP
+    LDI 50000, R1
+    MOVI_RA R1, [SP - 24]
+.loop:
+    MOVI_AR [SP - 24], R1
+    MOVI_A [SP - 24], [SP - 16]
+    MOVI_AR [SP - 16], R2
+    SUBI_I R2, 1, R2
+    MOVI_RA R2, [SP - 24]
+    GTI R2, R0, R3
+    JMPT R3, .loop
+    SUBU_I SP, 24, SP
+    RET
+";
+
+/// Accumulates floats in a loop
+const FLOAT: &str = "
+main:
+    LDI 50000, R1
+    LDF 0.0, R2
+    LDF 0.5, R3
+.loop:
+    ADDF R2, R3, R2
+    MULF_I R2, 0.999, R2
+    SUBI_I R1, 1, R1
+    GTI R1, R0, R4
+    JMPT R4, .loop
+    RET
+";
+
+fn bench_script(c: &mut Criterion, name: &str, source: &str) {
+    let output = assemble(source).expect("Could not assemble benchmark");
+    let uid = output.function_name_map["main"];
+    let mut core = Core::new(1024);
+    core.load_program(output);
+    c.bench_function(name, |b| {
+        b.iter(|| core.run_fn(uid).expect("Benchmark failed"))
+    });
+}
+
+fn bench_core(c: &mut Criterion) {
+    bench_script(c, "loop", LOOP);
+    bench_script(c, "fib", FIB);
+    bench_script(c, "memory", MEMORY);
+    bench_script(c, "float", FLOAT);
+}
+
+criterion_group!(benches, bench_core);
+criterion_main!(benches);
@@ -1,6 +1,16 @@
 use proc_macro::TokenStream;
-use quote::quote;
-use syn::{parse::{Parse, ParseStream}, Token, ExprReference, parse_macro_input};
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{
+    braced, bracketed,
+    parse::{Parse, ParseStream},
+    parse_macro_input, ExprReference, Ident, LitBool, LitFloat, LitInt, Token,
+};
+
+const REGISTERS: &[&str] = &[
+    "R0", "R1", "R2", "R3", "R4", "R5", "R6", "R7", "R8", "R9", "R10", "R11", "R12", "R13",
+    "R14", "R15", "SP", "IP", "BP",
+];
 
 struct Args {
     assembler_expr: ExprReference,
@@ -11,30 +21,224 @@ impl Parse for Args {
     fn parse(input: ParseStream) -> syn::Result<Self> {
         let assembler_expr: ExprReference = input.parse()?;
         if assembler_expr.mutability.is_none() {
-            panic!("Needs to be mutable reference!");
+            return Err(syn::Error::new_spanned(assembler_expr, "Needs to be mutable reference!"));
+        }
+        let mut assembly_lines = vec![];
+        if !input.is_empty() {
+            input.parse::<Token!(,)>()?;
+        }
+        while !input.is_empty() {
+            assembly_lines.push(input.parse()?);
         }
-        let _: syn::Result<Token!(,)> = input.parse();
         Ok(Self {
             assembler_expr,
-            assembly_lines: vec![]
+            assembly_lines
         })
     }
 }
 
 enum AssemblyLine {
-    Empty,
-    StaticLabel(String),
-    Instruction(String, Vec<String>)
+    Label(Ident),
+    Instruction(Ident, Vec<AsmOperand>)
 }
 
 impl Parse for AssemblyLine {
     fn parse(input: ParseStream) -> syn::Result<Self> {
-        Ok(Self::Empty)
+        let ident: Ident = input.parse()?;
+        if input.peek(Token!(:)) && !input.peek(Token!(::)) {
+            input.parse::<Token!(:)>()?;
+            return Ok(Self::Label(ident));
+        }
+        let mut operands = vec![];
+        while !input.is_empty() && !input.peek(Token!(;)) {
+            operands.push(input.parse()?);
+            if !input.peek(Token!(,)) {
+                break;
+            }
+            input.parse::<Token!(,)>()?;
+        }
+        if !input.is_empty() {
+            input.parse::<Token!(;)>()?;
+        }
+        Ok(Self::Instruction(ident, operands))
     }
 }
 
+enum AsmOperand {
+    Reg(Ident),
+    Label(Ident),
+    Int(i64),
+    UInt(u64),
+    Float(f32),
+    Bool(bool),
+    Expr(TokenStream2),
+    Mem(Box<AsmOperand>, bool, Option<Box<AsmOperand>>)
+}
+
+impl Parse for AsmOperand {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        if input.peek(syn::token::Bracket) {
+            let content;
+            bracketed!(content in input);
+            let base: AsmOperand = content.parse()?;
+            if !matches!(base, AsmOperand::Reg(_) | AsmOperand::Expr(_)) {
+                return Err(content.error("Expected register"));
+            }
+            let mut negative = false;
+            let mut offset = None;
+            if content.peek(Token!(+)) || content.peek(Token!(-)) {
+                negative = content.peek(Token!(-));
+                if negative {
+                    content.parse::<Token!(-)>()?;
+                } else {
+                    content.parse::<Token!(+)>()?;
+                }
+                let offset_operand: AsmOperand = content.parse()?;
+                if !matches!(offset_operand, AsmOperand::Int(_) | AsmOperand::Expr(_)) {
+                    return Err(content.error("Expected offset"));
+                }
+                offset = Some(Box::new(offset_operand));
+            }
+            return Ok(Self::Mem(Box::new(base), negative, offset));
+        }
+        if input.peek(syn::token::Brace) {
+            let content;
+            braced!(content in input);
+            return Ok(Self::Expr(content.parse()?));
+        }
+        if input.peek(LitBool) {
+            let lit: LitBool = input.parse()?;
+            return Ok(Self::Bool(lit.value));
+        }
+        let negative = input.peek(Token!(-));
+        if negative {
+            input.parse::<Token!(-)>()?;
+        }
+        if input.peek(LitFloat) {
+            let lit: LitFloat = input.parse()?;
+            let float: f32 = lit.base10_parse()?;
+            return Ok(Self::Float(if negative { -float } else { float }));
+        }
+        if input.peek(LitInt) {
+            let lit: LitInt = input.parse()?;
+            if negative {
+                return Ok(Self::Int(-lit.base10_parse::<i64>()?));
+            }
+            return match lit.base10_parse::<i64>() {
+                Ok(int) => Ok(Self::Int(int)),
+                Err(_) => Ok(Self::UInt(lit.base10_parse()?)),
+            };
+        }
+        if negative {
+            return Err(input.error("Expected number"));
+        }
+        let ident: Ident = input.parse()?;
+        if REGISTERS.contains(&ident.to_string().as_str()) {
+            Ok(Self::Reg(ident))
+        } else {
+            Ok(Self::Label(ident))
+        }
+    }
+}
+
+impl AsmOperand {
+    /// Lowers the operand into an expression creating a `mess_vm::codegen::asm::Operand`.
+    /// Interpolated expressions are collected into `args` to be evaluated up front.
+    fn lower(&self, args: &mut Vec<TokenStream2>) -> TokenStream2 {
+        match self {
+            Self::Reg(ident) => quote! {
+                ::mess_vm::codegen::asm::Operand::Reg(::mess_vm::codegen::register::Register::#ident)
+            },
+            Self::Label(ident) => {
+                let label = ident.to_string();
+                quote! { ::mess_vm::codegen::asm::Operand::Label(::std::string::String::from(#label)) }
+            }
+            Self::Int(int) => quote! { ::mess_vm::codegen::asm::Operand::Int(#int) },
+            Self::UInt(uint) => quote! { ::mess_vm::codegen::asm::Operand::UInt(#uint) },
+            Self::Float(float) => quote! { ::mess_vm::codegen::asm::Operand::Float(#float) },
+            Self::Bool(boolean) => quote! { ::mess_vm::codegen::asm::Operand::Bool(#boolean) },
+            Self::Expr(expr) => {
+                let arg = format_ident!("__asm_arg_{}", args.len());
+                args.push(quote! { let #arg = { #expr }; });
+                quote! { ::mess_vm::codegen::asm::Operand::from(#arg) }
+            }
+            Self::Mem(base, negative, offset) => {
+                let base = match base.as_ref() {
+                    Self::Reg(ident) => quote! { ::mess_vm::codegen::register::Register::#ident },
+                    Self::Expr(expr) => {
+                        let arg = format_ident!("__asm_arg_{}", args.len());
+                        args.push(quote! { let #arg = { #expr }; });
+                        quote! { #arg }
+                    }
+                    _ => unreachable!(),
+                };
+                let offset = match offset.as_deref() {
+                    Some(Self::Int(int)) => quote! { #int },
+                    Some(Self::Expr(expr)) => {
+                        let arg = format_ident!("__asm_arg_{}", args.len());
+                        args.push(quote! { let #arg = { #expr }; });
+                        quote! { (#arg as i64) }
+                    }
+                    _ => quote! { 0i64 },
+                };
+                let offset = if *negative { quote! { -#offset } } else { offset };
+                quote! { ::mess_vm::codegen::asm::Operand::mem(#base, #offset)? }
+            }
+        }
+    }
+}
+
+/// Assembles instructions into a `mess_vm::codegen::assembler::Assembler`.
+///
+/// ```ignore
+/// asm!(&mut self.assembler,
+///     loop_start:
+///     SUBI_I R1, 1, R1;
+///     MOVI_RA R1, [SP - {offset}];
+///     JMPT R2, loop_start;
+/// )?;
+/// ```
+///
+/// Mnemonics are `Opcode` variants and register names are `Register` variants,
+/// so typos fail to compile. `{expr}` interpolates a Rust expression converted
+/// with `Operand::from`. Evaluates to a `mess_vm::codegen::error::Result<()>`.
 #[proc_macro]
 pub fn asm(input: TokenStream) -> TokenStream {
     let args = parse_macro_input!(input as Args);
-    quote!{ () }.into()
-}
\ No newline at end of file
+    let assembler_expr = args.assembler_expr;
+    let mut arg_lets = vec![];
+    let mut stmts = vec![];
+    for line in args.assembly_lines.iter() {
+        match line {
+            AssemblyLine::Label(ident) => {
+                let label = ident.to_string();
+                stmts.push(quote! {
+                    __asm.push_label(::std::string::String::from(#label));
+                });
+            }
+            AssemblyLine::Instruction(mnemonic, operands) => {
+                let operands: Vec<TokenStream2> = operands
+                    .iter()
+                    .map(|operand| operand.lower(&mut arg_lets))
+                    .collect();
+                stmts.push(quote! {
+                    __asm.push_asm(
+                        ::mess_vm::exec::is::Opcode::#mnemonic,
+                        ::std::vec![#(#operands),*]
+                    )?;
+                });
+            }
+        }
+    }
+    quote! {
+        {
+            #(#arg_lets)*
+            let __asm: &mut ::mess_vm::codegen::assembler::Assembler = #assembler_expr;
+            (|| -> ::mess_vm::codegen::error::Result<()> {
+                #(#stmts)*
+                Ok(())
+            })()
+        }
+    }
+    .into()
+}
@@ -1,9 +1,10 @@
-use std::{sync::Arc, any::Any};
+use mess_api::prelude::{AdapterImpl, ForeignError, ForeignObject, Function, Type};
 
-use mess_api::prelude::{AdapterImpl, Function};
-
-use crate::{Core, codegen::register::Register, exec::register::RegisterAccess};
+use crate::{Core, codegen::register::Register, exec::core::CoreError};
 
+/// Gives a host function access to its arguments, return value and the
+/// foreign objects of the calling core. Arguments are read from the top of
+/// the caller's stack, the last argument ending at the stack pointer.
 pub struct Adapter<'c> {
     function: Function,
     core: &'c mut Core
@@ -11,33 +12,89 @@ pub struct Adapter<'c> {
 
 impl<'c> Adapter<'c> {
     pub fn new(func: &Function, core: &'c mut Core) -> Self {
+        let mut function = func.clone();
+        if !function.has_arg_sizes() {
+            let arg_sizes = function.args.iter().map(get_size_of_type).collect();
+            function.set_arg_sizes(arg_sizes);
+        }
         Self {
-            function: func.clone(),
+            function,
             core
         }
     }
 }
 
+/// Returns the size of a host function argument or return value on the VM
+pub fn get_size_of_type(var_type: &Type) -> usize {
+    match var_type {
+        Type::Void => 0,
+        Type::Bool | Type::I8 | Type::U8 => 1,
+        Type::I16 | Type::U16 => 2,
+        Type::Float | Type::I32 | Type::U32 => 4,
+        Type::Int | Type::U64 | Type::F64 => 8,
+        Type::Str | Type::Named(_) | Type::Ref(_) => 8,
+    }
+}
+
 impl<'c> AdapterImpl for Adapter<'c> {
     fn ret(&mut self, bytes: &[u8]) {
         if bytes.len() > 8 {
-            panic!("Return value too big");
+            self.core.set_host_error(CoreError::OperatorSerialize);
+            return;
         }
         let mut actual_bytes = [0u8; 8];
-        actual_bytes[0..8].copy_from_slice(bytes);
-        let reg0 = self.core.reg(Register::R0.into()).unwrap();
-        reg0.set(actual_bytes);
+        actual_bytes[0..bytes.len()].copy_from_slice(bytes);
+        if let Ok(reg0) = self.core.reg(Register::R0.into()) {
+            reg0.set(actual_bytes);
+        }
     }
 
     fn get_arg_bytes(&self, arg_index: usize) -> Vec<u8> {
-        unimplemented!("Not implemented yet");
+        let size = self.function.get_arg_size(arg_index);
+        let offset = self.function.get_arg_offset(arg_index);
+        let bytes = i16::try_from(offset)
+            .map_err(|_| CoreError::InvalidStackPointer)
+            .and_then(|offset| self.core.mem_get_n((self.core.get_sp(), offset), size));
+        match bytes {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                // Reads can't fail through the API, so the call fails afterwards
+                self.core.set_host_error(err);
+                vec![0; size]
+            }
+        }
     }
 
-    fn get_foreign_ptr(&self, ptr: u64) -> Box<dyn Any> {
-        unimplemented!("Not implemented yet");
+    fn get_foreign_object(&self, ptr: u64) -> Result<ForeignObject, ForeignError> {
+        self.core
+            .get_foreign_object(ptr)
+            .cloned()
+            .map_err(|_| ForeignError::InvalidHandle(ptr))
     }
 
-    fn insert_foreign_ptr(&mut self, object: Box<dyn Any>) -> u64 {
-        unimplemented!("Not implemented yet");
+    fn set_foreign_error(&self, err: ForeignError) {
+        self.core.set_host_error(match err {
+            ForeignError::InvalidHandle(ptr) => CoreError::InvalidHandle(ptr),
+            ForeignError::TypeMismatch { ptr, expected, found } => {
+                CoreError::ForeignTypeMismatch { ptr, expected, found }
+            }
+        });
     }
-}
\ No newline at end of file
+
+    fn get_str(&self, ptr: u64) -> Option<String> {
+        // Strings are stored length prefixed, like panic messages
+        let len: u64 = self.core.mem_get((ptr, 0)).ok()?;
+        let bytes = self.core.mem_get_n((ptr, 8), len as usize).ok()?;
+        String::from_utf8(bytes).ok()
+    }
+
+    fn insert_foreign_ptr(&mut self, object: ForeignObject) -> u64 {
+        match self.core.insert_foreign_object(object) {
+            Ok(ptr) => ptr,
+            Err(err) => {
+                self.core.set_host_error(err);
+                0
+            }
+        }
+    }
+}
@@ -0,0 +1,497 @@
+//! A textual assembly language for the VM, assembled into runnable output
+
+use std::{
+    convert::TryFrom,
+    result::Result as StdResult,
+    str::FromStr,
+};
+
+use super::{
+    assembler::Assembler,
+    error::{
+        Error,
+        Result,
+    },
+    instruction::Instruction,
+    object::Object,
+    output::{
+        LocalVar,
+        Output,
+    },
+    regalloc::VirtualRegister,
+    register::Register,
+};
+use mess_core::parser::ast::Type;
+
+use crate::exec::is::{
+    OperandKind,
+    Opcode,
+};
+
+/// An operand of an assembly instruction, before encoding
+#[derive(Clone, Debug, PartialEq)]
+pub enum Operand {
+    /// A register
+    Reg(Register),
+    /// A virtual register, mapped to a machine register by the register allocator
+    Virtual(VirtualRegister),
+    /// A signed integer immediate
+    Int(i64),
+    /// An unsigned integer immediate
+    UInt(u64),
+    /// A float immediate
+    Float(f32),
+    /// A boolean immediate
+    Bool(bool),
+    /// A register holding an address, plus an offset
+    Mem(Register, i16),
+    /// A label, resolved to a code offset, function uid or static variable
+    /// address when building
+    Label(String),
+}
+
+/// A reference to a label that is patched into an operand when building
+#[derive(Clone, Debug, PartialEq)]
+pub enum LabelRef {
+    /// The code offset of the label
+    Target(String),
+    /// The uid of the function the label marks
+    Function(String),
+    /// The address of the static variable with the name
+    Static(String),
+    /// An address in static data, already encoded, which moves with the
+    /// code when linking
+    Data(u64),
+}
+
+impl Operand {
+    /// Creates a memory operand, checking the offset fits into the encoding
+    pub fn mem(reg: Register, offset: i64) -> Result<Operand> {
+        let offset = i16::try_from(offset).map_err(|_| Error::OffsetOutOfRange(offset))?;
+        Ok(Operand::Mem(reg, offset))
+    }
+
+    /// Appends the encoded operand to the instruction. Returns the label
+    /// that still needs to be patched in, if any.
+    pub fn encode(
+        self,
+        opcode: &Opcode,
+        kind: OperandKind,
+        instr: &mut Instruction,
+    ) -> Result<Option<LabelRef>> {
+        let mismatch = || Error::OperandMismatch(opcode.clone(), kind);
+        match (kind, self) {
+            (OperandKind::Reg, Operand::Reg(reg)) => instr.append_operand::<u8>(reg.into()),
+            (OperandKind::Reg, Operand::Virtual(reg)) => instr.append_virtual(reg),
+            (OperandKind::Byte, Operand::Int(int)) => {
+                instr.append_operand(u8::try_from(int).map_err(|_| mismatch())?)
+            }
+            (OperandKind::Int, Operand::Int(int)) => instr.append_operand(int),
+            (OperandKind::UInt, Operand::Int(int)) => {
+                instr.append_operand(u64::try_from(int).map_err(|_| mismatch())?)
+            }
+            (OperandKind::UInt, Operand::UInt(uint)) => instr.append_operand(uint),
+            (OperandKind::Float, Operand::Float(float)) => instr.append_operand(float),
+            (OperandKind::Float, Operand::Int(int)) => instr.append_operand(int as f32),
+            (OperandKind::Bool, Operand::Bool(boolean)) => instr.append_operand(boolean),
+            (OperandKind::Size, Operand::Int(int)) => {
+                instr.append_operand(u32::try_from(int).map_err(|_| mismatch())?)
+            }
+            (OperandKind::Size, Operand::UInt(uint)) => {
+                instr.append_operand(u32::try_from(uint).map_err(|_| mismatch())?)
+            }
+            (OperandKind::Mem, Operand::Mem(reg, offset)) => {
+                instr.append_operand::<u8>(reg.into());
+                instr.append_operand(offset);
+            }
+            (OperandKind::Mem, Operand::Reg(reg)) => {
+                instr.append_operand::<u8>(reg.into());
+                instr.append_operand(0i16);
+            }
+            (OperandKind::Target | OperandKind::FnUid, Operand::Int(int)) => {
+                instr.append_operand(u64::try_from(int).map_err(|_| mismatch())?)
+            }
+            (OperandKind::Target | OperandKind::FnUid, Operand::UInt(uint)) => {
+                instr.append_operand(uint)
+            }
+            (OperandKind::Target, Operand::Label(label)) => {
+                instr.append_operand(0u64);
+                return Ok(Some(LabelRef::Target(label)));
+            }
+            (OperandKind::FnUid, Operand::Label(label)) => {
+                instr.append_operand(0u64);
+                return Ok(Some(LabelRef::Function(label)));
+            }
+            (OperandKind::UInt, Operand::Label(label)) => {
+                instr.append_operand(0u64);
+                return Ok(Some(LabelRef::Static(label)));
+            }
+            _ => return Err(mismatch()),
+        };
+        Ok(None)
+    }
+}
+
+impl From<Register> for Operand {
+    fn from(reg: Register) -> Self {
+        Operand::Reg(reg)
+    }
+}
+
+impl From<VirtualRegister> for Operand {
+    fn from(reg: VirtualRegister) -> Self {
+        Operand::Virtual(reg)
+    }
+}
+
+impl From<i64> for Operand {
+    fn from(int: i64) -> Self {
+        Operand::Int(int)
+    }
+}
+
+impl From<i32> for Operand {
+    fn from(int: i32) -> Self {
+        Operand::Int(int as i64)
+    }
+}
+
+impl From<u64> for Operand {
+    fn from(uint: u64) -> Self {
+        Operand::UInt(uint)
+    }
+}
+
+impl From<usize> for Operand {
+    fn from(uint: usize) -> Self {
+        Operand::UInt(uint as u64)
+    }
+}
+
+impl From<f32> for Operand {
+    fn from(float: f32) -> Self {
+        Operand::Float(float)
+    }
+}
+
+impl From<bool> for Operand {
+    fn from(boolean: bool) -> Self {
+        Operand::Bool(boolean)
+    }
+}
+
+impl From<(Register, i16)> for Operand {
+    fn from((reg, offset): (Register, i16)) -> Self {
+        Operand::Mem(reg, offset)
+    }
+}
+
+impl From<String> for Operand {
+    fn from(label: String) -> Self {
+        Operand::Label(label)
+    }
+}
+
+impl From<&str> for Operand {
+    fn from(label: &str) -> Self {
+        Operand::Label(String::from(label))
+    }
+}
+
+/// Assembles a textual program into a runnable output.
+///
+/// Every statement is either a label (`name:`) or an instruction made of an
+/// `Opcode` mnemonic and comma separated operands. Statements are separated
+/// by newlines or `;`, and `#` starts a comment.
+///
+/// ```text
+/// main:
+///     LDI 10, R1
+/// .loop:
+///     SUBI_I R1, 1, R1
+///     GTI R1, R0, R2
+///     JMPT R2, .loop
+///     RET
+/// ```
+///
+/// Operands are registers (`R0`..`R15`, `SP`, `IP`, `BP`), integer, float and
+/// boolean immediates, memory operands (`[SP - 8]`) and labels. Labels without
+/// a leading `.` mark functions, are callable with `CALL` and are exported
+/// in the output's function tables. Labels with a leading `.` are local to
+/// the function they appear in.
+///
+/// `.local name, type, offset` declares a named local of a primitive type,
+/// like `int`, `u8`, `f64` or `bool`, of the current function at an offset
+/// from its entry stack pointer, for inspection in a debugger.
+///
+/// `.container name, field: type, ...` declares the layout of a container
+/// type, whose fields follow each other without padding. Locals and later
+/// containers can use it as their type, debuggers then show its fields.
+///
+/// `.static name, type` reserves a zeroed static variable in program memory,
+/// whose name can be used as an address operand afterwards, e.g. with `LDA`.
+/// Statics keep their values when a core hot reloads the program.
+pub fn assemble(source: &str) -> Result<Output> {
+    assemble_file("<asm>", source)
+}
+
+/// Assembles a textual program like `assemble`, recording the line of every
+/// instruction under the given file name in the output's line table
+pub fn assemble_file(file: &str, source: &str) -> Result<Output> {
+    parse_file(file, source)?.build_output()
+}
+
+/// Assembles a textual program into a relocatable object named after the
+/// file, to link with other objects. Labels, functions and statics it uses
+/// but doesn't define are imported from them.
+pub fn assemble_object(file: &str, source: &str) -> Result<Object> {
+    parse_file(file, source)?.build_object(file)
+}
+
+/// Parses assembly source into an assembler without building it, e.g. to
+/// run passes over the instructions first
+pub fn parse_file(file: &str, source: &str) -> Result<Assembler> {
+    let mut assembler = Assembler::default();
+    let mut fn_name = String::new();
+    let mut fn_uid = 0;
+
+    for (line_index, line) in source.lines().enumerate() {
+        let line_nr = line_index + 1;
+        let code = line.split('#').next().unwrap_or_default();
+        for stmt in code.split(';') {
+            let mut stmt = stmt.trim();
+            if stmt.is_empty() {
+                continue;
+            }
+
+            let first_word = stmt.split_whitespace().next().unwrap_or_default();
+            if let Some(label) = first_word.strip_suffix(':') {
+                if !is_label(label) {
+                    return Err(Error::AsmSyntax(line_nr, format!("Invalid label {}", label)));
+                }
+                if label.starts_with('.') {
+                    assembler.push_label(scope_label(&fn_name, label));
+                } else {
+                    fn_name = String::from(label);
+                    assembler.push_fn_label(fn_name.clone(), fn_uid);
+                    fn_uid += 1;
+                }
+                stmt = stmt[first_word.len()..].trim();
+                if stmt.is_empty() {
+                    continue;
+                }
+            }
+
+            if let Some(local_str) = stmt.strip_prefix(".local ") {
+                if fn_uid == 0 {
+                    return Err(Error::AsmSyntax(line_nr, String::from("Local outside of function")));
+                }
+                let local = parse_local(&assembler, local_str)
+                    .map_err(|message| Error::AsmSyntax(line_nr, message))?;
+                assembler.push_local(fn_uid - 1, local);
+                continue;
+            }
+
+            if let Some(container_str) = stmt.strip_prefix(".container ") {
+                let (name, fields) = parse_container(&assembler, container_str)
+                    .map_err(|message| Error::AsmSyntax(line_nr, message))?;
+                assembler.push_container(name, fields);
+                continue;
+            }
+
+            if let Some(static_str) = stmt.strip_prefix(".static ") {
+                let (name, var_type, size) = parse_static(static_str)
+                    .map_err(|message| Error::AsmSyntax(line_nr, message))?;
+                if assembler.get_static_address(&name).is_some() {
+                    return Err(Error::AsmSyntax(line_nr, format!("Duplicate static {}", name)));
+                }
+                assembler.push_static(name, var_type, size);
+                continue;
+            }
+
+            let (mnemonic, operand_str) = match stmt.split_once(char::is_whitespace) {
+                Some((mnemonic, rest)) => (mnemonic, rest.trim()),
+                None => (stmt, ""),
+            };
+            let opcode = Opcode::from_str(mnemonic).map_err(|_| {
+                Error::AsmSyntax(line_nr, format!("Unknown mnemonic {}", mnemonic))
+            })?;
+            assembler.set_location(file, line_nr);
+            let mut operands = Vec::new();
+            if !operand_str.is_empty() {
+                for operand in operand_str.split(',') {
+                    let operand = parse_operand(operand.trim(), &fn_name)
+                        .map_err(|message| Error::AsmSyntax(line_nr, message))?;
+                    operands.push(operand);
+                }
+            }
+            assembler.push_asm(opcode, operands).map_err(|err| match err {
+                Error::OperandCount(..) | Error::OperandMismatch(..) => {
+                    Error::AsmSyntax(line_nr, format!("{:?}", err))
+                }
+                _ => err,
+            })?;
+        }
+    }
+
+    Ok(assembler)
+}
+
+fn is_label(label: &str) -> bool {
+    let name = label.strip_prefix('.').unwrap_or(label);
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) if first.is_ascii_alphabetic() || first == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+fn scope_label(fn_name: &str, label: &str) -> String {
+    format!("{}{}", fn_name, label)
+}
+
+fn parse_operand(operand: &str, fn_name: &str) -> StdResult<Operand, String> {
+    if let Some(inner) = operand
+        .strip_prefix('[')
+        .and_then(|rest| rest.strip_suffix(']'))
+    {
+        let (reg_str, offset) = match inner.find(|c| c == '+' || c == '-') {
+            Some(pos) => {
+                let offset_str: String = inner[pos..].split_whitespace().collect();
+                let offset = parse_int(&offset_str)
+                    .and_then(|offset| i16::try_from(offset).ok())
+                    .ok_or_else(|| format!("Invalid offset in {}", operand))?;
+                (inner[..pos].trim(), offset)
+            }
+            None => (inner.trim(), 0),
+        };
+        let reg = Register::from_str(reg_str)
+            .map_err(|_| format!("Invalid register {}", reg_str))?;
+        return Ok(Operand::Mem(reg, offset));
+    }
+    if let Ok(reg) = Register::from_str(operand) {
+        return Ok(Operand::Reg(reg));
+    }
+    match operand {
+        "true" => return Ok(Operand::Bool(true)),
+        "false" => return Ok(Operand::Bool(false)),
+        _ => {}
+    };
+    if is_label(operand) {
+        if operand.starts_with('.') {
+            return Ok(Operand::Label(scope_label(fn_name, operand)));
+        }
+        return Ok(Operand::Label(String::from(operand)));
+    }
+    if let Some(int) = parse_int(operand) {
+        return Ok(Operand::Int(int));
+    }
+    if let Ok(uint) = operand.parse::<u64>() {
+        return Ok(Operand::UInt(uint));
+    }
+    if let Ok(float) = operand.parse::<f32>() {
+        return Ok(Operand::Float(float));
+    }
+    Err(format!("Invalid operand {}", operand))
+}
+
+fn parse_local(assembler: &Assembler, local_str: &str) -> StdResult<LocalVar, String> {
+    let parts: Vec<&str> = local_str.split(',').map(str::trim).collect();
+    if parts.len() != 3 || !is_label(parts[0]) {
+        return Err(format!("Invalid local {}", local_str));
+    }
+    let (var_type, size) = parse_debug_type(assembler, parts[1])
+        .ok_or_else(|| format!("Invalid local type {}", parts[1]))?;
+    let offset = parse_int(parts[2])
+        .and_then(|offset| i32::try_from(offset).ok())
+        .ok_or_else(|| format!("Invalid local offset {}", parts[2]))?;
+    Ok(LocalVar {
+        name: String::from(parts[0]),
+        offset,
+        size,
+        var_type,
+    })
+}
+
+fn parse_static(static_str: &str) -> StdResult<(String, Type, usize), String> {
+    let parts: Vec<&str> = static_str.split(',').map(str::trim).collect();
+    if parts.len() != 2 || !is_label(parts[0]) || parts[0].starts_with('.') {
+        return Err(format!("Invalid static {}", static_str));
+    }
+    let (var_type, size) =
+        parse_type(parts[1]).ok_or_else(|| format!("Invalid static type {}", parts[1]))?;
+    Ok((String::from(parts[0]), var_type, size))
+}
+
+fn parse_container(
+    assembler: &Assembler,
+    container_str: &str,
+) -> StdResult<(String, Vec<LocalVar>), String> {
+    let mut parts = container_str.split(',').map(str::trim);
+    let name = parts.next().unwrap_or_default();
+    if !is_label(name) || name.starts_with('.') || parse_type(name).is_some() {
+        return Err(format!("Invalid container {}", container_str));
+    }
+    let mut fields: Vec<LocalVar> = Vec::new();
+    let mut offset = 0;
+    for field_str in parts {
+        let (field_name, type_str) = field_str
+            .split_once(':')
+            .map(|(field_name, type_str)| (field_name.trim(), type_str.trim()))
+            .filter(|(field_name, _)| is_label(field_name) && !field_name.starts_with('.'))
+            .ok_or_else(|| format!("Invalid field {}", field_str))?;
+        if fields.iter().any(|field| field.name == field_name) {
+            return Err(format!("Duplicate field {}", field_name));
+        }
+        let (var_type, size) = parse_debug_type(assembler, type_str)
+            .ok_or_else(|| format!("Invalid field type {}", type_str))?;
+        fields.push(LocalVar {
+            name: String::from(field_name),
+            offset,
+            size,
+            var_type,
+        });
+        offset += size as i32;
+    }
+    Ok((String::from(name), fields))
+}
+
+/// Returns a primitive or previously declared container type and its size
+fn parse_debug_type(assembler: &Assembler, type_str: &str) -> Option<(Type, usize)> {
+    parse_type(type_str).or_else(|| {
+        let size = assembler.get_container_size(type_str)?;
+        Some((Type::Named(String::from(type_str)), size))
+    })
+}
+
+/// Returns a primitive type and its size
+fn parse_type(type_str: &str) -> Option<(Type, usize)> {
+    Some(match type_str {
+        "int" | "i64" => (Type::Int, 8),
+        "float" | "f32" => (Type::Float, 4),
+        "i8" => (Type::I8, 1),
+        "i16" => (Type::I16, 2),
+        "i32" => (Type::I32, 4),
+        "u8" => (Type::U8, 1),
+        "u16" => (Type::U16, 2),
+        "u32" => (Type::U32, 4),
+        "u64" => (Type::U64, 8),
+        "f64" => (Type::F64, 8),
+        "bool" => (Type::Bool, 1),
+        _ => return None,
+    })
+}
+
+fn parse_int(int_str: &str) -> Option<i64> {
+    let (negative, digits) = match int_str.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, int_str.strip_prefix('+').unwrap_or(int_str)),
+    };
+    let value = match digits.strip_prefix("0x") {
+        Some(hex) => i64::from_str_radix(hex, 16).ok()?,
+        None => digits.parse::<i64>().ok()?,
+    };
+    Some(if negative { -value } else { value })
+}
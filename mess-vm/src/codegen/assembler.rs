@@ -1,13 +1,75 @@
 use std::{
-    collections::HashMap,
+    collections::{
+        BTreeMap,
+        BTreeSet,
+        HashMap,
+    },
     ops::DerefMut,
 };
 
 use bincode::serialize;
+use mess_api::prelude::Function;
+use mess_core::parser::ast::Type;
 use serde::Serialize;
 
-use super::instruction::Instruction;
-use crate::exec::is::Opcode;
+use super::{
+    asm::{
+        LabelRef,
+        Operand,
+    },
+    error::{
+        Error,
+        Result,
+    },
+    instruction::Instruction,
+    object::{
+        Object,
+        Relocation,
+    },
+    output::{
+        LocalVar,
+        Output,
+        SourceLocation,
+        StaticVar,
+    },
+};
+use crate::exec::{
+    address::{
+        Address,
+        AddressType,
+    },
+    is::{
+        OperandKind,
+        Opcode,
+    },
+};
+
+/// The instructions replacing a single instruction in `Assembler::rewrite`
+pub struct Replacement {
+    /// The new instructions, empty if the instruction is removed
+    pub instructions: Vec<Instruction>,
+    /// The position of the original instruction among the new ones, if it is kept.
+    /// Its operand layout has to stay the same, as label references point into it.
+    pub original: Option<usize>,
+}
+
+impl Replacement {
+    /// Keeps the instruction, possibly with changed operands
+    pub fn keep(instruction: Instruction) -> Self {
+        Replacement {
+            instructions: vec![instruction],
+            original: Some(0),
+        }
+    }
+
+    /// Removes the instruction
+    pub fn remove() -> Self {
+        Replacement {
+            instructions: Vec::new(),
+            original: None,
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct Assembler {
@@ -16,6 +78,23 @@ pub struct Assembler {
     pub jmp_instructions: Vec<usize>,
     pub labels: HashMap<String, usize>,
     pub tags: HashMap<u64, Vec<usize>>,
+    /// Operands still referencing a label, as (instruction index, operand byte offset, label)
+    pub label_refs: Vec<(usize, usize, LabelRef)>,
+    fn_labels: BTreeMap<String, u64>,
+    /// Host functions callable by name, keyed by their uid
+    foreign_functions: BTreeMap<u64, (String, Function)>,
+    /// Functions defined by other objects, keyed by the uid calls use for them
+    extern_fns: BTreeMap<u64, String>,
+    /// Functions and statics left out of the exports of built objects
+    private_symbols: BTreeSet<String>,
+    /// Source locations, keyed by the index of the first instruction they apply to
+    pub debug_lines: BTreeMap<usize, SourceLocation>,
+    /// Named locals of each function, keyed by function uid
+    pub debug_locals: BTreeMap<u64, Vec<LocalVar>>,
+    /// Fields of each container type, keyed by type name
+    pub debug_containers: BTreeMap<String, Vec<LocalVar>>,
+    /// Named static variables in static data, in declaration order
+    statics: Vec<StaticVar>,
     tag_counter: u64,
 }
 
@@ -27,6 +106,15 @@ impl Default for Assembler {
             labels: HashMap::new(),
             tags: HashMap::new(),
             jmp_instructions: Vec::new(),
+            label_refs: Vec::new(),
+            fn_labels: BTreeMap::new(),
+            foreign_functions: BTreeMap::new(),
+            extern_fns: BTreeMap::new(),
+            private_symbols: BTreeSet::new(),
+            debug_lines: BTreeMap::new(),
+            debug_locals: BTreeMap::new(),
+            debug_containers: BTreeMap::new(),
+            statics: Vec::new(),
             tag_counter: 0,
         }
     }
@@ -37,6 +125,71 @@ impl Assembler {
         self.labels.insert(label, self.instructions.len());
     }
 
+    /// Pushes a label marking the entry point of a function with the given uid
+    pub fn push_fn_label(&mut self, label: String, uid: u64) {
+        self.fn_labels.insert(label.clone(), uid);
+        self.push_label(label);
+    }
+
+    /// Registers a host function, which `CALL` can reference by name
+    pub fn push_foreign_fn(&mut self, name: String, uid: u64, function: Function) {
+        self.foreign_functions.insert(uid, (name, function));
+    }
+
+    /// Declares a function defined by another object. Calls to the uid are
+    /// linked to the function with the name.
+    pub fn push_extern_fn(&mut self, name: String, uid: u64) {
+        self.extern_fns.insert(uid, name);
+    }
+
+    /// Keeps the function or static variable with the name out of the
+    /// exports of built objects, so only code of the same object can use it
+    pub fn set_private(&mut self, name: String) {
+        self.private_symbols.insert(name);
+    }
+
+    /// Marks the following instructions as generated from the given source location
+    pub fn set_location(&mut self, file: &str, line: usize) {
+        let location = self
+            .debug_lines
+            .range(..=self.instructions.len())
+            .next_back()
+            .map(|(_, location)| location);
+        if location.map(|l| l.file == file && l.line == line) == Some(true) {
+            return;
+        }
+        self.debug_lines.insert(
+            self.instructions.len(),
+            SourceLocation {
+                file: String::from(file),
+                line,
+            },
+        );
+    }
+
+    /// Records a named local of the function with the given uid
+    pub fn push_local(&mut self, fn_uid: u64, local: LocalVar) {
+        self.debug_locals.entry(fn_uid).or_default().push(local);
+    }
+
+    /// Records the fields of a container type, for debuggers to show the
+    /// fields of its values
+    pub fn push_container(&mut self, name: String, fields: Vec<LocalVar>) {
+        self.debug_containers.insert(name, fields);
+    }
+
+    /// Returns the size of a container type recorded with `push_container`
+    pub fn get_container_size(&self, name: &str) -> Option<usize> {
+        let fields = self.debug_containers.get(name)?;
+        Some(
+            fields
+                .iter()
+                .map(|field| field.offset.max(0) as usize + field.size)
+                .max()
+                .unwrap_or(0),
+        )
+    }
+
     pub fn new_tag(&mut self) -> u64 {
         let ret = self.tag_counter;
         self.tag_counter += 1;
@@ -76,6 +229,25 @@ impl Assembler {
         self.instructions.push(instruction);
     }
 
+    /// Encodes an instruction from assembly operands and pushes it.
+    /// Label operands are resolved when building the output.
+    pub fn push_asm(&mut self, opcode: Opcode, operands: Vec<Operand>) -> Result<()> {
+        let kinds = opcode.operand_kinds();
+        if kinds.len() != operands.len() {
+            return Err(Error::OperandCount(opcode, operands.len()));
+        }
+        let mut instruction = Instruction::new(opcode.clone());
+        for (operand, kind) in operands.into_iter().zip(kinds.iter()) {
+            let offset = instruction.operands.len();
+            if let Some(label_ref) = operand.encode(&opcode, *kind, &mut instruction)? {
+                self.label_refs
+                    .push((self.instructions.len(), offset, label_ref));
+            }
+        }
+        self.push_instr(instruction);
+        Ok(())
+    }
+
     pub fn append_instr(&mut self, mut instructions: Vec<Instruction>) {
         self.instructions.append(&mut instructions);
     }
@@ -85,6 +257,171 @@ impl Assembler {
         self.data.append(&mut data);
     }
 
+    /// Stores a length prefixed string in static data, returning its address
+    pub fn push_string(&mut self, string: &str) -> u64 {
+        let address = Address::new(self.data.len() as u64, AddressType::Program);
+        self.push_data(string);
+        address.into()
+    }
+
+    /// Marks an operand of the last pushed instruction as an address in
+    /// static data, e.g. of a string from `push_string`, so it is moved with
+    /// the code when linking
+    pub fn push_data_ref(&mut self, operand_offset: usize, address: u64) {
+        if let Some(instr_index) = self.instructions.len().checked_sub(1) {
+            self.label_refs
+                .push((instr_index, operand_offset, LabelRef::Data(address)));
+        }
+    }
+
+    /// Reserves zeroed static data for a named static variable, returning its address
+    pub fn push_static(&mut self, name: String, var_type: Type, size: usize) -> u64 {
+        let offset = self.data.len();
+        self.data.resize(offset + size, 0);
+        self.statics.push(StaticVar {
+            name,
+            offset,
+            size,
+            var_type,
+        });
+        Address::new(offset as u64, AddressType::Program).into()
+    }
+
+    /// Reserves static data for a named static variable, initialized with
+    /// the given bytes, returning its address
+    pub fn push_static_value(&mut self, name: String, var_type: Type, value: &[u8]) -> u64 {
+        let offset = self.data.len();
+        let address = self.push_static(name, var_type, value.len());
+        self.data[offset..].copy_from_slice(value);
+        address
+    }
+
+    /// Returns the address of the static variable with the given name
+    pub fn get_static_address(&self, name: &str) -> Option<u64> {
+        self.statics
+            .iter()
+            .find(|var| var.name == name)
+            .map(|var| Address::new(var.offset as u64, AddressType::Program).into())
+    }
+
+    /// Replaces every instruction with the instructions of its replacement,
+    /// remapping labels, tags, label references, line tables and encoded jump
+    /// targets. Labels and jumps to an instruction land on the first of its
+    /// replacement instructions, or on the following ones if it was removed.
+    /// Tags of removed instructions are dropped.
+    pub fn rewrite(&mut self, replacements: Vec<Replacement>) {
+        assert_eq!(replacements.len(), self.instructions.len());
+        let count = self.instructions.len();
+
+        // Jumps with encoded targets, as (index, operand offset, target index)
+        let offsets = self.get_instr_offsets();
+        let mut encoded_jumps = Vec::new();
+        for (index, instr) in self.instructions.iter().enumerate() {
+            let operand_offset = match instr.get_target_offset() {
+                Some(operand_offset) => operand_offset,
+                None => continue,
+            };
+            let has_label = self.label_refs.iter().any(|(instr_index, offset, label_ref)| {
+                *instr_index == index
+                    && *offset == operand_offset
+                    && matches!(label_ref, LabelRef::Target(_))
+            });
+            let target = instr.get_operand::<u64>(operand_offset, 8) as usize;
+            if let (false, Ok(target_index)) = (has_label, offsets.binary_search(&target)) {
+                encoded_jumps.push((index, operand_offset, target_index));
+            }
+        }
+
+        let mut instructions = Vec::with_capacity(count);
+        let mut group_starts = Vec::with_capacity(count + 1);
+        let mut kept = Vec::with_capacity(count + 1);
+        for replacement in replacements {
+            group_starts.push(instructions.len());
+            kept.push(replacement.original.map(|pos| instructions.len() + pos));
+            instructions.extend(replacement.instructions);
+        }
+        group_starts.push(instructions.len());
+        kept.push(Some(instructions.len()));
+        let map = |index: usize| group_starts[index.min(count)];
+        let map_kept = |index: usize| kept[index.min(count)];
+
+        for label_index in self.labels.values_mut() {
+            *label_index = map(*label_index);
+        }
+        for tag_list in self.tags.values_mut() {
+            *tag_list = tag_list.iter().filter_map(|index| map_kept(*index)).collect();
+        }
+        self.label_refs = std::mem::take(&mut self.label_refs)
+            .into_iter()
+            .filter_map(|(index, offset, label_ref)| {
+                map_kept(index).map(|index| (index, offset, label_ref))
+            })
+            .collect();
+        let (kept_lines, removed_lines): (Vec<_>, Vec<_>) = std::mem::take(&mut self.debug_lines)
+            .into_iter()
+            .partition(|(index, _)| map_kept(*index).is_some());
+        self.debug_lines = kept_lines
+            .into_iter()
+            .map(|(index, location)| (map(index), location))
+            .collect();
+        // A removed instruction's location carries over unless the next has its own
+        for (index, location) in removed_lines {
+            self.debug_lines.entry(map(index)).or_insert(location);
+        }
+
+        self.instructions = instructions;
+        self.jmp_instructions = self
+            .instructions
+            .iter()
+            .enumerate()
+            .filter(|(_, instr)| instr.get_target_offset().is_some())
+            .map(|(index, _)| index)
+            .collect();
+
+        let offsets = self.get_instr_offsets();
+        for (index, operand_offset, target_index) in encoded_jumps {
+            if let Some(new_index) = map_kept(index) {
+                let target = offsets[map(target_index)] as u64;
+                self.instructions[new_index].set_operand(operand_offset, target);
+            }
+        }
+    }
+
+    /// Returns the index of the instruction the jump at the given index lands on,
+    /// given the offsets of `get_instr_offsets`. Returns `None` if the instruction
+    /// is not a jump or its target is not the start of an instruction.
+    pub fn get_jump_target(&self, index: usize, offsets: &[usize]) -> Option<usize> {
+        let instr = self.instructions.get(index)?;
+        let operand_offset = instr.get_target_offset()?;
+        let label = self.label_refs.iter().find_map(|(instr_index, offset, label_ref)| {
+            match label_ref {
+                LabelRef::Target(label) if *instr_index == index && *offset == operand_offset => {
+                    Some(label)
+                }
+                _ => None,
+            }
+        });
+        match label {
+            Some(label) => self.labels.get(label).copied(),
+            None => {
+                let target = instr.get_operand::<u64>(operand_offset, 8) as usize;
+                offsets.binary_search(&target).ok()
+            }
+        }
+    }
+
+    /// Returns the code offset of every instruction, followed by the end of the code
+    pub fn get_instr_offsets(&self) -> Vec<usize> {
+        let mut offsets = Vec::with_capacity(self.instructions.len() + 1);
+        let mut offset = self.data.len();
+        for instr in self.instructions.iter() {
+            offsets.push(offset);
+            offset += instr.get_size();
+        }
+        offsets.push(offset);
+        offsets
+    }
+
     pub fn build(mut self) -> Vec<u8> {
         let mut code = Vec::new();
 
@@ -98,6 +435,203 @@ impl Assembler {
         code
     }
 
+    /// Resolves all label references and builds a runnable output,
+    /// exposing every function label in its function tables. Fails with
+    /// `Error::MissingSymbol` if functions of other objects were declared,
+    /// as only a linker can resolve them.
+    pub fn build_output(mut self) -> Result<Output> {
+        if let Some(name) = self.extern_fns.values().next() {
+            return Err(Error::MissingSymbol(name.clone()));
+        }
+        let instr_offsets = self.get_instr_offsets();
+
+        for (instr_index, operand_offset, label_ref) in self.label_refs.iter() {
+            let value = match label_ref {
+                LabelRef::Target(label) => {
+                    let label_index = self
+                        .labels
+                        .get(label)
+                        .ok_or_else(|| Error::UnknownLabel(label.clone()))?;
+                    instr_offsets[*label_index] as u64
+                }
+                LabelRef::Function(label) => self
+                    .fn_labels
+                    .get(label)
+                    .copied()
+                    .or_else(|| {
+                        self.foreign_functions
+                            .iter()
+                            .find(|(_, (name, _))| name == label)
+                            .map(|(uid, _)| *uid)
+                    })
+                    .ok_or_else(|| Error::UnknownLabel(label.clone()))?,
+                LabelRef::Static(label) => self
+                    .get_static_address(label)
+                    .ok_or_else(|| Error::UnknownLabel(label.clone()))?,
+                LabelRef::Data(address) => *address,
+            };
+            let bytes = serialize(&value).expect("ERROR Serializing operand!");
+            let instruction = &mut self.instructions[*instr_index];
+            instruction.operands[*operand_offset..*operand_offset + bytes.len()]
+                .copy_from_slice(&bytes);
+        }
+
+        let mut functions = BTreeMap::new();
+        let mut function_name_map = BTreeMap::new();
+        for (label, uid) in std::mem::take(&mut self.fn_labels) {
+            let label_index = self.labels.get(&label).ok_or(Error::Unknown)?;
+            functions.insert(uid, instr_offsets[*label_index]);
+            function_name_map.insert(label, uid);
+        }
+
+        let debug_lines = std::mem::take(&mut self.debug_lines)
+            .into_iter()
+            .filter(|(instr_index, _)| *instr_index < self.instructions.len())
+            .map(|(instr_index, location)| (instr_offsets[instr_index], location))
+            .collect();
+        let debug_locals = std::mem::take(&mut self.debug_locals);
+        let debug_containers = std::mem::take(&mut self.debug_containers);
+        let statics = std::mem::take(&mut self.statics);
+        let foreign_functions = std::mem::take(&mut self.foreign_functions)
+            .into_iter()
+            .map(|(uid, (_, function))| (uid, function))
+            .collect();
+
+        Ok(Output::new()
+            .with_code(self.build())
+            .with_functions(functions)
+            .with_function_name_map(function_name_map)
+            .with_foreign_functions(foreign_functions)
+            .with_statics(statics)
+            .with_debug_lines(debug_lines)
+            .with_debug_locals(debug_locals)
+            .with_debug_containers(debug_containers))
+    }
+
+    /// Builds a relocatable object, leaving functions and statics that are
+    /// not defined here as imports. Code offsets in operands, function uids
+    /// and static addresses are recorded as relocations for the linker.
+    pub fn build_object(mut self, name: &str) -> Result<Object> {
+        let instr_offsets = self.get_instr_offsets();
+        let foreign_names: BTreeMap<u64, String> = self
+            .foreign_functions
+            .iter()
+            .map(|(uid, (name, _))| (*uid, name.clone()))
+            .collect();
+        let fn_names: BTreeMap<u64, String> = self
+            .fn_labels
+            .iter()
+            .map(|(label, uid)| (*uid, label.clone()))
+            .collect();
+        let is_function = |label: &String| {
+            self.fn_labels.contains_key(label) || foreign_names.values().any(|name| name == label)
+        };
+
+        let mut imports = BTreeSet::new();
+        let mut relocations = Vec::new();
+        let mut patched = BTreeSet::new();
+        for (instr_index, operand_offset, label_ref) in self.label_refs.iter() {
+            let (value, relocation) = match label_ref {
+                LabelRef::Target(label) => {
+                    let label_index = self
+                        .labels
+                        .get(label)
+                        .ok_or_else(|| Error::UnknownLabel(label.clone()))?;
+                    (instr_offsets[*label_index] as u64, Relocation::Local)
+                }
+                LabelRef::Data(address) => (*address, Relocation::Local),
+                LabelRef::Function(label) => {
+                    if !is_function(label) {
+                        imports.insert(label.clone());
+                    }
+                    (0, Relocation::Function(label.clone()))
+                }
+                LabelRef::Static(label) => {
+                    if self.get_static_address(label).is_none() {
+                        imports.insert(label.clone());
+                    }
+                    (0, Relocation::Static(label.clone()))
+                }
+            };
+            let bytes = serialize(&value).expect("ERROR Serializing operand!");
+            let instruction = &mut self.instructions[*instr_index];
+            instruction.operands[*operand_offset..*operand_offset + bytes.len()]
+                .copy_from_slice(&bytes);
+            patched.insert((*instr_index, *operand_offset));
+            relocations.push((instr_offsets[*instr_index] + 1 + operand_offset, relocation));
+        }
+
+        // Jump targets and calls encoded without labels, e.g. by the compiler
+        for (instr_index, instr) in self.instructions.iter().enumerate() {
+            for operand_offset in instr.get_operand_offsets(OperandKind::Target) {
+                if !patched.contains(&(instr_index, operand_offset)) {
+                    let offset = instr_offsets[instr_index] + 1 + operand_offset;
+                    relocations.push((offset, Relocation::Local));
+                }
+            }
+            for operand_offset in instr.get_operand_offsets(OperandKind::FnUid) {
+                if patched.contains(&(instr_index, operand_offset)) {
+                    continue;
+                }
+                let uid = instr.get_operand::<u64>(operand_offset, 8);
+                if let Some(name) = self.extern_fns.get(&uid) {
+                    imports.insert(name.clone());
+                }
+                let name = foreign_names
+                    .get(&uid)
+                    .or_else(|| fn_names.get(&uid))
+                    .or_else(|| self.extern_fns.get(&uid))
+                    .ok_or_else(|| Error::UnknownFunction(format!("uid {}", uid)))?;
+                relocations.push((
+                    instr_offsets[instr_index] + 1 + operand_offset,
+                    Relocation::Function(name.clone()),
+                ));
+            }
+        }
+        relocations.sort_by_key(|(offset, _)| *offset);
+
+        let mut functions = BTreeMap::new();
+        for (label, _) in self.fn_labels.iter() {
+            let label_index = self.labels.get(label).ok_or(Error::Unknown)?;
+            functions.insert(label.clone(), instr_offsets[*label_index]);
+        }
+        let debug_lines = std::mem::take(&mut self.debug_lines)
+            .into_iter()
+            .filter(|(instr_index, _)| *instr_index < self.instructions.len())
+            .map(|(instr_index, location)| (instr_offsets[instr_index], location))
+            .collect();
+        let debug_locals = std::mem::take(&mut self.debug_locals)
+            .into_iter()
+            .filter_map(|(uid, locals)| Some((fn_names.get(&uid)?.clone(), locals)))
+            .collect();
+        let foreign_functions = relocations
+            .iter()
+            .filter_map(|(_, relocation)| match relocation {
+                Relocation::Function(name) if foreign_names.values().any(|n| n == name) => {
+                    Some(name.clone())
+                }
+                _ => None,
+            })
+            .collect();
+        let debug_containers = std::mem::take(&mut self.debug_containers);
+        let statics = std::mem::take(&mut self.statics);
+        let private = std::mem::take(&mut self.private_symbols);
+
+        Ok(Object {
+            name: String::from(name),
+            code: self.build(),
+            functions,
+            statics,
+            private,
+            foreign_functions,
+            imports,
+            relocations,
+            debug_lines,
+            debug_locals,
+            debug_containers,
+        })
+    }
+
     pub fn get_label_offset(&mut self, label: &String) -> Option<usize> {
         let mut code_before_size = 0;
         let label_instr_offset = self.labels.get(label).or(None)?;
@@ -1,13 +1,16 @@
 use std::{
     collections::{
-        HashMap,
+        BTreeMap,
         VecDeque,
     },
     result::Result as StdResult,
     error::Error as StdError
 };
 
-use mess_api::prelude::Module;
+use mess_api::prelude::{
+    Function,
+    Module,
+};
 use mess_core::{
     codegen::{
         ctx::{
@@ -24,6 +27,7 @@ use mess_core::{
     },
     compiler::Compiler as CompilerTrait,
     parser::ast::{
+        AstItem,
         Declaration,
         Expression,
         Operator,
@@ -35,6 +39,7 @@ use mess_core::{
 use mess_vm_derive::asm;
 
 use crate::{
+    adapter::get_size_of_type as get_size_of_api_type,
     codegen::{
         assembler::Assembler,
         error::{
@@ -42,7 +47,16 @@ use crate::{
             Result,
         },
         instruction::Instruction,
-        output::Output as OutputVM,
+        object::Object,
+        output::{
+            LocalVar,
+            Output as OutputVM,
+        },
+        peephole,
+        regalloc::{
+            RegisterAllocator,
+            VirtualRegister,
+        },
         register::Register,
     },
     exec::{
@@ -51,60 +65,159 @@ use crate::{
     },
 };
 
+/// The first uid the compiler generates, for host functions and stack
+/// contexts. Script functions are numbered from 0 by the declarator.
+pub(crate) const FIRST_GENERATED_UID: u64 = 1 << 32;
+/// The file name of the line table when no source path was given
+const UNNAMED_SOURCE: &str = "<source>";
+
+/// Where an assigned value is stored
+enum AssignTarget {
+    /// The stack position of a local
+    Stack(i32),
+    /// The canonical name of a static variable
+    Static(String),
+}
+
 pub struct Compiler {
     mod_def_stack: VecDeque<ModuleDef>,
     stack_ctx_stack: VecDeque<StackContext>,
     fn_ctx_stack: VecDeque<FnContext>,
     uid_gen: UIDGenerator,
     assembler: Assembler,
+    reg_alloc: RegisterAllocator,
     declarator: Declarator,
+    foreign_functions: BTreeMap<u64, Function>,
+    foreign_modules: Vec<ModuleDef>,
+    source_path: Option<String>,
+    /// The uids script functions were declared with, by canonical name
+    label_uids: BTreeMap<String, u64>,
+    /// The types of the static variables, by canonical name
+    statics: BTreeMap<String, Type>,
 }
 
 impl CompilerTrait for Compiler {
     type Output = OutputVM;
     type Error = Error;
 
-    fn get_output(&mut self) -> Self::Output {
+    /// Builds the code compiled so far into a runnable output, with its
+    /// function tables, statics and debug tables
+    fn get_output(&mut self) -> StdResult<Self::Output, Self::Error> {
         let mut assembler = Assembler::default();
         std::mem::swap(&mut assembler, &mut self.assembler);
-        let code = assembler.build();
-        OutputVM::new()
-            .with_code(code)
+        self.push_foreign_fns(&mut assembler);
+        peephole::optimize(&mut assembler);
+        assembler.build_output()
     }
 
     fn compile(&mut self, decl_list: &[Declaration]) -> StdResult<(), Self::Error> {
         self.declarator.declare(decl_list).map_err(|_| Error::Unknown)?;
-        let (root_mod_def, _) = self.declarator.get_result().map_err(|_| Error::Unknown)?;
+        let (mut root_mod_def, _) = self.declarator.get_result().map_err(|_| Error::Unknown)?;
+        for fn_def in root_mod_def.functions.values() {
+            self.label_uids.insert(fn_def.canon_name.clone(), fn_def.label_uid);
+        }
+        for mod_def in self.foreign_modules.iter() {
+            root_mod_def.add_module(mod_def.clone());
+        }
         self.set_root_module(root_mod_def);
         self.compile_decl_list(decl_list)
     }
 
+    /// Makes the functions of a host module callable as `module::function`.
+    /// Each function gets a uid, which calls to it are compiled to.
     fn register_module(&mut self, module: Module) -> StdResult<(), Self::Error> {
-        unimplemented!("Not implemented yet!");
+        let mut mod_def = ModuleDef::new("root::", module.name.as_str());
+        let fn_path = format!("root::{}::", module.name);
+        for (_, mut function) in module.functions {
+            let uid = self.get_next_uid();
+            function.set_arg_sizes(function.args.iter().map(get_size_of_api_type).collect());
+            mod_def.add_function(FunctionDef::from_api(uid, &fn_path, function.clone()));
+            self.foreign_functions.insert(uid, function);
+        }
+        self.foreign_modules.push(mod_def);
+        Ok(())
     }
 }
 
 impl Default for Compiler {
     fn default() -> Self {
         let mod_def_stack = VecDeque::new();
-        let x = asm!(&mut self.assembler);
         Self {
             mod_def_stack,
-            uid_gen: UIDGenerator::default(),
+            uid_gen: UIDGenerator::starting_at(FIRST_GENERATED_UID),
             stack_ctx_stack: VecDeque::new(),
             fn_ctx_stack: VecDeque::new(),
             assembler: Assembler::default(),
+            reg_alloc: RegisterAllocator::new(),
             declarator: Declarator::default(),
+            foreign_functions: BTreeMap::new(),
+            foreign_modules: Vec::new(),
+            source_path: None,
+            label_uids: BTreeMap::new(),
+            statics: BTreeMap::new(),
         }
     }
 }
 
 impl Compiler {
+    /// Names the file the compiled declarations are parsed from, which the
+    /// line table refers to
+    pub fn with_source_path(mut self, path: &str) -> Compiler {
+        self.set_source_path(Some(path));
+        self
+    }
+
+    /// Names the file the compiled declarations are parsed from, which the
+    /// line table refers to, or `None` for source not read from a file
+    pub fn set_source_path(&mut self, path: Option<&str>) {
+        self.source_path = path.map(String::from);
+    }
+
     pub fn set_root_module(&mut self, module_def: ModuleDef) {
         self.mod_def_stack.clear();
         self.mod_def_stack.push_front(module_def);
     }
 
+    /// Builds the code compiled so far into a relocatable object, to link
+    /// with the objects of other modules. Host functions are referenced by
+    /// their module path, so each object can be compiled by its own compiler.
+    pub fn get_object(&mut self, name: &str) -> Result<Object> {
+        let mut assembler = Assembler::default();
+        std::mem::swap(&mut assembler, &mut self.assembler);
+        self.push_foreign_fns(&mut assembler);
+        peephole::optimize(&mut assembler);
+        assembler.build_object(name)
+    }
+
+    /// Registers the host functions with the assembler by their module path
+    fn push_foreign_fns(&self, assembler: &mut Assembler) {
+        for mod_def in self.foreign_modules.iter() {
+            for fn_def in mod_def.functions.values() {
+                if let Some(function) = self.foreign_functions.get(&fn_def.label_uid) {
+                    assembler.push_foreign_fn(
+                        fn_def.canon_name.clone(),
+                        fn_def.label_uid,
+                        function.clone(),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Drops the declarations and code compiled so far, keeping the
+    /// registered host modules, e.g. to compile a changed script again.
+    /// Functions compiled again keep their uids, so a core can reload the
+    /// new output without invalidating the uids its host looked up.
+    pub fn reset(&mut self) {
+        self.mod_def_stack.clear();
+        self.stack_ctx_stack.clear();
+        self.fn_ctx_stack.clear();
+        self.assembler = Assembler::default();
+        self.reg_alloc = RegisterAllocator::new();
+        self.declarator = Declarator::with_label_uids(self.label_uids.clone());
+        self.statics.clear();
+    }
+
     fn get_module_path(&self) -> Result<String> {
         let mut path = String::new();
         for mod_def in self.mod_def_stack.iter() {
@@ -148,17 +261,37 @@ impl Compiler {
     }
 
     fn get_var_position(&self, name: &str) -> Result<i32> {
-        let stack_ctx = self.stack_ctx_stack.get(0).ok_or(Error::Unknown)?;
+        let stack_ctx = self.get_var_ctx(name)?;
         let raw = stack_ctx.get_var(name);
         Ok(raw.0)
     }
 
     fn get_var_type(&self, name: &str) -> Result<Type> {
-        let stack_ctx = self.stack_ctx_stack.get(0).ok_or(Error::Unknown)?;
+        let stack_ctx = self.get_var_ctx(name)?;
         let raw = stack_ctx.get_var(name);
         Ok(raw.1.clone())
     }
 
+    /// Returns the stack context of the current function if it has the variable
+    fn get_var_ctx(&self, name: &str) -> Result<&StackContext> {
+        let stack_ctx = self.stack_ctx_stack.front().ok_or(Error::Unknown)?;
+        if !stack_ctx.has_var(name) {
+            return Err(Error::UnknownVariable(String::from(name)));
+        }
+        Ok(stack_ctx)
+    }
+
+    /// Returns the canonical name and type of the static variable of the
+    /// current module, unless a local of the current function shadows it
+    fn get_static(&self, name: &str) -> Option<(String, Type)> {
+        if self.get_var_ctx(name).is_ok() {
+            return None;
+        }
+        let canon_name = format!("{}{}", self.get_module_path().ok()?, name);
+        let var_type = self.statics.get(&canon_name)?.clone();
+        Some((canon_name, var_type))
+    }
+
     fn get_current_module(&self) -> Result<&ModuleDef> {
         self.mod_def_stack.get(0).ok_or(Error::Unknown)
     }
@@ -211,16 +344,24 @@ impl Compiler {
 
     fn get_size_of_type(&self, var_type: &Type) -> Result<usize> {
         match var_type {
-            Type::Int => Ok(8),
-            Type::Float => Ok(4),
-            Type::Ref(_) => Ok(8),
+            Type::Void => Ok(0),
+            Type::Int | Type::U64 | Type::F64 => Ok(8),
+            Type::Float | Type::I32 | Type::U32 => Ok(4),
+            Type::I16 | Type::U16 => Ok(2),
+            Type::I8 | Type::U8 => Ok(1),
+            Type::Ref(_) | Type::Coroutine(_) => Ok(8),
             Type::Bool => Ok(1),
             _ => return Err(Error::UnknownType(var_type.clone())),
         }
     }
 
+    /// Compiles the declarations, the static variables first, so functions
+    /// can use the ones declared after them
     pub fn compile_decl_list(&mut self, decl_list: &[Declaration]) -> Result<()> {
-        for decl in decl_list {
+        let (statics, decls): (Vec<_>, Vec<_>) = decl_list
+            .iter()
+            .partition(|decl| matches!(decl, Declaration::StaticVariable { .. }));
+        for decl in statics.into_iter().chain(decls) {
             self.compile_decl(decl)?;
         }
         Ok(())
@@ -233,10 +374,39 @@ impl Compiler {
             Declaration::Module { .. } => self.compile_decl_mod(decl),
             Declaration::Interface { .. } => self.compile_decl_intf(decl),
             Declaration::Import(..) => self.compile_decl_import(decl),
+            Declaration::StaticVariable { .. } => self.compile_decl_static(decl),
             _ => Err(Error::Unknown),
         }
     }
 
+    /// Compiles a static variable into static data, initialized with the
+    /// value of its literal. The data is named by the canonical name of the
+    /// variable, so a core reloading the program keeps its value. Only `pub`
+    /// statics are exported to other objects.
+    pub fn compile_decl_static(&mut self, decl: &Declaration) -> Result<()> {
+        let (name, public, var_type, expr) = match decl {
+            Declaration::StaticVariable {
+                name,
+                public,
+                r#type,
+                expr,
+            } => (name, *public, r#type, expr),
+            _ => return Err(Error::Unknown),
+        };
+        let canon_name = format!("{}{}", self.get_module_path()?, name);
+        if self.statics.contains_key(&canon_name) {
+            return Err(Error::DuplicateSymbol(canon_name));
+        }
+        let value = get_static_value(expr, var_type)?;
+        self.assembler
+            .push_static_value(canon_name.clone(), var_type.clone(), &value);
+        if !public {
+            self.assembler.set_private(canon_name.clone());
+        }
+        self.statics.insert(canon_name, var_type.clone());
+        Ok(())
+    }
+
     pub fn compile_decl_import(&mut self, decl: &Declaration) -> Result<()> {
         Err(Error::Unimplemented("Import declaration"))
     }
@@ -253,49 +423,56 @@ impl Compiler {
         Err(Error::Unimplemented("Container declaration"))
     }
 
+    /// Compiles a function. Its frame starts at `BP`, where the stack pointer
+    /// was when it was called: the arguments lie right below it, the locals
+    /// and temporaries above it, followed by the spilled registers.
+    /// Functions without a body are defined by another object, which calls
+    /// to them are linked to. Only `pub` functions are exported to other
+    /// objects.
     pub fn compile_decl_fn(&mut self, decl: &Declaration) -> Result<()> {
-        let (name, ret_type, args, public, external, stmt_list) = match decl {
+        let (name, public, ret_type, args, body) = match decl {
             Declaration::Function {
-                public,
-                external,
                 name,
+                public,
                 arguments,
                 returns,
                 body,
-            } => {
-                if body.is_none() {
-                    return Ok(());
-                }
-                (name, returns, arguments, public, external, body.as_ref().unwrap())
-            }
+                ..
+            } => (name, *public, returns, arguments, body),
             _ => return Err(Error::Unknown),
         };
-        let full_fn_name = self.get_module_path()? + "::" + name;
-        self.assembler.push_label(full_fn_name.clone());
-        // MOVA sp, bp
-        let mov_rbp_rsp = Instruction::new(Opcode::MOVA)
-            .with_operand::<u8>(Register::SP.into())
-            .with_operand::<u8>(Register::BP.into());
-        self.assembler.push_instr(mov_rbp_rsp);
+        let fn_def = self
+            .get_current_module()?
+            .get_function(name)
+            .map_err(|_| Error::UnknownFunction(name.clone()))?
+            .clone();
+        let stmt_list = match body {
+            Some(body) => body,
+            None => {
+                self.assembler
+                    .push_extern_fn(fn_def.canon_name.clone(), fn_def.label_uid);
+                return Ok(());
+            }
+        };
+        if !public {
+            self.assembler.set_private(fn_def.canon_name.clone());
+        }
+        let fn_start = self.assembler.instructions.len();
+        self.reg_alloc.reset();
+        self.assembler
+            .push_fn_label(fn_def.canon_name.clone(), fn_def.label_uid);
         // Tag for referencing the stack inc instruction
         let stack_inc_tag = self.assembler.new_tag();
-        let mut stack_inc_instr = Instruction::new_inc_stack(0);
-        self.assembler.push_instr(stack_inc_instr);
+        self.assembler.push_instr(Instruction::new_inc_stack(0));
 
         // Create a new stack context
-        let stack_ctx_uid = self.uid_gen.generate();
+        let stack_ctx_uid = fn_def.label_uid;
         let mut stack_ctx = StackContext::new(stack_ctx_uid);
 
-        // Insert function arguments into this context
-        for (arg_name, arg_type) in args {
-            let mut offset = 0;
-            for (other_arg_name, other_arg_type) in args.iter().rev() {
-                if arg_name == other_arg_name {
-                    break;
-                }
-                let size = self.get_size_of_type(other_arg_type)?;
-                offset -= size as i32;
-            }
+        // The caller puts the arguments right below the frame base
+        let mut offset = 0;
+        for (arg_name, arg_type) in args.iter().rev() {
+            offset -= self.get_size_of_type(arg_type)? as i32;
             stack_ctx.set_var(offset, arg_name, arg_type);
         }
 
@@ -305,42 +482,62 @@ impl Compiler {
         self.fn_ctx_stack.push_front(fn_ctx);
 
         // Compile the functions statement list
-        self.compile_stmt_list(stmt_list)?;
-
-        // Retrieve the stack context
+        let compiled = self.compile_stmt_list(stmt_list);
+        self.fn_ctx_stack.pop_front();
         stack_ctx = self.stack_ctx_stack.pop_front().ok_or(Error::Unknown)?;
+        compiled?;
+
+        // Falling off the end returns from void functions only
+        if *ret_type == Type::Void {
+            self.asm_stack_ret()?;
+        } else {
+            asm!(&mut self.assembler,
+                HALT 1;
+            )?;
+        }
+
+        // Record the named locals for debuggers
+        for (var_name, (var_pos, var_type)) in stack_ctx.get_vars() {
+            let local = LocalVar {
+                name: var_name.clone(),
+                offset: *var_pos,
+                size: self.get_size_of_type(var_type)?,
+                var_type: var_type.clone(),
+            };
+            self.assembler.push_local(stack_ctx_uid, local);
+        }
+
+        // Map the virtual registers of the body, spilling to the top of the frame.
+        // Spills are relative to the frame base, as calls move the stack pointer.
+        let fn_end = self.assembler.instructions.len();
+        let allocation = self.reg_alloc.allocate(&self.assembler, fn_start..fn_end)?;
+        let spill_size = allocation.get_spill_size();
+        let spill_offset = i16::try_from(stack_ctx.stack_extent)
+            .map_err(|_| Error::OffsetOutOfRange(stack_ctx.stack_extent as i64))?;
+        allocation.apply(&mut self.assembler, Register::BP, spill_offset)?;
 
         // Get the biggest stack size
-        let stack_size = stack_ctx.stack_extent;
-        // Create new stack inc instruction with this size
-        stack_inc_instr = Instruction::new_inc_stack(stack_size as usize);
+        let stack_size = stack_ctx.stack_extent as usize + spill_size;
         let stack_inc_instr_pos = self
             .assembler
-            .get_tag(&stack_ctx_uid)
+            .get_tag(&stack_inc_tag)
             .ok_or(Error::Unknown)?
             .pop()
             .ok_or(Error::Unknown)?;
-        // Replace the tagged instruction with this one
+        // Replace the tagged instruction with one of this size
         let stack_inc_instr_ref = self
             .assembler
             .get_instr(&stack_inc_instr_pos)
             .ok_or(Error::Unknown)?;
-        *stack_inc_instr_ref = stack_inc_instr;
-
-        self.assembler.push_instr(Instruction::new(Opcode::HALT));
-
-        let fn_uid = stack_ctx_uid;
-        let fn_name = full_fn_name;
-
-        if *external {
-            // TODO: Handle external function exposure
-        }
-
-        unimplemented!("TODO: Implement");
+        *stack_inc_instr_ref = Instruction::new_inc_stack(stack_size);
+        Ok(())
     }
 
-    pub fn compile_stmt_list(&mut self, stmt_list: &[Statement]) -> Result<()> {
+    /// Compiles the statements, marking the code of each with its source line
+    pub fn compile_stmt_list(&mut self, stmt_list: &[AstItem<Statement>]) -> Result<()> {
         for stmt in stmt_list {
+            let file = self.source_path.as_deref().unwrap_or(UNNAMED_SOURCE);
+            self.assembler.set_location(file, stmt.line);
             self.compile_stmt(stmt)?;
         }
         Ok(())
@@ -351,21 +548,61 @@ impl Compiler {
             Statement::VarDeclaration { .. } => self.compile_stmt_var_decl(stmt)?,
             Statement::ExpressionStmt(_) => self.compile_stmt_expr(stmt)?,
             Statement::Return(_) => self.compile_stmt_return(stmt)?,
+            Statement::Yield(_) => self.compile_stmt_yield(stmt)?,
             _ => return Err(Error::Unknown),
         };
         Ok(())
     }
 
+    /// Returns from the current function, with the return value in `R0`
     pub fn compile_stmt_return(&mut self, stmt: &Statement) -> Result<()> {
         let expr_opt = match stmt {
             Statement::Return(expr_opt) => expr_opt,
             _ => return Err(Error::Unknown),
         };
-        let (ret_type, suid) = {
-            let fn_ctx = self.get_current_fn_ctx()?;
-            (fn_ctx.ret_type.clone(), fn_ctx.stack_ctx_uid)
+        let ret_type = self.get_current_fn_ctx()?.ret_type.clone();
+        match expr_opt {
+            Some(expr) => self.compile_ret_value(expr, &ret_type)?,
+            None if ret_type != Type::Void => return Err(Error::ExpectedReturnExpression),
+            None => {}
         };
-        unimplemented!("TODO: Implement");
+        self.asm_stack_ret()
+    }
+
+    /// Suspends the coroutine running the current function. Yielded values
+    /// have the return type of the function.
+    pub fn compile_stmt_yield(&mut self, stmt: &Statement) -> Result<()> {
+        let expr_opt = match stmt {
+            Statement::Yield(expr_opt) => expr_opt,
+            _ => return Err(Error::Unknown),
+        };
+        if let Some(expr) = expr_opt {
+            let ret_type = self.get_current_fn_ctx()?.ret_type.clone();
+            self.compile_ret_value(expr, &ret_type)?;
+        }
+        asm!(&mut self.assembler,
+            YIELD;
+        )?;
+        Ok(())
+    }
+
+    /// Type checks a returned or yielded value against the return type and
+    /// moves it into `R0`
+    fn compile_ret_value(&mut self, expr: &Expression, ret_type: &Type) -> Result<()> {
+        let expr_type = self.get_expr_type(expr)?;
+        if expr_type != *ret_type {
+            return Err(Error::TypeMismatch(ret_type.clone(), expr_type));
+        }
+        let pos = self.get_stack_pos()?;
+        self.compile_expr(expr)?;
+        let size = self.get_size_of_type(ret_type)?;
+        let value = self.asm_load(pos, size)?;
+        self.assembler.push_instr(
+            Instruction::new(Opcode::MOVI)
+                .with_virtual(value)
+                .with_operand::<u8>(Register::R0.into()),
+        );
+        self.dec_stack(size as isize)
     }
 
     pub fn compile_stmt_var_decl(&mut self, stmt: &Statement) -> Result<()> {
@@ -394,7 +631,11 @@ impl Compiler {
             Statement::ExpressionStmt(expr) => expr,
             _ => return Err(Error::Unknown),
         };
-        self.compile_expr(expr)
+        // The value of the expression is dropped
+        let pos = self.get_stack_pos()?;
+        self.compile_expr(expr)?;
+        let end_pos = self.get_stack_pos()?;
+        self.dec_stack((end_pos - pos) as isize)
     }
 
     pub fn compile_expr(&mut self, expr: &Expression) -> Result<()> {
@@ -402,6 +643,20 @@ impl Compiler {
         let expr_type = self.get_expr_type(expr)?;
         let expr_size = self.get_size_of_type(&expr_type)?;
         match expr {
+            Expression::Variable(var_name) if self.get_static(var_name).is_some() => {
+                let (canon_name, var_type) = self.get_static(var_name).ok_or(Error::Unknown)?;
+                let size = self.get_size_of_type(&var_type)?;
+                let address = self.asm_static_address(canon_name)?;
+                let value = self.reg_alloc.new_virtual()?;
+                self.assembler.push_instr(
+                    Instruction::new(Opcode::MOVN_AR)
+                        .with_virtual(address)
+                        .with_operand(0i16)
+                        .with_virtual(value)
+                        .with_operand(size as u32),
+                );
+                self.asm_push(value, size)?;
+            }
             Expression::Variable(var_name) => {
                 let mut var_pos = self.get_var_position(var_name)?;
                 let var_type = self.get_var_type(var_name)?;
@@ -411,20 +666,40 @@ impl Compiler {
                 self.asm_stack_copy(var_pos, target_pos, var_size)?;
             }
             Expression::IntLiteral(int_val) => {
-                let pos = self.get_stack_pos()?;
-                self.inc_stack(8)?;
-                unimplemented!("TODO: Implement");
+                let value = self.reg_alloc.new_virtual()?;
+                asm!(&mut self.assembler,
+                    LDI {*int_val}, {value};
+                )?;
+                self.asm_push(value, 8)?;
             }
             Expression::FloatLiteral(float_val) => {
-                let float_int: i32 = unsafe { std::mem::transmute(*float_val) };
-                let pos = self.get_stack_pos()?;
-                self.inc_stack(4)?;
-                unimplemented!("TODO: Implement");
+                let value = self.reg_alloc.new_virtual()?;
+                asm!(&mut self.assembler,
+                    LDF {*float_val}, {value};
+                )?;
+                self.asm_push(value, 4)?;
+            }
+            Expression::BoolLiteral(bool_val) => {
+                let value = self.reg_alloc.new_virtual()?;
+                asm!(&mut self.assembler,
+                    LDB {*bool_val}, {value};
+                )?;
+                self.asm_push(value, 1)?;
+            }
+            Expression::StringLiteral(string) => {
+                let address = self.assembler.push_string(string);
+                let value = self.reg_alloc.new_virtual()?;
+                asm!(&mut self.assembler,
+                    LDA {address}, {value};
+                )?;
+                self.assembler.push_data_ref(0, address);
+                self.asm_push(value, 8)?;
             }
             Expression::Condition { .. } => {
                 self.compile_expr_cond(expr)?;
             }
             Expression::Call(fn_name, fn_args) => self.compile_expr_call(fn_name, fn_args)?,
+            Expression::Cast(cast_expr, cast_type) => self.compile_expr_cast(cast_expr, cast_type)?,
             Expression::Unary(op, op_expr) => {
                 match op {
                     Operator::Ref => self.compile_expr_ref(op_expr)?,
@@ -463,8 +738,265 @@ impl Compiler {
         Err(Error::Unimplemented("Condition expr compilation"))
     }
 
-    fn compile_expr_call(&mut self, fn_name: &String, fn_args: &[Expression]) -> Result<()> {
-        Err(Error::Unimplemented("Call expression"))
+    fn compile_expr_call(&mut self, fn_name: &str, fn_args: &[Expression]) -> Result<()> {
+        match fn_name {
+            "panic" => return self.compile_expr_panic(fn_args),
+            "assert" => return self.compile_expr_assert(fn_args),
+            "spawn" => return self.compile_expr_spawn(fn_args),
+            "resume" => return self.compile_expr_resume(fn_args),
+            "done" => return self.compile_expr_done(fn_args),
+            _ => {}
+        };
+        let (fn_def, args_size) = self.compile_call_args(fn_name, fn_args)?;
+        asm!(&mut self.assembler,
+            CALL {fn_def.label_uid};
+        )?;
+        self.asm_drop_call_args(args_size)?;
+
+        // The return value in R0 replaces the arguments
+        let ret_size = self.get_size_of_type(&fn_def.returns)?;
+        if ret_size > 0 {
+            let value = self.reg_alloc.new_virtual()?;
+            self.assembler.push_instr(
+                Instruction::new(Opcode::MOVI)
+                    .with_operand::<u8>(Register::R0.into())
+                    .with_virtual(value),
+            );
+            self.asm_push(value, ret_size)?;
+        }
+        Ok(())
+    }
+
+    /// Type checks and pushes the arguments of a call, copying them right
+    /// below the stack pointer where callees find them. Returns the called
+    /// function and the size of the arguments.
+    fn compile_call_args(
+        &mut self,
+        fn_name: &str,
+        fn_args: &[Expression],
+    ) -> Result<(FunctionDef, usize)> {
+        let fn_def = self
+            .resolve_fn(fn_name)
+            .map_err(|_| Error::UnknownFunction(String::from(fn_name)))?
+            .clone();
+        if fn_args.len() != fn_def.arguments.len() {
+            return Err(Error::ArgumentCount(String::from(fn_name), fn_args.len()));
+        }
+        let args_pos = self.get_stack_pos()?;
+        for (arg_expr, (_, arg_type)) in fn_args.iter().zip(fn_def.arguments.iter()) {
+            let expr_type = self.get_expr_type(arg_expr)?;
+            if expr_type != *arg_type {
+                return Err(Error::TypeMismatch(arg_type.clone(), expr_type));
+            }
+            self.compile_expr(arg_expr)?;
+        }
+        let args_size = (self.get_stack_pos()? - args_pos) as usize;
+        if args_size > 0 {
+            asm!(&mut self.assembler,
+                MOVN_A [BP + {args_pos}], [SP], {args_size};
+                ADDU_I SP, {args_size}, SP;
+            )?;
+        }
+        Ok((fn_def, args_size))
+    }
+
+    /// Moves the stack pointer back below the arguments of a call and
+    /// releases their stack space
+    fn asm_drop_call_args(&mut self, args_size: usize) -> Result<()> {
+        if args_size > 0 {
+            asm!(&mut self.assembler,
+                SUBU_I SP, {args_size}, SP;
+            )?;
+        }
+        self.dec_stack(args_size as isize)
+    }
+
+    /// Compiles `spawn(function(args))`, creating a coroutine of the function
+    /// with the arguments and pushing its handle
+    fn compile_expr_spawn(&mut self, fn_args: &[Expression]) -> Result<()> {
+        let (fn_name, call_args) = match fn_args {
+            [Expression::Call(fn_name, call_args)] => (fn_name, call_args),
+            [_] => return Err(Error::ExpectedSpawnCall),
+            _ => return Err(Error::ArgumentCount(String::from("spawn"), fn_args.len())),
+        };
+        let (fn_def, args_size) = self.compile_call_args(fn_name, call_args)?;
+        let args_len =
+            u32::try_from(args_size).map_err(|_| Error::OffsetOutOfRange(args_size as i64))?;
+        let handle = self.reg_alloc.new_virtual()?;
+        self.assembler.push_instr(
+            Instruction::new(Opcode::SPAWN)
+                .with_operand(fn_def.label_uid)
+                .with_operand(args_len)
+                .with_virtual(handle),
+        );
+        self.asm_drop_call_args(args_size)?;
+        self.asm_push(handle, 8)
+    }
+
+    /// Compiles `resume(handle)`, running the coroutine until it yields or
+    /// returns and pushing the value it produced
+    fn compile_expr_resume(&mut self, fn_args: &[Expression]) -> Result<()> {
+        let (handle, value_type) = self.compile_coroutine_handle("resume", fn_args)?;
+        let value = self.reg_alloc.new_virtual()?;
+        let done = self.reg_alloc.new_virtual()?;
+        self.assembler.push_instr(
+            Instruction::new(Opcode::RESUME)
+                .with_virtual(handle)
+                .with_virtual(value)
+                .with_virtual(done),
+        );
+        let value_size = self.get_size_of_type(&value_type)?;
+        if value_size > 0 {
+            self.asm_push(value, value_size)?;
+        }
+        Ok(())
+    }
+
+    /// Compiles `done(handle)`, pushing whether the coroutine returned
+    fn compile_expr_done(&mut self, fn_args: &[Expression]) -> Result<()> {
+        let (handle, _) = self.compile_coroutine_handle("done", fn_args)?;
+        let done = self.reg_alloc.new_virtual()?;
+        self.assembler.push_instr(
+            Instruction::new(Opcode::DONE)
+                .with_virtual(handle)
+                .with_virtual(done),
+        );
+        self.asm_push(done, 1)
+    }
+
+    /// Loads the coroutine handle passed to `resume` or `done` into a new
+    /// virtual register, returning it with the type of the values the
+    /// coroutine produces
+    fn compile_coroutine_handle(
+        &mut self,
+        builtin: &str,
+        fn_args: &[Expression],
+    ) -> Result<(VirtualRegister, Type)> {
+        let handle_expr = match fn_args {
+            [handle_expr] => handle_expr,
+            _ => return Err(Error::ArgumentCount(String::from(builtin), fn_args.len())),
+        };
+        let value_type = match self.get_expr_type(handle_expr)? {
+            Type::Coroutine(value_type) => *value_type,
+            other => {
+                return Err(Error::TypeMismatch(Type::Coroutine(Box::new(Type::Auto)), other))
+            }
+        };
+        let pos = self.get_stack_pos()?;
+        self.compile_expr(handle_expr)?;
+        let handle = self.asm_load(pos, 8)?;
+        self.dec_stack(8)?;
+        Ok((handle, value_type))
+    }
+
+    /// Compiles `panic(message)`, stopping the script with the message
+    fn compile_expr_panic(&mut self, fn_args: &[Expression]) -> Result<()> {
+        let message = match fn_args {
+            [message] => self.get_panic_message(message)?,
+            _ => return Err(Error::ArgumentCount(String::from("panic"), fn_args.len())),
+        };
+        let message_ptr = self.reg_alloc.new_virtual()?;
+        self.assembler.push_instr(
+            Instruction::new(Opcode::LDA)
+                .with_operand(message)
+                .with_virtual(message_ptr),
+        );
+        self.assembler.push_data_ref(0, message);
+        self.assembler
+            .push_instr(Instruction::new(Opcode::PANIC).with_virtual(message_ptr));
+        Ok(())
+    }
+
+    /// Compiles `assert(condition, message)`, stopping the script with the
+    /// message if the condition is false
+    fn compile_expr_assert(&mut self, fn_args: &[Expression]) -> Result<()> {
+        let (condition, message) = match fn_args {
+            [condition, message] => (condition, self.get_panic_message(message)?),
+            _ => return Err(Error::ArgumentCount(String::from("assert"), fn_args.len())),
+        };
+        let condition_type = self.get_expr_type(condition)?;
+        if condition_type != Type::Bool {
+            return Err(Error::TypeMismatch(Type::Bool, condition_type));
+        }
+        let pos = self.get_stack_pos()?;
+        self.compile_expr(condition)?;
+        let offset = i16::try_from(pos).map_err(|_| Error::OffsetOutOfRange(pos as i64))?;
+        let condition_reg = self.reg_alloc.new_virtual()?;
+        self.assembler.push_instr(
+            Instruction::new(Opcode::MOVB_AR)
+                .with_operand::<u8>(Register::BP.into())
+                .with_operand(offset)
+                .with_virtual(condition_reg),
+        );
+        self.dec_stack(1)?;
+        let message_ptr = self.reg_alloc.new_virtual()?;
+        self.assembler.push_instr(
+            Instruction::new(Opcode::LDA)
+                .with_operand(message)
+                .with_virtual(message_ptr),
+        );
+        self.assembler.push_data_ref(0, message);
+        self.assembler.push_instr(
+            Instruction::new(Opcode::ASSERT)
+                .with_virtual(condition_reg)
+                .with_virtual(message_ptr),
+        );
+        Ok(())
+    }
+
+    /// Stores the message of a `panic` or `assert`, which has to be a string
+    /// literal, returning its address
+    fn get_panic_message(&mut self, message: &Expression) -> Result<u64> {
+        match message {
+            Expression::StringLiteral(message) => Ok(self.assembler.push_string(message)),
+            _ => {
+                let str_type = Type::Ref(Box::new(Type::Str));
+                let message_type = self.get_expr_type(message)?;
+                if message_type != str_type {
+                    return Err(Error::TypeMismatch(str_type, message_type));
+                }
+                Err(Error::Unimplemented("Panic message from a non-literal string"))
+            }
+        }
+    }
+
+    fn compile_expr_cast(&mut self, expr: &Expression, cast_type: &Type) -> Result<()> {
+        let expr_type = self.get_expr_type(expr)?;
+        let conversions = get_cast_opcodes(&expr_type, cast_type)?;
+        let pos = self.get_stack_pos()?;
+        self.compile_expr(expr)?;
+        let from_size = self.get_size_of_type(&expr_type)?;
+        let to_size = self.get_size_of_type(cast_type)?;
+        if conversions.is_empty() && from_size == to_size {
+            return Ok(());
+        }
+        let offset = i16::try_from(pos).map_err(|_| Error::OffsetOutOfRange(pos as i64))?;
+        let value = self.reg_alloc.new_virtual()?;
+        self.assembler.push_instr(
+            Instruction::new(Opcode::MOVN_AR)
+                .with_operand::<u8>(Register::BP.into())
+                .with_operand(offset)
+                .with_virtual(value)
+                .with_operand(from_size as u32),
+        );
+        for conversion in conversions {
+            self.assembler.push_instr(
+                Instruction::new(conversion)
+                    .with_virtual(value)
+                    .with_virtual(value),
+            );
+        }
+        // Narrower integers keep the low bytes of the value
+        self.assembler.push_instr(
+            Instruction::new(Opcode::MOVN_RA)
+                .with_virtual(value)
+                .with_operand::<u8>(Register::BP.into())
+                .with_operand(offset)
+                .with_operand(to_size as u32),
+        );
+        self.dec_stack(from_size as isize)?;
+        self.inc_stack(to_size as isize)?;
+        Ok(())
     }
 
     fn compile_expr_deref(&mut self, expr: &Expression) -> Result<()> {
@@ -476,70 +1008,97 @@ impl Compiler {
     }
 
     fn compile_expr_add(&mut self, lhs_expr: &Expression, rhs_expr: &Expression) -> Result<()> {
-        let expr_type = self.get_expr_type(lhs_expr)?;
-        match expr_type {
-            Type::Int => {
-                let lhs_pos = self.get_stack_pos()?;
-                self.compile_expr(lhs_expr)?;
-                let rhs_pos = self.get_stack_pos()?;
-                self.compile_expr(rhs_expr)?;
-                let pos = self.get_stack_pos()?;
-                unimplemented!("TODO: Implement");
-                self.inc_stack(8)?;
-                unimplemented!("TODO: Implement");
-            }
-            Type::Float => {
-                let lhs_pos = self.get_stack_pos()?;
-                self.compile_expr(lhs_expr)?;
-                let rhs_pos = self.get_stack_pos()?;
-                self.compile_expr(rhs_expr)?;
-                let pos = self.get_stack_pos()?;
-                unimplemented!("TODO: Implement");
-                self.inc_stack(4)?;
-                unimplemented!("TODO: Implement");
-            }
-            _ => return Err(Error::Unknown),
-        };
-        Ok(())
+        self.compile_expr_arith(Operator::Plus, lhs_expr, rhs_expr)
     }
 
     fn compile_expr_sub(&mut self, lhs_expr: &Expression, rhs_expr: &Expression) -> Result<()> {
-        Err(Error::Unimplemented("Sub expression"))
+        self.compile_expr_arith(Operator::Minus, lhs_expr, rhs_expr)
     }
 
     fn compile_expr_mul(&mut self, lhs_expr: &Expression, rhs_expr: &Expression) -> Result<()> {
-        let expr_type = self.get_expr_type(lhs_expr)?;
-        match expr_type {
-            Type::Int => {
-                let lhs_pos = self.get_stack_pos()?;
-                self.compile_expr(lhs_expr)?;
-                let rhs_pos = self.get_stack_pos()?;
-                self.compile_expr(rhs_expr)?;
-                let pos = self.get_stack_pos()?;
-                unimplemented!("TODO: Implement");
-                self.inc_stack(8)?;
-                unimplemented!("TODO: Implement");
-            }
-            Type::Float => {
-                let lhs_pos = self.get_stack_pos()?;
-                self.compile_expr(lhs_expr)?;
-                let rhs_pos = self.get_stack_pos()?;
-                self.compile_expr(rhs_expr)?;
-                let pos = self.get_stack_pos()?;
-                unimplemented!("TODO: Implement");
-                self.inc_stack(4)?;
-                unimplemented!("TODO: Implement");
-            }
-            _ => return Err(Error::Unknown),
-        };
+        self.compile_expr_arith(Operator::Times, lhs_expr, rhs_expr)
     }
 
     fn compile_expr_div(&mut self, lhs_expr: &Expression, rhs_expr: &Expression) -> Result<()> {
-        Err(Error::Unimplemented("Division expression"))
+        self.compile_expr_arith(Operator::Divide, lhs_expr, rhs_expr)
+    }
+
+    /// Compiles a binary arithmetic expression, leaving the result where
+    /// the left hand side was put
+    fn compile_expr_arith(
+        &mut self,
+        op: Operator,
+        lhs_expr: &Expression,
+        rhs_expr: &Expression,
+    ) -> Result<()> {
+        let expr_type = self.get_expr_type(lhs_expr)?;
+        let rhs_type = self.get_expr_type(rhs_expr)?;
+        if rhs_type != expr_type {
+            return Err(Error::TypeMismatch(expr_type, rhs_type));
+        }
+        let opcode = get_arith_opcode(&op, &expr_type)?;
+        let size = self.get_size_of_type(&expr_type)?;
+        let lhs_pos = self.get_stack_pos()?;
+        self.compile_expr(lhs_expr)?;
+        let rhs_pos = self.get_stack_pos()?;
+        self.compile_expr(rhs_expr)?;
+        let lhs = self.asm_load(lhs_pos, size)?;
+        let rhs = self.asm_load(rhs_pos, size)?;
+        // Sized integers are computed on 64 bits and fit back into their
+        // size after, which wraps or traps like 64 bit arithmetic does
+        if let Some(extend) = get_sign_extend_opcode(&expr_type) {
+            for value in [lhs, rhs] {
+                self.assembler.push_instr(
+                    Instruction::new(extend.clone())
+                        .with_virtual(value)
+                        .with_virtual(value),
+                );
+            }
+        }
+        self.assembler.push_instr(
+            Instruction::new(opcode)
+                .with_virtual(lhs)
+                .with_virtual(rhs)
+                .with_virtual(lhs),
+        );
+        if size < 8 && expr_type.is_integer() {
+            let narrow = if expr_type.is_signed() { Opcode::NARROWI } else { Opcode::NARROWU };
+            self.assembler.push_instr(
+                Instruction::new(narrow)
+                    .with_virtual(lhs)
+                    .with_operand(size as u8)
+                    .with_virtual(lhs),
+            );
+        }
+        self.dec_stack((self.get_stack_pos()? - lhs_pos) as isize)?;
+        self.asm_push(lhs, size)
     }
 
     fn compile_expr_assign(&mut self, lhs_expr: &Expression, rhs_expr: &Expression) -> Result<()> {
-        Err(Error::Unimplemented("Assign expression"))
+        let (target, var_type) = self.get_assign_target(lhs_expr)?;
+        let expr_type = self.get_expr_type(rhs_expr)?;
+        if expr_type != var_type {
+            return Err(Error::TypeMismatch(var_type, expr_type));
+        }
+        let pos = self.get_stack_pos()?;
+        self.compile_expr(rhs_expr)?;
+        let size = self.get_size_of_type(&var_type)?;
+        self.asm_store(target, pos, size)
+    }
+
+    /// Compiles an arithmetic assignment like `a += b`, the value of the
+    /// expression is the assigned one
+    fn compile_expr_arith_assign(
+        &mut self,
+        op: Operator,
+        lhs_expr: &Expression,
+        rhs_expr: &Expression,
+    ) -> Result<()> {
+        let (target, var_type) = self.get_assign_target(lhs_expr)?;
+        let pos = self.get_stack_pos()?;
+        self.compile_expr_arith(op, lhs_expr, rhs_expr)?;
+        let size = self.get_size_of_type(&var_type)?;
+        self.asm_store(target, pos, size)
     }
 
     fn compile_expr_add_assign(
@@ -547,7 +1106,7 @@ impl Compiler {
         lhs_expr: &Expression,
         rhs_expr: &Expression,
     ) -> Result<()> {
-        Err(Error::Unimplemented("Add assign expression"))
+        self.compile_expr_arith_assign(Operator::Plus, lhs_expr, rhs_expr)
     }
 
     fn compile_expr_sub_assign(
@@ -555,7 +1114,7 @@ impl Compiler {
         lhs_expr: &Expression,
         rhs_expr: &Expression,
     ) -> Result<()> {
-        Err(Error::Unimplemented("Sub assign expression"))
+        self.compile_expr_arith_assign(Operator::Minus, lhs_expr, rhs_expr)
     }
 
     fn compile_expr_mul_assign(
@@ -563,7 +1122,7 @@ impl Compiler {
         lhs_expr: &Expression,
         rhs_expr: &Expression,
     ) -> Result<()> {
-        Err(Error::Unimplemented("Mul assign expression"))
+        self.compile_expr_arith_assign(Operator::Times, lhs_expr, rhs_expr)
     }
 
     fn compile_expr_div_assign(
@@ -571,106 +1130,86 @@ impl Compiler {
         lhs_expr: &Expression,
         rhs_expr: &Expression,
     ) -> Result<()> {
-        Err(Error::Unimplemented("Div assign expression"))
+        self.compile_expr_arith_assign(Operator::Divide, lhs_expr, rhs_expr)
     }
 
-    fn asm_stack_ret(&mut self, stack_ctx_uid: u64) -> Result<()> {
-        let mut pop_size = 0;
-        for stack_ctx in self.stack_ctx_stack.iter() {
-            pop_size += stack_ctx.stack_extent;
-            if stack_ctx.uid == stack_ctx_uid {
-                break;
-            }
+    /// Returns where the variable assigned to is stored, and its type
+    fn get_assign_target(&self, lhs_expr: &Expression) -> Result<(AssignTarget, Type)> {
+        match lhs_expr {
+            Expression::Variable(var_name) => match self.get_static(var_name) {
+                Some((canon_name, var_type)) => Ok((AssignTarget::Static(canon_name), var_type)),
+                None => Ok((
+                    AssignTarget::Stack(self.get_var_position(var_name)?),
+                    self.get_var_type(var_name)?,
+                )),
+            },
+            _ => Err(Error::Unimplemented("Assignment to a non-variable")),
         }
-        self.dec_stack(pop_size as isize)?;
-        unimplemented!("TODO: Implement");
-        Ok(())
     }
 
-    fn asm_args_to_stack(&mut self, fn_args: &[(String, Type)]) -> Result<()> {
-        let arg_size: isize = fn_args
-            .iter()
-            .map(|(_, arg_type)| self.get_size_of_type(arg_type).unwrap() as isize)
-            .sum();
-        self.inc_stack(arg_size)?;
-        let mut offset = 0;
-        let mut int_reg_ctr = 0;
-        let mut float_reg_ctr = 0;
-        for (arg_name, arg_type) in fn_args.iter() {
-            let arg_size = self.get_size_of_type(arg_type)?;
-            match arg_type {
-                Type::Int => {
-                    match int_reg_ctr {
-                        0 => unimplemented!("TODO: Implement"),
-                        1 => unimplemented!("TODO: Implement"),
-                        2 => unimplemented!("TODO: Implement"),
-                        3 => unimplemented!("TODO: Implement"),
-                        _ => return Err(Error::Unknown),
-                    };
-                    int_reg_ctr += 1;
-                    self.get_stack_ctx()?.set_var(offset, arg_name, arg_type);
-                    offset += 8;
-                }
-                Type::Float => {
-                    match float_reg_ctr {
-                        0 => unimplemented!("TODO: Implement"),
-                        1 => unimplemented!("TODO: Implement"),
-                        2 => unimplemented!("TODO: Implement"),
-                        3 => unimplemented!("TODO: Implement"),
-                        _ => return Err(Error::Unknown),
-                    };
-                    float_reg_ctr += 1;
-                    self.get_stack_ctx()?.set_var(offset, arg_name, arg_type);
-                    offset += 4;
-                }
-                _ => return Err(Error::Unimplemented("NOT IMPLEMENTED")),
-            };
+    /// Stores the value of the given size at a stack position in the
+    /// variable assigned to
+    fn asm_store(&mut self, target: AssignTarget, pos: i32, size: usize) -> Result<()> {
+        match target {
+            AssignTarget::Stack(var_pos) => self.asm_stack_copy(pos, var_pos, size),
+            AssignTarget::Static(canon_name) => {
+                let value = self.asm_load(pos, size)?;
+                let address = self.asm_static_address(canon_name)?;
+                self.assembler.push_instr(
+                    Instruction::new(Opcode::MOVN_RA)
+                        .with_virtual(value)
+                        .with_virtual(address)
+                        .with_operand(0i16)
+                        .with_operand(size as u32),
+                );
+                Ok(())
+            }
         }
-        Ok(())
     }
 
-    fn asm_stack_copy(&mut self, from_offset: i32, to_offset: i32, n: usize) -> Result<()> {
-        let qword_copies = n / 8;
-        let qword_rest = n % 8;
-        let dword_copies = qword_rest / 4;
-        let dword_rest = qword_rest % 4;
-        let word_copies = dword_rest / 2;
-        let word_rest = dword_rest % 2;
-        let byte_copies = word_rest;
-        let mut offset = 0;
-        for _ in 0..qword_copies {
-            let pos = from_offset + offset;
-            let target_pos = to_offset + offset;
-            unimplemented!("TODO: Implement");
-            offset += 8;
-        }
+    /// Loads the address of a static variable into a new virtual register
+    fn asm_static_address(&mut self, canon_name: String) -> Result<VirtualRegister> {
+        let address = self.reg_alloc.new_virtual()?;
+        asm!(&mut self.assembler,
+            LDA {canon_name}, {address};
+        )?;
+        Ok(address)
+    }
 
-        if offset > to_offset {
-            return Err(Error::Unknown);
-        }
+    /// Restores the stack pointer to the frame base and returns
+    fn asm_stack_ret(&mut self) -> Result<()> {
+        asm!(&mut self.assembler,
+            MOVA BP, SP;
+            RET;
+        )
+    }
 
-        for _ in 0..dword_copies {
-            let pos = from_offset + offset;
-            let target_pos = to_offset + offset;
-            unimplemented!("TODO: Implement");
-            offset += 4;
-        }
+    /// Loads the value of the given size at a stack position into a new
+    /// virtual register
+    fn asm_load(&mut self, pos: i32, size: usize) -> Result<VirtualRegister> {
+        let value = self.reg_alloc.new_virtual()?;
+        asm!(&mut self.assembler,
+            MOVN_AR [BP + {pos}], {value}, {size};
+        )?;
+        Ok(value)
+    }
 
-        for _ in 0..word_copies {
-            let pos = from_offset + offset;
-            let target_pos = to_offset + offset;
-            unimplemented!("TODO: Implement");
-            offset += 2;
-        }
+    /// Stores the value of the given size in a register on top of the stack
+    fn asm_push(&mut self, value: VirtualRegister, size: usize) -> Result<()> {
+        let pos = self.get_stack_pos()?;
+        asm!(&mut self.assembler,
+            MOVN_RA {value}, [BP + {pos}], {size};
+        )?;
+        self.inc_stack(size as isize)
+    }
 
-        for _ in 0..byte_copies {
-            let pos = from_offset + offset;
-            let target_pos = to_offset + offset;
-            unimplemented!("TODO: Implement");
-            offset += 1;
+    fn asm_stack_copy(&mut self, from_offset: i32, to_offset: i32, n: usize) -> Result<()> {
+        if n == 0 || from_offset == to_offset {
+            return Ok(());
         }
-
-        Ok(())
+        asm!(&mut self.assembler,
+            MOVN_A [BP + {from_offset}], [BP + {to_offset}], {n};
+        )
     }
 
     fn get_expr_type(&self, expr: &Expression) -> Result<Type> {
@@ -678,9 +1217,35 @@ impl Compiler {
             Expression::IntLiteral(_) => Type::Int,
             Expression::BoolLiteral(_) => Type::Bool,
             Expression::FloatLiteral(_) => Type::Float,
-            Expression::Variable(var_name) => self.get_var_type(var_name)?,
+            Expression::Variable(var_name) => match self.get_static(var_name) {
+                Some((_, var_type)) => var_type,
+                None => self.get_var_type(var_name)?,
+            },
             Expression::StringLiteral(_) => Type::Ref(Box::new(Type::Str)),
+            Expression::Call(fn_name, _) if fn_name == "panic" || fn_name == "assert" => Type::Void,
+            Expression::Call(fn_name, fn_args) if fn_name == "spawn" => match fn_args.as_slice() {
+                [Expression::Call(spawned_name, _)] => {
+                    Type::Coroutine(Box::new(self.resolve_fn(spawned_name)?.returns.clone()))
+                }
+                _ => return Err(Error::ExpectedSpawnCall),
+            },
+            Expression::Call(fn_name, fn_args) if fn_name == "resume" => match fn_args.as_slice() {
+                [handle_expr] => match self.get_expr_type(handle_expr)? {
+                    Type::Coroutine(value_type) => *value_type,
+                    other => {
+                        return Err(Error::TypeMismatch(Type::Coroutine(Box::new(Type::Auto)), other))
+                    }
+                },
+                _ => return Err(Error::ArgumentCount(fn_name.clone(), fn_args.len())),
+            },
+            Expression::Call(fn_name, _) if fn_name == "done" => Type::Bool,
+            Expression::Call(fn_name, _) => self.resolve_fn(fn_name)?.returns.clone(),
             Expression::Condition { .. } => self.get_expr_type_cond(expr)?,
+            Expression::Cast(cast_expr, cast_type) => {
+                let expr_type = self.get_expr_type(cast_expr)?;
+                get_cast_opcodes(&expr_type, cast_type)?;
+                cast_type.clone()
+            }
             Expression::Unary(op, op_expr) => match op {
                 Operator::Not => Type::Bool,
                 Operator::Ref => {
@@ -725,3 +1290,78 @@ impl Compiler {
         }
     }
 }
+
+/// Returns the opcode of a binary arithmetic operator on operands of the type
+/// Encodes the literal a static variable is initialized with
+fn get_static_value(expr: &Expression, var_type: &Type) -> Result<Vec<u8>> {
+    let (expr_type, value) = match expr {
+        Expression::IntLiteral(int) => (Type::Int, int.to_le_bytes().to_vec()),
+        Expression::FloatLiteral(float) => (Type::Float, float.to_le_bytes().to_vec()),
+        Expression::BoolLiteral(boolean) => (Type::Bool, vec![*boolean as u8]),
+        _ => return Err(Error::Unimplemented("Static initialized with a non-literal")),
+    };
+    if expr_type != *var_type {
+        return Err(Error::TypeMismatch(var_type.clone(), expr_type));
+    }
+    Ok(value)
+}
+
+fn get_arith_opcode(op: &Operator, var_type: &Type) -> Result<Opcode> {
+    let opcodes = match var_type {
+        _ if var_type.is_signed() => [Opcode::ADDI, Opcode::SUBI, Opcode::MULI, Opcode::DIVI],
+        _ if var_type.is_integer() => [Opcode::ADDU, Opcode::SUBU, Opcode::MULU, Opcode::DIVU],
+        Type::Float => [Opcode::ADDF, Opcode::SUBF, Opcode::MULF, Opcode::DIVF],
+        Type::F64 => [Opcode::ADDD, Opcode::SUBD, Opcode::MULD, Opcode::DIVD],
+        _ => return Err(Error::Unimplemented("Arithmetic on this type")),
+    };
+    let [add, sub, mul, div] = opcodes;
+    let opcode = match op {
+        Operator::Plus => add,
+        Operator::Minus => sub,
+        Operator::Times => mul,
+        Operator::Divide => div,
+        _ => return Err(Error::Unimplemented("Arithmetic operator")),
+    };
+    Ok(opcode)
+}
+
+/// Returns the opcode sign extending a sized signed integer to 64 bits
+fn get_sign_extend_opcode(var_type: &Type) -> Option<Opcode> {
+    match var_type {
+        Type::I8 => Some(Opcode::SEXT8),
+        Type::I16 => Some(Opcode::SEXT16),
+        Type::I32 => Some(Opcode::SEXT32),
+        _ => None,
+    }
+}
+
+/// Returns the opcodes converting a value between types with `as`, in order.
+/// The value is zero extended to 64 bits before and truncated to the size of
+/// the target type after, so integer casts wrap. Floats saturate at the 64
+/// bit integer bounds.
+fn get_cast_opcodes(from: &Type, to: &Type) -> Result<Vec<Opcode>> {
+    let is_numeric = |var_type: &Type| var_type.is_integer() || var_type.is_float();
+    if from == to {
+        return Ok(vec![]);
+    }
+    if !(is_numeric(from) || *from == Type::Bool) || !is_numeric(to) {
+        return Err(Error::InvalidCast(from.clone(), to.clone()));
+    }
+    let mut opcodes: Vec<Opcode> = get_sign_extend_opcode(from).into_iter().collect();
+    let signed = from.is_signed();
+    let conversion = match (from, to) {
+        (Type::Float, Type::F64) => Some(Opcode::FTOD),
+        (Type::F64, Type::Float) => Some(Opcode::DTOF),
+        (Type::Float, _) if to.is_signed() => Some(Opcode::FTOI),
+        (Type::Float, _) => Some(Opcode::FTOU),
+        (Type::F64, _) if to.is_signed() => Some(Opcode::DTOI),
+        (Type::F64, _) => Some(Opcode::DTOU),
+        (_, Type::Float) if signed => Some(Opcode::ITOF),
+        (_, Type::Float) => Some(Opcode::UTOF),
+        (_, Type::F64) if signed => Some(Opcode::ITOD),
+        (_, Type::F64) => Some(Opcode::UTOD),
+        _ => None,
+    };
+    opcodes.extend(conversion);
+    Ok(opcodes)
+}
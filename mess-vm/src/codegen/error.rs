@@ -10,6 +10,11 @@ use std::{
 
 use mess_core::parser::ast::Type;
 
+use crate::exec::is::{
+    OperandKind,
+    Opcode,
+};
+
 pub type Result<T> = StdResult<T, Error>;
 
 #[derive(Debug)]
@@ -21,6 +26,34 @@ pub enum Error {
     UnsupportedDeclaration,
     ExpectedReturnExpression,
     RegisterMapping,
+    /// The assembly source has a syntax error in the given line
+    AsmSyntax(usize, String),
+    /// The instruction was given the wrong number of operands
+    OperandCount(Opcode, usize),
+    /// An operand of the instruction isn't of the kind it takes
+    OperandMismatch(Opcode, OperandKind),
+    /// No label with the given name is defined
+    UnknownLabel(String),
+    /// A jump offset doesn't fit into its operand
+    OffsetOutOfRange(i64),
+    /// No function with the given name is declared
+    UnknownFunction(String),
+    /// No variable with the given name is in scope
+    UnknownVariable(String),
+    /// The named function was called with the given, wrong number of arguments
+    ArgumentCount(String, usize),
+    /// Values of the first type can't be cast to the second
+    InvalidCast(Type, Type),
+    /// `spawn` takes a call of the function to run as a coroutine
+    ExpectedSpawnCall,
+    /// Several linked objects define the symbol
+    DuplicateSymbol(String),
+    /// A linked object imports the symbol, but no object defines it
+    MissingSymbol(String),
+    /// An object could not be encoded
+    ObjectSerialize,
+    /// The bytes are no object encoded with `Object::to_bytes`
+    InvalidObject,
 }
 
 impl Display for Error {
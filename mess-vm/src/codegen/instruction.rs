@@ -7,13 +7,25 @@ use serde::{
     Serialize,
 };
 
-use super::register::Register;
-use crate::exec::is::Opcode;
+use super::{
+    regalloc::{
+        VirtualRegister,
+        UNMAPPED,
+    },
+    register::Register,
+};
+use crate::exec::is::{
+    OperandKind,
+    Opcode,
+};
 
 #[derive(Clone, Debug)]
 pub struct Instruction {
     pub opcode: Opcode,
     pub operands: Vec<u8>,
+    /// The virtual register operands by byte offset. Their operand bytes
+    /// hold `UNMAPPED` until the register allocator maps them.
+    pub virtual_regs: Vec<(usize, VirtualRegister)>,
 }
 
 impl Instruction {
@@ -21,6 +33,7 @@ impl Instruction {
         Instruction {
             opcode: opcode,
             operands: Vec::new(),
+            virtual_regs: Vec::new(),
         }
     }
 
@@ -49,12 +62,43 @@ impl Instruction {
         self.operands.append(&mut data);
     }
 
+    /// Appends a virtual register operand
+    pub fn with_virtual(mut self, reg: VirtualRegister) -> Instruction {
+        self.append_virtual(reg);
+        self
+    }
+
+    /// Appends a virtual register operand
+    pub fn append_virtual(&mut self, reg: VirtualRegister) {
+        self.virtual_regs.push((self.operands.len(), reg));
+        self.operands.push(UNMAPPED);
+    }
+
+    /// Returns the virtual register at the given byte offset, if there is one
+    pub fn get_virtual(&self, offset: usize) -> Option<VirtualRegister> {
+        self.virtual_regs
+            .iter()
+            .find(|(reg_offset, _)| *reg_offset == offset)
+            .map(|(_, reg)| *reg)
+    }
+
+    /// Overwrites the encoded operand at the given byte offset
+    pub fn set_operand<T: Serialize>(&mut self, offset: usize, operand: T) {
+        let data = serialize(&operand).expect("ERROR Serializing operand!");
+        self.operands[offset..offset + data.len()].copy_from_slice(&data);
+        self.virtual_regs
+            .retain(|(reg_offset, _)| !(offset..offset + data.len()).contains(reg_offset));
+    }
+
     pub fn remove_operand_bytes(&mut self, n: usize) {
         self.operands.truncate(self.operands.len() - n);
+        let len = self.operands.len();
+        self.virtual_regs.retain(|(reg_offset, _)| *reg_offset < len);
     }
 
     pub fn clear_operands(&mut self) {
         self.operands.clear();
+        self.virtual_regs.clear();
     }
 
     pub fn get_code(mut self) -> Vec<u8> {
@@ -79,4 +123,22 @@ impl Instruction {
             .expect("ERROR Deserializing operand!");
         t
     }
+
+    /// Returns the byte offsets of the operands of the given kind
+    pub fn get_operand_offsets(&self, kind: OperandKind) -> Vec<usize> {
+        let mut offsets = Vec::new();
+        let mut offset = 0;
+        for operand_kind in self.opcode.operand_kinds() {
+            if *operand_kind == kind {
+                offsets.push(offset);
+            }
+            offset += operand_kind.size();
+        }
+        offsets
+    }
+
+    /// Returns the byte offset of the jump target operand, if the instruction has one
+    pub fn get_target_offset(&self) -> Option<usize> {
+        self.get_operand_offsets(OperandKind::Target).first().copied()
+    }
 }
@@ -0,0 +1,264 @@
+//! Links separately compiled objects into a runnable program
+
+use std::collections::BTreeMap;
+
+use bincode::{
+    deserialize,
+    serialize,
+};
+use mess_api::prelude::{
+    Function,
+    Module,
+};
+
+use super::{
+    compiler::FIRST_GENERATED_UID,
+    error::{
+        Error,
+        Result,
+    },
+    object::{
+        Object,
+        Relocation,
+    },
+    output::{
+        LocalVar,
+        Output,
+        StaticVar,
+    },
+};
+use crate::{
+    adapter::get_size_of_type as get_size_of_api_type,
+    exec::address::{
+        Address,
+        AddressType,
+    },
+};
+
+/// A symbol defined by one of the linked objects
+#[derive(Clone)]
+enum Symbol {
+    /// A script function, with its uid and code offset
+    Function(u64, usize),
+    /// A static variable, moved to its place in the linked code
+    Static(StaticVar),
+}
+
+/// The symbols an object can use: its own ones, including private ones, and
+/// the ones other objects export
+struct Scope<'l> {
+    own: BTreeMap<String, Symbol>,
+    exports: &'l BTreeMap<String, Symbol>,
+}
+
+impl Scope<'_> {
+    fn get(&self, name: &str) -> Option<&Symbol> {
+        self.own.get(name).or_else(|| self.exports.get(name))
+    }
+}
+
+/// Links objects into one output, resolving the symbols they import from
+/// each other and the host functions they call.
+///
+/// Code of the objects is placed one after another in the given order.
+/// Script functions get uids in that order, host functions get uids from
+/// `1 << 32` upwards in name order, like the compiler gives them.
+#[derive(Default)]
+pub struct Linker {
+    foreign_functions: BTreeMap<String, Function>,
+}
+
+impl Linker {
+    /// Makes the functions of a host module callable as
+    /// `root::module::function`, the name the compiler calls them by
+    pub fn with_module(mut self, module: Module) -> Linker {
+        let fn_path = format!("root::{}::", module.name);
+        for (name, function) in module.functions {
+            self = self.with_foreign_fn(format!("{}{}", fn_path, name), function);
+        }
+        self
+    }
+
+    /// Makes a host function callable by the given name
+    pub fn with_foreign_fn(mut self, name: String, mut function: Function) -> Linker {
+        function.set_arg_sizes(function.args.iter().map(get_size_of_api_type).collect());
+        self.foreign_functions.insert(name, function);
+        self
+    }
+
+    /// Links the objects into one output. Fails with `Error::DuplicateSymbol`
+    /// if several objects export the same symbol, and with
+    /// `Error::MissingSymbol` if an object uses a symbol that neither the
+    /// object itself nor another one exports, or an unknown host function.
+    /// Private symbols are named after their object in the output, e.g.
+    /// `unit::root::helper`.
+    pub fn link(&self, objects: &[Object]) -> Result<Output> {
+        let mut bases = Vec::with_capacity(objects.len());
+        let mut code = Vec::new();
+        for object in objects {
+            bases.push(code.len());
+            code.extend_from_slice(&object.code);
+        }
+
+        let mut scopes = Vec::with_capacity(objects.len());
+        let mut exports = BTreeMap::new();
+        let mut next_uid = 0;
+        for (object, base) in objects.iter().zip(bases.iter()) {
+            let mut own = BTreeMap::new();
+            for (name, offset) in object.functions.iter() {
+                define(&mut own, name, Symbol::Function(next_uid, base + offset))?;
+                next_uid += 1;
+            }
+            for var in object.statics.iter() {
+                let var = StaticVar {
+                    offset: base + var.offset,
+                    ..var.clone()
+                };
+                define(&mut own, &var.name.clone(), Symbol::Static(var))?;
+            }
+            for (name, symbol) in own.iter() {
+                if !object.private.contains(name) {
+                    define(&mut exports, name, symbol.clone())?;
+                }
+            }
+            scopes.push(own);
+        }
+
+        // Objects calling the same host function share it
+        let mut foreign_uids = BTreeMap::new();
+        let mut foreign_functions = BTreeMap::new();
+        let called: BTreeMap<&String, &Function> = self
+            .foreign_functions
+            .iter()
+            .filter(|(name, _)| {
+                objects
+                    .iter()
+                    .any(|object| object.foreign_functions.contains(*name))
+            })
+            .collect();
+        for (uid, (name, function)) in (FIRST_GENERATED_UID..).zip(called) {
+            if exports.contains_key(name) {
+                return Err(Error::DuplicateSymbol(name.clone()));
+            }
+            foreign_uids.insert(name.clone(), uid);
+            foreign_functions.insert(uid, function.clone());
+        }
+
+        let scopes: Vec<Scope> = scopes
+            .into_iter()
+            .map(|own| Scope {
+                own,
+                exports: &exports,
+            })
+            .collect();
+        for object in objects {
+            let mut missing = object
+                .imports
+                .iter()
+                .chain(object.foreign_functions.iter())
+                .filter(|name| !exports.contains_key(*name) && !foreign_uids.contains_key(*name));
+            if let Some(name) = missing.next() {
+                return Err(Error::MissingSymbol(name.clone()));
+            }
+        }
+
+        for ((object, base), scope) in objects.iter().zip(bases.iter()).zip(scopes.iter()) {
+            for (offset, relocation) in object.relocations.iter() {
+                let pos = base + offset;
+                let operand = code
+                    .get_mut(pos..pos + 8)
+                    .ok_or(Error::OffsetOutOfRange(pos as i64))?;
+                let value = match relocation {
+                    Relocation::Local => {
+                        let value: u64 = deserialize(operand).map_err(|_| Error::Unknown)?;
+                        value + *base as u64
+                    }
+                    Relocation::Function(name) => match (scope.get(name), foreign_uids.get(name)) {
+                        (Some(Symbol::Function(uid, _)), _) | (None, Some(uid)) => *uid,
+                        _ => return Err(Error::MissingSymbol(name.clone())),
+                    },
+                    Relocation::Static(name) => match scope.get(name) {
+                        Some(Symbol::Static(var)) => {
+                            Address::new(var.offset as u64, AddressType::Program).into()
+                        }
+                        _ => return Err(Error::MissingSymbol(name.clone())),
+                    },
+                };
+                let bytes = serialize(&value).map_err(|_| Error::Unknown)?;
+                operand.copy_from_slice(&bytes);
+            }
+        }
+
+        let mut functions = BTreeMap::new();
+        let mut function_name_map = BTreeMap::new();
+        let mut statics = Vec::new();
+        for (object, scope) in objects.iter().zip(scopes.iter()) {
+            for (name, symbol) in scope.own.iter() {
+                let name = get_output_name(object, name);
+                match symbol {
+                    Symbol::Function(uid, offset) => {
+                        functions.insert(*uid, *offset);
+                        function_name_map.insert(name, *uid);
+                    }
+                    Symbol::Static(var) => statics.push(StaticVar {
+                        name,
+                        ..var.clone()
+                    }),
+                }
+            }
+        }
+        statics.sort_by_key(|var| var.offset);
+
+        let mut debug_lines = BTreeMap::new();
+        let mut debug_locals = BTreeMap::new();
+        let mut debug_containers: BTreeMap<String, Vec<LocalVar>> = BTreeMap::new();
+        for ((object, base), scope) in objects.iter().zip(bases.iter()).zip(scopes.iter()) {
+            for (offset, location) in object.debug_lines.iter() {
+                debug_lines.insert(base + offset, location.clone());
+            }
+            for (name, locals) in object.debug_locals.iter() {
+                if let Some(Symbol::Function(uid, _)) = scope.own.get(name) {
+                    debug_locals.insert(*uid, locals.clone());
+                }
+            }
+            // Units share container types, which have to agree on their layout
+            for (name, fields) in object.debug_containers.iter() {
+                match debug_containers.get(name) {
+                    Some(linked_fields) if linked_fields != fields => {
+                        return Err(Error::DuplicateSymbol(name.clone()));
+                    }
+                    Some(_) => {}
+                    None => {
+                        debug_containers.insert(name.clone(), fields.clone());
+                    }
+                }
+            }
+        }
+
+        Ok(Output::new()
+            .with_code(code)
+            .with_functions(functions)
+            .with_function_name_map(function_name_map)
+            .with_foreign_functions(foreign_functions)
+            .with_statics(statics)
+            .with_debug_lines(debug_lines)
+            .with_debug_locals(debug_locals)
+            .with_debug_containers(debug_containers))
+    }
+}
+
+fn define(symbols: &mut BTreeMap<String, Symbol>, name: &str, symbol: Symbol) -> Result<()> {
+    if symbols.insert(String::from(name), symbol).is_some() {
+        return Err(Error::DuplicateSymbol(String::from(name)));
+    }
+    Ok(())
+}
+
+/// Returns the name of a symbol of the object in the linked output
+fn get_output_name(object: &Object, name: &str) -> String {
+    if object.private.contains(name) {
+        format!("{}::{}", object.name, name)
+    } else {
+        String::from(name)
+    }
+}
@@ -2,10 +2,20 @@ pub mod compiler;
 
 pub mod assembler;
 
+pub mod asm;
+
 pub mod instruction;
 
+pub mod linker;
+
+pub mod object;
+
+pub mod peephole;
+
 pub mod output;
 
 pub mod register;
 
+pub mod regalloc;
+
 pub mod error;
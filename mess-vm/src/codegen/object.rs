@@ -0,0 +1,91 @@
+//! Relocatable objects, compiled separately and linked into one program
+
+use std::collections::{
+    BTreeMap,
+    BTreeSet,
+};
+
+use bincode::{
+    deserialize,
+    serialize,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use super::{
+    error::{
+        Error,
+        Result,
+    },
+    output::{
+        LocalVar,
+        SourceLocation,
+        StaticVar,
+    },
+};
+
+/// How an operand of an object is patched when linking
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Relocation {
+    /// A code offset or program address within the object, moved by the
+    /// offset the object is placed at
+    Local,
+    /// The uid of the named function
+    Function(String),
+    /// The address of the named static variable
+    Static(String),
+}
+
+/// A separately compiled unit of bytecode, with code offsets relative to its
+/// start. Objects are linked into a runnable `Output` with a `Linker`, which
+/// resolves the symbols they import from each other and the host functions
+/// they call. Objects hold no host code, so they can be stored as bytes.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Object {
+    /// The name of the unit, e.g. its source file
+    pub name: String,
+    /// The static data followed by the instructions
+    pub code: Vec<u8>,
+    /// The functions defined in the object and their code offsets
+    pub functions: BTreeMap<String, usize>,
+    /// The static variables defined in the object
+    pub statics: Vec<StaticVar>,
+    /// The functions and static variables only the object itself can use
+    pub private: BTreeSet<String>,
+    /// The names of the host functions called by the object
+    pub foreign_functions: BTreeSet<String>,
+    /// The symbols the object references but doesn't define
+    pub imports: BTreeSet<String>,
+    /// The code offsets of the 8 byte operands to patch, and how to patch them
+    pub relocations: Vec<(usize, Relocation)>,
+    /// Maps code offsets to the source location of the code starting there
+    pub debug_lines: BTreeMap<usize, SourceLocation>,
+    /// The named locals of each function, keyed by function name
+    pub debug_locals: BTreeMap<String, Vec<LocalVar>>,
+    /// The fields of each container type, keyed by type name
+    pub debug_containers: BTreeMap<String, Vec<LocalVar>>,
+}
+
+impl Object {
+    /// Returns the symbols the object defines for other objects, its
+    /// functions and static variables that aren't private
+    pub fn exports(&self) -> impl Iterator<Item = &str> {
+        self.functions
+            .keys()
+            .map(String::as_str)
+            .chain(self.statics.iter().map(|var| var.name.as_str()))
+            .filter(|name| !self.private.contains(*name))
+    }
+
+    /// Encodes the object, e.g. to cache it until its source changes
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        serialize(self).map_err(|_| Error::ObjectSerialize)
+    }
+
+    /// Decodes an object encoded with `Object::to_bytes`
+    pub fn from_bytes(bytes: &[u8]) -> Result<Object> {
+        deserialize(bytes).map_err(|_| Error::InvalidObject)
+    }
+}
@@ -1,31 +1,97 @@
 use std::{
-    collections::{
-        BTreeMap,
-        HashMap,
+    collections::BTreeMap,
+    fmt::{
+        Display,
+        Formatter,
+        Result as FmtResult,
     },
     ops::Range,
 };
 
 use mess_api::prelude::Function;
-use mess_core::artifact::Artifact;
+use mess_core::{
+    artifact::Artifact,
+    parser::ast::Type,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+/// A position in a source file
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct SourceLocation {
+    /// The source file name
+    pub file: String,
+    /// The line in the file, starting at 1
+    pub line: usize,
+}
+
+impl Display for SourceLocation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+/// A named variable in a function's stack frame
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct LocalVar {
+    /// The variable name
+    pub name: String,
+    /// The offset from the stack pointer at function entry
+    pub offset: i32,
+    /// The size of the variable in bytes
+    pub size: usize,
+    /// The variable type
+    pub var_type: Type,
+}
+
+/// A named static variable in program memory
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct StaticVar {
+    /// The variable name
+    pub name: String,
+    /// The offset of the variable in program memory
+    pub offset: usize,
+    /// The size of the variable in bytes
+    pub size: usize,
+    /// The variable type
+    pub var_type: Type,
+}
 
 #[derive(PartialEq, Debug)]
 pub struct Output {
     pub code: Vec<u8>,
-    pub function_name_map: HashMap<String, u64>,
-    pub functions: HashMap<u64, usize>,
-    pub foreign_functions: HashMap<u64, Function>,
+    /// The uids of the script functions, keyed by full name
+    pub function_name_map: BTreeMap<String, u64>,
+    /// The code offsets of the script functions, keyed by uid
+    pub functions: BTreeMap<u64, usize>,
+    /// The host functions the code calls, keyed by uid
+    pub foreign_functions: BTreeMap<u64, Function>,
     pub static_pointers: BTreeMap<usize, Range<usize>>,
+    /// The named static variables, kept across hot reloads
+    pub statics: Vec<StaticVar>,
+    /// Maps code offsets to the source location of the code starting there
+    pub debug_lines: BTreeMap<usize, SourceLocation>,
+    /// The named locals of each function, keyed by function uid
+    pub debug_locals: BTreeMap<u64, Vec<LocalVar>>,
+    /// The fields of each container type, keyed by type name. Field offsets
+    /// are from the start of the container.
+    pub debug_containers: BTreeMap<String, Vec<LocalVar>>,
 }
 
 impl Output { 
     pub fn new() -> Output {
         Output {
             code: Vec::new(),
-            functions: HashMap::new(),
-            function_name_map: HashMap::new(),
-            foreign_functions: HashMap::new(),
+            functions: BTreeMap::new(),
+            function_name_map: BTreeMap::new(),
+            foreign_functions: BTreeMap::new(),
             static_pointers: BTreeMap::new(),
+            statics: Vec::new(),
+            debug_lines: BTreeMap::new(),
+            debug_locals: BTreeMap::new(),
+            debug_containers: BTreeMap::new(),
         }
     }
 
@@ -34,15 +100,23 @@ impl Output {
         self
     }
 
-    pub fn with_functions(mut self, functions: HashMap<u64, usize>) -> Output {
+    /// Sets the code offsets of the script functions
+    pub fn with_functions(mut self, functions: BTreeMap<u64, usize>) -> Output {
         self.functions = functions;
         self
     }
 
-    /*pub fn with_foreign_functions(mut self, functions: HashMap<u64, Function>) -> Output {
+    /// Sets the uids of the script functions
+    pub fn with_function_name_map(mut self, function_name_map: BTreeMap<String, u64>) -> Output {
+        self.function_name_map = function_name_map;
+        self
+    }
+
+    /// Sets the host functions the code calls
+    pub fn with_foreign_functions(mut self, functions: BTreeMap<u64, Function>) -> Output {
         self.foreign_functions = functions;
         self
-    }*/
+    }
 
     pub fn with_static_pointers(
         mut self,
@@ -52,9 +126,75 @@ impl Output {
         self
     }
 
+    /// Sets the named static variables
+    pub fn with_statics(mut self, statics: Vec<StaticVar>) -> Output {
+        self.statics = statics;
+        self
+    }
+
+    /// Sets the source locations of the code
+    pub fn with_debug_lines(mut self, debug_lines: BTreeMap<usize, SourceLocation>) -> Output {
+        self.debug_lines = debug_lines;
+        self
+    }
+
+    /// Sets the named locals of the functions
+    pub fn with_debug_locals(mut self, debug_locals: BTreeMap<u64, Vec<LocalVar>>) -> Output {
+        self.debug_locals = debug_locals;
+        self
+    }
+
+    /// Sets the field layouts of the container types
+    pub fn with_debug_containers(
+        mut self,
+        debug_containers: BTreeMap<String, Vec<LocalVar>>,
+    ) -> Output {
+        self.debug_containers = debug_containers;
+        self
+    }
+
     pub fn get_size(&self) -> usize {
         self.code.len()
     }
+
+    /// Returns the uid and entry offset of the function containing the code offset
+    pub fn get_function_at(&self, offset: usize) -> Option<(u64, usize)> {
+        self.functions
+            .iter()
+            .filter(|(_, fn_offset)| **fn_offset <= offset)
+            .max_by_key(|(_, fn_offset)| **fn_offset)
+            .map(|(uid, fn_offset)| (*uid, *fn_offset))
+    }
+
+    /// Returns the uid of the function with the given canonical name. Script
+    /// functions of the root module can also be looked up without `root::`.
+    pub fn get_function_uid(&self, fn_name: &str) -> Option<u64> {
+        self.function_name_map
+            .get(fn_name)
+            .or_else(|| self.function_name_map.get(&format!("root::{}", fn_name)))
+            .copied()
+    }
+
+    /// Returns the name of the function with the given uid
+    pub fn get_function_name(&self, uid: u64) -> Option<&str> {
+        self.function_name_map
+            .iter()
+            .find(|(_, fn_uid)| **fn_uid == uid)
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Returns the static variable with the given name
+    pub fn get_static(&self, name: &str) -> Option<&StaticVar> {
+        self.statics.iter().find(|var| var.name == name)
+    }
+
+    /// Returns the source location of the code offset, if known
+    pub fn get_location(&self, offset: usize) -> Option<&SourceLocation> {
+        self.debug_lines
+            .range(..=offset)
+            .next_back()
+            .map(|(_, location)| location)
+    }
 }
 
 
@@ -0,0 +1,275 @@
+//! A peephole optimizer over the instructions of an `Assembler`
+
+use std::collections::{HashMap, HashSet};
+
+use super::{
+    asm::LabelRef,
+    assembler::{Assembler, Replacement},
+    instruction::Instruction,
+};
+use crate::exec::{decode::DecodedInstr, is::Opcode};
+
+/// The target of a jump, either a label or an encoded code offset
+#[derive(Clone, PartialEq, Debug)]
+enum JumpTarget {
+    Label(String),
+    Offset(usize),
+}
+
+/// Runs the peephole passes until no more instructions can be removed:
+///
+/// - jumps to unconditional jumps are retargeted to the final destination
+/// - jumps to the next instruction, no-op moves and zero adjustments are removed
+/// - `LDI`/`LDA` into a temporary followed by an operation consuming and
+///   overwriting it are fused into the immediate form, e.g. `ADDI_I`
+/// - loads of a value that was just stored from the same register are removed
+///
+/// Labels, tags, label references and line tables are remapped to the new
+/// instruction indices. Tags on removed instructions are dropped. Encoded jump
+/// targets are code offsets including the data section, as `build_output`
+/// resolves them. Programs with dynamic jumps or unresolvable targets are left
+/// untouched, as removing code would move their destinations.
+pub fn optimize(assembler: &mut Assembler) {
+    if !is_optimizable(assembler) {
+        return;
+    }
+    thread_jumps(assembler);
+    while simplify(assembler) {
+        thread_jumps(assembler);
+    }
+}
+
+fn is_optimizable(assembler: &Assembler) -> bool {
+    let offsets = assembler.get_instr_offsets();
+    assembler
+        .instructions
+        .iter()
+        .enumerate()
+        .all(|(index, instr)| match instr.opcode {
+            Opcode::DJMP | Opcode::DJMPT | Opcode::DJMPF => false,
+            _ => match get_target(assembler, index) {
+                Some(target) => resolve(assembler, &offsets, &target).is_some(),
+                None => true,
+            },
+        })
+}
+
+/// Retargets jumps that land on unconditional jumps
+fn thread_jumps(assembler: &mut Assembler) {
+    let offsets = assembler.get_instr_offsets();
+    for index in 0..assembler.instructions.len() {
+        let original = match get_target(assembler, index) {
+            Some(target) => target,
+            None => continue,
+        };
+        let mut target = original.clone();
+        let mut visited = HashSet::new();
+        visited.insert(index);
+        while let Some(target_index) = resolve(assembler, &offsets, &target) {
+            // Jump cycles are left as they are
+            if !visited.insert(target_index) {
+                break;
+            }
+            match assembler.instructions.get(target_index) {
+                Some(instr) if instr.opcode == Opcode::JMP => {
+                    target = match get_target(assembler, target_index) {
+                        Some(target) => target,
+                        None => break,
+                    };
+                }
+                _ => break,
+            };
+        }
+        if target != original {
+            set_target(assembler, index, target);
+        }
+    }
+}
+
+/// Removes and fuses instructions, returning whether anything changed
+fn simplify(assembler: &mut Assembler) -> bool {
+    let offsets = assembler.get_instr_offsets();
+    let count = assembler.instructions.len();
+
+    // Instructions control can enter from somewhere else than the one before
+    let mut entered: HashSet<usize> = assembler.labels.values().copied().collect();
+    entered.extend(assembler.tags.values().flatten().copied());
+    let mut jump_targets = HashMap::new();
+    for index in 0..count {
+        let target = match get_target(assembler, index) {
+            Some(target) => target,
+            None => continue,
+        };
+        let target_index = match resolve(assembler, &offsets, &target) {
+            Some(target_index) => target_index,
+            None => return false,
+        };
+        entered.insert(target_index);
+        jump_targets.insert(index, target_index);
+    }
+
+    let mut replacements: Vec<Replacement> = assembler
+        .instructions
+        .iter()
+        .cloned()
+        .map(Replacement::keep)
+        .collect();
+    let mut changed = false;
+    let mut index = 0;
+    while index < count {
+        let instr = match decode(&assembler.instructions[index]) {
+            Some(instr) => instr,
+            None => {
+                index += 1;
+                continue;
+            }
+        };
+        if is_noop(&instr) || jump_targets.get(&index) == Some(&(index + 1)) {
+            replacements[index] = Replacement::remove();
+            changed = true;
+        } else if index + 1 < count && !entered.contains(&(index + 1)) {
+            if let Some(next) = decode(&assembler.instructions[index + 1]) {
+                if let Some(fused) = fuse(&instr, &next) {
+                    replacements[index] = Replacement::keep(fused);
+                    replacements[index + 1] = Replacement::remove();
+                    changed = true;
+                    index += 2;
+                    continue;
+                }
+                if is_reload(&instr, &next) {
+                    replacements[index + 1] = Replacement::remove();
+                    changed = true;
+                    index += 2;
+                    continue;
+                }
+            }
+        }
+        index += 1;
+    }
+    if changed {
+        assembler.rewrite(replacements);
+    }
+    changed
+}
+
+/// Returns the target of the instruction, if it is a jump
+fn get_target(assembler: &Assembler, index: usize) -> Option<JumpTarget> {
+    let instr = &assembler.instructions[index];
+    let operand_offset = instr.get_target_offset()?;
+    let label = assembler
+        .label_refs
+        .iter()
+        .find_map(|(instr_index, offset, label_ref)| match label_ref {
+            LabelRef::Target(label) if *instr_index == index && *offset == operand_offset => {
+                Some(label.clone())
+            }
+            _ => None,
+        });
+    match label {
+        Some(label) => Some(JumpTarget::Label(label)),
+        None => Some(JumpTarget::Offset(
+            instr.get_operand::<u64>(operand_offset, 8) as usize,
+        )),
+    }
+}
+
+/// Returns the index of the instruction a target points to, which is the
+/// instruction count for the end of the code
+fn resolve(assembler: &Assembler, offsets: &[usize], target: &JumpTarget) -> Option<usize> {
+    match target {
+        JumpTarget::Label(label) => assembler.labels.get(label).copied(),
+        JumpTarget::Offset(offset) => offsets.binary_search(offset).ok(),
+    }
+}
+
+fn set_target(assembler: &mut Assembler, index: usize, target: JumpTarget) {
+    let operand_offset = match assembler.instructions[index].get_target_offset() {
+        Some(operand_offset) => operand_offset,
+        None => return,
+    };
+    assembler
+        .label_refs
+        .retain(|(instr_index, offset, label_ref)| {
+            !(*instr_index == index
+                && *offset == operand_offset
+                && matches!(label_ref, LabelRef::Target(_)))
+        });
+    match target {
+        JumpTarget::Label(label) => {
+            assembler
+                .label_refs
+                .push((index, operand_offset, LabelRef::Target(label)));
+        }
+        JumpTarget::Offset(offset) => {
+            assembler.instructions[index].set_operand(operand_offset, offset as u64);
+        }
+    };
+}
+
+fn decode(instr: &Instruction) -> Option<DecodedInstr> {
+    DecodedInstr::decode(&instr.clone().get_code(), 0).ok()
+}
+
+/// Whether the instruction has no effect
+fn is_noop(instr: &DecodedInstr) -> bool {
+    let ops = &instr.operands;
+    match instr.opcode {
+        Opcode::NOOP => true,
+        Opcode::MOVB | Opcode::MOVF | Opcode::MOVI | Opcode::MOVA => ops[0] == ops[1],
+        Opcode::MOVB_A | Opcode::MOVF_A | Opcode::MOVI_A | Opcode::MOVA_A | Opcode::MOVN_A => {
+            ops[0..2] == ops[2..4]
+        }
+        Opcode::ADDI_I | Opcode::SUBI_I | Opcode::ADDU_I | Opcode::SUBU_I => {
+            ops[1] == 0 && ops[0] == ops[2]
+        }
+        Opcode::MULI_I | Opcode::DIVI_I | Opcode::MULU_I | Opcode::DIVU_I => {
+            ops[1] == 1 && ops[0] == ops[2]
+        }
+        _ => false,
+    }
+}
+
+/// Fuses an immediate load into a temporary with the operation consuming it.
+/// The operation has to overwrite the temporary, so it is dead afterwards.
+fn fuse(load: &DecodedInstr, op: &DecodedInstr) -> Option<Instruction> {
+    let (imm, tmp) = (load.operands[0], load.operands[1]);
+    let (lhs, rhs, target) = (op.operands[0], op.operands[1], op.operands[2]);
+    let (fused, commutative) = match (&load.opcode, &op.opcode) {
+        (Opcode::LDI, Opcode::ADDI) => (Opcode::ADDI_I, true),
+        (Opcode::LDI, Opcode::SUBI) => (Opcode::SUBI_I, false),
+        (Opcode::LDI, Opcode::MULI) => (Opcode::MULI_I, true),
+        // ADDU_I and SUBU_I always wrap, so unsigned adds and subtracts
+        // stay as they are to keep trapping on overflow
+        (Opcode::LDA, Opcode::MULU) => (Opcode::MULU_I, true),
+        _ => return None,
+    };
+    if target != tmp {
+        return None;
+    }
+    let source = if rhs == tmp && lhs != tmp {
+        lhs
+    } else if commutative && lhs == tmp && rhs != tmp {
+        rhs
+    } else {
+        return None;
+    };
+    Some(
+        Instruction::new(fused)
+            .with_operand(source as u8)
+            .with_operand(imm)
+            .with_operand(target as u8),
+    )
+}
+
+/// Whether the second instruction loads the value the first just stored
+/// back into the same register
+fn is_reload(store: &DecodedInstr, load: &DecodedInstr) -> bool {
+    let matching = matches!(
+        (&store.opcode, &load.opcode),
+        (Opcode::MOVB_RA, Opcode::MOVB_AR)
+            | (Opcode::MOVF_RA, Opcode::MOVF_AR)
+            | (Opcode::MOVI_RA, Opcode::MOVI_AR)
+            | (Opcode::MOVA_RA, Opcode::MOVA_AR)
+    );
+    matching && store.operands[0] == load.operands[2] && store.operands[1..3] == load.operands[0..2]
+}
@@ -0,0 +1,507 @@
+//! Maps virtual registers to machine registers by liveness analysis and
+//! linear scan, spilling to the stack frame when registers run out
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    convert::TryFrom,
+    fmt,
+    ops::Range,
+};
+
+use num_traits::FromPrimitive;
+
+use super::{
+    assembler::{Assembler, Replacement},
+    error::{Error, Result},
+    instruction::Instruction,
+    register::Register,
+};
+use crate::exec::is::{Opcode, OperandAccess};
+
+/// The first register number denoting a virtual register, numbers below it
+/// are machine registers
+pub const FIRST_VIRTUAL: u32 = 32;
+
+/// The operand byte of a virtual register until the allocator rewrites it.
+/// It is not a valid register, so unmapped code fails to run.
+pub const UNMAPPED: u8 = u8::MAX;
+
+/// A virtual register. It is not limited to the register operand byte, its
+/// number is kept beside the instruction until it is mapped.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct VirtualRegister(u32);
+
+impl VirtualRegister {
+    /// Returns the register number, at least `FIRST_VIRTUAL`
+    pub fn get_id(&self) -> u32 {
+        self.0
+    }
+}
+
+impl fmt::Display for VirtualRegister {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "V{}", self.0)
+    }
+}
+
+/// The size of a spill slot, large enough for any register value
+pub const SLOT_SIZE: usize = 8;
+
+/// The registers holding spilled values while an instruction executes
+const SCRATCH: [Register; 2] = [Register::R14, Register::R15];
+
+/// Where a virtual register lives
+#[derive(Clone, PartialEq, Debug)]
+pub enum Location {
+    /// In a machine register for its whole lifetime
+    Register(Register),
+    /// In the spill slot with the given index, loaded into a scratch register
+    /// for every instruction using it
+    Spill(usize),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Access {
+    Use,
+    Def,
+}
+
+/// Hands out virtual registers and maps them to machine registers.
+/// All allocatable registers are caller-saved, so values live across a
+/// `CALL` are saved to the stack frame before it and restored after it.
+#[derive(PartialEq, Debug, Clone)]
+pub struct RegisterAllocator {
+    registers: Vec<Register>,
+    next_virtual: u32,
+}
+
+impl Default for RegisterAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RegisterAllocator {
+    /// Creates an allocator mapping to `R1` to `R13`. `R0` holds return
+    /// values, `R14` and `R15` hold spilled values.
+    pub fn new() -> RegisterAllocator {
+        let registers = (1..14).filter_map(Register::from_u8).collect();
+        RegisterAllocator {
+            registers,
+            next_virtual: FIRST_VIRTUAL,
+        }
+    }
+
+    /// Restricts the machine registers virtual registers are mapped to.
+    /// The scratch registers are never handed out.
+    pub fn with_registers(mut self, mut registers: Vec<Register>) -> Self {
+        registers.retain(|reg| !SCRATCH.contains(reg));
+        self.registers = registers;
+        self
+    }
+
+    /// Returns a new virtual register
+    pub fn new_virtual(&mut self) -> Result<VirtualRegister> {
+        let reg = VirtualRegister(self.next_virtual);
+        self.next_virtual = self
+            .next_virtual
+            .checked_add(1)
+            .ok_or(Error::RegisterMapping)?;
+        Ok(reg)
+    }
+
+    /// Releases all virtual registers, e.g. after allocating a function
+    pub fn reset(&mut self) {
+        self.next_virtual = FIRST_VIRTUAL;
+    }
+
+    /// Computes where the virtual registers used in the given range of
+    /// instructions live. The range is expected to be a function, control
+    /// may only enter it at its start.
+    pub fn allocate(&self, assembler: &Assembler, range: Range<usize>) -> Result<Allocation> {
+        let instrs = &assembler.instructions[range.clone()];
+        let accesses: Vec<_> = instrs.iter().map(register_accesses).collect();
+
+        // Machine registers used explicitly are never handed out
+        let mut free: BTreeSet<u32> = self
+            .registers
+            .iter()
+            .map(|reg| u32::from(Into::<u8>::into(reg.clone())))
+            .collect();
+        for (_, reg, _) in accesses.iter().flatten() {
+            free.remove(reg);
+        }
+
+        let successors = get_successors(assembler, &range);
+        let (live_in, live_out) = get_liveness(&accesses, &successors);
+
+        // The first and last index every virtual register is live at
+        let mut intervals: BTreeMap<u32, (usize, usize)> = BTreeMap::new();
+        let mut extend = |reg: u32, index: usize| {
+            let interval = intervals.entry(reg).or_insert((index, index));
+            interval.0 = interval.0.min(index);
+            interval.1 = interval.1.max(index);
+        };
+        for (index, instr_accesses) in accesses.iter().enumerate() {
+            for (_, reg, _) in instr_accesses
+                .iter()
+                .filter(|(_, reg, _)| *reg >= FIRST_VIRTUAL)
+            {
+                extend(*reg, index);
+            }
+            for reg in live_in[index].iter().chain(live_out[index].iter()) {
+                extend(*reg, index);
+            }
+        }
+
+        // Coalesce moves between a register that dies and one that is born there
+        let mut aliases = BTreeMap::new();
+        for (index, instr) in instrs.iter().enumerate() {
+            let (src, dst) = match get_move(instr) {
+                Some((src, dst)) if src >= FIRST_VIRTUAL && dst >= FIRST_VIRTUAL => {
+                    (find(&aliases, src), find(&aliases, dst))
+                }
+                _ => continue,
+            };
+            if src == dst {
+                continue;
+            }
+            let (src_interval, dst_interval) = (intervals[&src], intervals[&dst]);
+            if src_interval.1 == index && dst_interval.0 == index {
+                aliases.insert(dst, src);
+                intervals.remove(&dst);
+                intervals.insert(src, (src_interval.0, dst_interval.1));
+            }
+        }
+
+        // Linear scan, spilling the interval ending last when registers run out
+        let mut sorted: Vec<_> = intervals
+            .iter()
+            .map(|(reg, (start, end))| (*start, *end, *reg))
+            .collect();
+        sorted.sort_unstable();
+        let mut locations = BTreeMap::new();
+        let mut active: Vec<(usize, u32, u32)> = Vec::new();
+        let mut slot_count = 0;
+        let mut new_slot = || {
+            slot_count += 1;
+            slot_count - 1
+        };
+        for (start, end, reg) in sorted {
+            active.retain(|(active_end, _, machine_reg)| {
+                if *active_end < start {
+                    free.insert(*machine_reg);
+                    return false;
+                }
+                true
+            });
+            if let Some(machine_reg) = free.iter().next().copied() {
+                free.remove(&machine_reg);
+                active.push((end, reg, machine_reg));
+                locations.insert(reg, Location::Register(to_register(machine_reg)?));
+                continue;
+            }
+            let furthest = active
+                .iter()
+                .enumerate()
+                .max_by_key(|(_, (active_end, _, _))| *active_end)
+                .map(|(pos, (active_end, _, _))| (pos, *active_end));
+            match furthest {
+                Some((pos, active_end)) if active_end > end => {
+                    let (_, spilled, machine_reg) = active.remove(pos);
+                    locations.insert(spilled, Location::Spill(new_slot()));
+                    active.push((end, reg, machine_reg));
+                    locations.insert(reg, Location::Register(to_register(machine_reg)?));
+                }
+                _ => {
+                    locations.insert(reg, Location::Spill(new_slot()));
+                }
+            };
+        }
+
+        // Virtual registers in machine registers live across each call
+        let mut saves = BTreeMap::new();
+        let mut save_slots = BTreeMap::new();
+        for (index, instr) in instrs.iter().enumerate() {
+            if instr.opcode != Opcode::CALL {
+                continue;
+            }
+            let live: BTreeSet<u32> = live_out[index]
+                .iter()
+                .map(|reg| find(&aliases, *reg))
+                .collect();
+            let mut saved = Vec::new();
+            for reg in live {
+                if let Some(Location::Register(machine_reg)) = locations.get(&reg) {
+                    let slot = *save_slots.entry(reg).or_insert_with(&mut new_slot);
+                    saved.push((machine_reg.clone(), slot));
+                }
+            }
+            saves.insert(range.start + index, saved);
+        }
+
+        Ok(Allocation {
+            range,
+            locations,
+            aliases,
+            saves,
+            slot_count,
+        })
+    }
+}
+
+/// The result of allocating the registers of a range of instructions
+#[derive(Clone, Debug)]
+pub struct Allocation {
+    range: Range<usize>,
+    locations: BTreeMap<u32, Location>,
+    /// Coalesced virtual registers, mapped to the one they were merged into
+    aliases: BTreeMap<u32, u32>,
+    /// The machine registers saved around each call, with their slots
+    saves: BTreeMap<usize, Vec<(Register, usize)>>,
+    slot_count: usize,
+}
+
+impl Allocation {
+    /// Returns where the given virtual register lives
+    pub fn get_location(&self, reg: VirtualRegister) -> Option<&Location> {
+        self.locations.get(&find(&self.aliases, reg.0))
+    }
+
+    /// Returns the stack space needed for spilled and saved registers
+    pub fn get_spill_size(&self) -> usize {
+        self.slot_count * SLOT_SIZE
+    }
+
+    /// Rewrites the allocated instructions to use machine registers, inserting
+    /// spill code and saves around calls. Slot `n` is addressed as
+    /// `[base + offset + n * SLOT_SIZE]`. The instructions must not have changed
+    /// since the allocation was computed.
+    pub fn apply(self, assembler: &mut Assembler, base: Register, offset: i16) -> Result<()> {
+        let slot_offset = |slot: usize| {
+            let slot_offset = offset as i64 + (slot * SLOT_SIZE) as i64;
+            i16::try_from(slot_offset).map_err(|_| Error::OffsetOutOfRange(slot_offset))
+        };
+        let load = |slot: usize, reg: Register| -> Result<Instruction> {
+            Ok(Instruction::new(Opcode::MOVI_AR)
+                .with_operand::<u8>(base.clone().into())
+                .with_operand(slot_offset(slot)?)
+                .with_operand::<u8>(reg.into()))
+        };
+        let store = |slot: usize, reg: Register| -> Result<Instruction> {
+            Ok(Instruction::new(Opcode::MOVI_RA)
+                .with_operand::<u8>(reg.into())
+                .with_operand::<u8>(base.clone().into())
+                .with_operand(slot_offset(slot)?))
+        };
+
+        let spills = self
+            .locations
+            .values()
+            .any(|location| matches!(location, Location::Spill(_)));
+        let mut replacements = Vec::with_capacity(assembler.instructions.len());
+        for (index, instr) in assembler.instructions.iter().enumerate() {
+            if !self.range.contains(&index) {
+                replacements.push(Replacement::keep(instr.clone()));
+                continue;
+            }
+            let accesses = register_accesses(instr);
+            let scratch_used = accesses.iter().any(|(_, reg, _)| {
+                SCRATCH
+                    .iter()
+                    .any(|scratch| *reg == u32::from(Into::<u8>::into(scratch.clone())))
+            });
+            if spills && scratch_used {
+                return Err(Error::RegisterMapping);
+            }
+
+            let mut instr = instr.clone();
+            let mut before = Vec::new();
+            let mut after = Vec::new();
+            // Spilled registers by the scratch register holding them
+            let mut scratch: Vec<u32> = Vec::new();
+            for (access, reg, operand_offset) in accesses.iter() {
+                if *reg < FIRST_VIRTUAL {
+                    continue;
+                }
+                let reg = find(&self.aliases, *reg);
+                let machine_reg = match self.locations.get(&reg).ok_or(Error::RegisterMapping)? {
+                    Location::Register(machine_reg) => machine_reg.clone(),
+                    Location::Spill(slot) => {
+                        let pos = match scratch.iter().position(|spilled| *spilled == reg) {
+                            Some(pos) => pos,
+                            // Uses are read before the definition is written,
+                            // so a definition can share the first scratch
+                            None if *access == Access::Def => 0,
+                            None => {
+                                scratch.push(reg);
+                                let pos = scratch.len() - 1;
+                                before.push(load(*slot, SCRATCH[pos].clone())?);
+                                pos
+                            }
+                        };
+                        if *access == Access::Def {
+                            after.push(store(*slot, SCRATCH[pos].clone())?);
+                        }
+                        SCRATCH[pos].clone()
+                    }
+                };
+                instr.set_operand::<u8>(*operand_offset, machine_reg.into());
+            }
+
+            if let Some((src, dst)) = get_move(&instr) {
+                if src == dst {
+                    replacements.push(Replacement::remove());
+                    continue;
+                }
+            }
+            for (machine_reg, slot) in self.saves.get(&index).into_iter().flatten() {
+                before.push(store(*slot, machine_reg.clone())?);
+                after.push(load(*slot, machine_reg.clone())?);
+            }
+            let original = before.len();
+            let mut instructions = before;
+            instructions.push(instr);
+            instructions.extend(after);
+            replacements.push(Replacement {
+                instructions,
+                original: Some(original),
+            });
+        }
+        assembler.rewrite(replacements);
+        Ok(())
+    }
+}
+
+/// Follows the aliases of coalesced registers
+fn find(aliases: &BTreeMap<u32, u32>, mut reg: u32) -> u32 {
+    while let Some(alias) = aliases.get(&reg) {
+        reg = *alias;
+    }
+    reg
+}
+
+fn to_register(reg: u32) -> Result<Register> {
+    Register::from_u32(reg).ok_or(Error::RegisterMapping)
+}
+
+/// Returns the source and target of a register to register move
+fn get_move(instr: &Instruction) -> Option<(u32, u32)> {
+    match instr.opcode {
+        Opcode::MOVB | Opcode::MOVF | Opcode::MOVI | Opcode::MOVA => {
+            Some((get_register(instr, 0), get_register(instr, 1)))
+        }
+        _ => None,
+    }
+}
+
+/// Returns the number of the register operand at the given byte offset
+fn get_register(instr: &Instruction, offset: usize) -> u32 {
+    match instr.get_virtual(offset) {
+        Some(reg) => reg.0,
+        None => u32::from(instr.operands[offset]),
+    }
+}
+
+/// Returns the registers an instruction reads and writes, with their
+/// operand byte offsets. Memory operands read their base register.
+fn register_accesses(instr: &Instruction) -> Vec<(Access, u32, usize)> {
+    let mut accesses = Vec::new();
+    let mut offset = 0;
+    let operands = instr.opcode.operand_kinds().iter().zip(instr.opcode.operand_accesses());
+    for (kind, access) in operands {
+        let access = match access {
+            OperandAccess::Immediate => None,
+            OperandAccess::Read => Some(Access::Use),
+            OperandAccess::Write => Some(Access::Def),
+        };
+        if let Some(access) = access {
+            accesses.push((access, get_register(instr, offset), offset));
+        }
+        offset += kind.size();
+    }
+    // Reads first, so spilled values are loaded before definitions pick a scratch
+    accesses.sort_by_key(|(access, _, _)| *access == Access::Def);
+    accesses
+}
+
+/// Returns the successors of every instruction in the range, relative to its start
+fn get_successors(assembler: &Assembler, range: &Range<usize>) -> Vec<Vec<usize>> {
+    let offsets = assembler.get_instr_offsets();
+    let len = range.len();
+    let all: Vec<usize> = (0..len).collect();
+    let next = |index: usize| {
+        if index + 1 < len {
+            vec![index + 1]
+        } else {
+            Vec::new()
+        }
+    };
+    (0..len)
+        .map(|index| {
+            let instr = &assembler.instructions[range.start + index];
+            let target = || {
+                assembler
+                    .get_jump_target(range.start + index, &offsets)
+                    .map(|target| {
+                        target
+                            .checked_sub(range.start)
+                            .filter(|target| *target < len)
+                            .into_iter()
+                            .collect()
+                    })
+            };
+            match instr.opcode {
+                Opcode::HALT | Opcode::RET => Vec::new(),
+                // Dynamic jumps may go anywhere
+                Opcode::DJMP | Opcode::DJMPT | Opcode::DJMPF => all.clone(),
+                Opcode::JMP => target().unwrap_or_else(|| all.clone()),
+                Opcode::JMPT | Opcode::JMPF => match target() {
+                    Some(mut successors) => {
+                        successors.extend(next(index));
+                        successors
+                    }
+                    None => all.clone(),
+                },
+                _ => next(index),
+            }
+        })
+        .collect()
+}
+
+/// Computes the virtual registers live before and after every instruction
+fn get_liveness(
+    accesses: &[Vec<(Access, u32, usize)>],
+    successors: &[Vec<usize>],
+) -> (Vec<BTreeSet<u32>>, Vec<BTreeSet<u32>>) {
+    let len = accesses.len();
+    let mut live_in = vec![BTreeSet::new(); len];
+    let mut live_out = vec![BTreeSet::new(); len];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for index in (0..len).rev() {
+            let out: BTreeSet<u32> = successors[index]
+                .iter()
+                .flat_map(|succ| live_in[*succ].iter().copied())
+                .collect();
+            let mut inp = out.clone();
+            for (access, reg, _) in accesses[index].iter() {
+                if *access == Access::Def {
+                    inp.remove(reg);
+                }
+            }
+            for (access, reg, _) in accesses[index].iter() {
+                if *access == Access::Use && *reg >= FIRST_VIRTUAL {
+                    inp.insert(*reg);
+                }
+            }
+            if inp != live_in[index] || out != live_out[index] {
+                live_in[index] = inp;
+                live_out[index] = out;
+                changed = true;
+            }
+        }
+    }
+    (live_in, live_out)
+}
@@ -1,23 +1,11 @@
 use std::{
-    collections::{
-        HashMap,
-        HashSet,
-        VecDeque,
-    },
-    convert::{
-        From,
-        Into,
-    },
+    convert::Into,
+    str::FromStr,
 };
 
 use epd::*;
 use num_traits::FromPrimitive;
 
-use crate::codegen::error::{
-    Error as CompilerError,
-    Result as CompilerResult,
-};
-
 #[derive(Clone, PartialEq, Eq, Hash, Debug, Primitive)]
 pub enum Register {
     R0 = 0,
@@ -47,79 +35,22 @@ impl Into<u8> for Register {
     }
 }
 
-#[derive(PartialEq, Debug, Clone)]
-pub struct RegisterAllocator {
-    register_queue: VecDeque<Register>,
-    blocked_registers: HashSet<Register>,
-    forced_temp: Option<Register>,
-}
-
-impl RegisterAllocator {
-    /// Creates a new RegisterAllocator instance
-    pub fn new() -> RegisterAllocator {
-        let mut register_queue = VecDeque::new();
-        for i in 0u8..15 {
-            let reg = Register::from_u8(i).unwrap();
-            register_queue.push_back(Register::from(reg));
-        }
-        let mut reg_alloc = RegisterAllocator {
-            register_queue: register_queue,
-            blocked_registers: HashSet::new(),
-            forced_temp: None,
-        };
-        // Block the R0 register, as it is used for function return values
-        reg_alloc.block_register(Register::R0).unwrap();
-        reg_alloc
-    }
-
-    /// Gets the next temporary register, and puts it to the end of the queue
-    pub fn get_temp_register(&mut self) -> CompilerResult<Register> {
-        self.forced_temp = None;
-        let ret = self
-            .register_queue
-            .pop_front()
-            .ok_or(CompilerError::RegisterMapping)?;
-        self.register_queue.push_back(ret.clone());
-        Ok(ret)
-    }
-
-    /// Gets the last temporary register
-    pub fn get_last_temp_register(&self) -> CompilerResult<Register> {
-        if self.forced_temp.is_some() {
-            return Ok(self.forced_temp.as_ref().cloned().unwrap());
-        }
-        self.register_queue
-            .get(self.register_queue.len() - 1)
-            .cloned()
-            .ok_or(CompilerError::RegisterMapping)
-    }
-
-    /// Blocks a register from use for temporary calculations
-    pub fn block_register(&mut self, reg: Register) -> CompilerResult<()> {
-        let queue_index = self
-            .register_queue
-            .iter()
-            .position(|r| *r == reg)
-            .ok_or(CompilerError::RegisterMapping)?;
-        self.register_queue
-            .remove(queue_index)
-            .ok_or(CompilerError::RegisterMapping)?;
-        self.blocked_registers.insert(reg);
-        Ok(())
-    }
-
-    /// Unblocks a register for use for temporary calculations
-    pub fn unblock_register(&mut self, reg: Register) -> CompilerResult<()> {
-        let removed = self.blocked_registers.remove(&reg);
-        if !removed {
-            return Err(CompilerError::RegisterMapping);
+impl FromStr for Register {
+    type Err = ();
+
+    /// Parses a register from its assembly name, e.g. `"R3"` or `"SP"`
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "SP" => Ok(Register::SP),
+            "IP" => Ok(Register::IP),
+            "BP" => Ok(Register::BP),
+            _ => {
+                let index: u8 = name.strip_prefix('R').ok_or(())?.parse().map_err(|_| ())?;
+                if index > 15 {
+                    return Err(());
+                }
+                Register::from_u8(index).ok_or(())
+            }
         }
-        self.register_queue.push_back(reg);
-        Ok(())
-    }
-
-    /// Forces a certain register to be returned from get_last_temp_register()
-    pub fn force_temp_register(&mut self, reg: Register) {
-        self.forced_temp = Some(reg);
     }
 }
@@ -29,6 +29,21 @@ pub enum AddressType {
     Swap,
 }
 
+impl AddressType {
+    /// Returns the type encoded in the top bits of a raw address, if they
+    /// denote one
+    pub fn from_raw(raw: u64) -> Option<AddressType> {
+        match raw >> 61 {
+            0 => Some(AddressType::Program),
+            1 => Some(AddressType::Stack),
+            2 => Some(AddressType::Heap),
+            3 => Some(AddressType::Swap),
+            4 => Some(AddressType::Foreign),
+            _ => None,
+        }
+    }
+}
+
 impl Address {
     /// Creates a new address given the actual address and a type
     pub fn new(real_address: u64, address_type: AddressType) -> Address {
@@ -53,26 +68,15 @@ impl Address {
 
     /// Adds a signed 16-bit integer offset to the real address
     pub fn with_offset(mut self, offset: i16) -> Address {
-        if offset < 0 {
-            self.real_address -= offset.abs() as u64;
-        } else {
-            self.real_address += offset as u64;
-        }
+        self.real_address = self.real_address.wrapping_add(offset as i64 as u64);
         self
     }
 }
 
 impl From<u64> for Address {
     fn from(raw: u64) -> Address {
-        let type_raw = raw >> 61;
-        let address_type = match type_raw {
-            0 => AddressType::Program,
-            1 => AddressType::Stack,
-            2 => AddressType::Heap,
-            3 => AddressType::Swap,
-            4 => AddressType::Foreign,
-            _ => panic!("Address is not formatted correctly!"),
-        };
+        let address_type =
+            AddressType::from_raw(raw).expect("Address is not formatted correctly!");
         // Remove 2 left most bits, which are the type
         let mut real_address = raw << 3;
         real_address = real_address >> 3;
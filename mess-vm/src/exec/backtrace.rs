@@ -0,0 +1,70 @@
+//! Script call stacks, captured when execution fails
+
+use std::fmt::{
+    Display,
+    Formatter,
+    Result as FmtResult,
+};
+
+use crate::codegen::output::{
+    Output,
+    SourceLocation,
+};
+
+/// A single frame of a script backtrace
+#[derive(Clone, PartialEq, Debug)]
+pub struct Frame {
+    /// The code offset the frame is executing
+    pub offset: usize,
+    /// The uid of the function containing the offset
+    pub fn_uid: Option<u64>,
+    /// The name of the function containing the offset
+    pub fn_name: Option<String>,
+    /// The source location of the offset, if the program has a line table
+    pub location: Option<SourceLocation>,
+}
+
+impl Frame {
+    /// Resolves the frame executing the given code offset
+    pub fn new(program: &Output, offset: usize) -> Frame {
+        let fn_uid = program.get_function_at(offset).map(|(uid, _)| uid);
+        Frame {
+            offset,
+            fn_uid,
+            fn_name: fn_uid
+                .and_then(|uid| program.get_function_name(uid))
+                .map(String::from),
+            location: program.get_location(offset).cloned(),
+        }
+    }
+}
+
+impl Display for Frame {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self.fn_name.as_ref() {
+            Some(fn_name) => write!(f, "{}", fn_name)?,
+            None => write!(f, "<unknown>")?,
+        };
+        write!(f, " at offset {}", self.offset)?;
+        if let Some(location) = self.location.as_ref() {
+            write!(f, " ({})", location)?;
+        }
+        Ok(())
+    }
+}
+
+/// The script call stack at some point of execution, innermost frame first
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Backtrace {
+    /// The frames, innermost first
+    pub frames: Vec<Frame>,
+}
+
+impl Display for Backtrace {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        for (index, frame) in self.frames.iter().enumerate() {
+            writeln!(f, "{:>4}: {}", index, frame)?;
+        }
+        Ok(())
+    }
+}
@@ -1,6 +1,7 @@
 use std::{
     cell::RefCell,
     collections::{
+        BTreeSet,
         HashMap,
         HashSet,
         VecDeque,
@@ -16,26 +17,32 @@ use std::{
     mem::{
         size_of,
         size_of_val,
+        swap,
     },
-    ops::{
-        Deref,
-        Range,
-    },
+    ops::Range,
     sync::{
         Arc,
         Mutex,
     },
+    task::{
+        Context as TaskContext,
+        Poll,
+    },
+    time::Instant,
 };
 
 use bincode::{
     deserialize,
     serialize,
 };
+use mess_api::prelude::{
+    Adapter as ApiAdapter,
+    ForeignObject,
+};
 use mess_core::{
     exec::Executor,
-    util::uid::UIDGenerator,
+    parser::ast::Type,
 };
-use num_traits::FromPrimitive;
 use rand::{
     thread_rng,
     Rng,
@@ -51,32 +58,122 @@ use super::{
         Address,
         AddressType,
     },
+    backtrace::{
+        Backtrace,
+        Frame,
+    },
+    coroutine::{
+        Context,
+        Coroutine,
+        CoroutineHandle,
+        CoroutineIter,
+        CoroutineState,
+        Resumed,
+    },
+    debug::{
+        DebugValue,
+        StepMode,
+        StopReason,
+    },
+    decode::{
+        DecodeCache,
+        FromOperand,
+        MAX_OPERANDS,
+    },
+    future::{
+        PendingCall,
+        RunFuture,
+    },
+    handle::{
+        ForeignLeak,
+        HandleTable,
+    },
+    interrupt::InterruptHandle,
     is::Opcode,
+    limits::Limits,
+    profiler::Profiler,
     register::{
         Register,
         RegisterAccess,
     },
+    snapshot::{
+        diff_code,
+        hash_program,
+        ForeignSnapshot,
+        Snapshot,
+    },
+    tier::{
+        CompiledFn,
+        CompiledFnError,
+        HotCounters,
+        TierPolicy,
+        Tiering,
+    },
+};
+use crate::{
+    adapter::Adapter,
+    codegen::output::{
+        LocalVar,
+        Output as OutputVM,
+    },
 };
-use crate::codegen::output::Output as OutputVM;
 
 pub type CoreResult<T> = Result<T, CoreError>;
 
+/// Called with the foreign objects scripts still hold references to when a
+/// core is dropped, see `Core::set_leak_handler`
+pub type LeakHandler = Box<dyn FnMut(&[ForeignLeak]) + Send>;
+
 pub const STACK_GROW_INCREMENT: usize = 1024;
 pub const STACK_GROW_THRESHOLD: usize = 64;
 pub const SWAP_SPACE_SIZE: usize = 64;
+/// The most bytes of a panic message read from script memory
+pub const MAX_PANIC_MESSAGE_LEN: usize = 4096;
 
 pub struct Core {
     stack: Vec<u8>,
     heap: Vec<u8>,
     heap_pointers: Vec<Range<usize>>,
-    foreign_pointers: HashMap<u64, u64>,
+    foreign_objects: HandleTable,
     foreign_function_uids: HashSet<u64>,
     swap: Vec<u8>,
-    program: Option<OutputVM>,
+    program: Option<Arc<OutputVM>>,
+    private_code: Option<Vec<u8>>,
     call_stack: VecDeque<usize>,
     registers: [Register; 16],
     ip: Register,
     sp: Register,
+    /// The base of the innermost frame, where the stack pointer was when the
+    /// running function was called. Derived from the frame bases.
+    bp: Register,
+    fuel: Option<u64>,
+    overflow_mode: OverflowMode,
+    interrupt: InterruptHandle,
+    limits: Limits,
+    instr_offset: usize,
+    breakpoints: BTreeSet<usize>,
+    skip_breakpoint: Option<usize>,
+    frame_bases: VecDeque<u64>,
+    entry_frame_base: u64,
+    /// Whether a run was entered and has neither finished nor failed
+    in_run: bool,
+    /// Whether the last run failed, its frames are kept until the next one
+    run_failed: bool,
+    profiler: Option<Profiler>,
+    decode_cache: DecodeCache,
+    operands: [u64; MAX_OPERANDS],
+    operand_index: usize,
+    coroutines: HashMap<u64, Coroutine>,
+    /// The number of coroutines that have not finished
+    live_coroutines: usize,
+    next_coroutine_id: u64,
+    coroutine_depth: usize,
+    yielded: bool,
+    pending_call: Option<PendingCall>,
+    host_error: RefCell<Option<CoreError>>,
+    leak_handler: Option<LeakHandler>,
+    tiering: Option<Tiering>,
+    compiled_fns: HashMap<u64, CompiledFn>,
 }
 
 #[derive(Debug)]
@@ -90,35 +187,230 @@ pub enum CoreError {
     OperatorSerialize,
     EmptyCallStack,
     UnknownFunctionUid,
+    /// No function with the given name exists in the program
+    UnknownFunctionName(String),
     InvalidStackPointer,
     InvalidRegister,
     NoReturnValue,
+    /// The heap limit would be exceeded by an allocation
+    OutOfMemory,
+    /// The maximum call depth was exceeded
+    CallStackOverflow,
+    /// The maximum number of live foreign objects was exceeded
+    ForeignObjectLimit,
+    /// Memory was accessed outside of its bounds
+    InvalidAddress(u64),
     Halted(u8),
+    /// The instruction budget ran out, execution can be resumed after refueling
+    OutOfFuel,
+    /// Execution was stopped through an `InterruptHandle`, and can be resumed
+    Interrupted,
+    /// A breakpoint at the given offset was reached, execution can be resumed
+    Breakpoint(usize),
+    /// No code was generated for the given source line
+    NoCodeAtLine(String, usize),
+    /// The requested stack frame does not exist
+    InvalidFrame(usize),
+    /// `YIELD` was executed outside of a coroutine
+    YieldOutsideCoroutine,
+    /// No coroutine with the given handle exists
+    UnknownCoroutine(u64),
+    /// The coroutine with the given handle is running or has finished
+    CoroutineNotResumable(u64),
+    /// The maximum number of coroutines that have not finished was exceeded
+    CoroutineLimit,
+    /// The foreign object behind the pointer can't be snapshotted
+    NotSnapshottable(u64),
+    /// A snapshot could not be decoded or was taken of another program
+    InvalidSnapshot,
+    /// A snapshot could not be encoded
+    SnapshotSerialize,
+    /// The core waits for an async host function, execution continues
+    /// with `Core::poll_resume` once it completes
+    Pending,
+    /// Async host functions can't be called from inside a coroutine
+    AsyncCallInCoroutine,
+    /// No foreign object exists for the handle, or it was removed
+    InvalidHandle(u64),
+    /// The foreign object behind the handle has another type than requested
+    ForeignTypeMismatch {
+        /// The handle
+        ptr: u64,
+        /// The type the host function asked for
+        expected: &'static str,
+        /// The type of the object
+        found: &'static str,
+    },
+    /// Integer arithmetic overflowed with `OverflowMode::Trap`
+    IntegerOverflow,
+    /// An integer was divided by zero
+    DivisionByZero,
+    /// A program can't replace the loaded one, for the given reason
+    IncompatibleReload(String),
+    /// The script called `panic` or a failing `assert`. Messages are cut off
+    /// after `MAX_PANIC_MESSAGE_LEN` bytes. The failed run keeps its frames
+    /// until the next one, and functions can be run again.
+    ScriptPanic {
+        /// The message the script panicked with
+        message: String,
+        /// Where the script panicked
+        backtrace: Backtrace,
+    },
+    /// A compiled version of a script function failed for the given reason
+    CompiledFnFailed(String),
+    /// The last run failed and can't be resumed. Its frames are kept until
+    /// the next run or `Core::reset_run`
+    RunFailed,
+    /// A run failed with the error, which can't be resumed
+    Backtraced {
+        /// The error the run failed with
+        error: Box<CoreError>,
+        /// Where the run failed
+        backtrace: Backtrace,
+    },
+}
+
+impl CoreError {
+    /// Returns the error a failed run attached its backtrace to, or the
+    /// error itself
+    pub fn get_inner(&self) -> &CoreError {
+        match self {
+            CoreError::Backtraced { error, .. } => error.as_ref(),
+            _ => self,
+        }
+    }
+
+    /// Takes the error a failed run attached its backtrace to, or the error
+    /// itself
+    pub fn into_inner(self) -> CoreError {
+        match self {
+            CoreError::Backtraced { error, .. } => *error,
+            _ => self,
+        }
+    }
+
+    /// Returns where the script failed, if the error carries a backtrace
+    pub fn get_backtrace(&self) -> Option<&Backtrace> {
+        match self {
+            CoreError::Backtraced { backtrace, .. } | CoreError::ScriptPanic { backtrace, .. } => {
+                Some(backtrace)
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether execution can continue after the error, so the run keeps its frames
+    fn is_resumable(&self) -> bool {
+        matches!(
+            self,
+            CoreError::OutOfFuel | CoreError::Interrupted | CoreError::Pending | CoreError::Breakpoint(_)
+        )
+    }
+}
+
+/// What integer arithmetic does when its result is out of range
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum OverflowMode {
+    /// Keep the low bits of the result, like two's complement hardware
+    #[default]
+    Wrap,
+    /// Stop with `CoreError::IntegerOverflow`
+    Trap,
+}
+
+/// Returns the name of the function containing the offset, for profiles
+fn profile_fn_name(program: &OutputVM, offset: usize) -> String {
+    program
+        .get_function_at(offset)
+        .and_then(|(uid, _)| program.get_function_name(uid))
+        .map(String::from)
+        .unwrap_or_else(|| format!("<offset {}>", offset))
+}
+
+/// Decodes the bytes of a local or field of the given type for debuggers,
+/// expanding containers into their fields
+fn decode_debug_value(program: &OutputVM, var_type: &Type, bytes: Vec<u8>) -> CoreResult<DebugValue> {
+    let mut raw = [0u8; 8];
+    let len = bytes.len().min(8);
+    raw[0..len].copy_from_slice(&bytes[0..len]);
+    Ok(match var_type {
+        Type::Int => DebugValue::Int(i64::from_le_bytes(raw)),
+        Type::I8 => DebugValue::Int(raw[0] as i8 as i64),
+        Type::I16 => DebugValue::Int(i16::from_le_bytes([raw[0], raw[1]]) as i64),
+        Type::I32 => DebugValue::Int(i32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as i64),
+        Type::U8 | Type::U16 | Type::U32 | Type::U64 => DebugValue::UInt(u64::from_le_bytes(raw)),
+        Type::Float => DebugValue::Float(
+            deserialize(&bytes).map_err(|_| CoreError::OperatorDeserialize)?,
+        ),
+        Type::F64 => DebugValue::Double(f64::from_le_bytes(raw)),
+        Type::Bool => DebugValue::Bool(bytes[0] != 0),
+        Type::Named(name) => match program.debug_containers.get(name) {
+            Some(fields) => {
+                let mut values = Vec::new();
+                for field in fields.iter() {
+                    let field_bytes = usize::try_from(field.offset)
+                        .ok()
+                        .and_then(|start| bytes.get(start..start.checked_add(field.size)?))
+                        .ok_or(CoreError::OperatorDeserialize)?
+                        .to_vec();
+                    let value = decode_debug_value(program, &field.var_type, field_bytes)?;
+                    values.push((field.name.clone(), value));
+                }
+                DebugValue::Fields(values)
+            }
+            None => DebugValue::Bytes(bytes),
+        },
+        _ => DebugValue::Bytes(bytes),
+    })
 }
 
 impl Display for CoreError {
     fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
-        write!(f, "{:?}", self)
+        match self {
+            CoreError::Backtraced { error, backtrace } => write!(f, "{}\n{}", error, backtrace),
+            _ => write!(f, "{:?}", self),
+        }
+    }
+}
+
+impl Error for CoreError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CoreError::Backtraced { error, .. } => Some(error.as_ref()),
+            _ => None,
+        }
     }
 }
 
-impl Error for CoreError {}
+impl Drop for Core {
+    fn drop(&mut self) {
+        if let Some(handler) = self.leak_handler.as_mut() {
+            let leaks = self.foreign_objects.leaks();
+            if !leaks.is_empty() {
+                handler(&leaks);
+            }
+        }
+    }
+}
 
 impl Executor for Core {
     type Input = OutputVM;
     type Error = CoreError;
 
     fn run_fn(&mut self, fn_name: &str) -> Result<(), Self::Error> {
-        let program = self.program.as_ref().ok_or(CoreError::Unknown)?;
-        Err(CoreError::Unknown)
+        let program = self.program.as_ref().ok_or(CoreError::NoProgram)?;
+        let uid = program
+            .get_function_uid(fn_name)
+            .ok_or_else(|| CoreError::UnknownFunctionName(String::from(fn_name)))?;
+        Core::run_fn(self, uid)
     }
 
     fn run(&mut self) -> Result<(), Self::Error> {
-        Err(CoreError::Unknown)
+        Core::run(self)
     }
 
     fn set_input(&mut self, input: Self::Input) {
-        self.program = Some(input);
+        self.load_program(input);
     }
 }
 
@@ -133,25 +425,244 @@ impl Core {
         let address = Address::new(0, AddressType::Stack);
         sp.set::<u64>(address.into());
         Core {
-            program: Option::<OutputVM>::None,
+            program: None,
+            private_code: None,
             swap: swap,
             stack: stack,
             heap: Vec::new(),
             heap_pointers: Vec::new(),
-            foreign_pointers: HashMap::new(),
+            foreign_objects: HandleTable::default(),
             foreign_function_uids: HashSet::new(),
             call_stack: VecDeque::new(),
             registers: [Register::new(); 16],
             ip: Register::new(),
             sp: sp,
+            bp: sp,
+            fuel: None,
+            overflow_mode: OverflowMode::default(),
+            interrupt: InterruptHandle::default(),
+            limits: Limits::default(),
+            instr_offset: 0,
+            breakpoints: BTreeSet::new(),
+            skip_breakpoint: None,
+            frame_bases: VecDeque::new(),
+            entry_frame_base: sp.get(),
+            in_run: false,
+            run_failed: false,
+            profiler: None,
+            decode_cache: DecodeCache::default(),
+            operands: [0; MAX_OPERANDS],
+            operand_index: 0,
+            coroutines: HashMap::new(),
+            live_coroutines: 0,
+            next_coroutine_id: 0,
+            coroutine_depth: 0,
+            yielded: false,
+            pending_call: None,
+            host_error: RefCell::new(None),
+            leak_handler: None,
+            tiering: None,
+            compiled_fns: HashMap::new(),
+        }
+    }
+
+    /// Sets the resource limits of this core
+    pub fn with_limits(mut self, limits: Limits) -> Core {
+        self.limits = limits;
+        self
+    }
+
+    /// Sets the resource limits of this core
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Returns the resource limits of this core
+    pub fn get_limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Enables or disables collecting a profile while running
+    pub fn with_profiling(mut self, profiling: bool) -> Core {
+        self.set_profiling(profiling);
+        self
+    }
+
+    /// Enables or disables collecting a profile while running.
+    /// Enabling keeps the statistics collected so far.
+    pub fn set_profiling(&mut self, profiling: bool) {
+        if !profiling {
+            self.profiler = None;
+        } else if self.profiler.is_none() {
+            self.profiler = Some(Profiler::default());
+        }
+    }
+
+    /// Returns the profile collected so far, if profiling is enabled
+    pub fn get_profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    /// Returns the profile collected so far and starts a new one
+    pub fn take_profiler(&mut self) -> Option<Profiler> {
+        let profiler = self.profiler.take();
+        self.set_profiling(profiler.is_some());
+        profiler
+    }
+
+    /// Sets the number of instructions the core may execute before
+    /// stopping with `CoreError::OutOfFuel`. `None` means unlimited.
+    /// Functions compiled by tiered execution are interpreted while fuel is
+    /// set, as compiled code doesn't count instructions.
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
+
+    /// Returns the remaining instruction budget
+    pub fn get_fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    /// Adds to the remaining instruction budget, if one is set
+    pub fn add_fuel(&mut self, fuel: u64) {
+        if let Some(remaining) = self.fuel.as_mut() {
+            *remaining = remaining.saturating_add(fuel);
         }
     }
 
+    /// Sets what integer arithmetic does when its result is out of range
+    pub fn with_overflow_mode(mut self, overflow_mode: OverflowMode) -> Core {
+        self.overflow_mode = overflow_mode;
+        self
+    }
+
+    /// Sets what integer arithmetic does when its result is out of range
+    pub fn set_overflow_mode(&mut self, overflow_mode: OverflowMode) {
+        self.overflow_mode = overflow_mode;
+    }
+
+    /// Returns what integer arithmetic does when its result is out of range
+    pub fn get_overflow_mode(&self) -> OverflowMode {
+        self.overflow_mode
+    }
+
+    /// Returns a handle that can interrupt this core from another thread.
+    /// Functions compiled by tiered execution are interpreted from then on,
+    /// as compiled code doesn't check for interrupts.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
     #[inline]
     pub fn load_program(&mut self, program: OutputVM) {
-        /*self.foreign_function_uids.clear();
-        self.foreign_function_uids = program.foreign_functions.iter().map(|(k, _)| *k).collect();
-        self.program = Some(program);*/
+        self.load_shared_program(Arc::new(program));
+    }
+
+    /// Loads a program shared with other cores. The code is only copied
+    /// into this core once it writes to program memory.
+    pub fn load_shared_program(&mut self, program: Arc<OutputVM>) {
+        // The frames of a failed run point into the old program
+        if self.run_failed {
+            self.reset_run();
+        }
+        self.foreign_function_uids = program.foreign_functions.keys().copied().collect();
+        self.decode_cache.reset(program.code.len());
+        self.pending_call = None;
+        self.private_code = None;
+        self.program = Some(program);
+        // Compiled functions and counters are keyed by the uids of the old program
+        self.compiled_fns.clear();
+        if let Some(tiering) = self.tiering.as_mut() {
+            tiering.reset();
+        }
+    }
+
+    /// Replaces the function bodies of the loaded program with the ones of a
+    /// recompiled version of it, e.g. after its source file changed.
+    /// Functions present in both versions keep their uids, so uids the host
+    /// looked up call the new bodies, and static variables present in both
+    /// versions keep their values. The heap and foreign objects are kept as
+    /// they are, breakpoints are cleared.
+    ///
+    /// Fails with `CoreError::IncompatibleReload` while a run or coroutine is
+    /// suspended in the old code, when a function got another uid, e.g. from
+    /// a compiler that wasn't reset but created anew, or when a static
+    /// variable changed its type. The loaded program stays in place then.
+    pub fn reload_program(&mut self, mut program: OutputVM) -> CoreResult<()> {
+        let old_program = self.program.as_ref().ok_or(CoreError::NoProgram)?;
+        if self.in_run {
+            return Err(CoreError::IncompatibleReload(String::from(
+                "a run of the loaded program is suspended",
+            )));
+        }
+        if self
+            .coroutines
+            .values()
+            .any(|coroutine| coroutine.state != CoroutineState::Finished)
+        {
+            return Err(CoreError::IncompatibleReload(String::from(
+                "a coroutine of the loaded program has not finished",
+            )));
+        }
+
+        for (fn_name, uid) in program.function_name_map.iter() {
+            if let Some(old_uid) = old_program.function_name_map.get(fn_name) {
+                if old_uid != uid {
+                    return Err(CoreError::IncompatibleReload(format!(
+                        "function {} changed its uid from {} to {}",
+                        fn_name, old_uid, uid
+                    )));
+                }
+            }
+            if let Some(old_name) = old_program.get_function_name(*uid) {
+                if old_name != fn_name {
+                    return Err(CoreError::IncompatibleReload(format!(
+                        "function {} got the uid {} of function {}",
+                        fn_name, uid, old_name
+                    )));
+                }
+            }
+        }
+
+        let old_code = self.get_code()?;
+        for new_var in program.statics.iter() {
+            let old_var = match old_program.get_static(&new_var.name) {
+                Some(old_var) => old_var,
+                None => continue,
+            };
+            if old_var.var_type != new_var.var_type || old_var.size != new_var.size {
+                return Err(CoreError::IncompatibleReload(format!(
+                    "static {} changed from {:?} to {:?}",
+                    new_var.name, old_var.var_type, new_var.var_type
+                )));
+            }
+            let value = old_code
+                .get(old_var.offset..old_var.offset + old_var.size)
+                .ok_or(CoreError::InvalidAddress(old_var.offset as u64))?;
+            program
+                .code
+                .get_mut(new_var.offset..new_var.offset + new_var.size)
+                .ok_or(CoreError::InvalidAddress(new_var.offset as u64))?
+                .copy_from_slice(value);
+        }
+
+        self.breakpoints.clear();
+        self.skip_breakpoint = None;
+        self.load_program(program);
+        Ok(())
+    }
+
+    /// Returns the loaded program, e.g. to load it into another core
+    pub fn get_program(&self) -> Option<&Arc<OutputVM>> {
+        self.program.as_ref()
+    }
+
+    /// Returns the program memory of this core, including its writes
+    fn get_code(&self) -> CoreResult<&[u8]> {
+        match self.private_code.as_ref() {
+            Some(code) => Ok(code),
+            None => Ok(&self.program.as_ref().ok_or(CoreError::NoProgram)?.code),
+        }
     }
 
     #[inline]
@@ -168,12 +679,21 @@ impl Core {
     }
 
     #[inline]
+    /// Fetches the instruction at the instruction pointer, moving the
+    /// pointer past it. Its operands are read with `get_op`.
     pub fn get_opcode(&mut self) -> CoreResult<Opcode> {
         //println!("ip: {}", self.ip.get::<usize>());
-        let op: u8 = self.get_op()?;
-        let opcode = Opcode::from_u8(op).ok_or(CoreError::InvalidOpcode(op))?;
+        let ip: usize = self.ip.get();
+        let code: &[u8] = match self.private_code.as_ref() {
+            Some(code) => code,
+            None => &self.program.as_ref().ok_or(CoreError::NoProgram)?.code,
+        };
+        let instr = self.decode_cache.get(code, ip)?;
+        self.operands = instr.operands;
+        self.operand_index = 0;
+        self.ip.set(ip + instr.size);
         //println!("opcode: {:?}", opcode);
-        Ok(opcode)
+        Ok(instr.opcode.clone())
     }
 
     #[inline]
@@ -196,10 +716,285 @@ impl Core {
     }
 
     pub fn run_at(&mut self, offset: usize) -> CoreResult<()> {
+        self.enter_at(offset);
+        self.resume()
+    }
+
+    /// Sets up a call of the function at the given offset without running it,
+    /// e.g. to set breakpoints before stepping into it
+    pub fn enter_at(&mut self, offset: usize) {
+        // A run suspended by e.g. running out of fuel, or a failed one, is abandoned
+        if self.in_run || self.run_failed {
+            self.reset_run();
+        }
         self.ip.set(offset);
+        self.instr_offset = offset;
+        self.entry_frame_base = self.sp.get();
+        self.sync_bp();
+        self.pending_call = None;
+        self.in_run = true;
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.exit_all();
+        }
+    }
+
+    /// Continues execution at the current instruction pointer,
+    /// e.g. after `CoreError::OutOfFuel` or `CoreError::Interrupted`
+    pub fn resume(&mut self) -> CoreResult<()> {
+        match self.run_until(StepMode::Run)? {
+            StopReason::Breakpoint(offset) => Err(CoreError::Breakpoint(offset)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Runs until a breakpoint is hit or the entry function returns
+    pub fn continue_execution(&mut self) -> CoreResult<StopReason> {
+        self.run_until(StepMode::Run)
+    }
+
+    /// Executes a single instruction
+    pub fn step(&mut self) -> CoreResult<StopReason> {
+        self.run_until(StepMode::Step)
+    }
+
+    /// Executes a single instruction, running called functions to completion
+    pub fn step_over(&mut self) -> CoreResult<StopReason> {
+        let depth = self.call_stack.len();
+        self.run_until(StepMode::StepOver(depth))
+    }
+
+    /// Runs until the current function returns to its caller
+    pub fn step_out(&mut self) -> CoreResult<StopReason> {
+        let depth = self.call_stack.len();
+        self.run_until(StepMode::StepOut(depth))
+    }
+
+    fn run_until(&mut self, mode: StepMode) -> CoreResult<StopReason> {
+        if self.pending_call.is_some() {
+            return Err(CoreError::Pending);
+        }
+        if self.run_failed {
+            return Err(CoreError::RunFailed);
+        }
+        let result = self.execute(mode).map_err(|err| self.attach_backtrace(err));
+        match result {
+            Err(CoreError::OutOfFuel) | Err(CoreError::Interrupted) | Err(CoreError::Pending) => {}
+            // The run can't be resumed, its frames stay for inspection until
+            // the host starts the next one
+            Err(_) => {
+                self.in_run = false;
+                self.run_failed = true;
+            }
+            Ok(StopReason::Finished) => self.in_run = false,
+            Ok(_) => {}
+        }
+        result
+    }
+
+    /// Drops the frames of a suspended or failed run, restoring the stack
+    /// pointer it was entered with. Entering the next run does this as well.
+    pub fn reset_run(&mut self) {
+        self.sp.set(self.entry_frame_base);
+        self.call_stack.clear();
+        self.frame_bases.clear();
+        self.sync_bp();
+        self.in_run = false;
+        self.run_failed = false;
+    }
+
+    /// Whether the last run failed and keeps its frames until it is reset
+    pub fn has_failed_run(&self) -> bool {
+        self.run_failed
+    }
+
+    /// Runs the function with the given uid, suspending while async host
+    /// functions it calls are pending
+    pub fn run_fn_async(&mut self, uid: u64) -> CoreResult<RunFuture<'_>> {
+        let program = self.program.as_ref().ok_or(CoreError::NoProgram)?;
+        let offset = *program
+            .functions
+            .get(&uid)
+            .ok_or(CoreError::UnknownFunctionUid)?;
+        self.enter_at(offset);
+        Ok(RunFuture { core: self })
+    }
+
+    /// Continues execution like `Core::resume`, suspending while async host
+    /// functions are pending
+    pub fn resume_async(&mut self) -> RunFuture<'_> {
+        RunFuture { core: self }
+    }
+
+    /// Whether the core waits for an async host function
+    pub fn is_pending(&self) -> bool {
+        self.pending_call.is_some()
+    }
+
+    /// Polls the pending async host function, if any, and continues execution
+    /// once it completed. Returns `Poll::Pending` while the core waits for a
+    /// host function, the waker of the context is woken when it can continue.
+    pub fn poll_resume(&mut self, cx: &mut TaskContext<'_>) -> Poll<CoreResult<()>> {
+        loop {
+            if let Some(pending_call) = self.pending_call.as_mut() {
+                let completion = match pending_call.future.as_mut().poll(cx) {
+                    Poll::Ready(completion) => completion,
+                    Poll::Pending => return Poll::Pending,
+                };
+                if let Some(pending_call) = self.pending_call.take() {
+                    let mut adapter = ApiAdapter::new(Adapter::new(&pending_call.function, self));
+                    completion(&mut adapter);
+                }
+                if let Some(err) = self.host_error.take() {
+                    return Poll::Ready(Err(err));
+                }
+            }
+            match self.resume() {
+                Err(CoreError::Pending) => continue,
+                result => return Poll::Ready(result),
+            }
+        }
+    }
+
+    /// Sets a breakpoint at a code offset
+    pub fn add_breakpoint(&mut self, offset: usize) {
+        self.breakpoints.insert(offset);
+    }
+
+    /// Sets breakpoints at the code generated for a source line,
+    /// returning their offsets
+    pub fn add_line_breakpoint(&mut self, file: &str, line: usize) -> CoreResult<Vec<usize>> {
+        let program = self.program.as_ref().ok_or(CoreError::NoProgram)?;
+        let offsets: Vec<usize> = program
+            .debug_lines
+            .iter()
+            .filter(|(_, location)| location.file == file && location.line == line)
+            .map(|(offset, _)| *offset)
+            .collect();
+        if offsets.is_empty() {
+            return Err(CoreError::NoCodeAtLine(String::from(file), line));
+        }
+        self.breakpoints.extend(offsets.iter());
+        Ok(offsets)
+    }
+
+    /// Removes the breakpoint at a code offset, returning whether it existed
+    pub fn remove_breakpoint(&mut self, offset: usize) -> bool {
+        self.breakpoints.remove(&offset)
+    }
+
+    /// Removes all breakpoints
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    /// Returns the offsets of all breakpoints
+    pub fn get_breakpoints(&self) -> impl Iterator<Item = &usize> {
+        self.breakpoints.iter()
+    }
+
+    /// Returns the offset of the instruction executed next
+    pub fn get_ip(&self) -> usize {
+        self.ip.get()
+    }
+
+    /// Returns the general purpose registers
+    pub fn get_registers(&self) -> &[Register; 16] {
+        &self.registers
+    }
+
+    /// Returns the raw stack pointer
+    pub fn get_sp(&self) -> u64 {
+        self.sp.get()
+    }
+
+    /// Reads the named locals of a stack frame, innermost frame first
+    pub fn get_locals(&self, frame_index: usize) -> CoreResult<Vec<(LocalVar, DebugValue)>> {
+        let program = self.program.as_ref().ok_or(CoreError::NoProgram)?;
+        if frame_index > self.call_stack.len() {
+            return Err(CoreError::InvalidFrame(frame_index));
+        }
+        let frame_base = match self.frame_bases.get(frame_index) {
+            Some(frame_base) => *frame_base,
+            None => self.entry_frame_base,
+        };
+        let frame = &self.backtrace().frames[frame_index];
+        let locals = match frame.fn_uid.and_then(|uid| program.debug_locals.get(&uid)) {
+            Some(locals) => locals,
+            None => return Ok(Vec::new()),
+        };
+        let mut ret = Vec::new();
+        for local in locals.iter() {
+            let offset = i16::try_from(local.offset)
+                .map_err(|_| CoreError::InvalidAddress(frame_base))?;
+            let bytes = self.mem_get_n((frame_base, offset), local.size)?;
+            let value = decode_debug_value(program, &local.var_type, bytes)?;
+            ret.push((local.clone(), value));
+        }
+        Ok(ret)
+    }
+
+    /// Returns the script backtrace at the current instruction
+    pub fn backtrace(&self) -> Backtrace {
+        let program = match self.program.as_ref() {
+            Some(program) => program,
+            None => return Backtrace::default(),
+        };
+        let mut frames = vec![Frame::new(program, self.instr_offset)];
+        // Return addresses point behind the CALL, which still lies inside the caller
+        for ret_ip in self.call_stack.iter() {
+            frames.push(Frame::new(program, ret_ip.saturating_sub(1)));
+        }
+        Backtrace { frames }
+    }
+
+    /// Attaches the current backtrace to an error a run can't resume from.
+    /// Errors of a failing coroutine keep the backtrace inside the coroutine.
+    fn attach_backtrace(&self, err: CoreError) -> CoreError {
+        if err.is_resumable() || err.get_backtrace().is_some() || self.program.is_none() {
+            return err;
+        }
+        CoreError::Backtraced {
+            error: Box::new(err),
+            backtrace: self.backtrace(),
+        }
+    }
+
+    fn execute(&mut self, mode: StepMode) -> CoreResult<StopReason> {
         let program_len = self.program_len()?;
         //println!("Program length: {}", program_len);
         while self.ip.get::<usize>() < program_len {
+            self.instr_offset = self.ip.get();
+            // A breakpoint the core stopped at is skipped when resuming there.
+            // Coroutines run to their next yield without stopping.
+            let skip_breakpoint = self.skip_breakpoint.take();
+            if self.coroutine_depth == 0
+                && self.breakpoints.contains(&self.instr_offset)
+                && skip_breakpoint != Some(self.instr_offset)
+            {
+                self.skip_breakpoint = Some(self.instr_offset);
+                return Ok(StopReason::Breakpoint(self.instr_offset));
+            }
+            if self.interrupt.take() {
+                return Err(CoreError::Interrupted);
+            }
+            if let Some(fuel) = self.fuel.as_mut() {
+                if *fuel == 0 {
+                    return Err(CoreError::OutOfFuel);
+                }
+                *fuel -= 1;
+            }
+            let instr_start = match self.profiler.as_mut() {
+                Some(profiler) => {
+                    // Entering the function the core was started in
+                    if profiler.depth() == 0 {
+                        let program = self.program.as_ref().ok_or(CoreError::NoProgram)?;
+                        profiler.enter(&profile_fn_name(program, self.instr_offset));
+                    }
+                    Some(Instant::now())
+                }
+                None => None,
+            };
+            let mut finished = false;
             //println!("ip: {}", self.ip.get::<usize>());
             let opcode = self.get_opcode()?;
             //println!("opcode: {:?}", opcode);
@@ -377,7 +1172,8 @@ impl Core {
                     let target_reg: u8 = self.get_op()?;
                     let lhs: i64 = { self.reg(lhs_reg)?.get() };
                     let rhs: i64 = { self.reg(rhs_reg)?.get() };
-                    self.reg(target_reg)?.set(lhs + rhs);
+                    let value = self.check_overflow(lhs.checked_add(rhs), lhs.wrapping_add(rhs))?;
+                    self.reg(target_reg)?.set(value);
                 }
                 Opcode::SUBI => {
                     let lhs_reg: u8 = self.get_op()?;
@@ -385,7 +1181,8 @@ impl Core {
                     let target_reg: u8 = self.get_op()?;
                     let lhs: i64 = { self.reg(lhs_reg)?.get() };
                     let rhs: i64 = { self.reg(rhs_reg)?.get() };
-                    self.reg(target_reg)?.set(lhs - rhs);
+                    let value = self.check_overflow(lhs.checked_sub(rhs), lhs.wrapping_sub(rhs))?;
+                    self.reg(target_reg)?.set(value);
                 }
                 Opcode::MULI => {
                     let lhs_reg: u8 = self.get_op()?;
@@ -393,7 +1190,8 @@ impl Core {
                     let target_reg: u8 = self.get_op()?;
                     let lhs: i64 = { self.reg(lhs_reg)?.get() };
                     let rhs: i64 = { self.reg(rhs_reg)?.get() };
-                    self.reg(target_reg)?.set(lhs * rhs);
+                    let value = self.check_overflow(lhs.checked_mul(rhs), lhs.wrapping_mul(rhs))?;
+                    self.reg(target_reg)?.set(value);
                 }
                 Opcode::DIVI => {
                     let lhs_reg: u8 = self.get_op()?;
@@ -401,35 +1199,46 @@ impl Core {
                     let target_reg: u8 = self.get_op()?;
                     let lhs: i64 = { self.reg(lhs_reg)?.get() };
                     let rhs: i64 = { self.reg(rhs_reg)?.get() };
-                    self.reg(target_reg)?.set(lhs / rhs)
+                    if rhs == 0 {
+                        return Err(CoreError::DivisionByZero);
+                    }
+                    let value = self.check_overflow(lhs.checked_div(rhs), lhs.wrapping_div(rhs))?;
+                    self.reg(target_reg)?.set(value);
                 }
                 Opcode::ADDI_I => {
                     let lhs_reg: u8 = self.get_op()?;
                     let rhs: i64 = self.get_op()?;
                     let target_reg: u8 = self.get_op()?;
                     let lhs: i64 = { self.reg(lhs_reg)?.get() };
-                    self.reg(target_reg)?.set(lhs + rhs);
+                    let value = self.check_overflow(lhs.checked_add(rhs), lhs.wrapping_add(rhs))?;
+                    self.reg(target_reg)?.set(value);
                 }
                 Opcode::SUBI_I => {
                     let lhs_reg: u8 = self.get_op()?;
                     let rhs: i64 = self.get_op()?;
                     let target_reg: u8 = self.get_op()?;
                     let lhs: i64 = { self.reg(lhs_reg)?.get() };
-                    self.reg(target_reg)?.set(lhs - rhs);
+                    let value = self.check_overflow(lhs.checked_sub(rhs), lhs.wrapping_sub(rhs))?;
+                    self.reg(target_reg)?.set(value);
                 }
                 Opcode::MULI_I => {
                     let lhs_reg: u8 = self.get_op()?;
                     let rhs: i64 = self.get_op()?;
                     let target_reg: u8 = self.get_op()?;
                     let lhs: i64 = { self.reg(lhs_reg)?.get() };
-                    self.reg(target_reg)?.set(lhs * rhs);
+                    let value = self.check_overflow(lhs.checked_mul(rhs), lhs.wrapping_mul(rhs))?;
+                    self.reg(target_reg)?.set(value);
                 }
                 Opcode::DIVI_I => {
                     let lhs_reg: u8 = self.get_op()?;
                     let rhs: i64 = self.get_op()?;
                     let target_reg: u8 = self.get_op()?;
                     let lhs: i64 = { self.reg(lhs_reg)?.get() };
-                    self.reg(target_reg)?.set(lhs / rhs);
+                    if rhs == 0 {
+                        return Err(CoreError::DivisionByZero);
+                    }
+                    let value = self.check_overflow(lhs.checked_div(rhs), lhs.wrapping_div(rhs))?;
+                    self.reg(target_reg)?.set(value);
                 }
                 Opcode::ADDU => {
                     let lhs_reg: u8 = self.get_op()?;
@@ -437,7 +1246,8 @@ impl Core {
                     let target_reg: u8 = self.get_op()?;
                     let lhs: u64 = { self.reg(lhs_reg)?.get() };
                     let rhs: u64 = { self.reg(rhs_reg)?.get() };
-                    self.reg(target_reg)?.set(lhs + rhs);
+                    let value = self.check_overflow(lhs.checked_add(rhs), lhs.wrapping_add(rhs))?;
+                    self.reg(target_reg)?.set(value);
                 }
                 Opcode::SUBU => {
                     let lhs_reg: u8 = self.get_op()?;
@@ -445,7 +1255,8 @@ impl Core {
                     let target_reg: u8 = self.get_op()?;
                     let lhs: u64 = { self.reg(lhs_reg)?.get() };
                     let rhs: u64 = { self.reg(rhs_reg)?.get() };
-                    self.reg(target_reg)?.set(lhs - rhs)
+                    let value = self.check_overflow(lhs.checked_sub(rhs), lhs.wrapping_sub(rhs))?;
+                    self.reg(target_reg)?.set(value);
                 }
                 Opcode::MULU => {
                     let lhs_reg: u8 = self.get_op()?;
@@ -453,7 +1264,8 @@ impl Core {
                     let target_reg: u8 = self.get_op()?;
                     let lhs: u64 = { self.reg(lhs_reg)?.get() };
                     let rhs: u64 = { self.reg(rhs_reg)?.get() };
-                    self.reg(target_reg)?.set(lhs * rhs)
+                    let value = self.check_overflow(lhs.checked_mul(rhs), lhs.wrapping_mul(rhs))?;
+                    self.reg(target_reg)?.set(value);
                 }
                 Opcode::DIVU => {
                     let lhs_reg: u8 = self.get_op()?;
@@ -461,7 +1273,11 @@ impl Core {
                     let target_reg: u8 = self.get_op()?;
                     let lhs: u64 = { self.reg(lhs_reg)?.get() };
                     let rhs: u64 = { self.reg(rhs_reg)?.get() };
-                    self.reg(target_reg)?.set(lhs / rhs)
+                    if rhs == 0 {
+                        return Err(CoreError::DivisionByZero);
+                    }
+                    let value = self.check_overflow(lhs.checked_div(rhs), lhs.wrapping_div(rhs))?;
+                    self.reg(target_reg)?.set(value);
                 }
                 Opcode::ADDU_I => {
                     let lhs_reg: u8 = self.get_op()?;
@@ -472,11 +1288,10 @@ impl Core {
                     if lhs_reg == 16 && target_reg == 16 {
                         let lhs = Address::from(self.sp.get::<u64>()).real_address;
                         //println!("Incrementing SP(={}) by {}", lhs, rhs);
-                        if lhs + rhs > self.stack.len() as u64 {
-                            return Err(CoreError::StackOverflow);
-                        }
+                        let new_size = lhs.checked_add(rhs).ok_or(CoreError::StackOverflow)?;
+                        self.grow_stack(new_size as usize)?;
                     }
-                    self.reg(target_reg)?.set(lhs + rhs);
+                    self.reg(target_reg)?.set(lhs.wrapping_add(rhs));
                     //println!("SP After ADDU_I: {}", Address::from(self.sp.get::<u64>()).real_address);
                 }
                 Opcode::SUBU_I => {
@@ -488,21 +1303,26 @@ impl Core {
                         let lhs = Address::from(self.sp.get::<u64>()).real_address;
                         //println!("Decrementing SP(={}) by {}", lhs, rhs);
                     }
-                    self.reg(target_reg)?.set(lhs - rhs);
+                    self.reg(target_reg)?.set(lhs.wrapping_sub(rhs));
                 }
                 Opcode::MULU_I => {
                     let lhs_reg: u8 = self.get_op()?;
                     let rhs: u64 = self.get_op()?;
                     let target_reg: u8 = self.get_op()?;
                     let lhs: u64 = { self.reg(lhs_reg)?.get() };
-                    self.reg(target_reg)?.set(lhs * rhs);
+                    let value = self.check_overflow(lhs.checked_mul(rhs), lhs.wrapping_mul(rhs))?;
+                    self.reg(target_reg)?.set(value);
                 }
                 Opcode::DIVU_I => {
                     let lhs_reg: u8 = self.get_op()?;
                     let rhs: u64 = self.get_op()?;
                     let target_reg: u8 = self.get_op()?;
                     let lhs: u64 = { self.reg(lhs_reg)?.get() };
-                    self.reg(target_reg)?.set(lhs / rhs);
+                    if rhs == 0 {
+                        return Err(CoreError::DivisionByZero);
+                    }
+                    let value = self.check_overflow(lhs.checked_div(rhs), lhs.wrapping_div(rhs))?;
+                    self.reg(target_reg)?.set(value);
                 }
                 Opcode::ADDF => {
                     let lhs_reg: u8 = self.get_op()?;
@@ -536,6 +1356,38 @@ impl Core {
                     let rhs: f32 = { self.reg(rhs_reg)?.get() };
                     self.reg(target_reg)?.set(lhs / rhs);
                 }
+                Opcode::ADDD => {
+                    let lhs_reg: u8 = self.get_op()?;
+                    let rhs_reg: u8 = self.get_op()?;
+                    let target_reg: u8 = self.get_op()?;
+                    let lhs: f64 = { self.reg(lhs_reg)?.get() };
+                    let rhs: f64 = { self.reg(rhs_reg)?.get() };
+                    self.reg(target_reg)?.set(lhs + rhs);
+                }
+                Opcode::SUBD => {
+                    let lhs_reg: u8 = self.get_op()?;
+                    let rhs_reg: u8 = self.get_op()?;
+                    let target_reg: u8 = self.get_op()?;
+                    let lhs: f64 = { self.reg(lhs_reg)?.get() };
+                    let rhs: f64 = { self.reg(rhs_reg)?.get() };
+                    self.reg(target_reg)?.set(lhs - rhs);
+                }
+                Opcode::MULD => {
+                    let lhs_reg: u8 = self.get_op()?;
+                    let rhs_reg: u8 = self.get_op()?;
+                    let target_reg: u8 = self.get_op()?;
+                    let lhs: f64 = { self.reg(lhs_reg)?.get() };
+                    let rhs: f64 = { self.reg(rhs_reg)?.get() };
+                    self.reg(target_reg)?.set(lhs * rhs);
+                }
+                Opcode::DIVD => {
+                    let lhs_reg: u8 = self.get_op()?;
+                    let rhs_reg: u8 = self.get_op()?;
+                    let target_reg: u8 = self.get_op()?;
+                    let lhs: f64 = { self.reg(lhs_reg)?.get() };
+                    let rhs: f64 = { self.reg(rhs_reg)?.get() };
+                    self.reg(target_reg)?.set(lhs / rhs);
+                }
                 Opcode::ADDF_I => {
                     let lhs_reg: u8 = self.get_op()?;
                     let rhs: f32 = self.get_op()?;
@@ -566,14 +1418,14 @@ impl Core {
                 }
                 Opcode::JMP => {
                     let target_ip: u64 = self.get_op()?;
-                    self.ip.set(target_ip);
+                    self.jump(target_ip);
                 }
                 Opcode::JMPT => {
                     let lhs_reg: u8 = self.get_op()?;
                     let target_ip: u64 = self.get_op()?;
                     let lhs: bool = { self.reg(lhs_reg)?.get() };
                     if lhs {
-                        self.ip.set(target_ip);
+                        self.jump(target_ip);
                     }
                 }
                 Opcode::JMPF => {
@@ -581,7 +1433,7 @@ impl Core {
                     let target_ip: u64 = self.get_op()?;
                     let lhs: bool = { self.reg(lhs_reg)?.get() };
                     if !lhs {
-                        self.ip.set(target_ip);
+                        self.jump(target_ip);
                     }
                 }
                 Opcode::DJMP => {
@@ -613,9 +1465,10 @@ impl Core {
                 Opcode::RET => {
                     // Special case if function was called externally, the callstack is empty
                     if self.call_stack.len() == 0 {
-                        break;
+                        finished = true;
+                    } else {
+                        self.ret()?;
                     }
-                    self.ret()?;
                 }
                 Opcode::NOT => {
                     let lhs_reg: u8 = self.get_op()?;
@@ -735,90 +1588,604 @@ impl Core {
                     let rhs: f32 = { self.reg(rhs_reg)?.get() };
                     self.reg(target_reg)?.set(lhs >= rhs);
                 }
+                Opcode::ALLOC => {
+                    let lhs_reg: u8 = self.get_op()?;
+                    let target_reg: u8 = self.get_op()?;
+                    let size: u64 = { self.reg(lhs_reg)?.get() };
+                    let addr = self.alloc(size as usize)?;
+                    self.reg(target_reg)?.set(addr);
+                }
+                Opcode::FREE => {
+                    let lhs_reg: u8 = self.get_op()?;
+                    let addr: u64 = { self.reg(lhs_reg)?.get() };
+                    self.free(addr)?;
+                }
+                Opcode::YIELD => {
+                    if self.coroutine_depth == 0 {
+                        return Err(CoreError::YieldOutsideCoroutine);
+                    }
+                    self.yielded = true;
+                    finished = true;
+                }
+                Opcode::SPAWN => {
+                    let fn_uid: u64 = self.get_op()?;
+                    let args_size: usize = self.get_op::<u32>()? as usize;
+                    let target_reg: u8 = self.get_op()?;
+                    // The arguments were pushed right below the stack pointer
+                    let sp = Address::from(self.sp.get::<u64>());
+                    let args_start = sp
+                        .real_address
+                        .checked_sub(args_size as u64)
+                        .ok_or(CoreError::InvalidAddress(sp.raw_address))?;
+                    let args = self
+                        .mem_slice(&Address::new(args_start, sp.address_type), args_size)?
+                        .to_vec();
+                    let handle = self.spawn_coroutine_with_args(fn_uid, &args)?;
+                    self.reg(target_reg)?.set(handle.0);
+                }
+                Opcode::DONE => {
+                    let handle_reg: u8 = self.get_op()?;
+                    let target_reg: u8 = self.get_op()?;
+                    let handle: u64 = { self.reg(handle_reg)?.get() };
+                    let done = self
+                        .get_coroutine_state(CoroutineHandle(handle))
+                        .is_none_or(|state| state == CoroutineState::Finished);
+                    self.reg(target_reg)?.set(done);
+                }
+                Opcode::RESUME => {
+                    let handle_reg: u8 = self.get_op()?;
+                    let value_reg: u8 = self.get_op()?;
+                    let done_reg: u8 = self.get_op()?;
+                    let handle: u64 = { self.reg(handle_reg)?.get() };
+                    let (value, done) = match self.resume_coroutine(CoroutineHandle(handle)) {
+                        Ok(Resumed::Yielded(value)) => (value, false),
+                        // Nothing can resume it anymore, so scripts don't
+                        // have to drop the coroutines they ran to the end
+                        Ok(Resumed::Returned(value)) => {
+                            self.drop_coroutine(CoroutineHandle(handle));
+                            (value, true)
+                        }
+                        // Resuming the core executes the RESUME again, continuing
+                        // the coroutine where it stopped
+                        Err(err @ (CoreError::OutOfFuel | CoreError::Interrupted)) => {
+                            self.ip.set(self.instr_offset);
+                            return Err(err);
+                        }
+                        Err(err) => return Err(err),
+                    };
+                    *self.reg(value_reg)? = value;
+                    self.reg(done_reg)?.set(done);
+                }
+                // Conversions follow Rust's `as` semantics: floats round to
+                // nearest, float to integer truncates and saturates, NaN is 0
+                Opcode::ITOF => {
+                    let lhs_reg: u8 = self.get_op()?;
+                    let rhs_reg: u8 = self.get_op()?;
+                    let value: i64 = { self.reg(lhs_reg)?.get() };
+                    self.reg(rhs_reg)?.set(value as f32);
+                }
+                Opcode::UTOF => {
+                    let lhs_reg: u8 = self.get_op()?;
+                    let rhs_reg: u8 = self.get_op()?;
+                    let value: u64 = { self.reg(lhs_reg)?.get() };
+                    self.reg(rhs_reg)?.set(value as f32);
+                }
+                Opcode::FTOI => {
+                    let lhs_reg: u8 = self.get_op()?;
+                    let rhs_reg: u8 = self.get_op()?;
+                    let value: f32 = { self.reg(lhs_reg)?.get() };
+                    self.reg(rhs_reg)?.set(value as i64);
+                }
+                Opcode::FTOU => {
+                    let lhs_reg: u8 = self.get_op()?;
+                    let rhs_reg: u8 = self.get_op()?;
+                    let value: f32 = { self.reg(lhs_reg)?.get() };
+                    self.reg(rhs_reg)?.set(value as u64);
+                }
+                Opcode::SEXT8 | Opcode::SEXT16 | Opcode::SEXT32 => {
+                    let lhs_reg: u8 = self.get_op()?;
+                    let rhs_reg: u8 = self.get_op()?;
+                    let value: u64 = { self.reg(lhs_reg)?.get() };
+                    let value = match opcode {
+                        Opcode::SEXT8 => value as i8 as i64,
+                        Opcode::SEXT16 => value as i16 as i64,
+                        _ => value as i32 as i64,
+                    };
+                    self.reg(rhs_reg)?.set(value);
+                }
+                Opcode::ZEXT8 | Opcode::ZEXT16 | Opcode::ZEXT32 => {
+                    let lhs_reg: u8 = self.get_op()?;
+                    let rhs_reg: u8 = self.get_op()?;
+                    let value: u64 = { self.reg(lhs_reg)?.get() };
+                    let value = match opcode {
+                        Opcode::ZEXT8 => value as u8 as u64,
+                        Opcode::ZEXT16 => value as u16 as u64,
+                        _ => value as u32 as u64,
+                    };
+                    self.reg(rhs_reg)?.set(value);
+                }
+                Opcode::ITOD => {
+                    let lhs_reg: u8 = self.get_op()?;
+                    let rhs_reg: u8 = self.get_op()?;
+                    let value: i64 = { self.reg(lhs_reg)?.get() };
+                    self.reg(rhs_reg)?.set(value as f64);
+                }
+                Opcode::UTOD => {
+                    let lhs_reg: u8 = self.get_op()?;
+                    let rhs_reg: u8 = self.get_op()?;
+                    let value: u64 = { self.reg(lhs_reg)?.get() };
+                    self.reg(rhs_reg)?.set(value as f64);
+                }
+                Opcode::DTOI => {
+                    let lhs_reg: u8 = self.get_op()?;
+                    let rhs_reg: u8 = self.get_op()?;
+                    let value: f64 = { self.reg(lhs_reg)?.get() };
+                    self.reg(rhs_reg)?.set(value as i64);
+                }
+                Opcode::DTOU => {
+                    let lhs_reg: u8 = self.get_op()?;
+                    let rhs_reg: u8 = self.get_op()?;
+                    let value: f64 = { self.reg(lhs_reg)?.get() };
+                    self.reg(rhs_reg)?.set(value as u64);
+                }
+                Opcode::FTOD => {
+                    let lhs_reg: u8 = self.get_op()?;
+                    let rhs_reg: u8 = self.get_op()?;
+                    let value: f32 = { self.reg(lhs_reg)?.get() };
+                    self.reg(rhs_reg)?.set(value as f64);
+                }
+                Opcode::DTOF => {
+                    let lhs_reg: u8 = self.get_op()?;
+                    let rhs_reg: u8 = self.get_op()?;
+                    let value: f64 = { self.reg(lhs_reg)?.get() };
+                    self.reg(rhs_reg)?.set(value as f32);
+                }
+                Opcode::NARROWI => {
+                    let lhs_reg: u8 = self.get_op()?;
+                    let size: u8 = self.get_op()?;
+                    let rhs_reg: u8 = self.get_op()?;
+                    let value: i64 = { self.reg(lhs_reg)?.get() };
+                    let bits = 64 - 8 * (size.clamp(1, 8) as u32);
+                    let narrowed = (value << bits) >> bits;
+                    let value = self.check_overflow(Some(narrowed).filter(|n| *n == value), narrowed)?;
+                    self.reg(rhs_reg)?.set(value);
+                }
+                Opcode::NARROWU => {
+                    let lhs_reg: u8 = self.get_op()?;
+                    let size: u8 = self.get_op()?;
+                    let rhs_reg: u8 = self.get_op()?;
+                    let value: u64 = { self.reg(lhs_reg)?.get() };
+                    let bits = 64 - 8 * (size.clamp(1, 8) as u32);
+                    let narrowed = (value << bits) >> bits;
+                    let value = self.check_overflow(Some(narrowed).filter(|n| *n == value), narrowed)?;
+                    self.reg(rhs_reg)?.set(value);
+                }
+                Opcode::MOVN_AR => {
+                    let lhs_reg: u8 = self.get_op()?;
+                    let lhs_offset: i16 = self.get_op()?;
+                    let rhs_reg: u8 = self.get_op()?;
+                    let n: usize = self.get_op::<u32>()? as usize;
+                    if n > 8 {
+                        return Err(CoreError::OperatorDeserialize);
+                    }
+                    let lhs_addr: u64 = { self.reg(lhs_reg)?.get() };
+                    let mut bytes = [0u8; 8];
+                    bytes[0..n].copy_from_slice(&self.mem_get_n((lhs_addr, lhs_offset), n)?);
+                    self.reg(rhs_reg)?.set(bytes);
+                }
+                Opcode::PANIC => {
+                    let lhs_reg: u8 = self.get_op()?;
+                    let message_ptr: u64 = { self.reg(lhs_reg)?.get() };
+                    return Err(self.script_panic(message_ptr));
+                }
+                Opcode::ASSERT => {
+                    let lhs_reg: u8 = self.get_op()?;
+                    let rhs_reg: u8 = self.get_op()?;
+                    let condition: bool = { self.reg(lhs_reg)?.get() };
+                    if !condition {
+                        let message_ptr: u64 = { self.reg(rhs_reg)?.get() };
+                        return Err(self.script_panic(message_ptr));
+                    }
+                }
+                Opcode::MOVN_RA => {
+                    let lhs_reg: u8 = self.get_op()?;
+                    let rhs_reg: u8 = self.get_op()?;
+                    let rhs_offset: i16 = self.get_op()?;
+                    let n: usize = self.get_op::<u32>()? as usize;
+                    if n > 8 {
+                        return Err(CoreError::OperatorSerialize);
+                    }
+                    let rhs_addr: u64 = { self.reg(rhs_reg)?.get() };
+                    let bytes: [u8; 8] = { self.reg(lhs_reg)?.get() };
+                    let rhs_addr = Address::from(rhs_addr).with_offset(rhs_offset);
+                    self.mem_slice_mut(&rhs_addr, n)?.copy_from_slice(&bytes[0..n]);
+                }
                 _ => {
                     return Err(CoreError::UnimplementedOpcode(opcode));
                 }
             };
+
+            if let (Some(profiler), Some(instr_start)) = (self.profiler.as_mut(), instr_start) {
+                profiler.record(opcode, instr_start.elapsed());
+                let depth = self.call_stack.len() + 1;
+                while profiler.depth() > depth {
+                    profiler.exit();
+                }
+                if profiler.depth() < depth {
+                    let program = self.program.as_ref().ok_or(CoreError::NoProgram)?;
+                    profiler.enter(&profile_fn_name(program, self.ip.get()));
+                }
+            }
+            if finished {
+                break;
+            }
+
+            let stop = match mode {
+                StepMode::Run => false,
+                StepMode::Step => true,
+                StepMode::StepOver(depth) => self.call_stack.len() <= depth,
+                StepMode::StepOut(depth) => self.call_stack.len() < depth,
+            };
+            if stop {
+                self.instr_offset = self.ip.get();
+                return Ok(StopReason::Step);
+            }
         }
-        Ok(())
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.exit_all();
+        }
+        Ok(StopReason::Finished)
     }
 
-    fn mem_mov_n(&mut self, lhs: (u64, i16), rhs: (u64, i16), n: usize) -> CoreResult<()> {
-        let lhs_addr = Address::from(lhs.0).with_offset(lhs.1);
-        let rhs_addr = Address::from(rhs.0).with_offset(rhs.1);
+    /// Creates a suspended coroutine running the function with the given uid.
+    /// The current registers are passed as its arguments.
+    pub fn spawn_coroutine(&mut self, uid: u64) -> CoreResult<CoroutineHandle> {
+        self.spawn_coroutine_with_args(uid, &[])
+    }
 
-        let source_addr = lhs_addr.real_address as usize;
-        let target_addr = rhs_addr.real_address as usize;
+    /// Creates a suspended coroutine running the function with the given uid,
+    /// like `Core::spawn_coroutine`. The bytes are placed at the bottom of its
+    /// stack, where compiled functions expect their arguments.
+    pub fn spawn_coroutine_with_args(
+        &mut self,
+        uid: u64,
+        args: &[u8],
+    ) -> CoreResult<CoroutineHandle> {
+        let program = self.program.as_ref().ok_or(CoreError::NoProgram)?;
+        let offset = *program
+            .functions
+            .get(&uid)
+            .ok_or(CoreError::UnknownFunctionUid)?;
+        if self.live_coroutines >= self.limits.max_coroutines {
+            return Err(CoreError::CoroutineLimit);
+        }
+        self.next_coroutine_id += 1;
+        let coroutine = Coroutine {
+            state: CoroutineState::Suspended,
+            context: Context::new(offset, self.registers, args, STACK_GROW_INCREMENT),
+        };
+        self.coroutines.insert(self.next_coroutine_id, coroutine);
+        self.live_coroutines += 1;
+        Ok(CoroutineHandle(self.next_coroutine_id))
+    }
 
-        let bytes = {
-            let source: &[u8] = match lhs_addr.address_type {
-                AddressType::Stack => &self.stack,
-                AddressType::Program => {
-                    let program = self.program.as_ref().ok_or(CoreError::Unknown)?;
-                    &program.code
-                }
-                AddressType::Swap => &self.swap,
-                _ => return Err(CoreError::Unknown),
-            };
+    /// Runs a suspended coroutine until it yields or returns. Breakpoints are
+    /// not hit inside coroutines. After `CoreError::OutOfFuel` or
+    /// `CoreError::Interrupted` it stays suspended and can be resumed again,
+    /// other errors finish it.
+    pub fn resume_coroutine(&mut self, handle: CoroutineHandle) -> CoreResult<Resumed> {
+        let mut coroutine = match self.coroutines.remove(&handle.0) {
+            Some(coroutine) if coroutine.state == CoroutineState::Suspended => coroutine,
+            Some(coroutine) => {
+                self.coroutines.insert(handle.0, coroutine);
+                return Err(CoreError::CoroutineNotResumable(handle.0));
+            }
+            None => return Err(CoreError::UnknownCoroutine(handle.0)),
+        };
+        self.swap_context(&mut coroutine.context);
+        coroutine.state = CoroutineState::Running;
+        self.coroutines.insert(handle.0, coroutine);
+
+        self.coroutine_depth += 1;
+        let result = self.execute(StepMode::Run).map_err(|err| self.attach_backtrace(err));
+        self.coroutine_depth -= 1;
+        let yielded = std::mem::take(&mut self.yielded);
+        let value = self.registers[0];
+
+        let mut coroutine = self
+            .coroutines
+            .remove(&handle.0)
+            .ok_or(CoreError::UnknownCoroutine(handle.0))?;
+        self.swap_context(&mut coroutine.context);
+        coroutine.state = match result {
+            Ok(_) if yielded => CoroutineState::Suspended,
+            Err(CoreError::OutOfFuel) | Err(CoreError::Interrupted) => CoroutineState::Suspended,
+            _ => CoroutineState::Finished,
+        };
+        if coroutine.state == CoroutineState::Finished {
+            coroutine.context.stack = Vec::new();
+            self.live_coroutines -= 1;
+        }
+        self.coroutines.insert(handle.0, coroutine);
+        result?;
+        Ok(if yielded {
+            Resumed::Yielded(value)
+        } else {
+            Resumed::Returned(value)
+        })
+    }
 
-            let mut ret = Vec::with_capacity(n);
-            ret.resize(n, 0);
+    /// Returns an iterator resuming the coroutine for each value it yields
+    pub fn iter_coroutine(&mut self, handle: CoroutineHandle) -> CoroutineIter<'_> {
+        CoroutineIter { core: self, handle }
+    }
+
+    /// Returns the state of a coroutine, if it exists
+    pub fn get_coroutine_state(&self, handle: CoroutineHandle) -> Option<CoroutineState> {
+        self.coroutines.get(&handle.0).map(|coroutine| coroutine.state)
+    }
 
-            for i in 0..n {
-                ret[i] = source[source_addr + i];
+    /// Removes a coroutine, returning whether it existed
+    pub fn drop_coroutine(&mut self, handle: CoroutineHandle) -> bool {
+        match self.coroutines.remove(&handle.0) {
+            Some(coroutine) => {
+                if coroutine.state != CoroutineState::Finished {
+                    self.live_coroutines -= 1;
+                }
+                true
             }
+            None => false,
+        }
+    }
+
+    /// Exchanges the execution state of the core with a coroutine context
+    fn swap_context(&mut self, context: &mut Context) {
+        swap(&mut self.stack, &mut context.stack);
+        swap(&mut self.registers, &mut context.registers);
+        swap(&mut self.ip, &mut context.ip);
+        swap(&mut self.sp, &mut context.sp);
+        swap(&mut self.call_stack, &mut context.call_stack);
+        swap(&mut self.frame_bases, &mut context.frame_bases);
+        swap(&mut self.entry_frame_base, &mut context.entry_frame_base);
+        swap(&mut self.instr_offset, &mut context.instr_offset);
+        self.sync_bp();
+    }
+
+    /// Points `BP` at the base of the innermost frame
+    fn sync_bp(&mut self) {
+        let frame_base = self
+            .frame_bases
+            .front()
+            .copied()
+            .unwrap_or(self.entry_frame_base);
+        self.bp.set(frame_base);
+    }
+
+    /// Takes a snapshot of the execution state. Fails with
+    /// `CoreError::NotSnapshottable` if foreign objects are alive,
+    /// see `Core::snapshot_with`.
+    pub fn snapshot(&self) -> CoreResult<Snapshot> {
+        self.take_snapshot(None)
+    }
 
-            ret
+    /// Takes a snapshot of the execution state, serializing foreign objects
+    /// through the host
+    pub fn snapshot_with(&self, foreign: &mut dyn ForeignSnapshot) -> CoreResult<Snapshot> {
+        self.take_snapshot(Some(foreign))
+    }
+
+    fn take_snapshot(&self, mut foreign: Option<&mut dyn ForeignSnapshot>) -> CoreResult<Snapshot> {
+        let program = self.program.as_ref().ok_or(CoreError::NoProgram)?;
+        // Restoring would resume behind the error
+        if self.run_failed {
+            return Err(CoreError::RunFailed);
+        }
+        // The future of a host function can't be serialized
+        if self.pending_call.is_some() {
+            return Err(CoreError::Pending);
+        }
+        let mut foreign_objects = Vec::new();
+        for ptr in self.foreign_objects.handles() {
+            let bytes = foreign
+                .as_mut()
+                .and_then(|foreign| foreign.save(self, ptr))
+                .ok_or(CoreError::NotSnapshottable(ptr))?;
+            foreign_objects.push((ptr, self.foreign_objects.get_script_refs(ptr)?, bytes));
+        }
+        let code_changes = match self.private_code.as_ref() {
+            Some(code) => diff_code(&program.code, code),
+            None => Vec::new(),
         };
+        let mut coroutines: Vec<_> = self
+            .coroutines
+            .iter()
+            .map(|(id, coroutine)| (*id, coroutine.clone()))
+            .collect();
+        coroutines.sort_by_key(|(id, _)| *id);
+        Ok(Snapshot {
+            program_hash: hash_program(program),
+            context: Context {
+                stack: self.stack.clone(),
+                registers: self.registers,
+                ip: self.ip,
+                sp: self.sp,
+                call_stack: self.call_stack.clone(),
+                frame_bases: self.frame_bases.clone(),
+                entry_frame_base: self.entry_frame_base,
+                instr_offset: self.instr_offset,
+            },
+            heap: self.heap.clone(),
+            heap_pointers: self
+                .heap_pointers
+                .iter()
+                .map(|range| (range.start, range.end))
+                .collect(),
+            swap: self.swap.clone(),
+            code_changes,
+            coroutines,
+            next_coroutine_id: self.next_coroutine_id,
+            foreign_objects,
+        })
+    }
 
-        match rhs_addr.address_type {
-            AddressType::Stack => {
-                for i in 0..n {
-                    self.stack[target_addr + i] = bytes[i];
-                }
-            }
-            AddressType::Program => {
-                let program = self.program.as_mut().ok_or(CoreError::Unknown)?;
-                for i in 0..n {
-                    program.code[target_addr + i] = bytes[i];
-                }
-            }
-            AddressType::Swap => {
-                for i in 0..n {
-                    self.swap[target_addr + i] = bytes[i];
-                }
+    /// Restores the execution state from a snapshot of the loaded program,
+    /// e.g. to continue with `Core::resume`. Fails with
+    /// `CoreError::NotSnapshottable` if the snapshot contains foreign objects,
+    /// see `Core::restore_with`.
+    pub fn restore(&mut self, snapshot: &Snapshot) -> CoreResult<()> {
+        self.restore_snapshot(snapshot, None)
+    }
+
+    /// Restores the execution state from a snapshot of the loaded program,
+    /// recreating foreign objects through the host. If the host fails to
+    /// recreate one, the core keeps its state and foreign objects.
+    pub fn restore_with(
+        &mut self,
+        snapshot: &Snapshot,
+        foreign: &mut dyn ForeignSnapshot,
+    ) -> CoreResult<()> {
+        self.restore_snapshot(snapshot, Some(foreign))
+    }
+
+    fn restore_snapshot(
+        &mut self,
+        snapshot: &Snapshot,
+        foreign: Option<&mut dyn ForeignSnapshot>,
+    ) -> CoreResult<()> {
+        let program = self.program.as_ref().ok_or(CoreError::NoProgram)?;
+        if hash_program(program) != snapshot.program_hash {
+            return Err(CoreError::InvalidSnapshot);
+        }
+        if let (None, Some((ptr, _, _))) = (&foreign, snapshot.foreign_objects.first()) {
+            return Err(CoreError::NotSnapshottable(*ptr));
+        }
+        let private_code = if snapshot.code_changes.is_empty() {
+            None
+        } else {
+            let mut code = program.code.clone();
+            for (start, data) in snapshot.code_changes.iter() {
+                start
+                    .checked_add(data.len())
+                    .and_then(|end| code.get_mut(*start..end))
+                    .ok_or(CoreError::InvalidSnapshot)?
+                    .copy_from_slice(data);
             }
-            _ => return Err(CoreError::Unknown),
+            Some(code)
         };
+        // Only the code that differs from what this core ran so far is decoded again
+        let new_code = private_code.as_ref().unwrap_or(&program.code);
+        let changed_code = diff_code(self.get_code()?, new_code);
+
+        // Foreign objects are recreated before anything else is replaced,
+        // so a failing host leaves the core as it was
+        let old_foreign_objects = std::mem::take(&mut self.foreign_objects);
+        if let Some(foreign) = foreign {
+            if let Err(err) = self.restore_foreign_objects(snapshot, foreign) {
+                self.foreign_objects = old_foreign_objects;
+                return Err(err);
+            }
+        }
 
+        for (start, data) in changed_code {
+            self.decode_cache.invalidate(start..start + data.len());
+        }
+        self.private_code = private_code;
+
+        let mut context = snapshot.context.clone();
+        self.swap_context(&mut context);
+        self.heap = snapshot.heap.clone();
+        self.heap_pointers = snapshot
+            .heap_pointers
+            .iter()
+            .map(|(start, end)| *start..*end)
+            .collect();
+        self.swap = snapshot.swap.clone();
+        self.coroutines = snapshot.coroutines.iter().cloned().collect();
+        self.live_coroutines = self
+            .coroutines
+            .values()
+            .filter(|coroutine| coroutine.state != CoroutineState::Finished)
+            .count();
+        self.next_coroutine_id = snapshot.next_coroutine_id;
+        self.coroutine_depth = 0;
+        self.yielded = false;
+        // The snapshot may have been taken during a run
+        self.in_run = true;
+        self.run_failed = false;
+        self.skip_breakpoint = None;
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.exit_all();
+        }
         Ok(())
     }
 
-    fn mem_get_n(&self, addr: (u64, i16), n: usize) -> CoreResult<Vec<u8>> {
-        let mut data = Vec::with_capacity(n);
-        data.resize(n, 0);
-
-        let lhs_addr = Address::from(addr.0).with_offset(addr.1);
-        //println!("Getting n = {} bytes at address {:?}", n, lhs_addr);
-        //println!("SP: {}", Address::from(self.sp.get::<u64>()).real_address);
-
-        let source_addr = lhs_addr.real_address as usize;
+    /// Recreates the foreign objects of a snapshot through the host
+    fn restore_foreign_objects(
+        &mut self,
+        snapshot: &Snapshot,
+        foreign: &mut dyn ForeignSnapshot,
+    ) -> CoreResult<()> {
+        for (ptr, script_refs, bytes) in snapshot.foreign_objects.iter() {
+            foreign.restore(self, *ptr, bytes)?;
+            self.foreign_objects.set_script_refs(*ptr, *script_refs)?;
+        }
+        Ok(())
+    }
 
-        let source: &[u8] = match lhs_addr.address_type {
+    /// Returns the memory an address points into
+    fn mem_slice(&self, addr: &Address, n: usize) -> CoreResult<&[u8]> {
+        let memory: &[u8] = match addr.address_type {
             AddressType::Stack => &self.stack,
+            AddressType::Program => self.get_code()?,
+            AddressType::Swap => &self.swap,
+            AddressType::Heap => &self.heap,
+            _ => return Err(CoreError::InvalidAddress(addr.raw_address)),
+        };
+        let start = addr.real_address as usize;
+        start
+            .checked_add(n)
+            .and_then(|end| memory.get(start..end))
+            .ok_or(CoreError::InvalidAddress(addr.raw_address))
+    }
+
+    /// Returns the memory an address points into, mutably
+    fn mem_slice_mut(&mut self, addr: &Address, n: usize) -> CoreResult<&mut [u8]> {
+        let memory: &mut [u8] = match addr.address_type {
+            AddressType::Stack => &mut self.stack,
             AddressType::Program => {
                 let program = self.program.as_ref().ok_or(CoreError::Unknown)?;
-                &program.code
+                // Writes may hit code, which has to be decoded again
+                let start = addr.real_address as usize;
+                self.decode_cache.invalidate(start..start.saturating_add(n));
+                self.private_code.get_or_insert_with(|| program.code.clone())
             }
-            AddressType::Swap => &self.swap,
-            _ => return Err(CoreError::Unknown),
+            AddressType::Swap => &mut self.swap,
+            AddressType::Heap => &mut self.heap,
+            _ => return Err(CoreError::InvalidAddress(addr.raw_address)),
         };
+        let start = addr.real_address as usize;
+        start
+            .checked_add(n)
+            .and_then(|end| memory.get_mut(start..end))
+            .ok_or(CoreError::InvalidAddress(addr.raw_address))
+    }
 
-        for i in 0..n {
-            data[i] = source[source_addr + i];
-        }
+    fn mem_mov_n(&mut self, lhs: (u64, i16), rhs: (u64, i16), n: usize) -> CoreResult<()> {
+        let lhs_addr = Address::from(lhs.0).with_offset(lhs.1);
+        let rhs_addr = Address::from(rhs.0).with_offset(rhs.1);
+
+        let bytes = self.mem_slice(&lhs_addr, n)?.to_vec();
+        self.mem_slice_mut(&rhs_addr, n)?.copy_from_slice(&bytes);
+
+        Ok(())
+    }
+
+    pub(crate) fn mem_get_n(&self, addr: (u64, i16), n: usize) -> CoreResult<Vec<u8>> {
+        let lhs_addr = Address::from(addr.0).with_offset(addr.1);
+        //println!("Getting n = {} bytes at address {:?}", n, lhs_addr);
+        //println!("SP: {}", Address::from(self.sp.get::<u64>()).real_address);
 
-        Ok(data)
+        Ok(self.mem_slice(&lhs_addr, n)?.to_vec())
     }
 
     #[inline]
@@ -849,37 +2216,109 @@ impl Core {
 
         let data = serialize(&item).map_err(|_| CoreError::OperatorSerialize)?;
 
-        let target_addr = lhs_addr.real_address as usize;
+        self.mem_slice_mut(&lhs_addr, n)?.copy_from_slice(&data[0..n]);
 
-        match lhs_addr.address_type {
-            AddressType::Stack => {
-                for i in 0..n {
-                    self.stack[target_addr + i] = data[i];
-                }
-            }
-            AddressType::Program => {
-                let program = self.program.as_mut().ok_or(CoreError::Unknown)?;
-                for i in 0..n {
-                    program.code[target_addr + i] = data[i];
-                }
+        Ok(())
+    }
+
+    /// Reads the message of a panic and captures where it happened
+    fn script_panic(&self, message_ptr: u64) -> CoreError {
+        // The length comes from script memory, so only so much is read
+        let message = self.mem_get::<u64>((message_ptr, 0)).and_then(|len| {
+            let len = usize::try_from(len).unwrap_or(usize::MAX).min(MAX_PANIC_MESSAGE_LEN);
+            self.mem_get_n((message_ptr, 8), len)
+        });
+        match message {
+            Ok(bytes) => CoreError::ScriptPanic {
+                message: String::from_utf8_lossy(&bytes).into_owned(),
+                backtrace: self.backtrace(),
+            },
+            Err(err) => err,
+        }
+    }
+
+    /// Returns the result of integer arithmetic, or what it wrapped to if it
+    /// overflowed and overflows don't trap
+    fn check_overflow<T>(&self, checked: Option<T>, wrapped: T) -> CoreResult<T> {
+        match (checked, self.overflow_mode) {
+            (Some(value), _) => Ok(value),
+            (None, OverflowMode::Wrap) => Ok(wrapped),
+            (None, OverflowMode::Trap) => Err(CoreError::IntegerOverflow),
+        }
+    }
+
+    /// Allocates `size` bytes of zeroed heap memory, returning its address
+    pub fn alloc(&mut self, size: usize) -> CoreResult<u64> {
+        let size = size.max(1);
+        let mut start = 0;
+        let mut index = 0;
+        while index < self.heap_pointers.len() {
+            let range = &self.heap_pointers[index];
+            if range.start - start >= size {
+                break;
             }
-            _ => return Err(CoreError::Unknown),
-        };
+            start = range.end;
+            index += 1;
+        }
+        // Only live allocations count, freed gaps the new one doesn't fit in don't
+        let live = self.get_heap_size().checked_add(size).ok_or(CoreError::OutOfMemory)?;
+        if live > self.limits.max_heap_size {
+            return Err(CoreError::OutOfMemory);
+        }
+        let end = start.checked_add(size).ok_or(CoreError::OutOfMemory)?;
+        if end > self.heap.len() {
+            self.heap.resize(end, 0);
+        }
+        self.heap[start..end].fill(0);
+        self.heap_pointers.insert(index, start..end);
+        Ok(Address::new(start as u64, AddressType::Heap).into())
+    }
+
+    /// Frees heap memory previously returned by `Core::alloc`
+    pub fn free(&mut self, addr: u64) -> CoreResult<()> {
+        let heap_addr = Address::from(addr);
+        let start = heap_addr.real_address as usize;
+        if heap_addr.address_type != AddressType::Heap {
+            return Err(CoreError::InvalidAddress(addr));
+        }
+        let index = self
+            .heap_pointers
+            .iter()
+            .position(|range| range.start == start)
+            .ok_or(CoreError::InvalidAddress(addr))?;
+        self.heap_pointers.remove(index);
+        let heap_end = self.heap_pointers.last().map(|range| range.end).unwrap_or(0);
+        self.heap.truncate(heap_end);
+        Ok(())
+    }
+
+    /// Returns the number of heap bytes currently in use
+    pub fn get_heap_size(&self) -> usize {
+        self.heap_pointers.iter().map(|range| range.len()).sum()
+    }
 
+    /// Grows the stack to hold at least `size` bytes
+    fn grow_stack(&mut self, size: usize) -> CoreResult<()> {
+        if size <= self.stack.len() {
+            return Ok(());
+        }
+        if size > self.limits.max_stack_size {
+            return Err(CoreError::StackOverflow);
+        }
+        let increments = (size - self.stack.len()).div_ceil(STACK_GROW_INCREMENT);
+        let new_len = self.stack.len() + increments * STACK_GROW_INCREMENT;
+        self.stack.resize(new_len.min(self.limits.max_stack_size), 0);
         Ok(())
     }
 
     #[inline]
     pub fn reg(&mut self, reg: u8) -> CoreResult<&mut Register> {
-        if reg == 16 {
-            return Ok(&mut self.sp);
-        }
-        if reg == 17 {
-            return Ok(&mut self.ip);
-        } else if reg < 16 {
-            return Ok(&mut self.registers[reg as usize]);
-        } else {
-            return Err(CoreError::InvalidRegister);
+        match reg {
+            0..=15 => Ok(&mut self.registers[reg as usize]),
+            16 => Ok(&mut self.sp),
+            17 => Ok(&mut self.ip),
+            18 => Ok(&mut self.bp),
+            _ => Err(CoreError::InvalidRegister),
         }
     }
 
@@ -889,6 +2328,23 @@ impl Core {
         if self.foreign_function_uids.contains(&fn_uid) {
             return self.call_foreign_fn(fn_uid);
         }
+        if self.call_stack.len() >= self.limits.max_call_depth {
+            return Err(CoreError::CallStackOverflow);
+        }
+
+        let runs_compiled = self.runs_compiled_code();
+        let hot = match self.tiering.as_mut() {
+            Some(tiering) if runs_compiled => {
+                !self.compiled_fns.contains_key(&fn_uid) && tiering.count_call(fn_uid)
+            }
+            _ => false,
+        };
+        if hot {
+            self.tier_up(fn_uid);
+        }
+        if runs_compiled && self.compiled_fns.contains_key(&fn_uid) {
+            return self.call_compiled_fn(fn_uid);
+        }
 
         let program = self.program.as_ref().ok_or(CoreError::NoProgram)?;
 
@@ -899,120 +2355,267 @@ impl Core {
 
         let old_ip: usize = self.ip.get();
         self.call_stack.push_front(old_ip);
+        self.frame_bases.push_front(self.sp.get());
+        self.bp = self.sp;
         self.ip.set(*new_ip);
 
         Ok(())
     }
 
     /// Retrieves a foreign pointer and returns the correct
-    /// Arc<Mutex<T>> if found.
-    pub fn get_foreign_ptr<T>(&self, ptr: u64) -> CoreResult<Arc<Mutex<T>>> {
-        let arc_box_int = self.foreign_pointers.get(&ptr).ok_or(CoreError::Unknown)?;
-        let arc = unsafe {
-            let arc_box_raw: *mut Arc<Mutex<T>> = std::mem::transmute(*arc_box_int);
-            let arc_box = Box::from_raw(arc_box_raw);
-            let ret = arc_box.deref().clone();
-            std::mem::forget(arc_box);
-            ret
-        };
-        Ok(arc)
+    /// Arc<Mutex<T>> if found. Fails with `CoreError::ForeignTypeMismatch`
+    /// if the object was inserted with another type.
+    pub fn get_foreign_ptr<T: Send + 'static>(&self, ptr: u64) -> CoreResult<Arc<Mutex<T>>> {
+        self.foreign_objects.get(ptr)
     }
 
-    /// Inserts a foreign pointer
-    pub fn insert_foreign_ptr<T>(&mut self, item: Arc<Mutex<T>>) -> CoreResult<u64> {
-        let mut uid_gen = UIDGenerator::default();
+    /// Inserts a foreign pointer, holding a single script reference to it
+    pub fn insert_foreign_ptr<T: Send + 'static>(&mut self, item: Arc<Mutex<T>>) -> CoreResult<u64> {
+        self.insert_foreign_object(ForeignObject::new(item))
+    }
+
+    /// Returns the foreign object behind the pointer, for host functions
+    pub(crate) fn get_foreign_object(&self, ptr: u64) -> CoreResult<&ForeignObject> {
+        self.foreign_objects.get_foreign(ptr)
+    }
 
-        let mut addr = Address::new(uid_gen.generate(), AddressType::Foreign);
-        while self.foreign_pointers.contains_key(&addr.raw_address) {
-            addr = Address::new(uid_gen.generate(), AddressType::Foreign);
+    /// Inserts an object boxed by a host function, see `insert_foreign_ptr`
+    pub(crate) fn insert_foreign_object(&mut self, object: ForeignObject) -> CoreResult<u64> {
+        if self.foreign_objects.len() >= self.limits.max_foreign_objects {
+            return Err(CoreError::ForeignObjectLimit);
         }
+        Ok(self.foreign_objects.insert(object))
+    }
 
-        let ptr = addr.into();
+    /// Inserts a foreign object at the pointer it had when a snapshot was
+    /// taken, see `ForeignSnapshot::restore`
+    pub fn restore_foreign_ptr<T: Send + 'static>(
+        &mut self,
+        ptr: u64,
+        item: Arc<Mutex<T>>,
+    ) -> CoreResult<()> {
+        if self.foreign_objects.len() >= self.limits.max_foreign_objects {
+            return Err(CoreError::ForeignObjectLimit);
+        }
+        self.foreign_objects.restore(ptr, ForeignObject::new(item))
+    }
 
-        let arc_box = Box::new(item);
-        let arc_box_int: u64 = unsafe {
-            let arc_box_raw = Box::into_raw(arc_box);
-            std::mem::transmute(arc_box_raw)
-        };
+    /// Removes a foreign pointer, regardless of the script references to it
+    pub fn remove_foreign_ptr<T: Send + 'static>(&mut self, ptr: u64) -> CoreResult<Arc<Mutex<T>>> {
+        self.foreign_objects.remove(ptr)
+    }
 
-        self.foreign_pointers.insert(ptr, arc_box_int);
+    /// Adds a script reference to a foreign object, returning the new count
+    pub fn retain_foreign_ptr(&mut self, ptr: u64) -> CoreResult<usize> {
+        self.foreign_objects.retain(ptr)
+    }
 
-        Ok(ptr)
+    /// Drops a script reference to a foreign object, returning the remaining
+    /// count. The core lets go of the object once no script references are
+    /// left, it is dropped once the host holds no references either.
+    pub fn release_foreign_ptr(&mut self, ptr: u64) -> CoreResult<usize> {
+        self.foreign_objects.release(ptr)
     }
 
-    /// Removes a foreign pointer
-    pub fn remove_foreign_ptr<T>(&mut self, ptr: u64) -> CoreResult<Arc<Mutex<T>>> {
-        let arc_box_int = self
-            .foreign_pointers
-            .remove(&ptr)
-            .ok_or(CoreError::Unknown)?;
-        let arc = unsafe {
-            let arc_box_raw: *mut Arc<Mutex<T>> = std::mem::transmute(arc_box_int);
-            let arc_box = Box::from_raw(arc_box_raw);
-            *arc_box
-        };
-        Ok(arc)
+    /// Returns the number of script references to a foreign object
+    pub fn get_foreign_refs(&self, ptr: u64) -> CoreResult<usize> {
+        self.foreign_objects.get_script_refs(ptr)
+    }
+
+    /// Returns the foreign objects scripts still hold references to
+    pub fn get_foreign_leaks(&self) -> Vec<ForeignLeak> {
+        self.foreign_objects.leaks()
+    }
+
+    /// Enables tiered execution. Script functions are interpreted until the
+    /// policy counts them as hot, then the handler is asked to compile them.
+    /// Calls of a function the handler compiled run the compiled version,
+    /// interpreted callers and all.
+    pub fn set_tiering<F>(&mut self, policy: TierPolicy, handler: F)
+    where
+        F: FnMut(&str) -> Option<CompiledFn> + Send + 'static,
+    {
+        self.tiering = Some(Tiering::new(policy, Box::new(handler)));
+    }
+
+    /// Disables tiered execution and drops the compiled functions
+    pub fn clear_tiering(&mut self) {
+        self.tiering = None;
+        self.compiled_fns.clear();
+    }
+
+    /// Returns how often the function with the given uid ran interpreted, if
+    /// tiered execution is enabled
+    pub fn get_hot_counters(&self, uid: u64) -> Option<HotCounters> {
+        self.tiering.as_ref().and_then(|tiering| tiering.get_counters(uid))
+    }
+
+    /// Swaps in a compiled version of the function with the given uid
+    pub fn install_compiled_fn(&mut self, uid: u64, compiled: CompiledFn) {
+        self.compiled_fns.insert(uid, compiled);
+    }
+
+    /// Whether calls of the function with the given uid run compiled code
+    pub fn is_compiled(&self, uid: u64) -> bool {
+        self.compiled_fns.contains_key(&uid)
+    }
+
+    /// Sets a handler called with the foreign objects scripts still hold
+    /// references to when the core is dropped, if there are any
+    pub fn set_leak_handler<F: FnMut(&[ForeignLeak]) + Send + 'static>(&mut self, handler: F) {
+        self.leak_handler = Some(Box::new(handler));
     }
 
     fn call_foreign_fn(&mut self, uid: u64) -> CoreResult<()> {
-        /*let function = {
-            self.program
-                .as_mut()
-                .ok_or(CoreError::NoProgram)?
-                .foreign_functions
-                .remove(&uid)
-                .ok_or(CoreError::UnknownFunctionUid)?
+        let function = self
+            .program
+            .as_ref()
+            .ok_or(CoreError::NoProgram)?
+            .foreign_functions
+            .get(&uid)
+            .ok_or(CoreError::UnknownFunctionUid)?
+            .clone();
+        // Suspending would need the coroutine to be resumed by the host
+        if function.is_async() && self.coroutine_depth > 0 {
+            return Err(CoreError::AsyncCallInCoroutine);
+        }
+
+        let future = {
+            let mut adapter = ApiAdapter::new(Adapter::new(&function, self));
+            function.run(&mut adapter)
         };
+        if let Some(err) = self.host_error.take() {
+            return Err(err);
+        }
+        if let Some(future) = future {
+            self.pending_call = Some(PendingCall { function, future });
+            return Err(CoreError::Pending);
+        }
+        Ok(())
+    }
 
-        //println!("Calling foreign function {}", function.name);
+    /// Records an error of the adapter of a running host function, which
+    /// fails the call once the function returns
+    pub(crate) fn set_host_error(&self, err: CoreError) {
+        self.host_error.borrow_mut().get_or_insert(err);
+    }
+
+    /// Jumps to the given offset, counting backward jumps as loop iterations
+    /// of the current function if tiered execution is enabled
+    #[inline]
+    fn jump(&mut self, target_ip: u64) {
+        if self.tiering.is_some() && target_ip as usize <= self.instr_offset {
+            self.count_loop();
+        }
+        self.ip.set(target_ip);
+    }
 
+    fn count_loop(&mut self) {
+        let uid = match self
+            .program
+            .as_ref()
+            .and_then(|program| program.get_function_at(self.instr_offset))
         {
-            let mut adapter = Adapter::new(&function, self);
-            function.run(&mut adapter);
+            Some((uid, _)) => uid,
+            None => return,
+        };
+        if self.compiled_fns.contains_key(&uid) || !self.runs_compiled_code() {
+            return;
         }
+        let hot = self.tiering.as_mut().is_some_and(|tiering| tiering.count_loop(uid));
+        if hot {
+            self.tier_up(uid);
+        }
+    }
 
-        self.program
+    /// Whether calls can run compiled code, which neither uses fuel nor checks
+    /// for interrupts. Hot functions aren't compiled while it can't.
+    fn runs_compiled_code(&self) -> bool {
+        self.fuel.is_none() && !self.interrupt.is_shared()
+    }
+
+    /// Offers a hot function to the tier-up handler, swapping in the compiled
+    /// version it returns
+    fn tier_up(&mut self, uid: u64) {
+        let fn_name = match self
+            .program
+            .as_ref()
+            .and_then(|program| program.get_function_name(uid))
+        {
+            Some(fn_name) => String::from(fn_name),
+            None => return,
+        };
+        let compiled = self
+            .tiering
             .as_mut()
-            .ok_or(CoreError::NoProgram)?
-            .foreign_functions
-            .insert(uid, function);
+            .and_then(|tiering| tiering.tier_up(uid, &fn_name));
+        if let Some(compiled) = compiled {
+            self.compiled_fns.insert(uid, compiled);
+        }
+    }
 
-        */
+    /// Runs the compiled version of a function like a host function, reading
+    /// its arguments from the top of the stack and returning in `R0`
+    fn call_compiled_fn(&mut self, uid: u64) -> CoreResult<()> {
+        let mut compiled = self
+            .compiled_fns
+            .remove(&uid)
+            .ok_or(CoreError::UnknownFunctionUid)?;
+        let args = self.get_compiled_fn_args(&compiled);
+        let max_call_depth = self.limits.max_call_depth - self.call_stack.len();
+        let result = args.map(|args| compiled.call(&args, max_call_depth));
+        self.compiled_fns.insert(uid, compiled);
+        let ret = match result? {
+            Ok(ret) => ret,
+            Err(CompiledFnError::DivisionByZero) => return Err(CoreError::DivisionByZero),
+            Err(CompiledFnError::IntegerOverflow) => return Err(CoreError::IntegerOverflow),
+            Err(CompiledFnError::CallStackOverflow) => return Err(CoreError::CallStackOverflow),
+            Err(CompiledFnError::ScriptPanic(message)) => {
+                return Err(CoreError::ScriptPanic {
+                    message,
+                    backtrace: self.backtrace(),
+                })
+            }
+            Err(CompiledFnError::Failed(reason)) => return Err(CoreError::CompiledFnFailed(reason)),
+        };
+        // Return values go into R0
+        self.reg(0)?.set(ret);
         Ok(())
     }
 
+    /// Reads the arguments of a compiled function, the last one ending at the
+    /// stack pointer
+    fn get_compiled_fn_args(&self, compiled: &CompiledFn) -> CoreResult<Vec<Vec<u8>>> {
+        let mut offset = -(compiled.get_arg_sizes().iter().sum::<usize>() as i64);
+        let mut args = Vec::new();
+        for size in compiled.get_arg_sizes() {
+            let arg_offset = i16::try_from(offset).map_err(|_| CoreError::InvalidStackPointer)?;
+            args.push(self.mem_get_n((self.get_sp(), arg_offset), *size)?);
+            offset += *size as i64;
+        }
+        Ok(args)
+    }
+
     #[inline]
     fn ret(&mut self) -> CoreResult<()> {
         let old_ip = self
             .call_stack
             .pop_front()
             .ok_or(CoreError::EmptyCallStack)?;
+        self.frame_bases.pop_front();
+        self.sync_bp();
         self.ip.uint64 = old_ip as u64;
         Ok(())
     }
 
     #[inline]
-    fn get_op<T: DeserializeOwned + Debug>(&mut self) -> CoreResult<T> {
-        let op_size = size_of::<T>();
-
-        let program = self.program.as_ref().ok_or(CoreError::NoProgram)?;
-
-        let tmp_ip = self.ip.get::<usize>();
-
-        //println!("Getting op with size {}...", op_size);
-        //println!("Op ends at {}!", tmp_ip + op_size);
-        //println!("Program size: {}", program.code.len());
-
-        let raw_bytes: &[u8] = &program.code[tmp_ip..tmp_ip + op_size];
-        //println!("get_op raw bytes: {:?}", raw_bytes);
-
-        let ret: T = deserialize(raw_bytes).map_err(|_| CoreError::OperatorDeserialize)?;
-
-        //println!("Op: {:?}", ret);
-
-        self.ip.inc(op_size);
-
-        Ok(ret)
+    fn get_op<T: FromOperand>(&mut self) -> CoreResult<T> {
+        let raw = *self
+            .operands
+            .get(self.operand_index)
+            .ok_or(CoreError::OperatorDeserialize)?;
+        self.operand_index += 1;
+        T::from_operand(raw).ok_or(CoreError::OperatorDeserialize)
     }
 
     #[inline]
@@ -1024,9 +2627,8 @@ impl Core {
         let sp_addr = Address::from(self.sp.get::<u64>());
         let sp_real = sp_addr.real_address as usize;
 
-        for i in 0..op_size {
-            self.stack[sp_real + i] = raw_bytes[i];
-        }
+        self.grow_stack(sp_real + op_size)?;
+        self.stack[sp_real..sp_real + op_size].copy_from_slice(&raw_bytes[0..op_size]);
 
         self.sp.inc(op_size);
 
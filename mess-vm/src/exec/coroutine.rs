@@ -0,0 +1,111 @@
+//! Script functions that yield values and are resumed from the host
+
+use std::collections::VecDeque;
+
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use super::{
+    address::{
+        Address,
+        AddressType,
+    },
+    core::{
+        Core,
+        CoreResult,
+    },
+    register::Register,
+};
+
+/// Handle of a coroutine spawned in a `Core`
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct CoroutineHandle(pub u64);
+
+/// Whether a coroutine can be resumed
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum CoroutineState {
+    /// Created or stopped at a `YIELD`, waiting to be resumed
+    Suspended,
+    /// Currently executing, possibly resuming another coroutine
+    Running,
+    /// Returned from its function or failed
+    Finished,
+}
+
+/// The outcome of resuming a coroutine
+#[derive(Clone, Copy, Debug)]
+pub enum Resumed {
+    /// The coroutine yielded the value in `R0` and can be resumed again
+    Yielded(Register),
+    /// The coroutine returned the value in `R0`
+    Returned(Register),
+}
+
+/// The execution state that is swapped in and out of a `Core` when
+/// switching between coroutines
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct Context {
+    pub stack: Vec<u8>,
+    pub registers: [Register; 16],
+    pub ip: Register,
+    pub sp: Register,
+    pub call_stack: VecDeque<usize>,
+    pub frame_bases: VecDeque<u64>,
+    pub entry_frame_base: u64,
+    pub instr_offset: usize,
+}
+
+impl Context {
+    /// Creates a context entering the code at `offset` with the given
+    /// registers as arguments, and the argument bytes at the bottom of the stack
+    pub fn new(offset: usize, registers: [Register; 16], args: &[u8], stack_size: usize) -> Self {
+        let mut ip = Register::new();
+        ip.set(offset);
+        let mut sp = Register::new();
+        sp.set::<u64>(Address::new(args.len() as u64, AddressType::Stack).into());
+        let mut stack = vec![0; stack_size.max(args.len())];
+        stack[..args.len()].copy_from_slice(args);
+        Self {
+            stack,
+            registers,
+            ip,
+            sp,
+            call_stack: VecDeque::new(),
+            frame_bases: VecDeque::new(),
+            entry_frame_base: sp.get(),
+            instr_offset: offset,
+        }
+    }
+}
+
+/// A coroutine with its own stack, registers and call stack.
+/// While running, `context` holds the state of whoever resumed it.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct Coroutine {
+    pub state: CoroutineState,
+    pub context: Context,
+}
+
+/// Iterates the values a coroutine yields, see `Core::iter_coroutine`.
+/// Iteration ends when the coroutine returns.
+pub struct CoroutineIter<'a> {
+    pub(crate) core: &'a mut Core,
+    pub(crate) handle: CoroutineHandle,
+}
+
+impl Iterator for CoroutineIter<'_> {
+    type Item = CoreResult<Register>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.core.get_coroutine_state(self.handle) != Some(CoroutineState::Suspended) {
+            return None;
+        }
+        match self.core.resume_coroutine(self.handle) {
+            Ok(Resumed::Yielded(value)) => Some(Ok(value)),
+            Ok(Resumed::Returned(_)) => None,
+            Err(err) => Some(Err(err)),
+        }
+    }
+}
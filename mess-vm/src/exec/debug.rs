@@ -0,0 +1,73 @@
+//! Types a debugger driving a core inspects
+
+use std::fmt::{
+    Display,
+    Formatter,
+    Result as FmtResult,
+};
+
+/// The reason execution stopped when driven by a debugger
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StopReason {
+    /// The entry function returned
+    Finished,
+    /// A breakpoint at the given code offset was reached
+    Breakpoint(usize),
+    /// A step completed
+    Step,
+}
+
+/// The value of a local variable, read from a stack frame
+#[derive(Clone, PartialEq, Debug)]
+pub enum DebugValue {
+    /// A signed integer
+    Int(i64),
+    /// An unsigned integer
+    UInt(u64),
+    /// A float
+    Float(f32),
+    /// A 64 bit float
+    Double(f64),
+    /// A boolean
+    Bool(bool),
+    /// The fields of a container, by name
+    Fields(Vec<(String, DebugValue)>),
+    /// The raw bytes of a value of any other type
+    Bytes(Vec<u8>),
+}
+
+impl Display for DebugValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            DebugValue::Int(int) => write!(f, "{}", int),
+            DebugValue::UInt(uint) => write!(f, "{}", uint),
+            DebugValue::Float(float) => write!(f, "{}", float),
+            DebugValue::Double(double) => write!(f, "{}", double),
+            DebugValue::Bool(boolean) => write!(f, "{}", boolean),
+            DebugValue::Fields(fields) => {
+                write!(f, "{{ ")?;
+                for (index, (name, value)) in fields.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", name, value)?;
+                }
+                write!(f, " }}")
+            }
+            DebugValue::Bytes(bytes) => write!(f, "{:?}", bytes),
+        }
+    }
+}
+
+/// How far the core may run before stopping
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum StepMode {
+    /// Until a breakpoint is hit or the entry function returns
+    Run,
+    /// A single instruction
+    Step,
+    /// Until the call stack is at most the given depth again
+    StepOver(usize),
+    /// Until the call stack is below the given depth
+    StepOut(usize),
+}
@@ -0,0 +1,181 @@
+//! Instructions decoded ahead of execution, cached by code offset
+
+use std::ops::Range;
+
+use num_traits::FromPrimitive;
+
+use super::{
+    core::{
+        CoreError,
+        CoreResult,
+    },
+    is::{
+        OperandKind,
+        Opcode,
+    },
+};
+
+/// The maximum number of operand slots of an instruction.
+/// Memory operands take two slots, the register and the offset.
+pub const MAX_OPERANDS: usize = 5;
+
+const NOT_DECODED: u32 = u32::MAX;
+
+/// The size of the longest encoded instruction, `SPAWN`
+pub const MAX_INSTR_SIZE: usize = 14;
+
+/// An instruction with its operands decoded into fixed-width slots
+#[derive(Clone, Debug)]
+pub struct DecodedInstr {
+    /// The opcode
+    pub opcode: Opcode,
+    /// The size of the encoded instruction in bytes
+    pub size: usize,
+    /// The operands in the order the opcode reads them
+    pub operands: [u64; MAX_OPERANDS],
+}
+
+impl DecodedInstr {
+    /// Decodes the instruction starting at the given code offset
+    pub fn decode(code: &[u8], offset: usize) -> CoreResult<DecodedInstr> {
+        let op = *code.get(offset).ok_or(CoreError::OperatorDeserialize)?;
+        let opcode = Opcode::from_u8(op).ok_or(CoreError::InvalidOpcode(op))?;
+        let mut operands = [0; MAX_OPERANDS];
+        let mut pos = offset + 1;
+        let mut slot = 0;
+        for kind in opcode.operand_kinds() {
+            match kind {
+                OperandKind::Mem => {
+                    operands[slot] = read_le(code, pos, 1)?;
+                    operands[slot + 1] = read_le(code, pos + 1, 2)?;
+                    slot += 2;
+                }
+                _ => {
+                    operands[slot] = read_le(code, pos, kind.size())?;
+                    slot += 1;
+                }
+            };
+            pos += kind.size();
+        }
+        Ok(DecodedInstr {
+            opcode,
+            size: pos - offset,
+            operands,
+        })
+    }
+}
+
+fn read_le(code: &[u8], pos: usize, n: usize) -> CoreResult<u64> {
+    let bytes = code
+        .get(pos..pos + n)
+        .ok_or(CoreError::OperatorDeserialize)?;
+    let mut raw = [0; 8];
+    raw[..n].copy_from_slice(bytes);
+    Ok(u64::from_le_bytes(raw))
+}
+
+/// Caches decoded instructions by code offset. Instructions are decoded
+/// lazily on first execution, as code may be interleaved with static data.
+#[derive(Default)]
+pub struct DecodeCache {
+    index: Vec<u32>,
+    instrs: Vec<DecodedInstr>,
+    /// Slots of invalidated instructions, reused by the next decodes
+    free: Vec<u32>,
+}
+
+impl DecodeCache {
+    /// Drops all decoded instructions, e.g. after another program was loaded
+    pub fn reset(&mut self, code_len: usize) {
+        self.index.clear();
+        self.index.resize(code_len, NOT_DECODED);
+        self.instrs.clear();
+        self.free.clear();
+    }
+
+    /// Drops the decoded instructions overlapping a range of code that
+    /// was written to
+    pub fn invalidate(&mut self, range: Range<usize>) {
+        let start = range.start.saturating_sub(MAX_INSTR_SIZE - 1);
+        let end = range.end.min(self.index.len());
+        for offset in start..end {
+            let slot = self.index[offset];
+            if slot == NOT_DECODED || offset + self.instrs[slot as usize].size <= range.start {
+                continue;
+            }
+            self.index[offset] = NOT_DECODED;
+            self.free.push(slot);
+        }
+    }
+
+    /// Returns the instruction at the given offset, decoding it if needed
+    #[inline]
+    pub fn get(&mut self, code: &[u8], offset: usize) -> CoreResult<&DecodedInstr> {
+        if self.index.len() != code.len() {
+            self.reset(code.len());
+        }
+        let slot = *self.index.get(offset).ok_or(CoreError::OperatorDeserialize)?;
+        if slot != NOT_DECODED {
+            return Ok(&self.instrs[slot as usize]);
+        }
+        let instr = DecodedInstr::decode(code, offset)?;
+        let slot = match self.free.pop() {
+            Some(slot) => {
+                self.instrs[slot as usize] = instr;
+                slot
+            }
+            None => {
+                self.instrs.push(instr);
+                self.instrs.len() as u32 - 1
+            }
+        };
+        self.index[offset] = slot;
+        Ok(&self.instrs[slot as usize])
+    }
+}
+
+/// A type an operand slot can be read as, matching its bincode encoding
+pub trait FromOperand: Sized {
+    /// Converts the raw slot value, or returns `None` if it is not valid
+    fn from_operand(raw: u64) -> Option<Self>;
+}
+
+macro_rules! impl_from_operand {
+    ($($ty:ty),*) => {
+        $(
+            impl FromOperand for $ty {
+                #[inline]
+                fn from_operand(raw: u64) -> Option<Self> {
+                    Some(raw as $ty)
+                }
+            }
+        )*
+    };
+}
+
+impl_from_operand!(u8, u32, u64, i64, usize);
+
+impl FromOperand for i16 {
+    #[inline]
+    fn from_operand(raw: u64) -> Option<Self> {
+        Some(raw as u16 as i16)
+    }
+}
+
+impl FromOperand for f32 {
+    #[inline]
+    fn from_operand(raw: u64) -> Option<Self> {
+        Some(f32::from_bits(raw as u32))
+    }
+}
+
+impl FromOperand for bool {
+    #[inline]
+    fn from_operand(raw: u64) -> Option<Self> {
+        match raw {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,40 @@
+//! Running a core as a future while async host functions complete
+
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{
+        Context,
+        Poll,
+    },
+};
+
+use mess_api::{
+    function::HostFuture,
+    prelude::Function,
+};
+
+use super::core::{
+    Core,
+    CoreResult,
+};
+
+/// An async host function call the core is suspended in
+pub(crate) struct PendingCall {
+    pub function: Function,
+    pub future: HostFuture,
+}
+
+/// Runs a core to completion, resuming it whenever an async host function
+/// it waits for completes. See `Core::run_fn_async`.
+pub struct RunFuture<'a> {
+    pub(crate) core: &'a mut Core,
+}
+
+impl Future for RunFuture<'_> {
+    type Output = CoreResult<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.core.poll_resume(cx)
+    }
+}
@@ -0,0 +1,252 @@
+//! The handle table holding the foreign objects of a `Core`
+
+use std::{
+    any::{
+        type_name,
+        TypeId,
+    },
+    sync::{
+        Arc,
+        Mutex,
+    },
+};
+
+use mess_api::prelude::ForeignObject;
+
+use super::{
+    address::{
+        Address,
+        AddressType,
+    },
+    core::{
+        CoreError,
+        CoreResult,
+    },
+};
+
+/// Generations use the bits of a foreign address above the slot index
+const GENERATION_MASK: u32 = (1 << 29) - 1;
+
+/// A foreign object still referenced when its core was torn down
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ForeignLeak {
+    /// The handle of the object
+    pub ptr: u64,
+    /// The name of the type the object was inserted as
+    pub type_name: &'static str,
+    /// The references scripts hold on the object
+    pub script_refs: usize,
+    /// The references the host holds on the object, besides the table's own
+    pub host_refs: usize,
+}
+
+struct Object {
+    foreign: ForeignObject,
+    script_refs: usize,
+}
+
+struct Slot {
+    generation: u32,
+    object: Option<Object>,
+}
+
+/// Foreign objects keyed by generational indices, so stale handles to a
+/// removed object never reach an object inserted into the same slot later.
+/// Handles are foreign addresses with the slot index in the lower 32 bits
+/// and the generation above them.
+#[derive(Default)]
+pub(crate) struct HandleTable {
+    slots: Vec<Slot>,
+    free: Vec<u32>,
+    len: usize,
+}
+
+impl HandleTable {
+    /// The number of objects in the table
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Inserts an object with a single script reference, returning its handle
+    pub fn insert(&mut self, foreign: ForeignObject) -> u64 {
+        let index = match self.free.pop() {
+            Some(index) => index,
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    object: None,
+                });
+                (self.slots.len() - 1) as u32
+            }
+        };
+        let slot = &mut self.slots[index as usize];
+        slot.object = Some(Object {
+            foreign,
+            script_refs: 1,
+        });
+        self.len += 1;
+        encode(index, slot.generation)
+    }
+
+    /// Inserts an object at the handle it had when a snapshot was taken
+    pub fn restore(&mut self, ptr: u64, foreign: ForeignObject) -> CoreResult<()> {
+        let (index, generation) = decode(ptr).ok_or(CoreError::InvalidHandle(ptr))?;
+        if self.slots.len() <= index as usize {
+            let start = self.slots.len() as u32;
+            self.slots.resize_with(index as usize + 1, || Slot {
+                generation: 0,
+                object: None,
+            });
+            self.free.extend(start..index);
+        }
+        let slot = &mut self.slots[index as usize];
+        if slot.object.is_some() {
+            return Err(CoreError::InvalidHandle(ptr));
+        }
+        slot.generation = generation;
+        slot.object = Some(Object {
+            foreign,
+            script_refs: 1,
+        });
+        self.free.retain(|free| *free != index);
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Returns the object behind the handle, checking its type
+    pub fn get<T: Send + 'static>(&self, ptr: u64) -> CoreResult<Arc<Mutex<T>>> {
+        let object = self.get_object(ptr)?;
+        if object.foreign.type_id != TypeId::of::<Arc<Mutex<T>>>() {
+            return Err(type_mismatch::<T>(ptr, object));
+        }
+        object
+            .foreign
+            .object
+            .downcast_ref::<Arc<Mutex<T>>>()
+            .cloned()
+            .ok_or_else(|| type_mismatch::<T>(ptr, object))
+    }
+
+    /// Returns the object behind the handle without checking its type
+    pub fn get_foreign(&self, ptr: u64) -> CoreResult<&ForeignObject> {
+        self.get_object(ptr).map(|object| &object.foreign)
+    }
+
+    /// Removes the object behind the handle regardless of its references
+    pub fn remove<T: Send + 'static>(&mut self, ptr: u64) -> CoreResult<Arc<Mutex<T>>> {
+        let item = self.get::<T>(ptr)?;
+        self.free_slot(ptr);
+        Ok(item)
+    }
+
+    /// Adds a script reference to the object
+    pub fn retain(&mut self, ptr: u64) -> CoreResult<usize> {
+        let object = self.get_object_mut(ptr)?;
+        object.script_refs += 1;
+        Ok(object.script_refs)
+    }
+
+    /// Drops a script reference to the object, removing it from the table
+    /// once scripts hold no more references. The object itself is dropped
+    /// when the host holds no references either.
+    pub fn release(&mut self, ptr: u64) -> CoreResult<usize> {
+        let object = self.get_object_mut(ptr)?;
+        object.script_refs -= 1;
+        let script_refs = object.script_refs;
+        if script_refs == 0 {
+            self.free_slot(ptr);
+        }
+        Ok(script_refs)
+    }
+
+    /// Returns the number of script references to the object
+    pub fn get_script_refs(&self, ptr: u64) -> CoreResult<usize> {
+        Ok(self.get_object(ptr)?.script_refs)
+    }
+
+    /// Sets the number of script references, e.g. when restoring a snapshot
+    pub fn set_script_refs(&mut self, ptr: u64, script_refs: usize) -> CoreResult<()> {
+        if script_refs == 0 {
+            return Err(CoreError::InvalidHandle(ptr));
+        }
+        self.get_object_mut(ptr)?.script_refs = script_refs;
+        Ok(())
+    }
+
+    /// Returns the handles of all objects, in slot order
+    pub fn handles(&self) -> Vec<u64> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter(|(_, slot)| slot.object.is_some())
+            .map(|(index, slot)| encode(index as u32, slot.generation))
+            .collect()
+    }
+
+    /// Returns the objects that are still referenced
+    pub fn leaks(&self) -> Vec<ForeignLeak> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(index, slot)| {
+                let object = slot.object.as_ref()?;
+                Some(ForeignLeak {
+                    ptr: encode(index as u32, slot.generation),
+                    type_name: object.foreign.type_name,
+                    script_refs: object.script_refs,
+                    host_refs: object.foreign.get_host_refs(),
+                })
+            })
+            .collect()
+    }
+
+    fn get_object(&self, ptr: u64) -> CoreResult<&Object> {
+        let (index, generation) = decode(ptr).ok_or(CoreError::InvalidHandle(ptr))?;
+        self.slots
+            .get(index as usize)
+            .filter(|slot| slot.generation == generation)
+            .and_then(|slot| slot.object.as_ref())
+            .ok_or(CoreError::InvalidHandle(ptr))
+    }
+
+    fn get_object_mut(&mut self, ptr: u64) -> CoreResult<&mut Object> {
+        let (index, generation) = decode(ptr).ok_or(CoreError::InvalidHandle(ptr))?;
+        self.slots
+            .get_mut(index as usize)
+            .filter(|slot| slot.generation == generation)
+            .and_then(|slot| slot.object.as_mut())
+            .ok_or(CoreError::InvalidHandle(ptr))
+    }
+
+    /// Empties the slot of a valid handle and bumps its generation
+    fn free_slot(&mut self, ptr: u64) {
+        if let Some((index, _)) = decode(ptr) {
+            let slot = &mut self.slots[index as usize];
+            slot.object = None;
+            slot.generation = (slot.generation + 1) & GENERATION_MASK;
+            self.free.push(index);
+            self.len -= 1;
+        }
+    }
+}
+
+fn encode(index: u32, generation: u32) -> u64 {
+    Address::new((generation as u64) << 32 | index as u64, AddressType::Foreign).into()
+}
+
+fn decode(ptr: u64) -> Option<(u32, u32)> {
+    // Only foreign addresses are handles, other types may not even parse
+    if AddressType::from_raw(ptr) != Some(AddressType::Foreign) {
+        return None;
+    }
+    let real_address = Address::from(ptr).real_address;
+    Some((real_address as u32, (real_address >> 32) as u32))
+}
+
+fn type_mismatch<T: 'static>(ptr: u64, object: &Object) -> CoreError {
+    CoreError::ForeignTypeMismatch {
+        ptr,
+        expected: type_name::<T>(),
+        found: object.foreign.type_name,
+    }
+}
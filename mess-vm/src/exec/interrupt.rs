@@ -0,0 +1,41 @@
+//! Stopping a running core from another thread
+
+use std::sync::{
+    atomic::{
+        AtomicBool,
+        Ordering,
+    },
+    Arc,
+};
+
+/// Handle for interrupting a running core from another thread
+#[derive(Clone, Debug, Default)]
+pub struct InterruptHandle {
+    flag: Arc<AtomicBool>,
+}
+
+impl InterruptHandle {
+    /// Requests the core to stop before executing its next instruction.
+    /// The core then returns `CoreError::Interrupted` and can be resumed.
+    pub fn interrupt(&self) {
+        self.flag.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether an interrupt is pending
+    pub fn is_interrupted(&self) -> bool {
+        self.flag.load(Ordering::Relaxed)
+    }
+
+    /// Whether a clone of the handle was handed out, through which the core
+    /// can be interrupted
+    pub(crate) fn is_shared(&self) -> bool {
+        Arc::strong_count(&self.flag) > 1
+    }
+
+    /// Clears a pending interrupt, returning whether one was pending
+    #[inline]
+    pub(crate) fn take(&self) -> bool {
+        // Only pay for the swap when an interrupt is pending
+        self.is_interrupted() && self.flag.swap(false, Ordering::Relaxed)
+    }
+}
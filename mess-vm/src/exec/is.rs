@@ -1,12 +1,16 @@
 use std::{
+    collections::HashMap,
     convert::{
         Into,
         TryFrom,
     },
     fmt::UpperHex,
+    str::FromStr,
+    sync::OnceLock,
 };
 
 use enum_primitive_derive::*;
+use num_traits::FromPrimitive;
 
 use crate::exec::core::{
     CoreError,
@@ -18,7 +22,7 @@ use crate::exec::core::{
  *
  * Each Opcode represents one instruction
  */
-#[derive(PartialEq, Debug, Clone, Primitive)]
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Primitive)]
 #[allow(non_camel_case_types)]
 pub enum Opcode {
     /// Do not do anything
@@ -62,7 +66,10 @@ pub enum Opcode {
     SUBU = 33,
     MULU = 34,
     DIVU = 35,
+    /// Add an immediate to an unsigned integer. Always wraps, as it is used
+    /// for stack and address arithmetic
     ADDU_I = 36,
+    /// Subtract an immediate from an unsigned integer. Always wraps, like `ADDU_I`
     SUBU_I = 37,
     MULU_I = 38,
     DIVU_I = 39,
@@ -97,6 +104,82 @@ pub enum Opcode {
     GTF = 68,
     LTEQF = 69,
     GTEQF = 70,
+    /// Allocate heap memory, with the size and the resulting address in registers
+    ALLOC = 71,
+    /// Free heap memory at the address in a register
+    FREE = 72,
+    /// Suspend the running coroutine, yielding the value in `R0`
+    YIELD = 73,
+    /// Create a coroutine of a function and store its handle in a register.
+    /// The current registers and the given number of bytes below the stack
+    /// pointer are passed as its arguments
+    SPAWN = 74,
+    /// Resume the coroutine whose handle is in the first register, storing the
+    /// yielded or returned value and whether it finished in the other two.
+    /// A coroutine that returned is removed
+    RESUME = 75,
+    /// Store whether the coroutine whose handle is in the first register
+    /// finished, or no longer exists, in the second
+    DONE = 76,
+    /// Convert the signed integer in a register to the nearest float
+    ITOF = 78,
+    /// Convert the unsigned integer in a register to the nearest float
+    UTOF = 79,
+    /// Convert the float in a register to a signed integer, truncating toward
+    /// zero and saturating at the integer bounds. NaN converts to 0
+    FTOI = 80,
+    /// Convert the float in a register to an unsigned integer, truncating
+    /// toward zero and saturating at the integer bounds. NaN converts to 0
+    FTOU = 81,
+    /// Sign extend the lowest byte of a register to 64 bits
+    SEXT8 = 82,
+    /// Sign extend the lowest 16 bits of a register to 64 bits
+    SEXT16 = 83,
+    /// Sign extend the lowest 32 bits of a register to 64 bits
+    SEXT32 = 84,
+    /// Zero extend the lowest byte of a register to 64 bits
+    ZEXT8 = 85,
+    /// Zero extend the lowest 16 bits of a register to 64 bits
+    ZEXT16 = 86,
+    /// Zero extend the lowest 32 bits of a register to 64 bits
+    ZEXT32 = 87,
+    /// Convert the signed integer in a register to the nearest 64 bit float
+    ITOD = 88,
+    /// Convert the unsigned integer in a register to the nearest 64 bit float
+    UTOD = 89,
+    /// Convert the 64 bit float in a register to a signed integer, like `FTOI`
+    DTOI = 90,
+    /// Convert the 64 bit float in a register to an unsigned integer, like `FTOU`
+    DTOU = 91,
+    /// Widen the float in a register to a 64 bit float
+    FTOD = 92,
+    /// Narrow the 64 bit float in a register to the nearest float
+    DTOF = 93,
+    /// Fit the signed integer in a register into the given number of bytes.
+    /// Out of range values wrap or trap, depending on the overflow mode
+    NARROWI = 94,
+    /// Fit the unsigned integer in a register into the given number of bytes.
+    /// Out of range values wrap or trap, depending on the overflow mode
+    NARROWU = 95,
+    /// Load the given number of bytes from memory into a register, zero
+    /// extending them
+    MOVN_AR = 96,
+    /// Store the given number of low bytes of a register to memory
+    MOVN_RA = 97,
+    /// Stop the script with `CoreError::ScriptPanic`. The register holds the
+    /// address of the message, a length prefixed UTF-8 string
+    PANIC = 98,
+    /// Stop the script with `CoreError::ScriptPanic` if the boolean in the
+    /// first register is false, with the message in the second like `PANIC`
+    ASSERT = 99,
+    /// Add two 64 bit floats
+    ADDD = 100,
+    /// Subtract two 64 bit floats
+    SUBD = 101,
+    /// Multiply two 64 bit floats
+    MULD = 102,
+    /// Divide two 64 bit floats
+    DIVD = 103,
 }
 
 impl Into<u8> for Opcode {
@@ -104,3 +187,247 @@ impl Into<u8> for Opcode {
         self as u8
     }
 }
+
+impl FromStr for Opcode {
+    type Err = ();
+
+    /// Parses an opcode from its mnemonic, e.g. `"ADDI_I"`
+    fn from_str(mnemonic: &str) -> Result<Self, Self::Err> {
+        // The mnemonics are the variant names, collected on the first lookup
+        static MNEMONICS: OnceLock<HashMap<String, Opcode>> = OnceLock::new();
+        MNEMONICS
+            .get_or_init(|| {
+                (0..=u8::MAX)
+                    .filter_map(Opcode::from_u8)
+                    .map(|opcode| (format!("{:?}", opcode), opcode))
+                    .collect()
+            })
+            .get(mnemonic)
+            .cloned()
+            .ok_or(())
+    }
+}
+
+/// The kind of an encoded instruction operand
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum OperandKind {
+    /// A register index (u8)
+    Reg,
+    /// An unsigned byte immediate (u8)
+    Byte,
+    /// A signed integer immediate (i64)
+    Int,
+    /// An unsigned integer immediate (u64)
+    UInt,
+    /// A float immediate (f32)
+    Float,
+    /// A boolean immediate
+    Bool,
+    /// A byte count (u32)
+    Size,
+    /// A register holding an address plus a signed offset (u8, i16)
+    Mem,
+    /// A code offset to jump to (u64)
+    Target,
+    /// The uid of a function to call (u64)
+    FnUid,
+}
+
+impl OperandKind {
+    /// The size in bytes of this operand in bytecode
+    pub fn size(&self) -> usize {
+        match self {
+            OperandKind::Reg | OperandKind::Byte | OperandKind::Bool => 1,
+            OperandKind::Float | OperandKind::Size => 4,
+            OperandKind::Mem => 3,
+            OperandKind::Int | OperandKind::UInt | OperandKind::Target | OperandKind::FnUid => 8,
+        }
+    }
+}
+
+/// How an instruction accesses the register of an operand
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum OperandAccess {
+    /// The operand is an immediate and names no register
+    Immediate,
+    /// The register is read
+    Read,
+    /// The register is written
+    Write,
+}
+
+impl Opcode {
+    /// The operands this opcode reads from bytecode, in order
+    pub fn operand_kinds(&self) -> &'static [OperandKind] {
+        use OperandKind::*;
+        match self {
+            Opcode::NOOP | Opcode::RET | Opcode::YIELD => &[],
+            Opcode::HALT => &[Byte],
+            Opcode::MOVB | Opcode::MOVF | Opcode::MOVI | Opcode::MOVA | Opcode::NOT => &[Reg, Reg],
+            Opcode::ALLOC => &[Reg, Reg],
+            Opcode::ITOF
+            | Opcode::UTOF
+            | Opcode::FTOI
+            | Opcode::FTOU
+            | Opcode::SEXT8
+            | Opcode::SEXT16
+            | Opcode::SEXT32
+            | Opcode::ZEXT8
+            | Opcode::ZEXT16
+            | Opcode::ZEXT32
+            | Opcode::ITOD
+            | Opcode::UTOD
+            | Opcode::DTOI
+            | Opcode::DTOU
+            | Opcode::FTOD
+            | Opcode::DTOF => &[Reg, Reg],
+            Opcode::NARROWI | Opcode::NARROWU => &[Reg, Byte, Reg],
+            Opcode::MOVN_AR => &[Mem, Reg, Size],
+            Opcode::MOVN_RA => &[Reg, Mem, Size],
+            Opcode::MOVB_A | Opcode::MOVF_A | Opcode::MOVI_A | Opcode::MOVA_A => &[Mem, Mem],
+            Opcode::MOVN_A => &[Mem, Mem, Size],
+            Opcode::MOVB_AR | Opcode::MOVF_AR | Opcode::MOVI_AR | Opcode::MOVA_AR => &[Mem, Reg],
+            Opcode::MOVB_RA | Opcode::MOVF_RA | Opcode::MOVI_RA | Opcode::MOVA_RA => &[Reg, Mem],
+            Opcode::LDB => &[Bool, Reg],
+            Opcode::LDF => &[Float, Reg],
+            Opcode::LDI => &[Int, Reg],
+            Opcode::LDA => &[UInt, Reg],
+            Opcode::ADDI_I | Opcode::SUBI_I | Opcode::MULI_I | Opcode::DIVI_I => &[Reg, Int, Reg],
+            Opcode::ADDU_I | Opcode::SUBU_I | Opcode::MULU_I | Opcode::DIVU_I => &[Reg, UInt, Reg],
+            Opcode::ADDF_I | Opcode::SUBF_I | Opcode::MULF_I | Opcode::DIVF_I => &[Reg, Float, Reg],
+            Opcode::JMP => &[Target],
+            Opcode::JMPT | Opcode::JMPF => &[Reg, Target],
+            Opcode::DJMP | Opcode::FREE | Opcode::PANIC => &[Reg],
+            Opcode::DJMPT | Opcode::DJMPF | Opcode::ASSERT => &[Reg, Reg],
+            Opcode::CALL => &[FnUid],
+            Opcode::SPAWN => &[FnUid, Size, Reg],
+            Opcode::RESUME => &[Reg, Reg, Reg],
+            Opcode::DONE => &[Reg, Reg],
+            Opcode::ADDI
+            | Opcode::SUBI
+            | Opcode::MULI
+            | Opcode::DIVI
+            | Opcode::ADDU
+            | Opcode::SUBU
+            | Opcode::MULU
+            | Opcode::DIVU
+            | Opcode::ADDF
+            | Opcode::SUBF
+            | Opcode::MULF
+            | Opcode::DIVF
+            | Opcode::ADDD
+            | Opcode::SUBD
+            | Opcode::MULD
+            | Opcode::DIVD
+            | Opcode::AND
+            | Opcode::OR
+            | Opcode::EQI
+            | Opcode::NEQI
+            | Opcode::LTI
+            | Opcode::GTI
+            | Opcode::LTEQI
+            | Opcode::GTEQI
+            | Opcode::EQF
+            | Opcode::NEQF
+            | Opcode::LTF
+            | Opcode::GTF
+            | Opcode::LTEQF
+            | Opcode::GTEQF => &[Reg, Reg, Reg],
+        }
+    }
+
+    /// How this opcode accesses the registers of its operands, one entry per
+    /// operand of `operand_kinds`. Memory operands read their base register.
+    pub fn operand_accesses(&self) -> &'static [OperandAccess] {
+        use OperandAccess::*;
+        match self {
+            Opcode::NOOP | Opcode::RET | Opcode::YIELD => &[],
+            Opcode::HALT | Opcode::JMP | Opcode::CALL => &[Immediate],
+            Opcode::MOVB | Opcode::MOVF | Opcode::MOVI | Opcode::MOVA => &[Read, Write],
+            Opcode::NOT | Opcode::ALLOC => &[Read, Write],
+            Opcode::ITOF
+            | Opcode::UTOF
+            | Opcode::FTOI
+            | Opcode::FTOU
+            | Opcode::SEXT8
+            | Opcode::SEXT16
+            | Opcode::SEXT32
+            | Opcode::ZEXT8
+            | Opcode::ZEXT16
+            | Opcode::ZEXT32
+            | Opcode::ITOD
+            | Opcode::UTOD
+            | Opcode::DTOI
+            | Opcode::DTOU
+            | Opcode::FTOD
+            | Opcode::DTOF => &[Read, Write],
+            Opcode::NARROWI | Opcode::NARROWU => &[Read, Immediate, Write],
+            Opcode::MOVN_AR => &[Read, Write, Immediate],
+            Opcode::MOVN_RA => &[Read, Read, Immediate],
+            Opcode::MOVB_A | Opcode::MOVF_A | Opcode::MOVI_A | Opcode::MOVA_A => &[Read, Read],
+            Opcode::MOVN_A => &[Read, Read, Immediate],
+            Opcode::MOVB_AR | Opcode::MOVF_AR | Opcode::MOVI_AR | Opcode::MOVA_AR => &[Read, Write],
+            Opcode::MOVB_RA | Opcode::MOVF_RA | Opcode::MOVI_RA | Opcode::MOVA_RA => &[Read, Read],
+            Opcode::LDB | Opcode::LDF | Opcode::LDI | Opcode::LDA => &[Immediate, Write],
+            Opcode::ADDI_I
+            | Opcode::SUBI_I
+            | Opcode::MULI_I
+            | Opcode::DIVI_I
+            | Opcode::ADDU_I
+            | Opcode::SUBU_I
+            | Opcode::MULU_I
+            | Opcode::DIVU_I
+            | Opcode::ADDF_I
+            | Opcode::SUBF_I
+            | Opcode::MULF_I
+            | Opcode::DIVF_I => &[Read, Immediate, Write],
+            Opcode::JMPT | Opcode::JMPF => &[Read, Immediate],
+            Opcode::DJMP | Opcode::FREE | Opcode::PANIC => &[Read],
+            Opcode::DJMPT | Opcode::DJMPF | Opcode::ASSERT => &[Read, Read],
+            Opcode::SPAWN => &[Immediate, Immediate, Write],
+            // The handle is read, the value and whether the coroutine finished
+            // are written
+            Opcode::RESUME => &[Read, Write, Write],
+            Opcode::DONE => &[Read, Write],
+            Opcode::ADDI
+            | Opcode::SUBI
+            | Opcode::MULI
+            | Opcode::DIVI
+            | Opcode::ADDU
+            | Opcode::SUBU
+            | Opcode::MULU
+            | Opcode::DIVU
+            | Opcode::ADDF
+            | Opcode::SUBF
+            | Opcode::MULF
+            | Opcode::DIVF
+            | Opcode::ADDD
+            | Opcode::SUBD
+            | Opcode::MULD
+            | Opcode::DIVD
+            | Opcode::AND
+            | Opcode::OR
+            | Opcode::EQI
+            | Opcode::NEQI
+            | Opcode::LTI
+            | Opcode::GTI
+            | Opcode::LTEQI
+            | Opcode::GTEQI
+            | Opcode::EQF
+            | Opcode::NEQF
+            | Opcode::LTF
+            | Opcode::GTF
+            | Opcode::LTEQF
+            | Opcode::GTEQF => &[Read, Read, Write],
+        }
+    }
+
+    /// The size in bytes of an encoded instruction with this opcode
+    pub fn instruction_size(&self) -> usize {
+        1 + self
+            .operand_kinds()
+            .iter()
+            .map(|kind| kind.size())
+            .sum::<usize>()
+    }
+}
@@ -0,0 +1,60 @@
+//! Resource limits for the stack, heap and calls of a core
+
+/// Hard resource limits enforced by a `Core`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Limits {
+    /// Maximum size of the stack in bytes
+    pub max_stack_size: usize,
+    /// Maximum number of heap bytes allocated at once
+    pub max_heap_size: usize,
+    /// Maximum number of nested script function calls
+    pub max_call_depth: usize,
+    /// Maximum number of live foreign objects
+    pub max_foreign_objects: usize,
+    /// Maximum number of coroutines that have not finished
+    pub max_coroutines: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_stack_size: 1024 * 1024,
+            max_heap_size: 16 * 1024 * 1024,
+            max_call_depth: 1024,
+            max_foreign_objects: 4096,
+            max_coroutines: 256,
+        }
+    }
+}
+
+impl Limits {
+    /// Sets the maximum stack size in bytes
+    pub fn with_max_stack_size(mut self, max_stack_size: usize) -> Self {
+        self.max_stack_size = max_stack_size;
+        self
+    }
+
+    /// Sets the maximum number of heap bytes allocated at once
+    pub fn with_max_heap_size(mut self, max_heap_size: usize) -> Self {
+        self.max_heap_size = max_heap_size;
+        self
+    }
+
+    /// Sets the maximum call depth
+    pub fn with_max_call_depth(mut self, max_call_depth: usize) -> Self {
+        self.max_call_depth = max_call_depth;
+        self
+    }
+
+    /// Sets the maximum number of live foreign objects
+    pub fn with_max_foreign_objects(mut self, max_foreign_objects: usize) -> Self {
+        self.max_foreign_objects = max_foreign_objects;
+        self
+    }
+
+    /// Sets the maximum number of coroutines that have not finished
+    pub fn with_max_coroutines(mut self, max_coroutines: usize) -> Self {
+        self.max_coroutines = max_coroutines;
+        self
+    }
+}
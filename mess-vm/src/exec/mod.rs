@@ -5,3 +5,25 @@ pub mod is;
 pub mod address;
 
 pub mod register;
+
+pub mod interrupt;
+
+pub mod limits;
+
+pub mod backtrace;
+
+pub mod debug;
+
+pub mod decode;
+
+pub mod profiler;
+
+pub mod coroutine;
+
+pub mod snapshot;
+
+pub mod future;
+
+pub mod handle;
+
+pub mod tier;
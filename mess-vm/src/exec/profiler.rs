@@ -0,0 +1,178 @@
+//! Per-function and per-opcode execution statistics
+
+use std::{
+    collections::{
+        BTreeMap,
+        HashMap,
+    },
+    fmt::Write,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use num_traits::ToPrimitive;
+
+use super::is::Opcode;
+
+/// Execution statistics of a single function
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct FunctionStats {
+    /// How often the function was entered
+    pub calls: u64,
+    /// Instructions executed in the function itself
+    pub instructions: u64,
+    /// Time spent in the function itself
+    pub self_time: Duration,
+    /// Time spent in the function, including its callees
+    pub total_time: Duration,
+}
+
+/// Execution statistics of a single opcode
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct OpcodeStats {
+    /// How often the opcode was executed
+    pub count: u64,
+    /// Time spent executing the opcode
+    pub time: Duration,
+}
+
+struct ProfileFrame {
+    name: String,
+    path: String,
+    start: Instant,
+    child_time: Duration,
+}
+
+/// Collects per-function and per-opcode statistics while a core runs
+#[derive(Default)]
+pub struct Profiler {
+    functions: HashMap<String, FunctionStats>,
+    opcodes: HashMap<Opcode, OpcodeStats>,
+    folded: BTreeMap<String, u64>,
+    stack: Vec<ProfileFrame>,
+}
+
+impl Profiler {
+    /// Records entering the function with the given name
+    pub(crate) fn enter(&mut self, name: &str) {
+        let path = match self.stack.last() {
+            Some(frame) => format!("{};{}", frame.path, name),
+            None => String::from(name),
+        };
+        self.functions.entry(String::from(name)).or_default().calls += 1;
+        self.stack.push(ProfileFrame {
+            name: String::from(name),
+            path,
+            start: Instant::now(),
+            child_time: Duration::ZERO,
+        });
+    }
+
+    /// Records leaving the innermost function
+    pub(crate) fn exit(&mut self) {
+        let frame = match self.stack.pop() {
+            Some(frame) => frame,
+            None => return,
+        };
+        let total_time = frame.start.elapsed();
+        let stats = self.functions.entry(frame.name).or_default();
+        stats.total_time += total_time;
+        stats.self_time += total_time.saturating_sub(frame.child_time);
+        if let Some(parent) = self.stack.last_mut() {
+            parent.child_time += total_time;
+        }
+    }
+
+    /// Records leaving all functions, e.g. when the entry function returned
+    pub(crate) fn exit_all(&mut self) {
+        while !self.stack.is_empty() {
+            self.exit();
+        }
+    }
+
+    /// Returns the number of functions currently entered
+    pub(crate) fn depth(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// Records an executed instruction in the innermost function
+    pub(crate) fn record(&mut self, opcode: Opcode, time: Duration) {
+        let stats = self.opcodes.entry(opcode).or_default();
+        stats.count += 1;
+        stats.time += time;
+        if let Some(frame) = self.stack.last() {
+            if let Some(stats) = self.functions.get_mut(&frame.name) {
+                stats.instructions += 1;
+            }
+            match self.folded.get_mut(&frame.path) {
+                Some(count) => *count += 1,
+                None => {
+                    self.folded.insert(frame.path.clone(), 1);
+                }
+            };
+        }
+    }
+
+    /// Returns the statistics of every function that was entered, by name
+    pub fn get_functions(&self) -> &HashMap<String, FunctionStats> {
+        &self.functions
+    }
+
+    /// Returns the statistics of every opcode that was executed
+    pub fn get_opcodes(&self) -> &HashMap<Opcode, OpcodeStats> {
+        &self.opcodes
+    }
+
+    /// Returns a human readable report, sorted by self time
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+        let mut functions: Vec<_> = self.functions.iter().collect();
+        functions.sort_by(|(a_name, a), (b_name, b)| {
+            b.self_time.cmp(&a.self_time).then(a_name.cmp(b_name))
+        });
+        let _ = writeln!(
+            report,
+            "{:>10} {:>14} {:>12} {:>12}  function",
+            "calls", "instructions", "self ms", "total ms"
+        );
+        for (name, stats) in functions {
+            let _ = writeln!(
+                report,
+                "{:>10} {:>14} {:>12.3} {:>12.3}  {}",
+                stats.calls,
+                stats.instructions,
+                stats.self_time.as_secs_f64() * 1000.0,
+                stats.total_time.as_secs_f64() * 1000.0,
+                name
+            );
+        }
+        let mut opcodes: Vec<_> = self.opcodes.iter().collect();
+        opcodes.sort_by(|(a_op, a), (b_op, b)| {
+            b.count.cmp(&a.count).then(a_op.to_u8().cmp(&b_op.to_u8()))
+        });
+        let _ = writeln!(report);
+        let _ = writeln!(report, "{:>10} {:>12}  opcode", "count", "ms");
+        for (opcode, stats) in opcodes {
+            let _ = writeln!(
+                report,
+                "{:>10} {:>12.3}  {:?}",
+                stats.count,
+                stats.time.as_secs_f64() * 1000.0,
+                opcode
+            );
+        }
+        report
+    }
+
+    /// Returns the executed instructions per call stack in the folded format
+    /// read by flamegraph tools, one `outer;inner count` line per stack
+    pub fn folded(&self) -> String {
+        let mut folded = String::new();
+        for (path, count) in self.folded.iter() {
+            let _ = writeln!(folded, "{} {}", path, count);
+        }
+        folded
+    }
+}
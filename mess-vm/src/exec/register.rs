@@ -7,11 +7,20 @@ use std::{
     marker::Copy,
 };
 
+use serde::{
+    Deserialize,
+    Deserializer,
+    Serialize,
+    Serializer,
+};
+
 #[derive(Clone)]
 pub union Register {
     pub uint64: u64,
     pub int64: i64,
     pub float: f32,
+    /// The register as a 64 bit float
+    pub double: f64,
     pub boolean: bool,
     pub bytes: [u8; 8]
 }
@@ -58,6 +67,21 @@ impl Register {
 
 impl Copy for Register {}
 
+/// Registers are serialized as their raw 8 bytes, e.g. for snapshots
+impl Serialize for Register {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.get::<u64>().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Register {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut register = Register::new();
+        register.set(u64::deserialize(deserializer)?);
+        Ok(register)
+    }
+}
+
 pub trait RegisterAccess<T> {
     fn get_val(&self) -> T;
     fn set_val(&mut self, item: T);
@@ -122,6 +146,25 @@ impl RegisterAccess<f32> for Register {
     }
 }
 
+impl RegisterAccess<f64> for Register {
+    fn get_val(&self) -> f64 {
+        unsafe { self.double }
+    }
+    fn set_val(&mut self, item: f64) {
+        self.double = item;
+    }
+    fn inc_val(&mut self, item: f64) {
+        unsafe {
+            self.double += item;
+        }
+    }
+    fn dec_val(&mut self, item: f64) {
+        unsafe {
+            self.double -= item;
+        }
+    }
+}
+
 impl RegisterAccess<bool> for Register {
     fn get_val(&self) -> bool {
         unsafe { self.boolean }
@@ -0,0 +1,95 @@
+//! Saving the state of a core and restoring it later
+
+use bincode::{
+    deserialize,
+    serialize,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use super::{
+    core::{
+        Core,
+        CoreError,
+        CoreResult,
+    },
+    coroutine::{
+        Context,
+        Coroutine,
+    },
+};
+use crate::codegen::output::Output;
+
+/// The execution state of a `Core`, taken with `Core::snapshot` and
+/// restored with `Core::restore` into a core running the same program
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub(crate) program_hash: u64,
+    pub(crate) context: Context,
+    pub(crate) heap: Vec<u8>,
+    pub(crate) heap_pointers: Vec<(usize, usize)>,
+    pub(crate) swap: Vec<u8>,
+    pub(crate) code_changes: Vec<(usize, Vec<u8>)>,
+    pub(crate) coroutines: Vec<(u64, Coroutine)>,
+    pub(crate) next_coroutine_id: u64,
+    pub(crate) foreign_objects: Vec<(u64, usize, Vec<u8>)>,
+}
+
+impl Snapshot {
+    /// Encodes the snapshot, e.g. to write it to a save game
+    pub fn to_bytes(&self) -> CoreResult<Vec<u8>> {
+        serialize(self).map_err(|_| CoreError::SnapshotSerialize)
+    }
+
+    /// Decodes a snapshot encoded with `Snapshot::to_bytes`
+    pub fn from_bytes(bytes: &[u8]) -> CoreResult<Snapshot> {
+        deserialize(bytes).map_err(|_| CoreError::InvalidSnapshot)
+    }
+
+    /// Returns the pointers of the foreign objects in the snapshot
+    pub fn get_foreign_pointers(&self) -> impl Iterator<Item = u64> + '_ {
+        self.foreign_objects.iter().map(|(ptr, _, _)| *ptr)
+    }
+}
+
+/// Converts foreign objects to bytes and back when taking and restoring
+/// snapshots. Foreign objects are opaque to the core, so only the host
+/// knows how to serialize them.
+pub trait ForeignSnapshot {
+    /// Serializes the foreign object behind the pointer, or returns `None`
+    /// if it can't be snapshotted
+    fn save(&mut self, core: &Core, ptr: u64) -> Option<Vec<u8>>;
+
+    /// Recreates a foreign object from the bytes returned by `save`, inserting
+    /// it at the same pointer with `Core::restore_foreign_ptr`
+    fn restore(&mut self, core: &mut Core, ptr: u64, bytes: &[u8]) -> CoreResult<()>;
+}
+
+/// Hashes the code of a program, to only restore snapshots into the same one
+pub(crate) fn hash_program(program: &Output) -> u64 {
+    // FNV-1a, as it has to be stable across processes
+    program
+        .code
+        .iter()
+        .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+        })
+}
+
+/// Returns the ranges a core changed in its copy of the program memory,
+/// e.g. by writing static data, as offsets and their new bytes
+pub(crate) fn diff_code(original: &[u8], changed: &[u8]) -> Vec<(usize, Vec<u8>)> {
+    let mut changes: Vec<(usize, Vec<u8>)> = Vec::new();
+    for (offset, (old, new)) in original.iter().zip(changed.iter()).enumerate() {
+        if old == new {
+            continue;
+        }
+        match changes.last_mut() {
+            Some((start, data)) if *start + data.len() == offset => data.push(*new),
+            _ => changes.push((offset, vec![*new])),
+        }
+    }
+    changes
+}
@@ -0,0 +1,166 @@
+//! Tiered execution, swapping compiled versions in for hot script functions
+
+use std::collections::{
+    HashMap,
+    HashSet,
+};
+
+/// When an interpreted script function counts as hot and gets compiled
+#[derive(Clone, Debug, PartialEq)]
+pub struct TierPolicy {
+    /// Number of calls after which a function is compiled
+    pub call_threshold: u64,
+    /// Number of loop iterations after which a function is compiled. The
+    /// running call stays interpreted, the compiled code is used from its
+    /// next call on.
+    pub loop_threshold: u64,
+}
+
+impl Default for TierPolicy {
+    fn default() -> Self {
+        Self {
+            call_threshold: 1000,
+            loop_threshold: 10_000,
+        }
+    }
+}
+
+impl TierPolicy {
+    /// Sets the number of calls after which a function is compiled
+    pub fn with_call_threshold(mut self, call_threshold: u64) -> Self {
+        self.call_threshold = call_threshold;
+        self
+    }
+
+    /// Sets the number of loop iterations after which a function is compiled
+    pub fn with_loop_threshold(mut self, loop_threshold: u64) -> Self {
+        self.loop_threshold = loop_threshold;
+        self
+    }
+}
+
+/// How often an interpreted function was called and jumped back in its loops
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HotCounters {
+    /// Number of calls
+    pub calls: u64,
+    /// Number of backward jumps taken
+    pub loop_iterations: u64,
+}
+
+/// Why a compiled function failed
+#[derive(Clone, Debug, PartialEq)]
+pub enum CompiledFnError {
+    /// An integer was divided by zero
+    DivisionByZero,
+    /// Integer arithmetic overflowed, compiled with the core's
+    /// `OverflowMode::Trap`
+    IntegerOverflow,
+    /// The compiled code nested more calls than it was allowed to
+    CallStackOverflow,
+    /// The script called `panic` or a failing `assert` with the message
+    ScriptPanic(String),
+    /// The compiled code failed otherwise, for the given reason
+    Failed(String),
+}
+
+/// Runs compiled code with the bytes of each argument and the number of calls
+/// it can nest, returning the bits of the return value
+type CompiledBody = Box<dyn FnMut(&[Vec<u8>], usize) -> Result<u64, CompiledFnError> + Send>;
+
+/// A compiled version of a script function, run instead of interpreting it.
+/// It is called like a host function: the arguments are read from the top
+/// of the caller's stack and the return value is put into `R0`.
+///
+/// The core only runs compiled code without fuel and while no interrupt
+/// handle was handed out, as compiled code can't be stopped, and interprets
+/// the function otherwise.
+pub struct CompiledFn {
+    arg_sizes: Vec<usize>,
+    function: CompiledBody,
+}
+
+impl CompiledFn {
+    /// Creates a compiled function taking arguments of the given sizes. It is
+    /// called with the bytes of each argument and the number of calls it can
+    /// nest within the core's call depth limit, itself included, and returns
+    /// the bits of its return value.
+    pub fn new<F>(arg_sizes: Vec<usize>, function: F) -> Self
+    where
+        F: FnMut(&[Vec<u8>], usize) -> Result<u64, CompiledFnError> + Send + 'static,
+    {
+        Self {
+            arg_sizes,
+            function: Box::new(function),
+        }
+    }
+
+    /// Returns the sizes of the arguments, in order
+    pub fn get_arg_sizes(&self) -> &[usize] {
+        &self.arg_sizes
+    }
+
+    pub(crate) fn call(&mut self, args: &[Vec<u8>], max_call_depth: usize) -> Result<u64, CompiledFnError> {
+        (self.function)(args, max_call_depth)
+    }
+}
+
+/// Compiles a hot function, given its name. Returning `None` keeps the
+/// function interpreted, it is not offered again.
+pub type TierUpHandler = Box<dyn FnMut(&str) -> Option<CompiledFn> + Send>;
+
+/// The counters of a core with tiered execution enabled
+pub(crate) struct Tiering {
+    policy: TierPolicy,
+    handler: TierUpHandler,
+    counters: HashMap<u64, HotCounters>,
+    /// The functions that were offered to the handler
+    offered: HashSet<u64>,
+}
+
+impl Tiering {
+    pub(crate) fn new(policy: TierPolicy, handler: TierUpHandler) -> Self {
+        Self {
+            policy,
+            handler,
+            counters: HashMap::new(),
+            offered: HashSet::new(),
+        }
+    }
+
+    pub(crate) fn get_counters(&self, uid: u64) -> Option<HotCounters> {
+        self.counters.get(&uid).copied()
+    }
+
+    /// Counts a call, returning whether the function just became hot
+    pub(crate) fn count_call(&mut self, uid: u64) -> bool {
+        if self.offered.contains(&uid) {
+            return false;
+        }
+        let counters = self.counters.entry(uid).or_default();
+        counters.calls += 1;
+        counters.calls >= self.policy.call_threshold
+    }
+
+    /// Counts a loop iteration, returning whether the function just became hot
+    pub(crate) fn count_loop(&mut self, uid: u64) -> bool {
+        if self.offered.contains(&uid) {
+            return false;
+        }
+        let counters = self.counters.entry(uid).or_default();
+        counters.loop_iterations += 1;
+        counters.loop_iterations >= self.policy.loop_threshold
+    }
+
+    /// Offers a hot function to the handler
+    pub(crate) fn tier_up(&mut self, uid: u64, fn_name: &str) -> Option<CompiledFn> {
+        self.offered.insert(uid);
+        (self.handler)(fn_name)
+    }
+
+    /// Forgets the counters, as function uids change with the program
+    pub(crate) fn reset(&mut self) {
+        self.counters.clear();
+        self.offered.clear();
+    }
+}
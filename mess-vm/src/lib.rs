@@ -1,6 +1,7 @@
 #![warn(missing_docs)]
 
 extern crate enum_primitive_derive as epd;
+extern crate self as mess_vm;
 
 pub mod codegen;
 
@@ -9,4 +10,7 @@ pub mod exec;
 pub mod adapter;
 
 pub use codegen::compiler::Compiler;
-pub use exec::core::Core;
\ No newline at end of file
+pub use exec::core::Core;
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,96 @@
+use mess_vm_derive::asm;
+
+use super::{run_fn, Result};
+use crate::codegen::{asm::assemble, assembler::Assembler, error::Error, register::Register};
+
+#[test]
+fn test_asm_text_loop() -> Result {
+    let code = "
+    main:
+        LDI 10, R1      # counter
+        LDI 0, R2       # sum
+    .loop:
+        ADDI R2, R1, R2
+        SUBI_I R1, 1, R1
+        GTI R1, R0, R3
+        JMPT R3, .loop
+        RET
+    ";
+
+    let mut core = run_fn(assemble(code)?, "main")?;
+    assert_eq!(core.reg(2)?.get::<i64>(), 55);
+    Ok(())
+}
+
+#[test]
+fn test_asm_text_call() -> Result {
+    let code = "
+    double:
+        ADDI R1, R1, R0
+        RET
+    main:
+        LDI 21, R1; CALL double
+        MOVI R0, R4
+        RET
+    ";
+
+    let mut core = run_fn(assemble(code)?, "main")?;
+    assert_eq!(core.reg(4)?.get::<i64>(), 42);
+    Ok(())
+}
+
+#[test]
+fn test_asm_text_memory() -> Result {
+    let code = "
+    main:
+        ADDU_I SP, 16, SP
+        LDF 1.5, R1
+        MOVF_RA R1, [SP - 8]
+        MOVF_AR [SP-8], R2
+        RET
+    ";
+
+    let mut core = run_fn(assemble(code)?, "main")?;
+    assert_eq!(core.reg(2)?.get::<f32>(), 1.5);
+    Ok(())
+}
+
+#[test]
+fn test_asm_text_errors() {
+    let unknown_mnemonic = assemble("main:\n    LDX 1, R1\n");
+    assert!(matches!(unknown_mnemonic, Err(Error::AsmSyntax(2, _))));
+
+    let wrong_operand = assemble("main:\n    RET\n    LDI R1, R1\n");
+    assert!(matches!(wrong_operand, Err(Error::AsmSyntax(3, _))));
+
+    let unknown_label = assemble("main:\n    JMP .nowhere\n");
+    assert!(matches!(unknown_label, Err(Error::UnknownLabel(_))));
+
+    let unknown_field_type = assemble(".container Point, x: Vec\nmain:\n    RET\n");
+    assert!(matches!(unknown_field_type, Err(Error::AsmSyntax(1, _))));
+}
+
+#[test]
+fn test_asm_macro() -> Result {
+    let mut assembler = Assembler::default();
+    let target = Register::R3;
+    let offset = 8;
+    asm!(&mut assembler,
+        main:
+        ADDU_I SP, 16, SP;
+        LDI -4, R1;
+        MOVI_RA R1, [SP - {offset}];
+        MOVI_AR [SP - {offset}], R2;
+        MULI_I R2, {3i64}, {target.clone()};
+        JMP done;
+        LDI 0, {target};
+        done:
+        RET;
+    )?;
+    assembler.push_fn_label(String::from("entry"), 7);
+    asm!(&mut assembler, JMP main)?;
+
+    let mut core = run_fn(assembler.build_output()?, "entry")?;
+    assert_eq!(core.reg(3)?.get::<i64>(), -12);
+    Ok(())
+}
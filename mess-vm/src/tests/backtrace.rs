@@ -0,0 +1,97 @@
+use mess_core::{compiler::Compiler as CompilerTrait, exec::Executor, parser::Parser};
+
+use super::Result;
+use crate::{
+    codegen::asm::{assemble, assemble_file},
+    exec::core::CoreError,
+    Compiler,
+    Core,
+};
+
+const NESTED: &str = "main:
+    LDI 1, R1
+    CALL outer
+    RET
+outer:
+    CALL inner
+    RET
+inner:
+    NOOP
+    HALT 7
+";
+
+#[test]
+fn test_backtrace_with_lines() -> Result {
+    let output = assemble_file("nested.asm", NESTED)?;
+    let uid = output.function_name_map["main"];
+    let mut core = Core::new(1024);
+    core.load_program(output);
+    let err = core.run_fn(uid).err().ok_or("Expected an error")?;
+    assert!(matches!(err.get_inner(), CoreError::Halted(7)));
+
+    let backtrace = err.get_backtrace().ok_or("No backtrace")?;
+    let names: Vec<_> = backtrace
+        .frames
+        .iter()
+        .map(|frame| frame.fn_name.as_deref())
+        .collect();
+    assert_eq!(names, vec![Some("inner"), Some("outer"), Some("main")]);
+    let lines: Vec<_> = backtrace
+        .frames
+        .iter()
+        .map(|frame| frame.location.as_ref().map(|location| location.line))
+        .collect();
+    assert_eq!(lines, vec![Some(10), Some(6), Some(3)]);
+    assert!(backtrace.to_string().contains("inner at offset"));
+    assert!(backtrace.to_string().contains("(nested.asm:6)"));
+    Ok(())
+}
+
+#[test]
+fn test_backtrace_per_run() -> Result {
+    let output = assemble("main: CALL fail; RET\nfail: HALT 2\nok: RET\nalso_fail: HALT 3")?;
+    let fail = output.function_name_map["main"];
+    let ok = output.function_name_map["ok"];
+    let also_fail = output.function_name_map["also_fail"];
+    let mut core = Core::new(1024);
+    core.load_program(output);
+    let err = core.run_fn(fail).err().ok_or("Expected an error")?;
+    assert_eq!(err.get_backtrace().map(|bt| bt.frames.len()), Some(2));
+    core.run_fn(ok)?;
+    let err = core.run_fn(also_fail).err().ok_or("Expected an error")?;
+    assert_eq!(err.get_backtrace().map(|bt| bt.frames.len()), Some(1));
+    assert!(matches!(err.into_inner(), CoreError::Halted(3)));
+    Ok(())
+}
+
+#[test]
+fn test_backtrace_compiled_lines() -> Result {
+    let source = "fun main() {
+    var x = 1;
+    x = inner(x);
+}
+fun inner(a: int) ~ int {
+    var b = a + 1;
+}
+";
+    let decl_list = Parser::new(source).parse()?;
+    let mut compiler = Compiler::default().with_source_path("lines.mess");
+    compiler.compile(&decl_list)?;
+    let mut core = Core::new(1024);
+    core.load_program(compiler.get_output()?);
+    let err = Executor::run_fn(&mut core, "main").err().ok_or("Expected an error")?;
+    assert!(matches!(err.get_inner(), CoreError::NoReturnValue));
+    let backtrace = err.get_backtrace().ok_or("No backtrace")?;
+    let frames: Vec<_> = backtrace
+        .frames
+        .iter()
+        .map(|frame| {
+            let line = frame.location.as_ref().map(|location| location.line);
+            (frame.fn_name.as_deref(), line)
+        })
+        .collect();
+    // The missing return is reported at the last statement of the function
+    assert_eq!(frames, vec![(Some("root::inner"), Some(6)), (Some("root::main"), Some(3))]);
+    assert!(err.to_string().contains("(lines.mess:3)"));
+    Ok(())
+}
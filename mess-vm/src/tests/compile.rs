@@ -0,0 +1,186 @@
+use std::{error::Error, result::Result as StdResult};
+
+use mess_api::prelude::{Adapter, Function, Module, Type};
+use mess_core::{compiler::Compiler as CompilerTrait, exec::Executor, parser::Parser};
+
+use super::Result;
+use crate::{
+    codegen::output::Output,
+    exec::{
+        core::{CoreError, OverflowMode},
+        coroutine::CoroutineHandle,
+    },
+    Compiler,
+    Core,
+};
+
+fn double(adapter: &mut Adapter) {
+    let value: i64 = adapter.get_arg(0);
+    adapter.ret(value * 2);
+}
+
+fn halve(adapter: &mut Adapter) {
+    let value: f32 = adapter.get_arg(0);
+    adapter.ret(value / 2.0);
+}
+
+fn length(adapter: &mut Adapter) {
+    let text = adapter.get_str_arg(0).unwrap_or_default();
+    adapter.ret(text.len() as i64);
+}
+
+/// Compiles a script that can call `math::double`, `math::halve` and `math::length`
+fn compile(source: &str) -> StdResult<Output, Box<dyn Error>> {
+    let mut module = Module::new(String::from("math"));
+    module.add_function(Function::new("double", vec![Type::Int], Type::Int, double));
+    module.add_function(Function::new("halve", vec![Type::Float], Type::Float, halve));
+    module.add_function(Function::new(
+        "length",
+        vec![Type::Ref(Box::new(Type::Str))],
+        Type::Int,
+        length,
+    ));
+    let mut compiler = Compiler::default();
+    compiler.register_module(module)?;
+    let decl_list = Parser::new(source).parse()?;
+    compiler.compile(&decl_list)?;
+    Ok(compiler.get_output()?)
+}
+
+/// Runs a function of the compiled script, returning the core with the
+/// return value in `R0`
+fn run(output: Output, fn_name: &str) -> StdResult<Core, Box<dyn Error>> {
+    let mut core = Core::new(1024);
+    core.load_program(output);
+    Executor::run_fn(&mut core, fn_name)?;
+    Ok(core)
+}
+
+#[test]
+fn test_compile_host_call() -> Result {
+    let output = compile(include_str!("../../../test-scripts/host_call.mess"))?;
+    let mut core = run(output, "main")?;
+    assert_eq!(core.reg(0)?.get::<i64>(), 42);
+    Ok(())
+}
+
+#[test]
+fn test_compile_string_arg() -> Result {
+    let output = compile(include_str!("../../../test-scripts/string_arg.mess"))?;
+    let mut core = run(output, "main")?;
+    assert_eq!(core.reg(0)?.get::<i64>(), 11);
+    Ok(())
+}
+
+#[test]
+fn test_compile_script_calls() -> Result {
+    let output = compile(include_str!("../../../test-scripts/script_calls.mess"))?;
+    assert!(output.get_function_uid("root::sum").is_some());
+    let mut core = run(output, "main")?;
+    assert_eq!(core.reg(0)?.get::<i64>(), 1);
+    // Returning restores the stack pointer the run was entered with
+    assert_eq!(core.get_sp(), core.reg(18)?.get::<u64>());
+    Ok(())
+}
+
+#[test]
+fn test_compile_nested_calls() -> Result {
+    // Inner calls run while the arguments of the outer ones are put together
+    let output = compile(include_str!("../../../test-scripts/nested_calls.mess"))?;
+    let mut core = run(output, "main")?;
+    assert_eq!(core.reg(0)?.get::<i64>(), 18);
+    Ok(())
+}
+
+#[test]
+fn test_compile_sized_arith() -> Result {
+    let output = compile(include_str!("../../../test-scripts/sized_arith.mess"))?;
+    let mut core = run(output, "main")?;
+    assert_eq!(core.reg(0)?.get::<i64>(), -106);
+    Executor::run_fn(&mut core, "unsigned")?;
+    assert_eq!(core.reg(0)?.get::<i64>(), 3);
+    Executor::run_fn(&mut core, "signed")?;
+    assert_eq!(core.reg(0)?.get::<i64>(), -15);
+    Executor::run_fn(&mut core, "double")?;
+    assert_eq!(core.reg(0)?.get::<i64>(), 6);
+    // Overflowing sized arithmetic traps, while the stack and call
+    // arithmetic around it doesn't
+    core.set_overflow_mode(OverflowMode::Trap);
+    assert!(matches!(Executor::run_fn(&mut core, "main").map_err(CoreError::into_inner), Err(CoreError::IntegerOverflow)));
+    assert!(matches!(
+        Executor::run_fn(&mut core, "unsigned").map_err(CoreError::into_inner),
+        Err(CoreError::IntegerOverflow)
+    ));
+    Executor::run_fn(&mut core, "signed")?;
+    assert_eq!(core.reg(0)?.get::<i64>(), -15);
+    Ok(())
+}
+
+#[test]
+fn test_compile_coroutines() -> Result {
+    let output = compile(include_str!("../../../test-scripts/coroutines.mess"))?;
+    let mut core = run(output, "main")?;
+    assert_eq!(core.reg(0)?.get::<i64>(), 347);
+    // Coroutines resumed until they return are removed
+    assert_eq!(core.get_coroutine_state(CoroutineHandle(1)), None);
+    Executor::run_fn(&mut core, "early")?;
+    assert!(!core.reg(0)?.get::<bool>());
+    Ok(())
+}
+
+#[test]
+fn test_compile_statics() -> Result {
+    let output = compile(include_str!("../../../test-scripts/statics.mess"))?;
+    assert_eq!(output.get_static("root::total").map(|var| var.size), Some(8));
+    let mut core = run(output, "main")?;
+    assert_eq!(core.reg(0)?.get::<i64>(), 110);
+    // Statics keep their values between runs
+    Executor::run_fn(&mut core, "main")?;
+    assert_eq!(core.reg(0)?.get::<i64>(), 120);
+    Executor::run_fn(&mut core, "get_ready")?;
+    assert!(core.reg(0)?.get::<bool>());
+    Ok(())
+}
+
+#[test]
+fn test_compile_missing_return() -> Result {
+    let output = compile(include_str!("../../../test-scripts/missing_return.mess"))?;
+    let mut core = Core::new(1024);
+    core.load_program(output);
+    assert!(matches!(Executor::run_fn(&mut core, "main").map_err(CoreError::into_inner), Err(CoreError::NoReturnValue)));
+    Executor::run_fn(&mut core, "nothing")?;
+    Ok(())
+}
+
+#[test]
+fn test_compile_errors() {
+    let compile_err = |source: &str| compile(source).err().map(|err| err.to_string());
+    assert_eq!(
+        compile_err("fun main() ~ int { return y; }"),
+        Some(String::from("UnknownVariable(\"y\")"))
+    );
+    assert_eq!(
+        compile_err("fun main() ~ int { return math::double(1.0); }"),
+        Some(String::from("TypeMismatch(Int, Float)"))
+    );
+    assert_eq!(
+        compile_err("fun main() ~ int { return; }"),
+        Some(String::from("ExpectedReturnExpression"))
+    );
+    assert_eq!(
+        compile_err("fun main() ~ int { yield 1.0; return 1; }"),
+        Some(String::from("TypeMismatch(Int, Float)"))
+    );
+    assert_eq!(
+        compile_err("fun main() ~ int { var gen = spawn(1); return 1; }"),
+        Some(String::from("ExpectedSpawnCall"))
+    );
+    assert_eq!(
+        compile_err("static x: int = 1.0; fun main() ~ int { return x; }"),
+        Some(String::from("TypeMismatch(Int, Float)"))
+    );
+    assert_eq!(
+        compile_err("static x: int = 1; static x: int = 2; fun main() ~ int { return x; }"),
+        Some(String::from("DuplicateSymbol(\"root::x\")"))
+    );
+}
@@ -0,0 +1,104 @@
+use super::{run_fn, Result};
+use crate::codegen::asm::assemble;
+
+#[test]
+fn test_int_float_conversion() -> Result {
+    let output = assemble(
+        "main:
+        LDI -7, R1
+        ITOF R1, R1
+        LDI -1, R2
+        UTOF R2, R2
+        LDF -2.75, R3
+        FTOI R3, R3
+        LDF 2.75, R4
+        FTOU R4, R4
+        RET",
+    )?;
+    let mut core = run_fn(output, "main")?;
+    assert_eq!(core.reg(1)?.get::<f32>(), -7.0);
+    assert_eq!(core.reg(2)?.get::<f32>(), u64::MAX as f32);
+    // Floats are truncated toward zero
+    assert_eq!(core.reg(3)?.get::<i64>(), -2);
+    assert_eq!(core.reg(4)?.get::<u64>(), 2);
+    Ok(())
+}
+
+#[test]
+fn test_float_conversion_saturates() -> Result {
+    let output = assemble(
+        "main:
+        LDF 1e30, R1
+        FTOI R1, R1
+        LDF -1e30, R2
+        FTOI R2, R2
+        LDF -3.5, R3
+        FTOU R3, R3
+        LDI 2143289344, R4
+        FTOI R4, R4
+        LDI 2143289344, R5
+        FTOU R5, R5
+        RET",
+    )?;
+    let mut core = run_fn(output, "main")?;
+    assert_eq!(core.reg(1)?.get::<i64>(), i64::MAX);
+    assert_eq!(core.reg(2)?.get::<i64>(), i64::MIN);
+    assert_eq!(core.reg(3)?.get::<u64>(), 0);
+    // The bits of a quiet NaN convert to 0
+    assert_eq!(core.reg(4)?.get::<i64>(), 0);
+    assert_eq!(core.reg(5)?.get::<u64>(), 0);
+    Ok(())
+}
+
+#[test]
+fn test_integer_extension() -> Result {
+    let output = assemble(
+        "main:
+        LDI 384, R1
+        SEXT8 R1, R2
+        ZEXT8 R1, R3
+        LDI 98304, R1
+        SEXT16 R1, R4
+        ZEXT16 R1, R5
+        LDI 6442450944, R1
+        SEXT32 R1, R6
+        ZEXT32 R1, R7
+        RET",
+    )?;
+    let mut core = run_fn(output, "main")?;
+    // Only the low bits are kept, the rest is filled with the sign or zeros
+    assert_eq!(core.reg(2)?.get::<i64>(), -128);
+    assert_eq!(core.reg(3)?.get::<i64>(), 128);
+    assert_eq!(core.reg(4)?.get::<i64>(), -32768);
+    assert_eq!(core.reg(5)?.get::<i64>(), 32768);
+    assert_eq!(core.reg(6)?.get::<i64>(), -2147483648);
+    assert_eq!(core.reg(7)?.get::<i64>(), 2147483648);
+    Ok(())
+}
+
+#[test]
+fn test_double_conversion() -> Result {
+    let output = assemble(
+        "main:
+        LDI 9007199254740993, R1
+        ITOD R1, R1
+        LDI -1, R2
+        UTOD R2, R2
+        LDF 0.1, R3
+        FTOD R3, R4
+        DTOF R4, R4
+        LDF -2.5, R5
+        FTOD R5, R5
+        DTOI R5, R5
+        FTOD R3, R6
+        DTOU R6, R6
+        RET",
+    )?;
+    let mut core = run_fn(output, "main")?;
+    assert_eq!(core.reg(1)?.get::<f64>(), 9007199254740993i64 as f64);
+    assert_eq!(core.reg(2)?.get::<f64>(), u64::MAX as f64);
+    assert_eq!(core.reg(4)?.get::<f32>(), 0.1);
+    assert_eq!(core.reg(5)?.get::<i64>(), -2);
+    assert_eq!(core.reg(6)?.get::<u64>(), 0);
+    Ok(())
+}
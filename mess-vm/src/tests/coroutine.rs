@@ -0,0 +1,166 @@
+use std::{error::Error, result::Result as StdResult};
+
+use super::{run_fn, Result};
+use crate::{
+    codegen::asm::assemble,
+    exec::{
+        core::CoreError,
+        coroutine::{CoroutineState, Resumed},
+        limits::Limits,
+    },
+    Core,
+};
+
+/// Yields 1 up to the limit in R2, then returns 100
+const COUNTER: &str = "
+counter:
+    LDI 0, R1
+.loop:
+    ADDI_I R1, 1, R1
+    MOVI R1, R0
+    YIELD
+    LTI R1, R2, R3
+    JMPT R3, .loop
+    LDI 100, R0
+    RET
+
+main:
+    LDI 3, R2
+    SPAWN counter, 0, R4
+    LDI 0, R5
+.loop:
+    RESUME R4, R6, R7
+    JMPT R7, .done
+    ADDI R5, R6, R5
+    JMP .loop
+.done:
+    RET
+";
+
+fn counter_core(limit: i64) -> StdResult<(Core, u64), Box<dyn Error>> {
+    let output = assemble(COUNTER)?;
+    let uid = output.function_name_map["counter"];
+    let mut core = Core::new(1024);
+    core.load_program(output);
+    core.reg(2)?.set(limit);
+    Ok((core, uid))
+}
+
+#[test]
+fn test_resume_from_script() -> Result {
+    let mut core = run_fn(assemble(COUNTER)?, "main")?;
+    assert_eq!(core.reg(5)?.get::<i64>(), 6);
+    assert_eq!(core.reg(6)?.get::<i64>(), 100);
+    // The coroutine counted in its own registers
+    assert_eq!(core.reg(1)?.get::<i64>(), 0);
+    Ok(())
+}
+
+#[test]
+fn test_iterate_from_host() -> Result {
+    let (mut core, uid) = counter_core(4)?;
+    let handle = core.spawn_coroutine(uid)?;
+    let values = core
+        .iter_coroutine(handle)
+        .map(|value| value.map(|value| value.get::<i64>()))
+        .collect::<StdResult<Vec<_>, _>>()?;
+    assert_eq!(values, vec![1, 2, 3, 4]);
+    assert_eq!(
+        core.get_coroutine_state(handle),
+        Some(CoroutineState::Finished)
+    );
+    assert!(matches!(
+        core.resume_coroutine(handle),
+        Err(CoreError::CoroutineNotResumable(_))
+    ));
+    assert!(core.drop_coroutine(handle));
+    assert!(matches!(
+        core.resume_coroutine(handle),
+        Err(CoreError::UnknownCoroutine(_))
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_resume_returns_value() -> Result {
+    let (mut core, uid) = counter_core(1)?;
+    let handle = core.spawn_coroutine(uid)?;
+    assert!(
+        matches!(core.resume_coroutine(handle)?, Resumed::Yielded(value) if value.get::<i64>() == 1)
+    );
+    assert!(
+        matches!(core.resume_coroutine(handle)?, Resumed::Returned(value) if value.get::<i64>() == 100)
+    );
+    Ok(())
+}
+
+#[test]
+fn test_yield_from_nested_call() -> Result {
+    let source = "
+    emit:
+        MOVI R1, R0
+        YIELD
+        RET
+    pair:
+        LDI 7, R1
+        CALL emit
+        LDI 8, R1
+        CALL emit
+        RET
+    ";
+    let output = assemble(source)?;
+    let uid = output.function_name_map["pair"];
+    let mut core = Core::new(1024);
+    core.load_program(output);
+    let handle = core.spawn_coroutine(uid)?;
+    let values = core
+        .iter_coroutine(handle)
+        .map(|value| value.map(|value| value.get::<i64>()))
+        .collect::<StdResult<Vec<_>, _>>()?;
+    assert_eq!(values, vec![7, 8]);
+    Ok(())
+}
+
+#[test]
+fn test_resume_after_out_of_fuel() -> Result {
+    let output = assemble(COUNTER)?;
+    let uid = output.function_name_map["main"];
+    let mut core = Core::new(1024);
+    core.load_program(output);
+    core.set_fuel(Some(5));
+    let mut result = core.run_fn(uid);
+    while let Err(CoreError::OutOfFuel) = result {
+        core.add_fuel(5);
+        result = core.resume();
+    }
+    result?;
+    assert_eq!(core.reg(5)?.get::<i64>(), 6);
+    Ok(())
+}
+
+#[test]
+fn test_yield_outside_coroutine() -> Result {
+    let output = assemble("main: YIELD; RET")?;
+    let mut core = Core::new(1024);
+    core.load_program(output);
+    assert!(matches!(core.run().map_err(CoreError::into_inner), Err(CoreError::YieldOutsideCoroutine)));
+    Ok(())
+}
+
+#[test]
+fn test_coroutine_limit() -> Result {
+    let (core, uid) = counter_core(1)?;
+    let mut core = core.with_limits(Limits::default().with_max_coroutines(1));
+    let handle = core.spawn_coroutine(uid)?;
+    assert!(matches!(
+        core.spawn_coroutine(uid),
+        Err(CoreError::CoroutineLimit)
+    ));
+    // Finished coroutines no longer count
+    core.iter_coroutine(handle).for_each(drop);
+    let handle = core.spawn_coroutine(uid)?;
+    // Neither do dropped ones
+    assert!(core.drop_coroutine(handle));
+    core.spawn_coroutine(uid)?;
+    Ok(())
+}
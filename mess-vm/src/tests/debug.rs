@@ -0,0 +1,150 @@
+use super::Result;
+use crate::{
+    codegen::asm::assemble_file,
+    exec::{
+        core::CoreError,
+        debug::{DebugValue, StopReason},
+    },
+    Core,
+};
+
+const PROGRAM: &str = "main:
+    .local x, int, 0
+    .local scale, float, 8
+    ADDU_I SP, 12, SP
+    LDI 5, R1
+    MOVI_RA R1, [SP - 12]
+    LDF 1.5, R2
+    MOVF_RA R2, [SP - 4]
+    CALL double
+    CALL double
+    RET
+double:
+    ADDI R1, R1, R1
+    RET
+";
+
+fn load() -> std::result::Result<Core, Box<dyn std::error::Error>> {
+    let output = assemble_file("debug.asm", PROGRAM)?;
+    let offset = output.functions[&output.function_name_map["main"]];
+    let mut core = Core::new(1024);
+    core.load_program(output);
+    core.enter_at(offset);
+    Ok(core)
+}
+
+#[test]
+fn test_line_breakpoint_and_locals() -> Result {
+    let mut core = load()?;
+    let offsets = core.add_line_breakpoint("debug.asm", 9)?;
+    assert_eq!(offsets.len(), 1);
+    assert_eq!(core.continue_execution()?, StopReason::Breakpoint(offsets[0]));
+    assert_eq!(core.get_ip(), offsets[0]);
+
+    let locals = core.get_locals(0)?;
+    let values: Vec<_> = locals
+        .iter()
+        .map(|(local, value)| (local.name.as_str(), value.clone()))
+        .collect();
+    assert_eq!(
+        values,
+        vec![("x", DebugValue::Int(5)), ("scale", DebugValue::Float(1.5))]
+    );
+
+    // Resuming does not stop at the same breakpoint again
+    assert_eq!(core.continue_execution()?, StopReason::Finished);
+    assert_eq!(core.get_registers()[1].get::<i64>(), 20);
+    assert!(matches!(
+        core.add_line_breakpoint("debug.asm", 2),
+        Err(CoreError::NoCodeAtLine(_, 2))
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_stepping() -> Result {
+    let mut core = load()?;
+    let offsets = core.add_line_breakpoint("debug.asm", 9)?;
+    core.continue_execution()?;
+
+    // Step into the first call
+    assert_eq!(core.step()?, StopReason::Step);
+    let frames = core.backtrace().frames;
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[0].fn_name.as_deref(), Some("double"));
+    assert_eq!(frames[0].location.as_ref().map(|l| l.line), Some(13));
+    assert_eq!(core.get_locals(0)?, vec![]);
+    assert_eq!(core.get_locals(1)?.len(), 2);
+    assert!(matches!(core.get_locals(2), Err(CoreError::InvalidFrame(2))));
+
+    // Step out of it, back behind the call
+    assert_eq!(core.step_out()?, StopReason::Step);
+    assert_eq!(core.backtrace().frames.len(), 1);
+    assert_eq!(core.get_registers()[1].get::<i64>(), 10);
+
+    // Step over the second call
+    assert_eq!(core.step_over()?, StopReason::Step);
+    assert_eq!(core.backtrace().frames[0].location.as_ref().map(|l| l.line), Some(11));
+    assert_eq!(core.get_registers()[1].get::<i64>(), 20);
+
+    assert!(core.remove_breakpoint(offsets[0]));
+    assert_eq!(core.step()?, StopReason::Finished);
+    Ok(())
+}
+
+#[test]
+fn test_breakpoint_stops_plain_run() -> Result {
+    let mut core = load()?;
+    let offset = core.add_line_breakpoint("debug.asm", 13)?[0];
+    assert!(matches!(core.resume(), Err(CoreError::Breakpoint(o)) if o == offset));
+    assert!(matches!(core.resume(), Err(CoreError::Breakpoint(o)) if o == offset));
+    core.clear_breakpoints();
+    core.resume()?;
+    assert_eq!(core.get_registers()[1].get::<i64>(), 20);
+    Ok(())
+}
+
+#[test]
+fn test_container_locals() -> Result {
+    let source = "
+    .container Point, x: i32, y: bool
+    .container Line, from: Point, to: Point
+    main:
+        .local line, Line, 0
+        ADDU_I SP, 10, SP
+        LDI 3, R1
+        MOVN_RA R1, [SP - 10], 4
+        LDB true, R2
+        MOVB_RA R2, [SP - 6]
+        LDI -4, R1
+        MOVN_RA R1, [SP - 5], 4
+        NOOP
+        RET
+    ";
+    let output = assemble_file("points.asm", source)?;
+    assert_eq!(output.debug_containers["Line"][1].offset, 5);
+    let offset = output.functions[&output.function_name_map["main"]];
+    let mut core = Core::new(1024);
+    core.load_program(output);
+    core.enter_at(offset);
+    let offsets = core.add_line_breakpoint("points.asm", 13)?;
+    assert_eq!(core.continue_execution()?, StopReason::Breakpoint(offsets[0]));
+
+    let locals = core.get_locals(0)?;
+    let point = |x, y| DebugValue::Fields(vec![
+        (String::from("x"), DebugValue::Int(x)),
+        (String::from("y"), DebugValue::Bool(y)),
+    ]);
+    assert_eq!(
+        locals[0].1,
+        DebugValue::Fields(vec![
+            (String::from("from"), point(3, true)),
+            (String::from("to"), point(-4, false)),
+        ])
+    );
+    assert_eq!(
+        locals[0].1.to_string(),
+        "{ from: { x: 3, y: true }, to: { x: -4, y: false } }"
+    );
+    Ok(())
+}
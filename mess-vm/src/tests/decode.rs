@@ -0,0 +1,97 @@
+use num_traits::FromPrimitive;
+
+use super::Result;
+use crate::{
+    codegen::{asm::assemble, output::Output},
+    exec::{
+        core::CoreError,
+        decode::{
+            DecodeCache,
+            DecodedInstr,
+            MAX_INSTR_SIZE,
+        },
+        is::Opcode,
+    },
+    Core,
+};
+
+#[test]
+fn test_decode_operands() -> Result {
+    let output = assemble("main: MOVI_A [SP - 8], [R2 + 300]; LDF -1.5, R1; LDB true, R3")?;
+    let code = &output.code;
+
+    let instr = DecodedInstr::decode(code, 0)?;
+    assert_eq!(instr.opcode, Opcode::MOVI_A);
+    assert_eq!(instr.size, Opcode::MOVI_A.instruction_size());
+    assert_eq!(instr.operands[..4], [16, (-8i16) as u16 as u64, 2, 300]);
+
+    let instr = DecodedInstr::decode(code, 7)?;
+    assert_eq!(instr.opcode, Opcode::LDF);
+    assert_eq!(f32::from_bits(instr.operands[0] as u32), -1.5);
+    assert_eq!(instr.operands[1], 1);
+
+    let instr = DecodedInstr::decode(code, 13)?;
+    assert_eq!(instr.opcode, Opcode::LDB);
+    assert_eq!(instr.operands[..2], [1, 3]);
+    Ok(())
+}
+
+#[test]
+fn test_decode_errors() -> Result {
+    let mut core = Core::new(1024);
+    core.load_program(Output::new().with_code(vec![255]));
+    assert!(matches!(core.run_at(0).map_err(CoreError::into_inner), Err(CoreError::InvalidOpcode(255))));
+
+    // A truncated LDI
+    let mut core = Core::new(1024);
+    core.load_program(Output::new().with_code(vec![Opcode::LDI as u8, 1, 2]));
+    assert!(matches!(core.run_at(0).map_err(CoreError::into_inner), Err(CoreError::OperatorDeserialize)));
+    Ok(())
+}
+
+#[test]
+fn test_max_instr_size() {
+    let longest = (0..=u8::MAX)
+        .filter_map(Opcode::from_u8)
+        .map(|opcode| opcode.instruction_size())
+        .max();
+    assert_eq!(longest, Some(MAX_INSTR_SIZE));
+}
+
+#[test]
+fn test_invalidate_written_range() -> Result {
+    let output = assemble("main: LDI 1, R1; LDI 2, R2; RET")?;
+    let mut code = output.code.clone();
+    let second = Opcode::LDI.instruction_size();
+    let mut cache = DecodeCache::default();
+    cache.get(&code, 0)?;
+    cache.get(&code, second)?;
+
+    // Both immediates change, but only the first one's bytes are invalidated
+    code[1] = 7;
+    code[second + 1] = 9;
+    cache.invalidate(1..2);
+    assert_eq!(cache.get(&code, 0)?.operands[0], 7);
+    assert_eq!(cache.get(&code, second)?.operands[0], 2);
+
+    // A write ending inside an instruction invalidates it too
+    cache.invalidate(second - 1..second + 2);
+    assert_eq!(cache.get(&code, second)?.operands[0], 9);
+    Ok(())
+}
+
+#[test]
+fn test_code_writes_invalidate_decoded() -> Result {
+    let output = assemble("main: LDI 1, R1; RET")?;
+    let uid = output.function_name_map["main"];
+    let mut core = Core::new(1024);
+    core.load_program(output);
+    core.run_fn(uid)?;
+    assert_eq!(core.reg(1)?.get::<i64>(), 1);
+
+    // Patch the immediate of the already decoded LDI
+    core.mem_set::<i64>((0, 1), 99)?;
+    core.run_fn(uid)?;
+    assert_eq!(core.reg(1)?.get::<i64>(), 99);
+    Ok(())
+}
@@ -0,0 +1,74 @@
+use std::{thread, time::Duration};
+
+use super::Result;
+use crate::{codegen::asm::assemble, exec::core::CoreError, Core};
+
+const COUNTDOWN: &str = "
+main:
+    LDI 100, R1
+.loop:
+    SUBI_I R1, 1, R1
+    GTI R1, R0, R2
+    JMPT R2, .loop
+    RET
+";
+
+const ENDLESS: &str = "
+main:
+.loop:
+    JMP .loop
+";
+
+#[test]
+fn test_out_of_fuel_resume() -> Result {
+    let output = assemble(COUNTDOWN)?;
+    let uid = output.function_name_map["main"];
+    let mut core = Core::new(1024);
+    core.load_program(output);
+    core.set_fuel(Some(50));
+
+    assert!(matches!(core.run_fn(uid), Err(CoreError::OutOfFuel)));
+    assert_eq!(core.get_fuel(), Some(0));
+    let remaining: i64 = core.reg(1)?.get();
+    assert!(remaining > 0);
+
+    core.add_fuel(1000);
+    core.resume()?;
+    assert_eq!(core.reg(1)?.get::<i64>(), 0);
+    // 1 load, 3 instructions per iteration, 1 return
+    assert_eq!(core.get_fuel(), Some(1050 - 302));
+    Ok(())
+}
+
+#[test]
+fn test_unlimited_fuel() -> Result {
+    let output = assemble(COUNTDOWN)?;
+    let uid = output.function_name_map["main"];
+    let mut core = Core::new(1024);
+    core.load_program(output);
+    core.run_fn(uid)?;
+    assert_eq!(core.get_fuel(), None);
+    Ok(())
+}
+
+#[test]
+fn test_interrupt_from_thread() -> Result {
+    let output = assemble(ENDLESS)?;
+    let uid = output.function_name_map["main"];
+    let mut core = Core::new(1024);
+    core.load_program(output);
+
+    let handle = core.interrupt_handle();
+    let interrupter = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(20));
+        handle.interrupt();
+    });
+    assert!(matches!(core.run_fn(uid), Err(CoreError::Interrupted)));
+    interrupter.join().unwrap();
+
+    // The interrupt is consumed, so resuming continues until the fuel runs out
+    assert!(!core.interrupt_handle().is_interrupted());
+    core.set_fuel(Some(10));
+    assert!(matches!(core.resume(), Err(CoreError::OutOfFuel)));
+    Ok(())
+}
@@ -0,0 +1,116 @@
+use std::{
+    error::Error,
+    future::Future,
+    pin::{pin, Pin},
+    result::Result as StdResult,
+    task::{Context, Poll, Waker},
+};
+
+use mess_api::{
+    function::{Completion, HostFuture},
+    prelude::{Adapter, Function, Type},
+};
+
+use super::Result;
+use crate::{
+    codegen::asm::assemble,
+    exec::core::{CoreError, CoreResult},
+    Core,
+};
+
+const FETCH_UID: u64 = 777;
+
+/// A mock of a network request, completing with 41 after a number of polls
+struct Delayed {
+    polls: usize,
+}
+
+impl Future for Delayed {
+    type Output = Completion;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.polls == 0 {
+            return Poll::Ready(Box::new(|adapter: &mut Adapter| adapter.ret(41i64)));
+        }
+        self.polls -= 1;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+fn fetch(_: &mut Adapter) -> HostFuture {
+    Box::pin(Delayed { polls: 2 })
+}
+
+/// Polls the future to completion on this thread, returning the number of polls
+fn block_on<F: Future>(future: F) -> (F::Output, usize) {
+    let mut future = pin!(future);
+    let mut cx = Context::from_waker(Waker::noop());
+    let mut polls = 1;
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return (output, polls),
+            Poll::Pending => polls += 1,
+        }
+    }
+}
+
+fn load(source: &str) -> StdResult<(Core, u64), Box<dyn Error>> {
+    let mut output = assemble(source)?;
+    output.foreign_functions.insert(
+        FETCH_UID,
+        Function::new_async("fetch", vec![], Type::Int, fetch),
+    );
+    let uid = output.function_name_map["main"];
+    let mut core = Core::new(1024);
+    core.load_program(output);
+    Ok((core, uid))
+}
+
+#[test]
+fn test_run_async() -> Result {
+    let (mut core, uid) = load("main: CALL 777; ADDI_I R0, 1, R0; RET")?;
+    let (result, polls) = block_on(core.run_fn_async(uid)?);
+    result?;
+    assert_eq!(polls, 3);
+    assert_eq!(core.reg(0)?.get::<i64>(), 42);
+    assert!(!core.is_pending());
+    Ok(())
+}
+
+#[test]
+fn test_suspend_sync_run() -> Result {
+    let (mut core, uid) = load("main: CALL 777; MOVI R0, R1; CALL 777; ADDI R0, R1, R0; RET")?;
+    assert!(matches!(core.run_fn(uid), Err(CoreError::Pending)));
+    assert!(core.is_pending());
+    // Resuming without the result keeps waiting for it
+    assert!(matches!(core.resume(), Err(CoreError::Pending)));
+    assert!(matches!(core.snapshot(), Err(CoreError::Pending)));
+
+    let (result, _) = block_on(core.resume_async());
+    result?;
+    assert_eq!(core.reg(0)?.get::<i64>(), 82);
+    Ok(())
+}
+
+#[test]
+fn test_poll_resume() -> Result {
+    let (mut core, uid) = load("main: CALL 777; RET")?;
+    assert!(matches!(core.run_fn(uid), Err(CoreError::Pending)));
+    let mut cx = Context::from_waker(Waker::noop());
+    assert!(core.poll_resume(&mut cx).is_pending());
+    assert!(core.poll_resume(&mut cx).is_pending());
+    assert!(matches!(core.poll_resume(&mut cx), Poll::Ready(Ok(()))));
+    assert_eq!(core.reg(0)?.get::<i64>(), 41);
+    Ok(())
+}
+
+#[test]
+fn test_async_call_in_coroutine() -> Result {
+    let (mut core, _) = load("main: RET; gen: CALL 777; YIELD; RET")?;
+    let uid = core.get_program().ok_or("No program")?.function_name_map["gen"];
+    let handle = core.spawn_coroutine(uid)?;
+    let result: CoreResult<_> = core.resume_coroutine(handle).map_err(CoreError::into_inner);
+    assert!(matches!(result, Err(CoreError::AsyncCallInCoroutine)));
+    Ok(())
+}
@@ -0,0 +1,103 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+    Mutex,
+};
+
+use super::Result;
+use crate::{
+    exec::{core::CoreError, handle::ForeignLeak},
+    Core,
+};
+
+/// Sets a flag when dropped
+struct Tracked(Arc<AtomicBool>);
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+#[test]
+fn test_foreign_type_mismatch() -> Result {
+    let mut core = Core::new(1024);
+    let ptr = core.insert_foreign_ptr(Arc::new(Mutex::new(5i64)))?;
+    match core.get_foreign_ptr::<String>(ptr) {
+        Err(CoreError::ForeignTypeMismatch { expected, found, .. }) => {
+            assert_eq!(expected, "alloc::string::String");
+            assert_eq!(found, "i64");
+        }
+        _ => return Err("Expected a type mismatch".into()),
+    };
+    assert!(matches!(
+        core.remove_foreign_ptr::<u8>(ptr),
+        Err(CoreError::ForeignTypeMismatch { .. })
+    ));
+    assert_eq!(*core.get_foreign_ptr::<i64>(ptr)?.lock().unwrap(), 5);
+    Ok(())
+}
+
+#[test]
+fn test_stale_foreign_handle() -> Result {
+    let mut core = Core::new(1024);
+    let old = core.insert_foreign_ptr(Arc::new(Mutex::new(1u8)))?;
+    core.remove_foreign_ptr::<u8>(old)?;
+    // The slot is reused with a new generation
+    let new = core.insert_foreign_ptr(Arc::new(Mutex::new(2u8)))?;
+    assert_ne!(old, new);
+    assert!(matches!(core.get_foreign_ptr::<u8>(old), Err(CoreError::InvalidHandle(_))));
+    assert!(matches!(core.release_foreign_ptr(old), Err(CoreError::InvalidHandle(_))));
+    assert_eq!(*core.get_foreign_ptr::<u8>(new)?.lock().unwrap(), 2);
+    // Pointers into other memory are no handles
+    assert!(matches!(core.get_foreign_ptr::<u8>(8), Err(CoreError::InvalidHandle(8))));
+    Ok(())
+}
+
+#[test]
+fn test_script_refcount() -> Result {
+    let dropped = Arc::new(AtomicBool::new(false));
+    let host = Arc::new(Mutex::new(Tracked(dropped.clone())));
+    let mut core = Core::new(1024);
+    let ptr = core.insert_foreign_ptr(host.clone())?;
+    assert_eq!(core.retain_foreign_ptr(ptr)?, 2);
+    assert_eq!(core.release_foreign_ptr(ptr)?, 1);
+    assert_eq!(core.release_foreign_ptr(ptr)?, 0);
+
+    // The script dropped its last reference, the host still holds one
+    assert!(matches!(core.get_foreign_refs(ptr), Err(CoreError::InvalidHandle(_))));
+    assert!(!dropped.load(Ordering::SeqCst));
+    assert_eq!(Arc::strong_count(&host), 1);
+    drop(host);
+    assert!(dropped.load(Ordering::SeqCst));
+
+    // Releasing again is an error instead of a double free
+    assert!(matches!(core.release_foreign_ptr(ptr), Err(CoreError::InvalidHandle(_))));
+    Ok(())
+}
+
+#[test]
+fn test_foreign_leaks() -> Result {
+    let leaks: Arc<Mutex<Vec<ForeignLeak>>> = Arc::default();
+    let mut core = Core::new(1024);
+    let reported = leaks.clone();
+    core.set_leak_handler(move |leaks| reported.lock().unwrap().extend_from_slice(leaks));
+    let host = Arc::new(Mutex::new(3i32));
+    let ptr = core.insert_foreign_ptr(host.clone())?;
+    let released = core.insert_foreign_ptr(Arc::new(Mutex::new(4i32)))?;
+    core.release_foreign_ptr(released)?;
+    core.retain_foreign_ptr(ptr)?;
+    drop(core);
+
+    let leaks = leaks.lock().unwrap();
+    assert_eq!(
+        *leaks,
+        vec![ForeignLeak {
+            ptr,
+            type_name: "i32",
+            script_refs: 2,
+            host_refs: 1,
+        }]
+    );
+    Ok(())
+}
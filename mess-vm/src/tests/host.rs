@@ -0,0 +1,206 @@
+use std::{error::Error, result::Result as StdResult};
+
+use mess_api::prelude::{Adapter, Function, Module, Type, Value};
+use mess_core::compiler::Compiler as CompilerTrait;
+
+use super::Result;
+use crate::{
+    adapter::get_size_of_type,
+    codegen::asm::parse_file,
+    exec::core::CoreError,
+    Compiler,
+    Core,
+};
+
+struct Counter(i64);
+
+struct Flag;
+
+fn sub(adapter: &mut Adapter) {
+    let lhs: i64 = adapter.get_arg(0);
+    let rhs: i64 = adapter.get_arg(1);
+    adapter.ret(lhs - rhs);
+}
+
+fn scale(adapter: &mut Adapter) {
+    let value: f32 = adapter.get_arg(0);
+    let double: bool = adapter.get_arg(1);
+    adapter.ret(if double { value * 2.0 } else { value });
+}
+
+fn counter_new(adapter: &mut Adapter) {
+    adapter.ret_foreign_object(Counter(0));
+}
+
+fn flag_new(adapter: &mut Adapter) {
+    adapter.ret_foreign_object(Flag);
+}
+
+fn counter_inc(adapter: &mut Adapter) {
+    // Failing to get the counter fails the call
+    let Ok(counter) = adapter.get_foreign_arg::<Counter>(0) else {
+        return;
+    };
+    let mut counter = counter.lock().unwrap();
+    counter.0 += 1;
+    adapter.ret(counter.0);
+}
+
+/// Assembles the source with the host functions callable by name
+fn load(source: &str) -> StdResult<(Core, u64), Box<dyn Error>> {
+    let mut assembler = parse_file("<asm>", source)?;
+    let functions = vec![
+        Function::new("sub", vec![Type::Int, Type::Int], Type::Int, sub),
+        Function::new("scale", vec![Type::Float, Type::Bool], Type::Float, scale),
+        Function::new("counter_new", vec![], Type::Named(String::from("Counter")), counter_new),
+        Function::new(
+            "counter_inc",
+            vec![Type::Named(String::from("Counter"))],
+            Type::Int,
+            counter_inc,
+        ),
+        Function::new("flag_new", vec![], Type::Named(String::from("Flag")), flag_new),
+    ];
+    for (uid, function) in functions.into_iter().enumerate() {
+        assembler.push_foreign_fn(function.name.clone(), 1000 + uid as u64, function);
+    }
+    let output = assembler.build_output()?;
+    let uid = output.function_name_map["main"];
+    let mut core = Core::new(1024);
+    core.load_program(output);
+    Ok((core, uid))
+}
+
+#[test]
+fn test_host_fn_args() -> Result {
+    let (mut core, uid) = load(
+        "main:
+        ADDU_I SP, 16, SP
+        LDI 50, R1
+        MOVI_RA R1, [SP - 16]
+        LDI 8, R1
+        MOVI_RA R1, [SP - 8]
+        CALL sub
+        RET",
+    )?;
+    core.run_fn(uid)?;
+    assert_eq!(core.reg(0)?.get::<i64>(), 42);
+    Ok(())
+}
+
+#[test]
+fn test_host_fn_small_args() -> Result {
+    // The float is followed by the bool, so arguments are not aligned
+    let (mut core, uid) = load(
+        "main:
+        ADDU_I SP, 5, SP
+        LDI -1, R0
+        LDF 1.25, R1
+        MOVF_RA R1, [SP - 5]
+        LDB true, R2
+        MOVB_RA R2, [SP - 1]
+        CALL scale
+        RET",
+    )?;
+    core.run_fn(uid)?;
+    assert_eq!(core.reg(0)?.get::<f32>(), 2.5);
+    // Shorter return values clear the rest of the register
+    assert_eq!(core.reg(0)?.get::<u64>() >> 32, 0);
+    Ok(())
+}
+
+#[test]
+fn test_host_foreign_objects() -> Result {
+    let (mut core, uid) = load(
+        "main:
+        CALL counter_new
+        ADDU_I SP, 8, SP
+        MOVA_RA R0, [SP - 8]
+        CALL counter_inc
+        CALL counter_inc
+        MOVI R0, R1
+        MOVA_AR [SP - 8], R0
+        RET",
+    )?;
+    core.run_fn(uid)?;
+    assert_eq!(core.reg(1)?.get::<i64>(), 2);
+    let ptr = core.reg(0)?.get::<u64>();
+    assert_eq!(core.get_foreign_ptr::<Counter>(ptr)?.lock().unwrap().0, 2);
+    Ok(())
+}
+
+#[test]
+fn test_host_foreign_object_errors() -> Result {
+    let (mut core, uid) = load(
+        "main:
+        CALL flag_new
+        ADDU_I SP, 8, SP
+        MOVA_RA R0, [SP - 8]
+        CALL counter_inc
+        RET",
+    )?;
+    match core.run_fn(uid).map_err(CoreError::into_inner) {
+        Err(CoreError::ForeignTypeMismatch { expected, found, .. }) => {
+            assert!(expected.ends_with("Counter"));
+            assert!(found.ends_with("Flag"));
+        }
+        _ => return Err("Expected a type mismatch".into()),
+    };
+
+    let (mut core, uid) = load(
+        "main:
+        ADDU_I SP, 8, SP
+        LDI 8, R1
+        MOVI_RA R1, [SP - 8]
+        CALL counter_inc
+        RET",
+    )?;
+    assert!(matches!(core.run_fn(uid).map_err(CoreError::into_inner), Err(CoreError::InvalidHandle(8))));
+    Ok(())
+}
+
+#[test]
+fn test_host_fn_invalid_args() -> Result {
+    // Nothing was pushed, so the arguments would be below the stack
+    let (mut core, uid) = load("main: CALL sub; RET")?;
+    assert!(matches!(core.run_fn(uid).map_err(CoreError::into_inner), Err(CoreError::InvalidAddress(_))));
+    Ok(())
+}
+
+#[test]
+fn test_register_module() -> Result {
+    let mut module = Module::new(String::from("math"));
+    module.add_function(Function::new(
+        "scale",
+        vec![Type::Float, Type::Bool],
+        Type::Float,
+        scale,
+    ));
+    let mut compiler = Compiler::default();
+    compiler.register_module(module)?;
+    let output = compiler.get_output()?;
+    let function = output.foreign_functions.values().next().ok_or("Not registered")?;
+    assert_eq!(function.get_arg_offset(0), -5);
+    assert_eq!(function.get_arg_offset(1), -1);
+    Ok(())
+}
+
+#[test]
+fn test_value_sizes() -> Result {
+    // Host values have the size scripts pass them with
+    fn check<T: Value>() {
+        assert_eq!(T::size(), get_size_of_type(&T::var_type()), "{:?}", T::var_type());
+    }
+    check::<i8>();
+    check::<i16>();
+    check::<i32>();
+    check::<i64>();
+    check::<u8>();
+    check::<u16>();
+    check::<u32>();
+    check::<u64>();
+    check::<f32>();
+    check::<f64>();
+    check::<bool>();
+    Ok(())
+}
@@ -0,0 +1,99 @@
+use std::sync::{Arc, Mutex};
+
+use super::Result;
+use crate::{
+    codegen::asm::assemble,
+    exec::{core::CoreError, limits::Limits},
+    Core,
+};
+
+#[test]
+fn test_call_depth_limit() -> Result {
+    let output = assemble("recurse: CALL recurse; RET")?;
+    let uid = output.function_name_map["recurse"];
+    let mut core = Core::new(1024).with_limits(Limits::default().with_max_call_depth(16));
+    core.load_program(output);
+    assert!(matches!(core.run_fn(uid).map_err(CoreError::into_inner), Err(CoreError::CallStackOverflow)));
+    Ok(())
+}
+
+#[test]
+fn test_stack_limit() -> Result {
+    let limits = Limits::default().with_max_stack_size(4096);
+
+    let output = assemble("grow: ADDU_I SP, 2048, SP; RET")?;
+    let uid = output.function_name_map["grow"];
+    let mut core = Core::new(1024).with_limits(limits.clone());
+    core.load_program(output);
+    core.run_fn(uid)?;
+
+    let output = assemble("overflow: ADDU_I SP, 8192, SP; RET")?;
+    let uid = output.function_name_map["overflow"];
+    let mut core = Core::new(1024).with_limits(limits);
+    core.load_program(output);
+    assert!(matches!(core.run_fn(uid).map_err(CoreError::into_inner), Err(CoreError::StackOverflow)));
+    Ok(())
+}
+
+#[test]
+fn test_heap_limit() -> Result {
+    let output = assemble(
+        "
+main:
+    LDI 64, R1
+    ALLOC R1, R2
+    LDI 42, R3
+    MOVI_RA R3, [R2 + 8]
+    MOVI_AR [R2 + 8], R4
+    FREE R2
+    LDI 512, R1
+    ALLOC R1, R2
+    RET
+",
+    )?;
+    let uid = output.function_name_map["main"];
+    let mut core = Core::new(1024).with_limits(Limits::default().with_max_heap_size(256));
+    core.load_program(output);
+    assert!(matches!(core.run_fn(uid).map_err(CoreError::into_inner), Err(CoreError::OutOfMemory)));
+    assert_eq!(core.reg(4)?.get::<i64>(), 42);
+    assert_eq!(core.get_heap_size(), 0);
+    Ok(())
+}
+
+#[test]
+fn test_heap_reuse() -> Result {
+    let mut core = Core::new(1024).with_limits(Limits::default().with_max_heap_size(32));
+    let first = core.alloc(16)?;
+    let second = core.alloc(16)?;
+    assert!(matches!(core.alloc(1), Err(CoreError::OutOfMemory)));
+    core.free(first)?;
+    assert_eq!(core.alloc(8)?, first);
+    assert!(matches!(core.free(first + 1), Err(CoreError::InvalidAddress(_))));
+    core.free(second)?;
+    Ok(())
+}
+
+#[test]
+fn test_heap_limit_counts_live_bytes() -> Result {
+    let mut core = Core::new(1024).with_limits(Limits::default().with_max_heap_size(32));
+    let first = core.alloc(8)?;
+    core.alloc(16)?;
+    core.free(first)?;
+    // The freed gap is too small, so this ends past the limit with 32 live bytes
+    core.alloc(16)?;
+    assert_eq!(core.get_heap_size(), 32);
+    assert!(matches!(core.alloc(1), Err(CoreError::OutOfMemory)));
+    Ok(())
+}
+
+#[test]
+fn test_foreign_object_limit() -> Result {
+    let mut core = Core::new(1024).with_limits(Limits::default().with_max_foreign_objects(2));
+    core.insert_foreign_ptr(Arc::new(Mutex::new(1u8)))?;
+    core.insert_foreign_ptr(Arc::new(Mutex::new(2u8)))?;
+    assert!(matches!(
+        core.insert_foreign_ptr(Arc::new(Mutex::new(3u8))),
+        Err(CoreError::ForeignObjectLimit)
+    ));
+    Ok(())
+}
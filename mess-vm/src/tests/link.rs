@@ -0,0 +1,209 @@
+use std::{error::Error as StdError, result::Result as StdResult};
+
+use mess_api::prelude::{Adapter, Function, Module, Type};
+use mess_core::{compiler::Compiler as CompilerTrait, parser::Parser};
+
+use super::Result;
+use crate::{
+    codegen::{
+        asm::{assemble_object, parse_file},
+        error::Error,
+        linker::Linker,
+        object::{Object, Relocation},
+    },
+    Compiler,
+    Core,
+};
+
+fn double(adapter: &mut Adapter) {
+    let value: i64 = adapter.get_arg(0);
+    adapter.ret(value * 2);
+}
+
+fn math_module() -> Module {
+    let mut module = Module::new(String::from("math"));
+    module.add_function(Function::new("double", vec![Type::Int], Type::Int, double));
+    module
+}
+
+/// Compiles a script into an object with its own compiler
+fn compile_object(name: &str, source: &str) -> StdResult<Object, Box<dyn StdError>> {
+    let mut compiler = Compiler::default();
+    compiler.register_module(math_module())?;
+    compiler.compile(&Parser::new(source).parse()?)?;
+    Ok(compiler.get_object(name)?)
+}
+
+/// Exports `scale` and a counter, keeping its helper private
+const UTIL: &str = "
+pub static calls: int = 0;
+pub fun scale(x: int) ~ int {
+    calls += 1;
+    return math::double(x) + offset();
+}
+fun offset() ~ int {
+    return 1;
+}
+";
+
+/// Calls `scale` of the util unit, with a private helper of the same name
+const GAME: &str = "
+fun scale(x: int) ~ int;
+pub fun main() ~ int {
+    var first = scale(10);
+    return first + scale(offset());
+}
+fun offset() ~ int {
+    return 100;
+}
+";
+
+const MAIN: &str = "
+.static total, int
+main:
+    CALL count
+    LDA total, R1
+    MOVI_AR [R1], R0
+    RET
+";
+
+/// Adds 1 to `total` five times, then doubles it through the host
+const COUNT: &str = "
+count:
+    LDA total, R1
+    LDI 5, R2
+.loop:
+    MOVI_AR [R1], R3
+    ADDI_I R3, 1, R3
+    MOVI_RA R3, [R1]
+    SUBI_I R2, 1, R2
+    GTI R2, R0, R4
+    JMPT R4, .loop
+    ADDU_I SP, 8, SP
+    MOVI_RA R3, [SP - 8]
+    CALL double
+    MOVI_RA R0, [R1]
+    RET
+";
+
+#[test]
+fn test_link_objects() -> Result {
+    let main = assemble_object("main.asm", MAIN)?;
+    assert!(main.imports.contains("count"));
+    assert_eq!(main.exports().collect::<Vec<_>>(), vec!["main", "total"]);
+
+    let mut assembler = parse_file("count.asm", COUNT)?;
+    assembler.push_foreign_fn(
+        String::from("double"),
+        7,
+        Function::new("double", vec![Type::Int], Type::Int, double),
+    );
+    let count = assembler.build_object("count.asm")?;
+    assert_eq!(count.foreign_functions.iter().collect::<Vec<_>>(), vec!["double"]);
+    assert_eq!(count.imports.iter().collect::<Vec<_>>(), vec!["total"]);
+    assert!(count
+        .relocations
+        .iter()
+        .any(|(_, relocation)| *relocation == Relocation::Function(String::from("double"))));
+
+    // The count object is placed behind the main object
+    let linker = Linker::default().with_foreign_fn(
+        String::from("double"),
+        Function::new("double", vec![Type::Int], Type::Int, double),
+    );
+    let output = linker.link(&[main, count])?;
+    // Host functions get uids like the compiler gives them
+    assert_eq!(output.foreign_functions.keys().collect::<Vec<_>>(), vec![&(1 << 32)]);
+    let count_offset = output.functions[&output.function_name_map["count"]];
+    let location = output.get_location(count_offset).ok_or("No location")?;
+    assert_eq!(location.file, "count.asm");
+    let uid = output.function_name_map["main"];
+    let mut core = Core::new(1024);
+    core.load_program(output);
+    core.run_fn(uid)?;
+    assert_eq!(core.reg(0)?.get::<i64>(), 10);
+    Ok(())
+}
+
+#[test]
+fn test_link_duplicate_symbol() -> Result {
+    let first = assemble_object("first.asm", "main: RET")?;
+    let second = assemble_object("second.asm", "main: RET")?;
+    assert!(matches!(
+        Linker::default().link(&[first, second]),
+        Err(Error::DuplicateSymbol(symbol)) if symbol == "main"
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_link_missing_symbol() -> Result {
+    let main = assemble_object("main.asm", MAIN)?;
+    assert!(matches!(
+        Linker::default().link(&[main]),
+        Err(Error::MissingSymbol(symbol)) if symbol == "count"
+    ));
+    // The host function isn't registered with the linker
+    let count = assemble_object("count.asm", "count: CALL double\nRET")?;
+    assert!(matches!(
+        Linker::default().link(&[count]),
+        Err(Error::MissingSymbol(symbol)) if symbol == "double"
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_compiler_object() -> Result {
+    let object = compile_object("util", UTIL)?;
+    // Host functions are named by their module path, not their uid
+    let names: Vec<&str> = object.foreign_functions.iter().map(String::as_str).collect();
+    assert_eq!(names, vec!["root::math::double"]);
+    assert!(object.imports.is_empty());
+    assert_eq!(object.exports().collect::<Vec<_>>(), vec!["root::scale", "root::calls"]);
+    // Objects hold no host code, so they can be cached as bytes
+    assert_eq!(Object::from_bytes(&object.to_bytes()?)?, object);
+    assert!(matches!(Object::from_bytes(&[1, 2, 3]), Err(Error::InvalidObject)));
+    Ok(())
+}
+
+#[test]
+fn test_link_compiled_units() -> Result {
+    let util = Object::from_bytes(&compile_object("util", UTIL)?.to_bytes()?)?;
+    let game = Object::from_bytes(&compile_object("game", GAME)?.to_bytes()?)?;
+    assert_eq!(game.imports.iter().collect::<Vec<_>>(), vec!["root::scale"]);
+
+    let output = Linker::default().with_module(math_module()).link(&[util, game])?;
+    // Private functions are named after their unit
+    assert!(output.get_function_uid("util::root::offset").is_some());
+    assert!(output.get_function_uid("game::root::offset").is_some());
+    let uid = output.get_function_uid("main").ok_or("No main function")?;
+    let mut core = Core::new(1024);
+    core.load_program(output);
+    core.run_fn(uid)?;
+    // scale(10) = 21, scale(100) = 201
+    assert_eq!(core.reg(0)?.get::<i64>(), 222);
+    Ok(())
+}
+
+#[test]
+fn test_link_private_symbol() -> Result {
+    let util = compile_object("util", UTIL)?;
+    let game = compile_object(
+        "game",
+        "
+        fun offset() ~ int;
+        pub fun main() ~ int {
+            return offset();
+        }
+        ",
+    )?;
+    assert!(matches!(
+        Linker::default().with_module(math_module()).link(&[util, game]),
+        Err(Error::MissingSymbol(symbol)) if symbol == "root::offset"
+    ));
+    // Without a linker, calls to other units can't be resolved
+    let mut compiler = Compiler::default();
+    compiler.compile(&Parser::new("fun other(); fun main() { other(); }").parse()?)?;
+    assert!(matches!(compiler.get_output(), Err(Error::MissingSymbol(symbol)) if symbol == "root::other"));
+    Ok(())
+}
@@ -0,0 +1,63 @@
+mod asm;
+
+mod backtrace;
+
+mod compile;
+
+mod convert;
+
+mod coroutine;
+
+mod debug;
+
+mod decode;
+
+mod fuel;
+
+mod future;
+
+mod handle;
+
+mod host;
+
+mod limits;
+
+mod link;
+
+mod overflow;
+
+mod panic;
+
+mod peephole;
+
+mod profiler;
+
+mod regalloc;
+
+mod reload;
+
+mod reproducible;
+
+mod shared;
+
+mod snapshot;
+
+mod tier;
+
+use std::{result::Result as StdResult, error::Error};
+
+use crate::{codegen::output::Output, Core};
+
+type Result = StdResult<(), Box<dyn Error>>;
+
+/// Loads the output into a fresh core and runs the function with the given name
+fn run_fn(output: Output, fn_name: &str) -> StdResult<Core, Box<dyn Error>> {
+    let uid = *output
+        .function_name_map
+        .get(fn_name)
+        .ok_or("Unknown function")?;
+    let mut core = Core::new(1024);
+    core.load_program(output);
+    core.run_fn(uid)?;
+    Ok(core)
+}
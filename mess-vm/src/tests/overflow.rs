@@ -0,0 +1,111 @@
+use super::Result;
+use crate::{
+    codegen::asm::assemble,
+    exec::core::{CoreError, OverflowMode},
+    Core,
+};
+
+/// Runs `main` of the source with the given overflow mode
+fn run(source: &str, overflow_mode: OverflowMode) -> std::result::Result<Core, CoreError> {
+    let output = assemble(source).expect("Invalid assembly");
+    let uid = output.function_name_map["main"];
+    let mut core = Core::new(1024).with_overflow_mode(overflow_mode);
+    core.load_program(output);
+    core.run_fn(uid).map_err(CoreError::into_inner)?;
+    Ok(core)
+}
+
+const ADD_MAX: &str = "
+main:
+    LDI 9223372036854775807, R1
+    ADDI_I R1, 1, R1
+    RET
+";
+
+const MIN_DIV: &str = "
+main:
+    LDI -9223372036854775807, R1
+    SUBI_I R1, 1, R1
+    LDI -1, R2
+    DIVI R1, R2, R1
+    RET
+";
+
+#[test]
+fn test_overflow_wraps() -> Result {
+    let mut core = run(ADD_MAX, OverflowMode::Wrap)?;
+    assert_eq!(core.reg(1)?.get::<i64>(), i64::MIN);
+    let mut core = run("main: LDI 0, R1; SUBU_I R1, 1, R1; RET", OverflowMode::Wrap)?;
+    assert_eq!(core.reg(1)?.get::<u64>(), u64::MAX);
+    Ok(())
+}
+
+#[test]
+fn test_overflow_traps() -> Result {
+    assert!(matches!(run(ADD_MAX, OverflowMode::Trap), Err(CoreError::IntegerOverflow)));
+    assert!(matches!(run(MIN_DIV, OverflowMode::Trap), Err(CoreError::IntegerOverflow)));
+    let mut core = run(MIN_DIV, OverflowMode::Wrap)?;
+    assert_eq!(core.reg(1)?.get::<i64>(), i64::MIN);
+    // Division by zero is an error in either mode
+    let div_zero = "main: LDI 1, R1; DIVU R1, R0, R1; RET";
+    assert!(matches!(run(div_zero, OverflowMode::Wrap), Err(CoreError::DivisionByZero)));
+    Ok(())
+}
+
+#[test]
+fn test_address_arith_wraps() -> Result {
+    // Stack and address arithmetic never traps, only integer arithmetic does
+    let source = "
+    main:
+        ADDU_I SP, 16, SP
+        SUBU_I SP, 8, SP
+        LDI 0, R1
+        SUBU_I R1, 1, R1
+        RET
+    ";
+    let mut core = run(source, OverflowMode::Trap)?;
+    assert_eq!(core.reg(1)?.get::<u64>(), u64::MAX);
+    let sub_zero = "main: LDI 0, R1; LDI 1, R2; SUBU R1, R2, R1; RET";
+    assert!(matches!(run(sub_zero, OverflowMode::Trap), Err(CoreError::IntegerOverflow)));
+    Ok(())
+}
+
+#[test]
+fn test_narrow() -> Result {
+    let source = "
+    main:
+        LDI -129, R1
+        NARROWI R1, 1, R1
+        LDI -128, R2
+        NARROWI R2, 1, R2
+        LDI 65536, R3
+        NARROWU R3, 2, R3
+        RET
+    ";
+    let mut core = run(source, OverflowMode::Wrap)?;
+    assert_eq!(core.reg(1)?.get::<i64>(), 127);
+    assert_eq!(core.reg(2)?.get::<i64>(), -128);
+    assert_eq!(core.reg(3)?.get::<u64>(), 0);
+    assert!(matches!(run(source, OverflowMode::Trap), Err(CoreError::IntegerOverflow)));
+    let mut core = run("main: LDI -128, R1; NARROWI R1, 1, R1; RET", OverflowMode::Trap)?;
+    assert_eq!(core.reg(1)?.get::<i64>(), -128);
+    Ok(())
+}
+
+#[test]
+fn test_sized_moves() -> Result {
+    let source = "
+    main:
+        ADDU_I SP, 8, SP
+        LDI -1, R1
+        MOVI_RA R1, [SP - 8]
+        LDI 4660, R1
+        MOVN_RA R1, [SP - 8], 2
+        MOVN_AR [SP - 8], R2, 3
+        RET
+    ";
+    let mut core = run(source, OverflowMode::Wrap)?;
+    // Only the stored bytes changed, and loads zero extend
+    assert_eq!(core.reg(2)?.get::<u64>(), 0xff_1234);
+    Ok(())
+}
@@ -0,0 +1,142 @@
+use std::{error::Error, result::Result as StdResult};
+
+use mess_core::exec::Executor;
+
+use super::Result;
+use crate::{
+    codegen::asm::{assemble, parse_file},
+    exec::{
+        address::{Address, AddressType},
+        core::{CoreError, MAX_PANIC_MESSAGE_LEN},
+    },
+    Core,
+};
+
+/// Assembles the source with the message stored at the start of the program
+fn load(source: &str, message: &str) -> StdResult<Core, Box<dyn Error>> {
+    let mut assembler = parse_file("<asm>", source)?;
+    assert_eq!(assembler.push_string(message), message_ptr());
+    let mut core = Core::new(1024);
+    core.load_program(assembler.build_output()?);
+    Ok(core)
+}
+
+fn message_ptr() -> u64 {
+    Address::new(0, AddressType::Program).into()
+}
+
+#[test]
+fn test_script_panic() -> Result {
+    let source = format!(
+        "main:
+        ADDU_I SP, 16, SP
+        CALL inner
+        RET
+    inner:
+        ADDU_I SP, 8, SP
+        LDA {}, R1
+        PANIC R1
+        RET
+    other:
+        LDI 5, R2
+        RET",
+        message_ptr()
+    );
+    let mut core = load(&source, "out of cheese")?;
+    let sp = core.get_sp();
+    match Executor::run_fn(&mut core, "main") {
+        Err(CoreError::ScriptPanic { message, backtrace }) => {
+            assert_eq!(message, "out of cheese");
+            let names: Vec<_> = backtrace.frames.iter().map(|f| f.fn_name.clone()).collect();
+            assert_eq!(names, vec![Some(String::from("inner")), Some(String::from("main"))]);
+        }
+        _ => return Err("Expected a panic".into()),
+    };
+
+    // The failed run keeps its frames for inspection, but can't be resumed
+    assert!(core.has_failed_run());
+    assert_eq!(core.backtrace().frames.len(), 2);
+    assert_eq!(core.get_sp(), sp + 24);
+    assert!(matches!(core.resume(), Err(CoreError::RunFailed)));
+    assert!(matches!(core.snapshot(), Err(CoreError::RunFailed)));
+    core.reset_run();
+    assert_eq!(core.get_sp(), sp);
+    assert!(!core.has_failed_run());
+
+    // The next run starts from a clean stack without resetting first
+    assert!(Executor::run_fn(&mut core, "main").is_err());
+    Executor::run_fn(&mut core, "other")?;
+    assert_eq!(core.reg(2)?.get::<i64>(), 5);
+    assert_eq!(core.get_sp(), sp);
+    Ok(())
+}
+
+#[test]
+fn test_panic_message_limit() -> Result {
+    let source = format!("main: LDA {}, R1; PANIC R1; RET", message_ptr());
+    let long_message = "x".repeat(MAX_PANIC_MESSAGE_LEN + 10);
+    let mut core = load(&source, &long_message)?;
+    match Executor::run_fn(&mut core, "main") {
+        Err(CoreError::ScriptPanic { message, .. }) => assert_eq!(message.len(), MAX_PANIC_MESSAGE_LEN),
+        _ => return Err("Expected a panic".into()),
+    };
+    Ok(())
+}
+
+#[test]
+fn test_assert() -> Result {
+    let source = format!(
+        "main:
+        LDA {}, R1
+        LDB true, R2
+        ASSERT R2, R1
+        LDI 1, R3
+        ASSERT R0, R1
+        LDI 2, R3
+        RET",
+        message_ptr()
+    );
+    let mut core = load(&source, "R0 is not set")?;
+    match Executor::run_fn(&mut core, "main") {
+        Err(CoreError::ScriptPanic { message, .. }) => assert_eq!(message, "R0 is not set"),
+        _ => return Err("Expected a failed assert".into()),
+    };
+    assert_eq!(core.reg(3)?.get::<i64>(), 1);
+    Ok(())
+}
+
+#[test]
+fn test_reuse_after_error() -> Result {
+    let output = assemble(
+        "main:
+        CALL inner
+        LDI 7, R2
+        RET
+    inner:
+        LDI 1, R1
+        DIVI R1, R0, R1
+        RET
+    endless:
+        CALL endless
+    other:
+        LDI 5, R3
+        RET",
+    )?;
+    let uids = output.function_name_map.clone();
+    let mut core = Core::new(1024);
+    core.load_program(output);
+    let sp = core.get_sp();
+    assert!(matches!(core.run_fn(uids["main"]).map_err(CoreError::into_inner), Err(CoreError::DivisionByZero)));
+    // Returning from the entry function must not resume the failed run
+    core.run_fn(uids["other"])?;
+    assert_eq!(core.reg(2)?.get::<i64>(), 0);
+    assert_eq!(core.reg(3)?.get::<i64>(), 5);
+
+    // Runs abandoned while suspended are unwound too
+    core.set_fuel(Some(10));
+    assert!(matches!(core.run_fn(uids["endless"]), Err(CoreError::OutOfFuel)));
+    core.set_fuel(None);
+    core.run_fn(uids["other"])?;
+    assert_eq!(core.get_sp(), sp);
+    Ok(())
+}
@@ -0,0 +1,161 @@
+use std::{error::Error, result::Result as StdResult};
+
+use super::{run_fn, Result};
+use crate::{
+    codegen::{
+        asm::{assemble, parse_file},
+        assembler::Assembler,
+        output::SourceLocation,
+        peephole::optimize,
+    },
+    exec::{decode::DecodedInstr, is::Opcode},
+};
+
+fn optimized(source: &str) -> StdResult<Assembler, Box<dyn Error>> {
+    let mut assembler = parse_file("<asm>", source)?;
+    optimize(&mut assembler);
+    Ok(assembler)
+}
+
+fn opcodes(assembler: &Assembler) -> Vec<Opcode> {
+    assembler.instructions.iter().map(|instr| instr.opcode.clone()).collect()
+}
+
+/// Runs main in both the optimized and the plain program, comparing registers
+fn assert_same_result(source: &str, regs: &[u8]) -> Result {
+    let mut plain = run_fn(assemble(source)?, "main")?;
+    let mut fast = run_fn(optimized(source)?.build_output()?, "main")?;
+    for reg in regs {
+        assert_eq!(plain.reg(*reg)?.get::<i64>(), fast.reg(*reg)?.get::<i64>());
+    }
+    Ok(())
+}
+
+#[test]
+fn test_remove_noops() -> Result {
+    let source = "main: MOVI R1, R1; ADDU_I SP, 0, SP; MOVI_A [SP + 8], [SP + 8]; LDI 3, R1; MULI_I R1, 1, R1; RET";
+    let assembler = optimized(source)?;
+    assert_eq!(opcodes(&assembler), vec![Opcode::LDI, Opcode::RET]);
+    assert_eq!(assembler.labels["main"], 0);
+    assert_same_result(source, &[1])
+}
+
+#[test]
+fn test_fuse_immediates() -> Result {
+    let source = "main:
+        LDI 5, R1
+        LDI 7, R2
+        ADDI R1, R2, R2
+        LDI 3, R3
+        MULI R3, R2, R3
+        LDI 1, R4
+        SUBI R3, R4, R4
+        LDI 2, R5
+        ADDI R5, R5, R5
+        RET";
+    let assembler = optimized(source)?;
+    assert_eq!(
+        opcodes(&assembler),
+        vec![
+            Opcode::LDI,
+            Opcode::ADDI_I,
+            Opcode::MULI_I,
+            Opcode::SUBI_I,
+            Opcode::LDI,
+            Opcode::ADDI,
+            Opcode::RET,
+        ]
+    );
+    assert_same_result(source, &[1, 2, 3, 4, 5])
+}
+
+#[test]
+fn test_no_fusion_into_jump_target() -> Result {
+    // The ADDI is entered from the jump with another value in R2
+    let source = "main:
+        LDI 1, R1
+        LDI 0, R2
+        JMPF R2, .add
+        LDI 5, R2
+    .add:
+        ADDI R1, R2, R2
+        RET";
+    let assembler = optimized(source)?;
+    assert!(!opcodes(&assembler).contains(&Opcode::ADDI_I));
+    assert_same_result(source, &[1, 2])
+}
+
+#[test]
+fn test_remove_reload() -> Result {
+    let source = "main: ADDU_I SP, 8, SP; LDI 4, R1; MOVI_RA R1, [SP - 8]; MOVI_AR [SP - 8], R1; ADDI_I R1, 1, R1; RET";
+    let assembler = optimized(source)?;
+    assert!(!opcodes(&assembler).contains(&Opcode::MOVI_AR));
+    assert_same_result(source, &[1])
+}
+
+#[test]
+fn test_thread_jumps() -> Result {
+    let source = "main:
+        LDI 0, R1
+        JMP .a
+        LDI 99, R1
+    .a:
+        JMP .b
+        LDI 98, R1
+    .b:
+        ADDI_I R1, 1, R1
+        RET";
+    let assembler = optimized(source)?;
+    assert_eq!(assembler.labels["main.b"], 5);
+    let output = assembler.build_output()?;
+    let jmp = DecodedInstr::decode(&output.code, Opcode::LDI.instruction_size())?;
+    assert_eq!(jmp.opcode, Opcode::JMP);
+    let mut target = 0;
+    for _ in 0..5 {
+        target += DecodedInstr::decode(&output.code, target)?.size;
+    }
+    assert_eq!(jmp.operands[0], target as u64);
+    assert_same_result(source, &[1])
+}
+
+#[test]
+fn test_thread_encoded_targets() -> Result {
+    // The first JMP lands on the second, which jumps over the HALT to the RET
+    let source = "main: LDI 1, R1; JMP 19; JMP 33; MOVI R1, R1; HALT 2; RET";
+    let assembler = optimized(source)?;
+    assert_eq!(
+        opcodes(&assembler),
+        vec![Opcode::LDI, Opcode::JMP, Opcode::JMP, Opcode::HALT, Opcode::RET]
+    );
+    let output = assembler.build_output()?;
+    let jmp = DecodedInstr::decode(&output.code, 10)?;
+    assert_eq!(jmp.operands[0], 30);
+    assert_same_result(source, &[1])
+}
+
+#[test]
+fn test_remove_jump_to_next() -> Result {
+    let source = "main: LDI 1, R1; JMPT R1, .next; .next: JMP .end; .end: RET";
+    let assembler = optimized(source)?;
+    assert_eq!(opcodes(&assembler), vec![Opcode::LDI, Opcode::RET]);
+    assert_eq!(assembler.labels["main.end"], 1);
+    assert!(assembler.label_refs.is_empty());
+    assert!(assembler.jmp_instructions.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_keep_tags_and_lines() -> Result {
+    let mut assembler = parse_file("test.asm", "main:\nMOVI R1, R1\nLDI 1, R1\nMOVI R2, R2\nRET")?;
+    assembler.tags.insert(100, vec![0, 1]);
+    optimize(&mut assembler);
+    assert_eq!(assembler.tags[&100], vec![0]);
+    let line = |line| SourceLocation {
+        file: String::from("test.asm"),
+        line,
+    };
+    assert_eq!(assembler.debug_lines.get(&0), Some(&line(3)));
+    // The RET keeps its own line over the one of the removed move
+    assert_eq!(assembler.debug_lines.get(&1), Some(&line(5)));
+    Ok(())
+}
@@ -0,0 +1,67 @@
+use super::Result;
+use crate::{codegen::asm::assemble, exec::is::Opcode, Core};
+
+const PROGRAM: &str = "
+main:
+    LDI 3, R1
+.loop:
+    CALL work
+    SUBI_I R1, 1, R1
+    GTI R1, R0, R2
+    JMPT R2, .loop
+    RET
+work:
+    CALL leaf
+    RET
+leaf:
+    NOOP
+    RET
+";
+
+#[test]
+fn test_profile_counts() -> Result {
+    let output = assemble(PROGRAM)?;
+    let uid = output.function_name_map["main"];
+    let mut core = Core::new(1024).with_profiling(true);
+    core.load_program(output);
+    core.run_fn(uid)?;
+
+    let profiler = core.get_profiler().ok_or("Profiling disabled")?;
+    let functions = profiler.get_functions();
+    assert_eq!(functions["main"].calls, 1);
+    // 1 load, 4 instructions per iteration, 1 return
+    assert_eq!(functions["main"].instructions, 14);
+    assert_eq!(functions["work"].calls, 3);
+    assert_eq!(functions["work"].instructions, 6);
+    assert_eq!(functions["leaf"].instructions, 6);
+    assert!(functions["main"].total_time >= functions["work"].total_time);
+    assert!(functions["work"].total_time >= functions["leaf"].total_time);
+
+    let opcodes = profiler.get_opcodes();
+    assert_eq!(opcodes[&Opcode::CALL].count, 6);
+    assert_eq!(opcodes[&Opcode::RET].count, 7);
+    assert_eq!(opcodes[&Opcode::NOOP].count, 3);
+
+    assert_eq!(profiler.folded(), "main 14\nmain;work 6\nmain;work;leaf 6\n");
+    let report = profiler.report();
+    assert!(report.contains("main"));
+    assert!(report.contains("CALL"));
+    Ok(())
+}
+
+#[test]
+fn test_profile_disabled() -> Result {
+    let output = assemble(PROGRAM)?;
+    let uid = output.function_name_map["main"];
+    let mut core = Core::new(1024);
+    core.load_program(output);
+    core.run_fn(uid)?;
+    assert!(core.get_profiler().is_none());
+
+    core.set_profiling(true);
+    core.run_fn(uid)?;
+    let profiler = core.take_profiler().ok_or("Profiling disabled")?;
+    assert_eq!(profiler.get_functions()["main"].calls, 1);
+    assert_eq!(core.get_profiler().map(|p| p.get_functions().len()), Some(0));
+    Ok(())
+}
@@ -0,0 +1,300 @@
+use std::{error::Error, ops::Range, result::Result as StdResult};
+
+use num_traits::FromPrimitive;
+
+use super::{run_fn, Result};
+use crate::{
+    codegen::{
+        asm::{parse_file, LabelRef, Operand},
+        assembler::Assembler,
+        instruction::Instruction,
+        output::Output,
+        regalloc::{Location, RegisterAllocator, VirtualRegister, FIRST_VIRTUAL},
+        register::Register,
+    },
+    exec::{
+        core::CoreError,
+        is::{OperandAccess, OperandKind, Opcode},
+    },
+};
+
+fn ldi(value: i64, reg: VirtualRegister) -> Instruction {
+    Instruction::new(Opcode::LDI)
+        .with_operand(value)
+        .with_virtual(reg)
+}
+
+fn op(
+    opcode: Opcode,
+    lhs: VirtualRegister,
+    rhs: VirtualRegister,
+    target: VirtualRegister,
+) -> Instruction {
+    Instruction::new(opcode)
+        .with_virtual(lhs)
+        .with_virtual(rhs)
+        .with_virtual(target)
+}
+
+fn mov(src: VirtualRegister, dst: VirtualRegister) -> Instruction {
+    Instruction::new(Opcode::MOVI).with_virtual(src).with_virtual(dst)
+}
+
+fn ret_value(reg: VirtualRegister) -> Vec<Instruction> {
+    vec![
+        Instruction::new(Opcode::MOVI)
+            .with_virtual(reg)
+            .with_operand::<u8>(Register::R0.into()),
+        Instruction::new(Opcode::RET),
+    ]
+}
+
+/// Pushes main with a stack frame for spills, returning the range to allocate
+fn push_main(assembler: &mut Assembler, body: Vec<Instruction>) -> Range<usize> {
+    assembler.push_fn_label(String::from("main"), 100);
+    let start = assembler.instructions.len();
+    assembler.push_instr(Instruction::new_inc_stack(256));
+    for instr in body {
+        assembler.push_instr(instr);
+    }
+    start..assembler.instructions.len()
+}
+
+fn assert_no_virtual(assembler: &Assembler) {
+    for instr in assembler.instructions.iter() {
+        assert!(instr.virtual_regs.is_empty());
+        for offset in instr.get_operand_offsets(OperandKind::Reg) {
+            assert!(u32::from(instr.operands[offset]) < FIRST_VIRTUAL);
+        }
+    }
+}
+
+#[test]
+fn test_allocate_registers() -> Result {
+    let mut reg_alloc = RegisterAllocator::new();
+    let (a, b, c) = (reg_alloc.new_virtual()?, reg_alloc.new_virtual()?, reg_alloc.new_virtual()?);
+    let mut body = vec![ldi(5, a), ldi(7, b), op(Opcode::ADDI, a, b, c)];
+    body.extend(ret_value(c));
+
+    let mut assembler = Assembler::default();
+    let range = push_main(&mut assembler, body);
+    let allocation = reg_alloc.allocate(&assembler, range)?;
+    assert_eq!(allocation.get_spill_size(), 0);
+    assert_eq!(allocation.get_location(a), Some(&Location::Register(Register::R1)));
+    assert_eq!(allocation.get_location(b), Some(&Location::Register(Register::R2)));
+    allocation.apply(&mut assembler, Register::SP, 0)?;
+    assert_no_virtual(&assembler);
+
+    let mut core = run_fn(assembler.build_output()?, "main")?;
+    assert_eq!(core.reg(0)?.get::<i64>(), 12);
+    Ok(())
+}
+
+#[test]
+fn test_spill_in_loop() -> Result {
+    // Sums 5 + 4 + 3 + 2 + 1 with four values live in the loop and two registers
+    let mut reg_alloc = RegisterAllocator::new().with_registers(vec![Register::R1, Register::R2]);
+    let (sum, counter, zero, cond) = (
+        reg_alloc.new_virtual()?,
+        reg_alloc.new_virtual()?,
+        reg_alloc.new_virtual()?,
+        reg_alloc.new_virtual()?,
+    );
+    let mut assembler = Assembler::default();
+    let start = push_main(&mut assembler, vec![ldi(0, sum), ldi(5, counter), ldi(0, zero)]).start;
+    assembler.push_label(String::from("main.loop"));
+    assembler.push_instr(op(Opcode::ADDI, sum, counter, sum));
+    assembler.push_instr(
+        Instruction::new(Opcode::SUBI_I)
+            .with_virtual(counter)
+            .with_operand(1i64)
+            .with_virtual(counter),
+    );
+    assembler.push_instr(op(Opcode::GTI, counter, zero, cond));
+    let jmpt = assembler.instructions.len();
+    assembler.push_instr(Instruction::new(Opcode::JMPT).with_virtual(cond).with_operand(0u64));
+    assembler
+        .label_refs
+        .push((jmpt, 1, LabelRef::Target(String::from("main.loop"))));
+    for instr in ret_value(sum) {
+        assembler.push_instr(instr);
+    }
+
+    let allocation = reg_alloc.allocate(&assembler, start..assembler.instructions.len())?;
+    assert!(allocation.get_spill_size() > 0);
+    allocation.apply(&mut assembler, Register::SP, -128)?;
+    assert_no_virtual(&assembler);
+    let mut core = run_fn(assembler.build_output()?, "main")?;
+    assert_eq!(core.reg(0)?.get::<i64>(), 15);
+    Ok(())
+}
+
+/// Yields 7 and returns 9
+const GEN: &str = "
+gen:
+    LDI 7, R0
+    YIELD
+    LDI 9, R0
+    RET
+";
+
+/// Builds a main that resumes `gen`, passes the value through memory and
+/// returns it plus 42, with two registers for its eleven values. It panics
+/// first if `fail` is set.
+fn spill_program(fail: bool) -> StdResult<Output, Box<dyn Error>> {
+    let mut assembler = parse_file("<asm>", GEN)?;
+    let message = assembler.push_string("spilled");
+    let mut reg_alloc = RegisterAllocator::new().with_registers(vec![Register::R1, Register::R2]);
+    let regs = (0..11).map(|_| reg_alloc.new_virtual()).collect::<StdResult<Vec<_>, _>>()?;
+    let [a, b, msg, ok, bad, handle, value, done, loaded, x, y] = regs[..] else {
+        unreachable!()
+    };
+    let start = push_main(&mut assembler, vec![ldi(40, a), ldi(2, b)]).start;
+    assembler.push_instr(Instruction::new(Opcode::LDA).with_operand(message).with_virtual(msg));
+    assembler.push_data_ref(0, message);
+    assembler.push_asm(Opcode::LDB, vec![Operand::Bool(true), Operand::Virtual(ok)])?;
+    assembler.push_asm(Opcode::LDB, vec![Operand::Bool(fail), Operand::Virtual(bad)])?;
+    assembler.push_asm(Opcode::ASSERT, vec![Operand::Virtual(ok), Operand::Virtual(msg)])?;
+    assembler.push_asm(
+        Opcode::SPAWN,
+        vec![Operand::from("gen"), Operand::UInt(0), Operand::Virtual(handle)],
+    )?;
+    assembler.push_asm(
+        Opcode::RESUME,
+        vec![Operand::Virtual(handle), Operand::Virtual(value), Operand::Virtual(done)],
+    )?;
+    assembler.push_asm(
+        Opcode::MOVN_RA,
+        vec![Operand::Virtual(value), Operand::mem(Register::SP, -8)?, Operand::UInt(8)],
+    )?;
+    assembler.push_asm(
+        Opcode::MOVN_AR,
+        vec![Operand::mem(Register::SP, -8)?, Operand::Virtual(loaded), Operand::UInt(8)],
+    )?;
+    assembler.push_instr(op(Opcode::ADDI, a, loaded, x));
+    assembler.push_instr(op(Opcode::ADDI, x, b, y));
+    assembler.push_asm(Opcode::JMPF, vec![Operand::Virtual(bad), Operand::from("main.ok")])?;
+    assembler.push_asm(Opcode::PANIC, vec![Operand::Virtual(msg)])?;
+    assembler.push_label(String::from("main.ok"));
+    for instr in ret_value(y) {
+        assembler.push_instr(instr);
+    }
+
+    let allocation = reg_alloc.allocate(&assembler, start..assembler.instructions.len())?;
+    assert!(allocation.get_spill_size() > 0);
+    allocation.apply(&mut assembler, Register::SP, -128)?;
+    assert_no_virtual(&assembler);
+    Ok(assembler.build_output()?)
+}
+
+#[test]
+fn test_spill_operand_accesses() -> Result {
+    // The value RESUME writes and MOVN_RA stores, and the message ASSERT and
+    // PANIC read, survive being spilled
+    let mut core = run_fn(spill_program(false)?, "main")?;
+    assert_eq!(core.reg(0)?.get::<i64>(), 49);
+    let err = run_fn(spill_program(true)?, "main").err().ok_or("Expected a panic")?;
+    match err.downcast::<CoreError>().map(|err| err.into_inner()) {
+        Ok(CoreError::ScriptPanic { message, .. }) => assert_eq!(message, "spilled"),
+        _ => return Err("Expected a panic".into()),
+    };
+    Ok(())
+}
+
+#[test]
+fn test_operand_accesses() {
+    // Every register operand is read or written, and only those are
+    for opcode in (0..=u8::MAX).filter_map(Opcode::from_u8) {
+        let kinds = opcode.operand_kinds();
+        let accesses = opcode.operand_accesses();
+        assert_eq!(kinds.len(), accesses.len(), "{:?}", opcode);
+        for (kind, access) in kinds.iter().zip(accesses) {
+            let is_register = matches!(kind, OperandKind::Reg | OperandKind::Mem);
+            assert_eq!(is_register, *access != OperandAccess::Immediate, "{:?}", opcode);
+        }
+    }
+}
+
+#[test]
+fn test_coalesce_moves() -> Result {
+    let mut reg_alloc = RegisterAllocator::new();
+    let (a, b) = (reg_alloc.new_virtual()?, reg_alloc.new_virtual()?);
+    let mut body = vec![
+        ldi(3, a),
+        mov(a, b),
+        Instruction::new(Opcode::ADDI_I)
+            .with_virtual(b)
+            .with_operand(1i64)
+            .with_virtual(b),
+    ];
+    body.extend(ret_value(b));
+
+    let mut assembler = Assembler::default();
+    let range = push_main(&mut assembler, body);
+    reg_alloc.allocate(&assembler, range)?.apply(&mut assembler, Register::SP, 0)?;
+    let opcodes: Vec<_> = assembler.instructions.iter().map(|instr| instr.opcode.clone()).collect();
+    assert_eq!(
+        opcodes,
+        vec![Opcode::ADDU_I, Opcode::LDI, Opcode::ADDI_I, Opcode::MOVI, Opcode::RET]
+    );
+    let mut core = run_fn(assembler.build_output()?, "main")?;
+    assert_eq!(core.reg(0)?.get::<i64>(), 4);
+    Ok(())
+}
+
+#[test]
+fn test_save_across_call() -> Result {
+    // The callee clobbers every register the allocator hands out
+    let mut clobber = String::from("clobber:\n");
+    for reg in 1..14 {
+        clobber += &format!("LDI 99, R{}\n", reg);
+    }
+    clobber += "RET\n";
+    let mut assembler = parse_file("<asm>", &clobber)?;
+
+    let mut reg_alloc = RegisterAllocator::new();
+    let (a, b) = (reg_alloc.new_virtual()?, reg_alloc.new_virtual()?);
+    let start = push_main(&mut assembler, vec![ldi(10, a), ldi(20, b)]).start;
+    assembler.push_asm(Opcode::CALL, vec![Operand::from("clobber")])?;
+    assembler.push_instr(op(Opcode::ADDI, a, b, a));
+    for instr in ret_value(a) {
+        assembler.push_instr(instr);
+    }
+
+    let allocation = reg_alloc.allocate(&assembler, start..assembler.instructions.len())?;
+    assert_eq!(allocation.get_spill_size(), 16);
+    allocation.apply(&mut assembler, Register::SP, -16)?;
+    let mut core = run_fn(assembler.build_output()?, "main")?;
+    assert_eq!(core.reg(0)?.get::<i64>(), 30);
+    Ok(())
+}
+
+#[test]
+fn test_virtual_registers_beyond_operand_byte() -> Result {
+    let mut reg_alloc = RegisterAllocator::new();
+    let first = reg_alloc.new_virtual()?;
+    assert_eq!(first.get_id(), FIRST_VIRTUAL);
+
+    // Sums 1 to 300, each in its own virtual register
+    let mut regs = vec![first];
+    let mut body = vec![ldi(0, first)];
+    for n in 1..=300 {
+        let (value, sum) = (reg_alloc.new_virtual()?, reg_alloc.new_virtual()?);
+        body.push(ldi(n, value));
+        body.push(op(Opcode::ADDI, *regs.last().unwrap(), value, sum));
+        regs.push(sum);
+    }
+    assert!(regs.last().unwrap().get_id() > u32::from(u8::MAX));
+    body.extend(ret_value(*regs.last().unwrap()));
+
+    let mut assembler = Assembler::default();
+    let range = push_main(&mut assembler, body);
+    reg_alloc.allocate(&assembler, range)?.apply(&mut assembler, Register::SP, 0)?;
+    assert_no_virtual(&assembler);
+    let mut core = run_fn(assembler.build_output()?, "main")?;
+    assert_eq!(core.reg(0)?.get::<i64>(), 45150);
+
+    reg_alloc.reset();
+    assert_eq!(reg_alloc.new_virtual()?, first);
+    Ok(())
+}
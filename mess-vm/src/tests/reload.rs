@@ -0,0 +1,182 @@
+use std::{
+    error::Error,
+    result::Result as StdResult,
+    sync::{Arc, Mutex},
+};
+
+use mess_core::{
+    codegen::compat::{check_compatible, Incompatibility},
+    compiler::Compiler as CompilerTrait,
+    parser::{ast::Declaration, Parser},
+};
+
+use super::Result;
+use crate::{codegen::asm::assemble, exec::core::CoreError, Compiler, Core};
+
+const COUNTER: &str = "
+.static counter, int
+main:
+    LDA counter, R1
+    MOVI_AR [R1], R2
+    ADDI_I R2, 1, R2
+    MOVI_RA R2, [R1]
+    MOVI R2, R0
+    RET
+";
+
+/// Counts in steps of 10, with another static moving the counter
+const COUNTER_CHANGED: &str = "
+.static scale, i32
+.static counter, int
+main:
+    LDA counter, R1
+    MOVI_AR [R1], R2
+    ADDI_I R2, 10, R2
+    MOVI_RA R2, [R1]
+    MOVI R2, R0
+    RET
+";
+
+const COUNTER_RETYPED: &str = "
+.static counter, f64
+main:
+    RET
+";
+
+const SCRIPT: &str = "
+static count: int = 0;
+fun main() ~ int {
+    count += 1;
+    return count;
+}
+";
+
+/// Counts in steps of `step`, declared before `main`
+const SCRIPT_CHANGED: &str = "
+fun step() ~ int {
+    return 10;
+}
+static count: int = 0;
+static scale: float = 2.0;
+fun main() ~ int {
+    count += step();
+    return count;
+}
+";
+
+const SCRIPT_RESIGNED: &str = "
+static count: int = 0;
+fun main() ~ float {
+    return 1.0;
+}
+";
+
+fn parse(source: &str) -> StdResult<Vec<Declaration>, Box<dyn Error>> {
+    Ok(Parser::new(source).parse()?)
+}
+
+fn load(source: &str) -> StdResult<Core, Box<dyn Error>> {
+    let mut core = Core::new(1024);
+    core.load_program(assemble(source)?);
+    Ok(core)
+}
+
+fn run_main(core: &mut Core) -> StdResult<i64, Box<dyn Error>> {
+    let uid = core
+        .get_program()
+        .and_then(|program| program.get_function_uid("main"))
+        .ok_or("No main function")?;
+    core.run_fn(uid)?;
+    Ok(core.reg(0)?.get::<i64>())
+}
+
+#[test]
+fn test_reload_keeps_state() -> Result {
+    let mut core = load(COUNTER)?;
+    assert_eq!(run_main(&mut core)?, 1);
+    assert_eq!(run_main(&mut core)?, 2);
+    let ptr = core.insert_foreign_ptr(Arc::new(Mutex::new(7u8)))?;
+
+    let output = assemble(COUNTER_CHANGED)?;
+    assert_ne!(output.get_static("counter").map(|var| var.offset), Some(0));
+    core.reload_program(output)?;
+    assert_eq!(run_main(&mut core)?, 12);
+    assert_eq!(*core.get_foreign_ptr::<u8>(ptr)?.lock().unwrap(), 7);
+    Ok(())
+}
+
+#[test]
+fn test_reload_incompatible_static() -> Result {
+    let mut core = load(COUNTER)?;
+    assert_eq!(run_main(&mut core)?, 1);
+    assert!(matches!(
+        core.reload_program(assemble(COUNTER_RETYPED)?),
+        Err(CoreError::IncompatibleReload(_))
+    ));
+    // The old program is still loaded
+    assert_eq!(run_main(&mut core)?, 2);
+    Ok(())
+}
+
+#[test]
+fn test_reload_suspended() -> Result {
+    let mut core = load(COUNTER)?;
+    core.set_fuel(Some(2));
+    let err = run_main(&mut core).err().ok_or("Expected the run to stop")?;
+    assert!(matches!(err.downcast::<CoreError>().map(|err| err.into_inner()), Ok(CoreError::OutOfFuel)));
+    assert!(matches!(
+        core.reload_program(assemble(COUNTER_CHANGED)?),
+        Err(CoreError::IncompatibleReload(_))
+    ));
+    core.set_fuel(None);
+    core.resume()?;
+    core.reload_program(assemble(COUNTER_CHANGED)?)?;
+    assert_eq!(run_main(&mut core)?, 11);
+    Ok(())
+}
+
+#[test]
+fn test_reload_script() -> Result {
+    let decl_list = parse(SCRIPT)?;
+    let mut compiler = Compiler::default();
+    compiler.compile(&decl_list)?;
+    let mut core = Core::new(1024);
+    core.load_program(compiler.get_output()?);
+    let main_uid = core.get_program().ok_or("No program")?.function_name_map["root::main"];
+    assert_eq!(run_main(&mut core)?, 1);
+    assert_eq!(run_main(&mut core)?, 2);
+
+    let changed = parse(SCRIPT_CHANGED)?;
+    assert_eq!(check_compatible(&decl_list, &changed), Ok(()));
+    compiler.reset();
+    compiler.compile(&changed)?;
+    core.reload_program(compiler.get_output()?)?;
+    // `main` keeps its uid, the host can call it without looking it up again
+    core.run_fn(main_uid)?;
+    assert_eq!(core.reg(0)?.get::<i64>(), 12);
+    Ok(())
+}
+
+#[test]
+fn test_reload_script_incompatible() -> Result {
+    let decl_list = parse(SCRIPT)?;
+    let mut compiler = Compiler::default();
+    compiler.compile(&decl_list)?;
+    let mut core = Core::new(1024);
+    core.load_program(compiler.get_output()?);
+    assert_eq!(run_main(&mut core)?, 1);
+
+    assert_eq!(
+        check_compatible(&decl_list, &parse(SCRIPT_RESIGNED)?),
+        Err(Incompatibility::FunctionSignature(String::from("main")))
+    );
+    // A new compiler numbers the functions anew, moving the uid of `main`
+    let mut fresh = Compiler::default();
+    fresh.compile(&parse(SCRIPT_CHANGED)?)?;
+    assert!(matches!(
+        core.reload_program(fresh.get_output()?),
+        Err(CoreError::IncompatibleReload(_))
+    ));
+    assert_eq!(run_main(&mut core)?, 2);
+    Ok(())
+}
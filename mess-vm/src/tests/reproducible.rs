@@ -0,0 +1,75 @@
+use std::{error::Error, result::Result as StdResult};
+
+use mess_api::prelude::{Adapter, Function, Module, Type};
+use mess_core::{compiler::Compiler as CompilerTrait, parser::Parser};
+
+use super::Result;
+use crate::{codegen::output::Output, Compiler};
+
+const SCRIPT: &str = "
+static frames: int = 0;
+fun main() ~ int {
+    var total = 0;
+    total += step(1);
+    total += step(2);
+    host::log(total);
+    return total;
+}
+fun step(dt: int) ~ int {
+    frames += 1;
+    host::tick(dt);
+    host::draw(frames);
+    return dt * 2 + frames;
+}
+fun counter(n: int) ~ int {
+    yield n;
+    yield n + 1;
+    return n + 2;
+}
+fun name() ~ &str {
+    return \"player\";
+}
+";
+
+fn noop(_: &mut Adapter) {}
+
+/// Compiles the script with a fresh compiler that has a host module registered
+fn compile() -> StdResult<Output, Box<dyn Error>> {
+    let mut module = Module::new(String::from("host"));
+    for name in ["tick", "draw", "log"] {
+        module.add_function(Function::new(name, vec![Type::Int], Type::Void, noop));
+    }
+    let mut compiler = Compiler::default();
+    compiler.register_module(module)?;
+    compiler.compile(&Parser::new(SCRIPT).parse()?)?;
+    Ok(compiler.get_output()?)
+}
+
+#[test]
+fn test_reproducible_output() -> Result {
+    let first = compile()?;
+    let second = compile()?;
+    assert_eq!(first.code, second.code);
+    assert_eq!(first, second);
+    // Another thread compiles the same bytes
+    let third = std::thread::spawn(|| compile().map_err(|err| err.to_string()))
+        .join()
+        .map_err(|_| "Compiler thread panicked")??;
+    assert_eq!(first, third);
+    // Host functions get uids in name order
+    let uids: Vec<u64> = first.foreign_functions.keys().copied().collect();
+    assert_eq!(uids, vec![1 << 32, (1 << 32) + 1, (1 << 32) + 2]);
+    let names: Vec<&str> = first
+        .foreign_functions
+        .values()
+        .map(|function| function.name.as_str())
+        .collect();
+    assert_eq!(names, vec!["draw", "log", "tick"]);
+    // Script functions get uids in declaration order
+    let script_uids: Vec<Option<u64>> = ["main", "step", "counter", "name"]
+        .iter()
+        .map(|name| first.get_function_uid(name))
+        .collect();
+    assert_eq!(script_uids, vec![Some(0), Some(1), Some(2), Some(3)]);
+    Ok(())
+}
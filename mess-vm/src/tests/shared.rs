@@ -0,0 +1,77 @@
+use std::{sync::Arc, thread};
+
+use super::Result;
+use crate::{
+    codegen::{asm::assemble, output::Output},
+    exec::address::{Address, AddressType},
+    Core,
+};
+
+const DOUBLE: &str = "
+main:
+    ADDI R1, R1, R0
+    RET
+    NOOP
+    NOOP
+    NOOP
+    NOOP
+    NOOP
+    NOOP
+    NOOP
+    NOOP
+";
+
+fn assert_send<T: Send>() {}
+
+fn assert_sync<T: Send + Sync>() {}
+
+#[test]
+fn test_core_is_send() {
+    assert_send::<Core>();
+    assert_sync::<Arc<Output>>();
+}
+
+#[test]
+fn test_run_shared_program_on_threads() -> Result {
+    let program = Arc::new(assemble(DOUBLE)?);
+    let uid = program.function_name_map["main"];
+    let workers: Vec<_> = (0..8)
+        .map(|input| {
+            let program = Arc::clone(&program);
+            thread::spawn(move || {
+                let mut core = Core::new(1024);
+                core.load_shared_program(program);
+                core.reg(1)?.set::<i64>(input);
+                core.run_fn(uid)?;
+                core.reg(0).map(|value| value.get::<i64>())
+            })
+        })
+        .collect();
+    for (input, worker) in workers.into_iter().enumerate() {
+        let result = worker.join().map_err(|_| "Worker panicked")??;
+        assert_eq!(result, input as i64 * 2);
+    }
+    assert_eq!(Arc::strong_count(&program), 1);
+    Ok(())
+}
+
+#[test]
+fn test_program_writes_stay_private() -> Result {
+    let program = Arc::new(assemble(DOUBLE)?);
+    let mut writer = Core::new(1024);
+    writer.load_shared_program(Arc::clone(&program));
+    let mut reader = Core::new(1024);
+    reader.load_shared_program(Arc::clone(&program));
+
+    // Overwrite the trailing NOOPs
+    let addr: u64 = Address::new(program.code.len() as u64 - 8, AddressType::Program).into();
+    writer.mem_set((addr, 0), 42i64)?;
+    assert_eq!(writer.mem_get::<i64>((addr, 0))?, 42);
+    assert_eq!(reader.mem_get::<i64>((addr, 0))?, 0);
+    assert!(program.code.ends_with(&[0; 8]));
+
+    // The write is part of a snapshot of the writer
+    reader.restore(&writer.snapshot()?)?;
+    assert_eq!(reader.mem_get::<i64>((addr, 0))?, 42);
+    Ok(())
+}
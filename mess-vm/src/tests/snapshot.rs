@@ -0,0 +1,176 @@
+use std::{
+    error::Error,
+    result::Result as StdResult,
+    sync::{Arc, Mutex},
+};
+
+use super::Result;
+use crate::{
+    codegen::{asm::assemble, output::Output},
+    exec::{
+        core::{CoreError, CoreResult},
+        snapshot::{ForeignSnapshot, Snapshot},
+    },
+    Core,
+};
+
+/// Sums 1 to 10 into a heap cell, with the counter in R1
+const HEAP_SUM: &str = "
+main:
+    LDA 8, R1
+    ALLOC R1, R2
+    LDI 10, R1
+.loop:
+    MOVI_AR [R2 + 0], R3
+    ADDI R3, R1, R3
+    MOVI_RA R3, [R2 + 0]
+    SUBI_I R1, 1, R1
+    GTI R1, R0, R4
+    JMPT R4, .loop
+    MOVI_AR [R2 + 0], R0
+    RET
+";
+
+const COUNTER: &str = "
+counter:
+    LDI 0, R1
+.loop:
+    ADDI_I R1, 1, R1
+    MOVI R1, R0
+    YIELD
+    JMP .loop
+";
+
+fn load(source: &str) -> StdResult<(Core, Output), Box<dyn Error>> {
+    let mut core = Core::new(1024);
+    core.load_program(assemble(source)?);
+    Ok((core, assemble(source)?))
+}
+
+#[test]
+fn test_restore_and_resume() -> Result {
+    let (mut core, output) = load(HEAP_SUM)?;
+    let uid = output.function_name_map["main"];
+    core.set_fuel(Some(20));
+    assert!(matches!(core.run_fn(uid), Err(CoreError::OutOfFuel)));
+    let bytes = core.snapshot()?.to_bytes()?;
+
+    let mut restored = Core::new(1024);
+    restored.load_program(output);
+    restored.restore(&Snapshot::from_bytes(&bytes)?)?;
+    assert_eq!(restored.get_ip(), core.get_ip());
+    assert_eq!(restored.get_heap_size(), 8);
+    restored.resume()?;
+    assert_eq!(restored.reg(0)?.get::<i64>(), 55);
+
+    core.set_fuel(None);
+    core.resume()?;
+    assert_eq!(core.reg(0)?.get::<i64>(), 55);
+    Ok(())
+}
+
+#[test]
+fn test_restore_coroutines() -> Result {
+    let (mut core, output) = load(COUNTER)?;
+    let handle = core.spawn_coroutine(output.function_name_map["counter"])?;
+    core.resume_coroutine(handle)?;
+    let snapshot = core.snapshot()?;
+
+    let mut restored = Core::new(1024);
+    restored.load_program(output);
+    restored.restore(&snapshot)?;
+    let values = restored
+        .iter_coroutine(handle)
+        .take(2)
+        .map(|value| value.map(|value| value.get::<i64>()))
+        .collect::<StdResult<Vec<_>, _>>()?;
+    assert_eq!(values, vec![2, 3]);
+    Ok(())
+}
+
+#[test]
+fn test_restore_other_program() -> Result {
+    let (core, _) = load(HEAP_SUM)?;
+    let snapshot = core.snapshot()?;
+    let (mut other, _) = load(COUNTER)?;
+    assert!(matches!(
+        other.restore(&snapshot),
+        Err(CoreError::InvalidSnapshot)
+    ));
+    assert!(matches!(
+        Snapshot::from_bytes(&[1, 2, 3]),
+        Err(CoreError::InvalidSnapshot)
+    ));
+    Ok(())
+}
+
+/// Snapshots foreign objects that are all integers
+struct IntObjects;
+
+impl ForeignSnapshot for IntObjects {
+    fn save(&mut self, core: &Core, ptr: u64) -> Option<Vec<u8>> {
+        let object = core.get_foreign_ptr::<i64>(ptr).ok()?;
+        let value = *object.lock().ok()?;
+        Some(value.to_le_bytes().to_vec())
+    }
+
+    fn restore(&mut self, core: &mut Core, ptr: u64, bytes: &[u8]) -> CoreResult<()> {
+        let mut value = [0; 8];
+        value.copy_from_slice(bytes);
+        core.restore_foreign_ptr(ptr, Arc::new(Mutex::new(i64::from_le_bytes(value))))
+    }
+}
+
+#[test]
+fn test_foreign_objects() -> Result {
+    let (mut core, output) = load(COUNTER)?;
+    let ptr = core.insert_foreign_ptr(Arc::new(Mutex::new(7i64)))?;
+    assert!(matches!(core.snapshot(), Err(CoreError::NotSnapshottable(p)) if p == ptr));
+    let snapshot = core.snapshot_with(&mut IntObjects)?;
+    assert_eq!(
+        snapshot.get_foreign_pointers().collect::<Vec<_>>(),
+        vec![ptr]
+    );
+
+    let mut restored = Core::new(1024);
+    restored.load_program(output);
+    assert!(matches!(
+        restored.restore(&snapshot),
+        Err(CoreError::NotSnapshottable(_))
+    ));
+    restored.restore_with(&snapshot, &mut IntObjects)?;
+    assert_eq!(*restored.get_foreign_ptr::<i64>(ptr)?.lock().unwrap(), 7);
+    Ok(())
+}
+
+/// Fails to recreate any foreign object
+struct FailingObjects;
+
+impl ForeignSnapshot for FailingObjects {
+    fn save(&mut self, _core: &Core, _ptr: u64) -> Option<Vec<u8>> {
+        Some(Vec::new())
+    }
+
+    fn restore(&mut self, _core: &mut Core, ptr: u64, _bytes: &[u8]) -> CoreResult<()> {
+        Err(CoreError::InvalidHandle(ptr))
+    }
+}
+
+#[test]
+fn test_failed_restore_keeps_state() -> Result {
+    let (mut core, output) = load(COUNTER)?;
+    core.insert_foreign_ptr(Arc::new(Mutex::new(7i64)))?;
+    let snapshot = core.snapshot_with(&mut FailingObjects)?;
+
+    let mut restored = Core::new(1024);
+    restored.load_program(output);
+    let ptr = restored.insert_foreign_ptr(Arc::new(Mutex::new(9i64)))?;
+    let sp = restored.get_sp();
+    assert!(matches!(
+        restored.restore_with(&snapshot, &mut FailingObjects),
+        Err(CoreError::InvalidHandle(_))
+    ));
+    assert_eq!(*restored.get_foreign_ptr::<i64>(ptr)?.lock().unwrap(), 9);
+    assert_eq!(restored.get_sp(), sp);
+    Ok(())
+}
@@ -0,0 +1,246 @@
+use std::sync::{
+    atomic::{
+        AtomicUsize,
+        Ordering,
+    },
+    Arc,
+    Mutex,
+};
+
+use super::Result;
+use crate::{
+    codegen::asm::assemble,
+    exec::{
+        core::CoreError,
+        limits::Limits,
+        tier::{
+            CompiledFn,
+            CompiledFnError,
+            HotCounters,
+            TierPolicy,
+        },
+    },
+    Core,
+};
+
+const CALLS: &str = "
+double:
+    MOVI_AR [SP - 8], R1
+    ADDI R1, R1, R0
+    RET
+main:
+    LDI 0, R5
+    LDI 0, R6
+    LDI 10, R8
+.loop:
+    ADDU_I SP, 8, SP
+    MOVI_RA R5, [SP - 8]
+    CALL double
+    SUBU_I SP, 8, SP
+    ADDI R6, R0, R6
+    ADDI_I R5, 1, R5
+    GTI R8, R5, R7
+    JMPT R7, .loop
+    RET
+";
+
+const LOOPS: &str = "
+count:
+    LDI 0, R0
+    LDI 10, R1
+    LDI 0, R3
+.loop:
+    ADDI_I R0, 1, R0
+    SUBI_I R1, 1, R1
+    GTI R1, R3, R2
+    JMPT R2, .loop
+    RET
+main:
+    CALL count
+    MOVI R0, R4
+    CALL count
+    MOVI R0, R5
+    RET
+";
+
+/// A compiled `double` counting its calls
+fn compiled_double(calls: Arc<AtomicUsize>) -> CompiledFn {
+    CompiledFn::new(vec![8], move |args, _| {
+        calls.fetch_add(1, Ordering::Relaxed);
+        let arg = i64::from_le_bytes(args[0].as_slice().try_into().unwrap());
+        Ok((arg * 2) as u64)
+    })
+}
+
+#[test]
+fn test_tier_up_hot_calls() -> Result {
+    let output = assemble(CALLS)?;
+    let main = output.function_name_map["main"];
+    let double = output.function_name_map["double"];
+    let mut core = Core::new(1024);
+    core.load_program(output);
+
+    let compiled_calls = Arc::new(AtomicUsize::new(0));
+    let offered = Arc::new(Mutex::new(Vec::new()));
+    let policy = TierPolicy::default().with_call_threshold(3).with_loop_threshold(1000);
+    let (handler_calls, handler_offered) = (compiled_calls.clone(), offered.clone());
+    core.set_tiering(policy, move |fn_name| {
+        handler_offered.lock().unwrap().push(String::from(fn_name));
+        Some(compiled_double(handler_calls.clone()))
+    });
+
+    core.run_fn(main)?;
+    // The results don't depend on which tier ran
+    assert_eq!(core.reg(6)?.get::<i64>(), 90);
+    assert!(core.is_compiled(double));
+    // The third call was the first one to run compiled
+    assert_eq!(compiled_calls.load(Ordering::Relaxed), 8);
+    assert_eq!(
+        core.get_hot_counters(double),
+        Some(HotCounters {
+            calls: 3,
+            loop_iterations: 0
+        })
+    );
+    assert_eq!(*offered.lock().unwrap(), vec![String::from("double")]);
+    // The stack was left as the interpreted calls leave it
+    core.run_fn(main)?;
+    assert_eq!(core.reg(6)?.get::<i64>(), 90);
+    Ok(())
+}
+
+#[test]
+fn test_tier_up_hot_loops() -> Result {
+    let output = assemble(LOOPS)?;
+    let main = output.function_name_map["main"];
+    let count = output.function_name_map["count"];
+    let mut core = Core::new(1024);
+    core.load_program(output);
+
+    let policy = TierPolicy::default().with_loop_threshold(5);
+    core.set_tiering(policy, |fn_name| {
+        assert_eq!(fn_name, "count");
+        Some(CompiledFn::new(vec![], |_, _| Ok(42)))
+    });
+
+    core.run_fn(main)?;
+    // The call that got hot finished interpreted, the next one ran compiled
+    assert_eq!(core.reg(4)?.get::<i64>(), 10);
+    assert_eq!(core.reg(5)?.get::<i64>(), 42);
+    assert!(core.is_compiled(count));
+    assert_eq!(core.get_hot_counters(count).map(|counters| counters.loop_iterations), Some(5));
+    Ok(())
+}
+
+#[test]
+fn test_tier_up_declined() -> Result {
+    let output = assemble(CALLS)?;
+    let main = output.function_name_map["main"];
+    let double = output.function_name_map["double"];
+    let mut core = Core::new(1024);
+    core.load_program(output);
+
+    let offers = Arc::new(AtomicUsize::new(0));
+    let handler_offers = offers.clone();
+    let policy = TierPolicy::default().with_call_threshold(2).with_loop_threshold(1000);
+    core.set_tiering(policy, move |_| {
+        handler_offers.fetch_add(1, Ordering::Relaxed);
+        None
+    });
+
+    core.run_fn(main)?;
+    assert_eq!(core.reg(6)?.get::<i64>(), 90);
+    assert!(!core.is_compiled(double));
+    // A function is only offered once
+    assert_eq!(offers.load(Ordering::Relaxed), 1);
+    Ok(())
+}
+
+#[test]
+fn test_compiled_fn_errors() -> Result {
+    let output = assemble(LOOPS)?;
+    let main = output.function_name_map["main"];
+    let count = output.function_name_map["count"];
+    let mut core = Core::new(1024);
+    core.load_program(output);
+    let sp = core.get_sp();
+
+    core.install_compiled_fn(
+        count,
+        CompiledFn::new(vec![], |_, _| Err(CompiledFnError::ScriptPanic(String::from("boom")))),
+    );
+    match core.run_fn(main) {
+        Err(CoreError::ScriptPanic { message, backtrace }) => {
+            assert_eq!(message, "boom");
+            assert!(!backtrace.frames.is_empty());
+        }
+        _ => return Err("Expected a panic".into()),
+    };
+    assert_eq!(core.get_sp(), sp);
+
+    core.install_compiled_fn(count, CompiledFn::new(vec![], |_, _| Err(CompiledFnError::DivisionByZero)));
+    assert!(matches!(core.run_fn(main).map_err(CoreError::into_inner), Err(CoreError::DivisionByZero)));
+
+    core.install_compiled_fn(count, CompiledFn::new(vec![], |_, _| Err(CompiledFnError::IntegerOverflow)));
+    assert!(matches!(core.run_fn(main).map_err(CoreError::into_inner), Err(CoreError::IntegerOverflow)));
+
+    // Loading a program drops the compiled functions
+    core.load_program(assemble(LOOPS)?);
+    core.run_fn(main)?;
+    assert_eq!(core.reg(5)?.get::<i64>(), 10);
+    Ok(())
+}
+
+#[test]
+fn test_compiled_fns_interpreted_with_fuel_or_interrupts() -> Result {
+    let output = assemble(CALLS)?;
+    let main = output.function_name_map["main"];
+    let double = output.function_name_map["double"];
+    let mut core = Core::new(1024);
+    core.load_program(output);
+    let compiled_calls = Arc::new(AtomicUsize::new(0));
+    core.install_compiled_fn(double, compiled_double(compiled_calls.clone()));
+
+    // Compiled code can't run out of fuel
+    core.set_fuel(Some(1_000_000));
+    core.run_fn(main)?;
+    assert_eq!(core.reg(6)?.get::<i64>(), 90);
+    assert_eq!(compiled_calls.load(Ordering::Relaxed), 0);
+
+    core.set_fuel(None);
+    core.run_fn(main)?;
+    assert_eq!(compiled_calls.load(Ordering::Relaxed), 10);
+
+    // Nor can it be interrupted
+    let _handle = core.interrupt_handle();
+    core.run_fn(main)?;
+    assert_eq!(core.reg(6)?.get::<i64>(), 90);
+    assert_eq!(compiled_calls.load(Ordering::Relaxed), 10);
+    Ok(())
+}
+
+#[test]
+fn test_compiled_fn_call_depth() -> Result {
+    let output = assemble(CALLS)?;
+    let main = output.function_name_map["main"];
+    let double = output.function_name_map["double"];
+    let mut core = Core::new(1024).with_limits(Limits::default().with_max_call_depth(16));
+    core.load_program(output);
+
+    let depths = Arc::new(Mutex::new(Vec::new()));
+    let compiled_depths = depths.clone();
+    core.install_compiled_fn(
+        double,
+        CompiledFn::new(vec![8], move |_, max_call_depth| {
+            compiled_depths.lock().unwrap().push(max_call_depth);
+            Err(CompiledFnError::CallStackOverflow)
+        }),
+    );
+    assert!(matches!(
+        core.run_fn(main).map_err(CoreError::into_inner),
+        Err(CoreError::CallStackOverflow)
+    ));
+    // Calls from the function the run started with have the whole limit
+    assert_eq!(*depths.lock().unwrap(), vec![16]);
+    Ok(())
+}
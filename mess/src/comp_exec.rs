@@ -1,15 +1,39 @@
-use mess_core::{compiler::Compiler, parser::ast::Declaration};
+use mess_api::prelude::Module;
+use mess_core::{compiler::Compiler, exec::Executor, parser::ast::Declaration};
 #[cfg(feature = "exec-vm")]
 use mess_vm::{
+    exec::{
+        core::{
+            CoreError as VmCoreError,
+            OverflowMode as VmOverflowMode,
+        },
+        profiler::Profiler as VmProfiler,
+        tier::TierPolicy,
+    },
     Compiler as VmCompiler,
     Core as VmCore
 };
 
+#[cfg(feature = "exec-jit")]
+use mess_jit::{
+    codegen::compiler::OverflowMode as JitOverflowMode,
+    Compiler as JitCompiler,
+    Runtime as JitRuntime,
+};
+
+#[cfg(all(feature = "exec-vm", feature = "exec-jit"))]
+use crate::tier::jit_tier_up_handler;
 use crate::error::Error;
 
 pub enum CompExecPair {
     #[cfg(feature = "exec-vm")]
     VM(VmCompiler, VmCore),
+    #[cfg(feature = "exec-jit")]
+    JIT(JitCompiler, JitRuntime),
+    /// Interprets scripts, compiling hot functions with the JIT, which calls
+    /// the registered host modules as well
+    #[cfg(all(feature = "exec-vm", feature = "exec-jit"))]
+    Tiered(VmCompiler, VmCore, TierPolicy, Vec<Module>),
 }
 
 impl CompExecPair {
@@ -17,8 +41,195 @@ impl CompExecPair {
     pub fn compile(&mut self, decl_list: &[Declaration]) -> Result<(), Error> {
         match self {
             #[cfg(feature = "exec-vm")]
-            CompExecPair::VM(compiler, _) => compiler.compile(decl_list)?
+            CompExecPair::VM(compiler, _) => compiler.compile(decl_list)?,
+            #[cfg(feature = "exec-jit")]
+            CompExecPair::JIT(compiler, _) => compiler.compile(decl_list)?,
+            #[cfg(all(feature = "exec-vm", feature = "exec-jit"))]
+            CompExecPair::Tiered(compiler, core, policy, modules) => {
+                compiler.compile(decl_list)?;
+                let handler =
+                    jit_tier_up_handler(decl_list.to_vec(), modules.clone(), core.get_overflow_mode());
+                core.set_tiering(policy.clone(), handler)
+            }
         };
         Ok(())
     }
+
+    /// Names the file the next compiled declarations are parsed from, for
+    /// the line table of the bytecode compiler
+    pub fn set_source_path(&mut self, path: Option<&str>) {
+        match self {
+            #[cfg(feature = "exec-vm")]
+            CompExecPair::VM(compiler, _) => compiler.set_source_path(path),
+            // Compiled code has no line table
+            #[cfg(feature = "exec-jit")]
+            CompExecPair::JIT(..) => {}
+            #[cfg(all(feature = "exec-vm", feature = "exec-jit"))]
+            CompExecPair::Tiered(compiler, ..) => compiler.set_source_path(path),
+        };
+    }
+
+    /// Registers a host module with the compiler of the chosen backend
+    pub fn register_module(&mut self, module: Module) -> Result<(), Error> {
+        match self {
+            #[cfg(feature = "exec-vm")]
+            CompExecPair::VM(compiler, _) => compiler.register_module(module)?,
+            #[cfg(feature = "exec-jit")]
+            CompExecPair::JIT(compiler, _) => compiler.register_module(module)?,
+            // Hot functions are compiled with the modules registered by then
+            #[cfg(all(feature = "exec-vm", feature = "exec-jit"))]
+            CompExecPair::Tiered(compiler, _, _, modules) => {
+                modules.push(module.clone());
+                compiler.register_module(module)?
+            }
+        };
+        Ok(())
+    }
+
+    /// Compiles a changed declaration list from scratch and swaps it in for
+    /// the program loaded into the executor
+    pub fn reload(&mut self, decl_list: &[Declaration]) -> Result<(), Error> {
+        match self {
+            #[cfg(feature = "exec-vm")]
+            CompExecPair::VM(compiler, core) => {
+                compiler.reset();
+                compiler.compile(decl_list)?;
+                core.reload_program(compiler.get_output()?)?
+            }
+            // Compiled code keeps no state between runs, so the new output
+            // simply replaces the old one
+            #[cfg(feature = "exec-jit")]
+            CompExecPair::JIT(compiler, runtime) => {
+                compiler.reset()?;
+                compiler.compile(decl_list)?;
+                runtime.set_input(compiler.get_output()?)
+            }
+            #[cfg(all(feature = "exec-vm", feature = "exec-jit"))]
+            CompExecPair::Tiered(compiler, core, policy, modules) => {
+                compiler.reset();
+                compiler.compile(decl_list)?;
+                core.reload_program(compiler.get_output()?)?;
+                let handler =
+                    jit_tier_up_handler(decl_list.to_vec(), modules.clone(), core.get_overflow_mode());
+                core.set_tiering(policy.clone(), handler)
+            }
+        };
+        Ok(())
+    }
+
+    /// Hands the compiled output to the executor
+    pub fn load(&mut self) -> Result<(), Error> {
+        match self {
+            #[cfg(feature = "exec-vm")]
+            CompExecPair::VM(compiler, core) => core.set_input(compiler.get_output()?),
+            #[cfg(feature = "exec-jit")]
+            CompExecPair::JIT(compiler, runtime) => runtime.set_input(compiler.get_output()?),
+            #[cfg(all(feature = "exec-vm", feature = "exec-jit"))]
+            CompExecPair::Tiered(compiler, core, ..) => core.set_input(compiler.get_output()?),
+        };
+        Ok(())
+    }
+
+    /// Runs the function with the given name
+    pub fn run_fn(&mut self, fn_name: &str) -> Result<(), Error> {
+        match self {
+            #[cfg(feature = "exec-vm")]
+            CompExecPair::VM(_, core) => Executor::run_fn(core, fn_name)?,
+            #[cfg(feature = "exec-jit")]
+            CompExecPair::JIT(_, runtime) => Executor::run_fn(runtime, fn_name)?,
+            #[cfg(all(feature = "exec-vm", feature = "exec-jit"))]
+            CompExecPair::Tiered(_, core, ..) => Executor::run_fn(core, fn_name)?,
+        };
+        Ok(())
+    }
+
+    /// Runs the function with the given name, suspending while async host
+    /// functions it calls are pending
+    pub async fn run_fn_async(&mut self, fn_name: &str) -> Result<(), Error> {
+        match self {
+            #[cfg(feature = "exec-vm")]
+            CompExecPair::VM(_, core) => Self::run_vm_fn_async(core, fn_name).await?,
+            // Compiled code can't suspend, it rejects calls to async host
+            // functions instead
+            #[cfg(feature = "exec-jit")]
+            CompExecPair::JIT(_, runtime) => Executor::run_fn(runtime, fn_name)?,
+            #[cfg(all(feature = "exec-vm", feature = "exec-jit"))]
+            CompExecPair::Tiered(_, core, ..) => Self::run_vm_fn_async(core, fn_name).await?,
+        };
+        Ok(())
+    }
+
+    #[cfg(feature = "exec-vm")]
+    async fn run_vm_fn_async(core: &mut VmCore, fn_name: &str) -> Result<(), Error> {
+        let program = core.get_program().ok_or(VmCoreError::NoProgram)?;
+        let uid = program
+            .get_function_uid(fn_name)
+            .ok_or_else(|| VmCoreError::UnknownFunctionName(String::from(fn_name)))?;
+        core.run_fn_async(uid)?.await?;
+        Ok(())
+    }
+
+    /// Enables or disables profiling in the executor
+    pub fn set_profiling(&mut self, profiling: bool) {
+        match self {
+            #[cfg(feature = "exec-vm")]
+            CompExecPair::VM(_, core) => core.set_profiling(profiling),
+            // Only the interpreted calls are profiled
+            #[cfg(all(feature = "exec-vm", feature = "exec-jit"))]
+            CompExecPair::Tiered(_, core, ..) => core.set_profiling(profiling),
+            #[cfg(feature = "exec-jit")]
+            CompExecPair::JIT(..) => (),
+        };
+    }
+
+    /// Sets what integer arithmetic in the bytecode interpreter does on overflow
+    #[cfg(feature = "exec-vm")]
+    pub fn set_vm_overflow_mode(&mut self, overflow_mode: VmOverflowMode) {
+        match self {
+            CompExecPair::VM(_, core) => core.set_overflow_mode(overflow_mode),
+            // Hot functions are compiled with the mode the core has when the
+            // script is compiled
+            #[cfg(feature = "exec-jit")]
+            CompExecPair::Tiered(_, core, ..) => core.set_overflow_mode(overflow_mode),
+            #[cfg(feature = "exec-jit")]
+            CompExecPair::JIT(..) => (),
+        };
+    }
+
+    /// Sets what integer arithmetic compiled by the JIT does on overflow
+    #[cfg(feature = "exec-jit")]
+    pub fn set_jit_overflow_mode(&mut self, overflow_mode: JitOverflowMode) {
+        match self {
+            CompExecPair::JIT(compiler, _) => compiler.set_overflow_mode(overflow_mode),
+            #[cfg(feature = "exec-vm")]
+            CompExecPair::VM(..) => (),
+            // Hot functions follow the overflow mode of the interpreter
+            #[cfg(feature = "exec-vm")]
+            CompExecPair::Tiered(..) => (),
+        };
+    }
+
+    /// Returns the core of the bytecode interpreter, if it runs the scripts
+    #[cfg(feature = "exec-vm")]
+    pub fn get_vm_core(&self) -> Option<&VmCore> {
+        match self {
+            CompExecPair::VM(_, core) => Some(core),
+            #[cfg(feature = "exec-jit")]
+            CompExecPair::Tiered(_, core, ..) => Some(core),
+            #[cfg(feature = "exec-jit")]
+            CompExecPair::JIT(..) => None,
+        }
+    }
+
+    /// Returns the profile collected by the bytecode interpreter, if enabled
+    #[cfg(feature = "exec-vm")]
+    pub fn get_vm_profiler(&self) -> Option<&VmProfiler> {
+        match self {
+            CompExecPair::VM(_, core) => core.get_profiler(),
+            #[cfg(feature = "exec-jit")]
+            CompExecPair::Tiered(_, core, ..) => core.get_profiler(),
+            #[cfg(feature = "exec-jit")]
+            CompExecPair::JIT(..) => None,
+        }
+    }
 }
\ No newline at end of file
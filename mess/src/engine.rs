@@ -6,13 +6,28 @@ use std::{
 
 use mess_core::{
     compiler::Compiler as CompilerTrait,
-    parser::Parser, codegen::decl::Declarator,
+    parser::{Parser, ast::Declaration}, codegen::{compat::check_compatible, decl::Declarator},
 };
 #[cfg(feature = "exec-vm")]
 use mess_vm::{
+    codegen::{
+        asm::assemble_file,
+        output::Output as VmOutput,
+    },
+    exec::{
+        core::OverflowMode as VmOverflowMode,
+        profiler::Profiler as VmProfiler,
+        tier::TierPolicy,
+    },
     Compiler as VmCompiler,
     Core as VmExec,
 };
+#[cfg(feature = "exec-jit")]
+use mess_jit::{
+    codegen::compiler::OverflowMode as JitOverflowMode,
+    Compiler as JitCompiler,
+    Runtime as JitRuntime,
+};
 
 use mess_api::prelude::Module;
 
@@ -21,7 +36,8 @@ use crate::{comp_exec::CompExecPair, error::Error};
 
 pub struct Engine {
     comp_exec_pair: CompExecPair,
-    declarator: Declarator
+    declarator: Declarator,
+    decl_list: Vec<Declaration>
 }
 
 impl Engine {
@@ -30,34 +46,163 @@ impl Engine {
     pub fn new_vm(stack_size: usize) -> Engine {
         Engine {
             declarator: Declarator::default(),
+            decl_list: Vec::new(),
             comp_exec_pair: CompExecPair::VM(VmCompiler::default(), VmExec::new(stack_size)),
         }
     }
 
-    /// Creates a new engine with the x64 JIT backend
+    /// Enables or disables profiling of script execution
+    pub fn with_profiling(mut self, profiling: bool) -> Engine {
+        self.comp_exec_pair.set_profiling(profiling);
+        self
+    }
+
+    /// Sets whether integer overflow in the bytecode interpreter wraps or
+    /// stops the script with an error
+    #[cfg(feature = "exec-vm")]
+    pub fn with_vm_overflow_mode(mut self, overflow_mode: VmOverflowMode) -> Engine {
+        self.comp_exec_pair.set_vm_overflow_mode(overflow_mode);
+        self
+    }
+
+    /// Sets whether integer overflow in code compiled by the JIT wraps or
+    /// stops the script with an error
     #[cfg(feature = "exec-jit")]
-    pub fn new_jit() -> Engine {
-        unimplemented!("Not implemented yet");
+    pub fn with_jit_overflow_mode(mut self, overflow_mode: JitOverflowMode) -> Engine {
+        self.comp_exec_pair.set_jit_overflow_mode(overflow_mode);
+        self
     }
 
-    /// Registers a foreign module
-    pub fn register_module(&mut self, module: Module) -> Result<(), Error> {
-        unimplemented!("Not implemented yet");
+    /// Returns the profile collected by the bytecode interpreter, if enabled
+    #[cfg(feature = "exec-vm")]
+    pub fn get_vm_profiler(&self) -> Option<&VmProfiler> {
+        self.comp_exec_pair.get_vm_profiler()
     }
 
-    /// Runs a script file at the given path
-    pub fn run_file<P: AsRef<Path>>(&mut self, file_path: P) -> Result<(), Error> {
+    /// Returns the core of the bytecode interpreter, if it runs the scripts,
+    /// e.g. to check which functions tiered execution compiled
+    #[cfg(feature = "exec-vm")]
+    pub fn get_vm_core(&self) -> Option<&VmExec> {
+        self.comp_exec_pair.get_vm_core()
+    }
+
+    /// Compiles a script file for the bytecode interpreter without running it.
+    /// Files with an `.asm` extension are assembled as VM assembly.
+    #[cfg(feature = "exec-vm")]
+    pub fn compile_vm_file<P: AsRef<Path>>(file_path: P) -> Result<VmOutput, Error> {
         let file_path = file_path.as_ref();
+        if file_path.extension().map(|ext| ext == "asm") == Some(true) {
+            let mut source = String::new();
+            File::open(file_path)?.read_to_string(&mut source)?;
+            return Ok(assemble_file(&file_path.display().to_string(), &source)?);
+        }
         let mut parser = Parser::new_with_path(file_path);
         let decl_list = parser.parse()?;
-        self.comp_exec_pair.compile(&decl_list)?;
+        let mut compiler = VmCompiler::default().with_source_path(&file_path.display().to_string());
+        compiler.compile(&decl_list)?;
+        Ok(compiler.get_output()?)
+    }
+
+    /// Creates a new engine interpreting scripts with the bytecode
+    /// interpreter, which compiles the functions the policy counts as hot
+    /// with the x64 JIT and runs them compiled from then on
+    #[cfg(all(feature = "exec-vm", feature = "exec-jit"))]
+    pub fn new_tiered(stack_size: usize, policy: TierPolicy) -> Engine {
+        Engine {
+            declarator: Declarator::default(),
+            decl_list: Vec::new(),
+            comp_exec_pair: CompExecPair::Tiered(VmCompiler::default(), VmExec::new(stack_size), policy, Vec::new()),
+        }
+    }
+
+    /// Creates a new engine with the x64 JIT backend. Fails if no executable
+    /// memory can be allocated for compiled code.
+    #[cfg(feature = "exec-jit")]
+    pub fn new_jit() -> Result<Engine, Error> {
+        Ok(Engine {
+            declarator: Declarator::default(),
+            decl_list: Vec::new(),
+            comp_exec_pair: CompExecPair::JIT(JitCompiler::new()?, JitRuntime::new()),
+        })
+    }
+
+    /// Registers a foreign module, whose functions scripts compiled
+    /// afterwards can call as `module::function`
+    pub fn register_module(&mut self, module: Module) -> Result<(), Error> {
+        self.comp_exec_pair.register_module(module)
+    }
+
+    /// Runs the `main` function of a script file at the given path
+    pub fn run_file<P: AsRef<Path>>(&mut self, file_path: P) -> Result<(), Error> {
+        let file_path = file_path.as_ref();
+        match &mut self.comp_exec_pair {
+            #[cfg(feature = "exec-vm")]
+            CompExecPair::VM(_, core) if file_path.extension().map(|ext| ext == "asm") == Some(true) => {
+                core.load_program(Self::compile_vm_file(file_path)?);
+            }
+            // Assembly has no source to compile hot functions from
+            #[cfg(all(feature = "exec-vm", feature = "exec-jit"))]
+            CompExecPair::Tiered(_, core, ..) if file_path.extension().map(|ext| ext == "asm") == Some(true) => {
+                core.clear_tiering();
+                core.load_program(Self::compile_vm_file(file_path)?);
+            }
+            _ => {
+                let mut parser = Parser::new_with_path(file_path);
+                let decl_list = parser.parse()?;
+                self.comp_exec_pair.set_source_path(Some(&file_path.display().to_string()));
+                self.comp_exec_pair.compile(&decl_list)?;
+                self.comp_exec_pair.load()?;
+                self.decl_list = decl_list;
+            }
+        };
+        self.comp_exec_pair.run_fn("main")
+    }
+
+    /// Recompiles a changed script file and replaces the function bodies of
+    /// the loaded one with it. Functions keep their uids, static variables
+    /// keep their values and live foreign objects are kept.
+    /// The loaded script stays in place if a function signature, container
+    /// layout or static variable type changed.
+    pub fn reload_file<P: AsRef<Path>>(&mut self, file_path: P) -> Result<(), Error> {
+        let file_path = file_path.as_ref();
+        match &mut self.comp_exec_pair {
+            #[cfg(feature = "exec-vm")]
+            CompExecPair::VM(_, core) if file_path.extension().map(|ext| ext == "asm") == Some(true) => {
+                core.reload_program(Self::compile_vm_file(file_path)?)?;
+            }
+            #[cfg(all(feature = "exec-vm", feature = "exec-jit"))]
+            CompExecPair::Tiered(_, core, ..) if file_path.extension().map(|ext| ext == "asm") == Some(true) => {
+                core.clear_tiering();
+                core.reload_program(Self::compile_vm_file(file_path)?)?;
+            }
+            _ => {
+                let mut parser = Parser::new_with_path(file_path);
+                let decl_list = parser.parse()?;
+                check_compatible(&self.decl_list, &decl_list)?;
+                self.comp_exec_pair.set_source_path(Some(&file_path.display().to_string()));
+                self.comp_exec_pair.reload(&decl_list)?;
+                self.decl_list = decl_list;
+            }
+        };
         Ok(())
     }
 
+    /// Runs a function of the loaded script by name
+    pub fn run_fn(&mut self, fn_name: &str) -> Result<(), Error> {
+        self.comp_exec_pair.run_fn(fn_name)
+    }
+
+    /// Runs a function of the loaded script as a future, which suspends while
+    /// async host functions are pending instead of blocking the thread
+    pub async fn run_fn_async(&mut self, fn_name: &str) -> Result<(), Error> {
+        self.comp_exec_pair.run_fn_async(fn_name).await
+    }
+
     /// Runs a piece of code
     pub fn run_code<S: ToString>(&mut self, code: S) -> Result<(), Error> {
         let mut parser = Parser::new(code);
         let decl_list = parser.parse()?;
+        self.comp_exec_pair.set_source_path(None);
         self.comp_exec_pair.compile(&decl_list)?;
         Ok(())
     }
@@ -67,7 +212,9 @@ impl Engine {
         let file_path = file_path.as_ref();
         let mut parser = Parser::new_with_path(file_path);
         let decl_list = parser.parse()?;
+        self.comp_exec_pair.set_source_path(Some(&file_path.display().to_string()));
         self.comp_exec_pair.compile(&decl_list)?;
+        self.decl_list = decl_list;
         Ok(())
     }
 
@@ -75,6 +222,7 @@ impl Engine {
     pub fn load_code<S: ToString>(&mut self, code: S) -> Result<(), Error> {
         let mut parser = Parser::new(code);
         let decl_list = parser.parse()?;
+        self.comp_exec_pair.set_source_path(None);
         self.comp_exec_pair.compile(&decl_list)?;
         Ok(())
     }
@@ -1,8 +1,16 @@
 use std::fmt::Display;
 use std::error::Error as StdError;
+use std::io::Error as IoError;
 
+#[cfg(feature = "exec-vm")]
 use mess_vm::codegen::error::Error as VmCompileError;
+#[cfg(feature = "exec-vm")]
 use mess_vm::exec::core::CoreError as VmCoreError;
+#[cfg(feature = "exec-jit")]
+use mess_jit::codegen::error::Error as JitCompileError;
+#[cfg(feature = "exec-jit")]
+use mess_jit::exec::runtime::RuntimeError as JitRuntimeError;
+use mess_core::codegen::compat::Incompatibility;
 use mess_core::parser::error::Error as ParseError;
 
 #[derive(Debug)]
@@ -11,7 +19,13 @@ pub enum Error {
     VmCompileError(VmCompileError),
     #[cfg(feature = "exec-vm")]
     VmCoreError(VmCoreError),
-    ParseError(ParseError)
+    #[cfg(feature = "exec-jit")]
+    JitCompileError(JitCompileError),
+    #[cfg(feature = "exec-jit")]
+    JitRuntimeError(JitRuntimeError),
+    ParseError(ParseError),
+    IoError(IoError),
+    IncompatibleReload(Incompatibility)
 }
 
 impl Display for Error {
@@ -28,6 +42,18 @@ impl From<ParseError> for Error {
     }
 }
 
+impl From<Incompatibility> for Error {
+    fn from(i: Incompatibility) -> Self {
+        Self::IncompatibleReload(i)
+    }
+}
+
+impl From<IoError> for Error {
+    fn from(e: IoError) -> Self {
+        Self::IoError(e)
+    }
+}
+
 #[cfg(feature = "exec-vm")]
 impl From<VmCoreError> for Error {
     fn from(e: VmCoreError) -> Self {
@@ -40,4 +66,18 @@ impl From<VmCompileError> for Error {
     fn from(e: VmCompileError) -> Self {
         Self::VmCompileError(e)
     }
+}
+
+#[cfg(feature = "exec-jit")]
+impl From<JitCompileError> for Error {
+    fn from(e: JitCompileError) -> Self {
+        Self::JitCompileError(e)
+    }
+}
+
+#[cfg(feature = "exec-jit")]
+impl From<JitRuntimeError> for Error {
+    fn from(e: JitRuntimeError) -> Self {
+        Self::JitRuntimeError(e)
+    }
 }
\ No newline at end of file
@@ -1,9 +1,19 @@
 #![warn(missing_docs)]
 
 pub extern crate mess_api as api;
+#[cfg(feature = "exec-vm")]
+pub extern crate mess_vm as vm;
+#[cfg(feature = "exec-jit")]
+pub extern crate mess_jit as jit;
 
 pub mod engine;
 
 pub mod comp_exec;
 
-pub mod error;
\ No newline at end of file
+pub mod error;
+
+#[cfg(all(feature = "exec-vm", feature = "exec-jit"))]
+pub mod tier;
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,9 @@
+use std::{error::Error, result::Result as StdResult};
+
+#[cfg(all(feature = "exec-vm", feature = "exec-jit"))]
+mod parity;
+
+#[cfg(all(feature = "exec-vm", feature = "exec-jit"))]
+mod tier;
+
+type Result = StdResult<(), Box<dyn Error>>;
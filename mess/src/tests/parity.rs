@@ -0,0 +1,189 @@
+use std::{
+    cell::RefCell,
+    error::Error as StdError,
+    fs,
+    path::Path,
+    result::Result as StdResult,
+};
+
+use mess_api::prelude::{
+    Adapter,
+    Function,
+    Module,
+    Type,
+};
+use mess_core::{
+    compiler::Compiler,
+    exec::Executor,
+    parser::{
+        ast::{
+            Declaration,
+            Type as AstType,
+        },
+        Parser,
+    },
+};
+use mess_jit::{
+    codegen::error::Error as JitError,
+    Compiler as JitCompiler,
+};
+use mess_vm::{
+    Compiler as VmCompiler,
+    Core as VmCore,
+};
+
+use super::Result;
+
+/// The directories of the scripts run by both backends, relative to the
+/// workspace
+const CORPUS: [&str; 2] = ["example-scripts", "test-scripts"];
+
+/// The scripts of the corpus using constructs the JIT compiler doesn't
+/// support yet
+const JIT_UNSUPPORTED: [&str; 2] = ["coroutines.mess", "statics.mess"];
+
+thread_local! {
+    static PRINTED: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+fn print(adapter: &mut Adapter) {
+    let text = adapter.get_str_arg(0).unwrap_or_default();
+    PRINTED.with(|printed| printed.borrow_mut().push(text));
+}
+
+fn double(adapter: &mut Adapter) {
+    let value: i64 = adapter.get_arg(0);
+    adapter.ret(value * 2);
+}
+
+fn halve(adapter: &mut Adapter) {
+    let value: f32 = adapter.get_arg(0);
+    adapter.ret(value / 2.0);
+}
+
+fn length(adapter: &mut Adapter) {
+    let text = adapter.get_str_arg(0).unwrap_or_default();
+    adapter.ret(text.len() as i64);
+}
+
+/// The `io` and `math` modules the scripts of the corpus call
+fn host_modules() -> Vec<Module> {
+    let str_ref = Type::Ref(Box::new(Type::Str));
+    let mut io = Module::new(String::from("io"));
+    io.add_function(Function::new("print", vec![str_ref.clone()], Type::Void, print));
+    let mut math = Module::new(String::from("math"));
+    math.add_function(Function::new("double", vec![Type::Int], Type::Int, double));
+    math.add_function(Function::new("halve", vec![Type::Float], Type::Float, halve));
+    math.add_function(Function::new("length", vec![str_ref], Type::Int, length));
+    vec![io, math]
+}
+
+/// The outcome of running a function: the bits of its return value, or
+/// `None` if it failed, and the text it printed
+type Outcome = (Option<u64>, Vec<String>);
+
+/// Keeps only the bytes of the return value that its type uses
+fn truncate(bits: u64, ret_type: &AstType) -> u64 {
+    match ret_type {
+        AstType::Void => 0,
+        AstType::Bool | AstType::I8 | AstType::U8 => bits as u8 as u64,
+        AstType::I16 | AstType::U16 => bits as u16 as u64,
+        AstType::Float | AstType::I32 | AstType::U32 => bits as u32 as u64,
+        _ => bits,
+    }
+}
+
+/// Returns the functions of the script without arguments, which the test
+/// runs, with their return types
+fn get_entry_fns(decl_list: &[Declaration]) -> Vec<(String, AstType)> {
+    decl_list
+        .iter()
+        .filter_map(|decl| match decl {
+            Declaration::Function {
+                name,
+                returns,
+                arguments,
+                body: Some(_),
+                ..
+            } if arguments.is_empty() => Some((name.clone(), returns.clone())),
+            _ => None,
+        })
+        .collect()
+}
+
+fn run_vm(
+    decl_list: &[Declaration],
+    fns: &[(String, AstType)],
+) -> StdResult<Vec<Outcome>, Box<dyn StdError>> {
+    let mut compiler = VmCompiler::default();
+    for module in host_modules() {
+        compiler.register_module(module)?;
+    }
+    compiler.compile(decl_list)?;
+    let mut core = VmCore::new(1024 * 1024);
+    core.load_program(compiler.get_output()?);
+    let mut outcomes = Vec::new();
+    for (fn_name, ret_type) in fns {
+        let ret = match Executor::run_fn(&mut core, fn_name) {
+            Ok(()) => Some(truncate(core.reg(0)?.get::<u64>(), ret_type)),
+            Err(_) => None,
+        };
+        outcomes.push((ret, PRINTED.with(|printed| printed.take())));
+    }
+    Ok(outcomes)
+}
+
+/// Runs the functions with the JIT, returning `None` if the script uses
+/// constructs the JIT compiler doesn't support
+fn run_jit(
+    decl_list: &[Declaration],
+    fns: &[(String, AstType)],
+) -> StdResult<Option<Vec<Outcome>>, Box<dyn StdError>> {
+    let mut compiler = JitCompiler::new()?;
+    for module in host_modules() {
+        compiler.register_module(module)?;
+    }
+    match compiler.compile(decl_list) {
+        Ok(()) => {}
+        Err(JitError::Unimplemented(_)) => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    let output = compiler.get_output()?;
+    let mut outcomes = Vec::new();
+    for (fn_name, _) in fns {
+        let ret = output.call_dynamic(fn_name, &[]).ok();
+        outcomes.push((ret, PRINTED.with(|printed| printed.take())));
+    }
+    Ok(Some(outcomes))
+}
+
+#[test]
+fn test_parity_corpus() -> Result {
+    let workspace = Path::new(env!("CARGO_MANIFEST_DIR")).join("..");
+    let mut unsupported = Vec::new();
+    let mut script_count = 0;
+    for dir in CORPUS {
+        let mut paths: Vec<_> = fs::read_dir(workspace.join(dir))?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<StdResult<_, _>>()?;
+        paths.sort();
+        for path in paths {
+            let file_name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+            let decl_list = Parser::new(&fs::read_to_string(&path)?).parse()?;
+            let fns = get_entry_fns(&decl_list);
+            let vm_outcomes = run_vm(&decl_list, &fns).map_err(|err| format!("{}: {}", file_name, err))?;
+            match run_jit(&decl_list, &fns).map_err(|err| format!("{}: {}", file_name, err))? {
+                Some(jit_outcomes) => {
+                    for ((fn_name, _), (vm, jit)) in fns.iter().zip(vm_outcomes.iter().zip(jit_outcomes)) {
+                        assert_eq!(*vm, jit, "{}: {}", file_name, fn_name);
+                    }
+                }
+                None => unsupported.push(String::from(file_name)),
+            };
+            script_count += 1;
+        }
+    }
+    assert!(script_count > JIT_UNSUPPORTED.len());
+    assert_eq!(unsupported, JIT_UNSUPPORTED);
+    Ok(())
+}
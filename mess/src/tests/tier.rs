@@ -0,0 +1,153 @@
+use std::{
+    cell::RefCell,
+    env,
+    error::Error as StdError,
+    fs,
+    process,
+    result::Result as StdResult,
+};
+
+use mess_api::prelude::{
+    Adapter,
+    Function,
+    Module,
+    Type,
+};
+use mess_vm::exec::{
+    core::{
+        CoreError,
+        OverflowMode,
+    },
+    tier::TierPolicy,
+};
+
+use super::Result;
+use crate::{
+    engine::Engine,
+    error::Error,
+};
+
+thread_local! {
+    static RECORDED: RefCell<Vec<i64>> = const { RefCell::new(Vec::new()) };
+}
+
+fn record(adapter: &mut Adapter) {
+    let value: i64 = adapter.get_arg(0);
+    RECORDED.with(|recorded| recorded.borrow_mut().push(value));
+}
+
+/// Returns the values `host::record` was called with since the last call
+fn take_recorded() -> Vec<i64> {
+    RECORDED.with(|recorded| recorded.take())
+}
+
+/// Creates a tiered engine compiling functions after their fifth call, with
+/// `host::record` registered
+fn new_engine() -> StdResult<Engine, Box<dyn StdError>> {
+    let mut engine = Engine::new_tiered(1024 * 1024, TierPolicy::default().with_call_threshold(5));
+    let mut module = Module::new(String::from("host"));
+    module.add_function(Function::new("record", vec![Type::Int], Type::Void, record));
+    engine.register_module(module)?;
+    take_recorded();
+    Ok(engine)
+}
+
+/// The result of running a script and the values it recorded
+type Run = (StdResult<(), Error>, Vec<i64>);
+
+/// Runs `main` of the script from a file
+fn run_file(engine: &mut Engine, name: &str, source: &str) -> StdResult<Run, Box<dyn StdError>> {
+    let path = env::temp_dir().join(format!("mess-tier-{}-{}.mess", name, process::id()));
+    fs::write(&path, source)?;
+    let result = engine.run_file(&path);
+    fs::remove_file(&path)?;
+    Ok((result, take_recorded()))
+}
+
+/// Whether calls of the named function run compiled code
+fn is_compiled(engine: &Engine, fn_name: &str) -> StdResult<bool, Box<dyn StdError>> {
+    let core = engine.get_vm_core().ok_or("No interpreter")?;
+    let uid = core
+        .get_program()
+        .and_then(|program| program.get_function_uid(fn_name))
+        .ok_or("Unknown function")?;
+    Ok(core.is_compiled(uid))
+}
+
+/// Returns the functions with a `main` running the statement for the numbers
+/// up to `count`, with `$` replaced by the number. The bytecode compiler has
+/// no loops, so the statements are repeated.
+fn with_main(functions: &str, stmt: &str, count: i64) -> String {
+    let body: String = (0..count)
+        .map(|i| format!("    {}\n", stmt.replace('$', &i.to_string())))
+        .collect();
+    format!("{}fun main() {{\n{}}}\n", functions, body)
+}
+
+const SQUARES: &str = "
+fun square(n: int) ~ int {
+    return n * n;
+}
+fun report(n: int) {
+    host::record(square(n) + n);
+}
+";
+
+#[test]
+fn test_tiered_script() -> Result {
+    let mut engine = new_engine()?;
+    let (result, recorded) = run_file(&mut engine, "squares", &with_main(SQUARES, "report($);", 12))?;
+    result?;
+    let expected: Vec<i64> = (0..12).map(|n| n * n + n).collect();
+    assert_eq!(recorded, expected);
+    // Compiled code calls the host module as well
+    assert!(is_compiled(&engine, "report")?);
+    Ok(())
+}
+
+const ADD_I8: &str = "
+fun add(a: i8, b: i8) ~ i8 {
+    return a + b;
+}
+";
+
+#[test]
+fn test_tiered_overflow_mode() -> Result {
+    let source = with_main(ADD_I8, "host::record(add($ as i8, 100 as i8) as int);", 30);
+    let mut engine = new_engine()?;
+    let (result, recorded) = run_file(&mut engine, "wrap", &source)?;
+    result?;
+    assert!(is_compiled(&engine, "add")?);
+    assert_eq!(recorded[27], 127);
+    assert_eq!(recorded[28], -128);
+
+    // Compiled code traps like the interpreter
+    let mut engine = new_engine()?.with_vm_overflow_mode(OverflowMode::Trap);
+    let (result, recorded) = run_file(&mut engine, "trap", &source)?;
+    assert!(is_compiled(&engine, "add")?);
+    assert_eq!(recorded.len(), 28);
+    match result {
+        Err(Error::VmCoreError(err)) => assert!(matches!(err.into_inner(), CoreError::IntegerOverflow)),
+        _ => return Err("Expected an overflow".into()),
+    };
+    Ok(())
+}
+
+const TOTAL: &str = "
+static total: int = 0;
+fun add_total(n: int) ~ int {
+    total += n;
+    return total;
+}
+";
+
+#[test]
+fn test_tiered_statics_interpreted() -> Result {
+    let mut engine = new_engine()?;
+    let (result, recorded) = run_file(&mut engine, "total", &with_main(TOTAL, "host::record(add_total($));", 10))?;
+    result?;
+    assert_eq!(recorded, vec![0, 1, 3, 6, 10, 15, 21, 28, 36, 45]);
+    // The interpreter keeps the values of static variables
+    assert!(!is_compiled(&engine, "add_total")?);
+    Ok(())
+}
@@ -0,0 +1,139 @@
+use std::sync::{
+    Arc,
+    Mutex,
+};
+
+use mess_api::prelude::Module;
+use mess_core::{
+    compiler::Compiler as CompilerTrait,
+    parser::ast::{
+        Declaration,
+        Type,
+    },
+};
+use mess_jit::{
+    codegen::{
+        compiler::OverflowMode as JitOverflowMode,
+        output::Output as JitOutput,
+    },
+    exec::runtime::RuntimeError as JitRuntimeError,
+    Compiler as JitCompiler,
+};
+use mess_vm::exec::{
+    core::OverflowMode as VmOverflowMode,
+    tier::{
+        CompiledFn,
+        CompiledFnError,
+    },
+};
+
+/// Returns a tier-up handler compiling hot functions of the script with the
+/// JIT. The whole script is compiled once the first function gets hot, and
+/// compiled code calls the compiled versions of its callees. Integer
+/// arithmetic of compiled code overflows like the core's, given its
+/// overflow mode, and its calls count towards the call depth limit of the
+/// core. The core interprets compiled functions while it has fuel or can be
+/// interrupted.
+///
+/// Compiled code calls the functions of the given host modules. Async ones
+/// aren't registered with the JIT, which can't wait for them, so scripts
+/// calling them stay interpreted.
+///
+/// Compiled code has its own memory, so only values are passed between the
+/// tiers. Functions stay interpreted if the script can't be JIT compiled or
+/// declares static variables, whose values the interpreter keeps, or if they
+/// take or return references or handles of foreign objects.
+pub fn jit_tier_up_handler(
+    decl_list: Vec<Declaration>,
+    modules: Vec<Module>,
+    overflow_mode: VmOverflowMode,
+) -> impl FnMut(&str) -> Option<CompiledFn> + Send {
+    let overflow_mode = match overflow_mode {
+        VmOverflowMode::Wrap => JitOverflowMode::Wrap,
+        VmOverflowMode::Trap => JitOverflowMode::Trap,
+    };
+    let mut output: Option<Option<Arc<Mutex<JitOutput>>>> = None;
+    move |fn_name| {
+        let output = output
+            .get_or_insert_with(|| compile(&decl_list, &modules, overflow_mode))
+            .clone()?;
+        let (arg_sizes, returns) = {
+            let output = output.lock().ok()?;
+            let def = output.get_function_def(fn_name)?;
+            let arg_sizes = def
+                .arguments
+                .iter()
+                .map(|(_, arg_type)| get_size_of_type(arg_type))
+                .collect::<Option<Vec<_>>>()?;
+            (arg_sizes, def.returns.clone())
+        };
+        if returns != Type::Void {
+            get_size_of_type(&returns)?;
+        }
+        let fn_name = String::from(fn_name);
+        Some(CompiledFn::new(arg_sizes, move |args, max_call_depth| {
+            let args: Vec<u64> = args
+                .iter()
+                .map(|bytes| {
+                    let mut bits = [0; 8];
+                    bits[..bytes.len()].copy_from_slice(bytes);
+                    u64::from_le_bytes(bits)
+                })
+                .collect();
+            let output = output
+                .lock()
+                .map_err(|_| CompiledFnError::Failed(String::from("Poisoned JIT output")))?;
+            output.set_max_call_depth(Some(max_call_depth));
+            output.call_dynamic(&fn_name, &args).map_err(|err| match err {
+                JitRuntimeError::DivisionByZero => CompiledFnError::DivisionByZero,
+                JitRuntimeError::IntegerOverflow => CompiledFnError::IntegerOverflow,
+                JitRuntimeError::CallStackOverflow => CompiledFnError::CallStackOverflow,
+                JitRuntimeError::ScriptPanic(message) => CompiledFnError::ScriptPanic(message),
+                err => CompiledFnError::Failed(err.to_string()),
+            })
+        }))
+    }
+}
+
+fn compile(
+    decl_list: &[Declaration],
+    modules: &[Module],
+    overflow_mode: JitOverflowMode,
+) -> Option<Arc<Mutex<JitOutput>>> {
+    if declares_statics(decl_list) {
+        return None;
+    }
+    let mut compiler = JitCompiler::new().ok()?.with_overflow_mode(overflow_mode);
+    for module in modules {
+        let mut sync_module = Module::new(module.name.clone());
+        for function in module.functions.values().filter(|function| !function.is_async()) {
+            sync_module.add_function(function.clone());
+        }
+        compiler.register_module(sync_module).ok()?;
+    }
+    compiler.compile(decl_list).ok()?;
+    Some(Arc::new(Mutex::new(compiler.get_output().ok()?)))
+}
+
+/// Whether the declarations or the modules among them declare static
+/// variables
+fn declares_statics(decl_list: &[Declaration]) -> bool {
+    decl_list.iter().any(|decl| match decl {
+        Declaration::StaticVariable { .. } => true,
+        Declaration::Module { decl_list, .. } => declares_statics(decl_list),
+        _ => false,
+    })
+}
+
+/// Returns the size of values passed between the tiers, which are the same
+/// on the VM and in compiled code
+fn get_size_of_type(var_type: &Type) -> Option<usize> {
+    match var_type {
+        Type::Int | Type::U64 | Type::F64 => Some(8),
+        Type::Float | Type::I32 | Type::U32 => Some(4),
+        Type::I16 | Type::U16 => Some(2),
+        Type::Bool | Type::I8 | Type::U8 => Some(1),
+        // References and handles point into the memory of one tier
+        _ => None,
+    }
+}